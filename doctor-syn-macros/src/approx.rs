@@ -0,0 +1,308 @@
+//! Polynomial approximations computed at compile time.
+//!
+//! Both `approx!` and `#[approximate]` take a domain such as `x in -0.5..0.5`
+//! followed by options:
+//!
+//! * `terms = N` the number of polynomial terms, at least 2 (required).
+//! * `parity = odd | even | neither` the symmetry of the function (default `neither`).
+//! * `digits = N` the number of decimal digits used in the calculation
+//!   (default 20 for f32 and 40 for f64).
+
+use doctor_syn::{num_digits_for, Error, ErrorKind, Expression, Name, Parity};
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::convert::TryInto;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{Expr, Ident, ItemFn, Lit, Token, Type};
+
+/// The variable and its range, eg. `x in -0.5..0.5`.
+pub struct Domain {
+    variable: Ident,
+    min: Expr,
+    max: Expr,
+}
+
+impl Parse for Domain {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let variable: Ident = input.parse()?;
+        input.parse::<Token![in]>()?;
+        let range: Expr = input.parse()?;
+        match range {
+            Expr::Range(syn::ExprRange {
+                from: Some(min),
+                to: Some(max),
+                ..
+            }) => Ok(Domain {
+                variable,
+                min: *min,
+                max: *max,
+            }),
+            _ => Err(syn::Error::new(
+                range.span(),
+                "expected a range such as `-0.5..0.5`",
+            )),
+        }
+    }
+}
+
+/// A single `name = value` option.
+struct Setting {
+    name: Ident,
+    value: Expr,
+}
+
+impl Parse for Setting {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(Setting { name, value })
+    }
+}
+
+/// The options that follow the domain.
+pub struct Options {
+    terms: usize,
+    parity: Parity,
+    digits: Option<i64>,
+}
+
+fn parse_int<N>(value: &Expr) -> syn::Result<N>
+where
+    N: std::str::FromStr,
+    N::Err: std::fmt::Display,
+{
+    match value {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Int(i), ..
+        }) => i.base10_parse(),
+        _ => Err(syn::Error::new(value.span(), "expected an integer")),
+    }
+}
+
+impl Parse for Options {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut terms = None;
+        let mut parity = Parity::Neither;
+        let mut digits = None;
+        let settings = Punctuated::<Setting, Token![,]>::parse_terminated(input)?;
+        for Setting { name, value } in settings {
+            match name.to_string().as_str() {
+                "terms" => {
+                    let n: usize = parse_int(&value)?;
+                    if n < 2 {
+                        return Err(syn::Error::new(
+                            value.span(),
+                            "expected at least 2 terms",
+                        ));
+                    }
+                    terms = Some(n);
+                }
+                "digits" => digits = Some(parse_int(&value)?),
+                "parity" => {
+                    parity = match quote!(#value).to_string().as_str() {
+                        "odd" => Parity::Odd,
+                        "even" => Parity::Even,
+                        "neither" => Parity::Neither,
+                        _ => {
+                            return Err(syn::Error::new(
+                                value.span(),
+                                "expected `odd`, `even` or `neither`",
+                            ))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        "expected `terms`, `parity` or `digits`",
+                    ))
+                }
+            }
+        }
+        let terms = terms.ok_or_else(|| input.error("missing `terms = N`"))?;
+        Ok(Options {
+            terms,
+            parity,
+            digits,
+        })
+    }
+}
+
+/// Arguments to `approx!(f32, expr, x in min..max, terms = N, ...)`.
+pub struct ApproxArgs {
+    ty: Type,
+    expr: Expr,
+    domain: Domain,
+    options: Options,
+}
+
+impl Parse for ApproxArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ty = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let expr = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let domain = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let options = input.parse()?;
+        Ok(ApproxArgs {
+            ty,
+            expr,
+            domain,
+            options,
+        })
+    }
+}
+
+/// Arguments to `#[approximate(x in min..max, terms = N, ...)]`.
+pub struct AttrArgs {
+    domain: Domain,
+    options: Options,
+}
+
+impl Parse for AttrArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let domain = input.parse()?;
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+        }
+        let options = input.parse()?;
+        Ok(AttrArgs { domain, options })
+    }
+}
+
+fn num_bits(ty: &Type) -> syn::Result<usize> {
+    match quote!(#ty).to_string().as_str() {
+        "f32" => Ok(32),
+        "f64" => Ok(64),
+        _ => Err(syn::Error::new(ty.span(), "expected `f32` or `f64`")),
+    }
+}
+
+fn eval_bound(bound: &Expr, num_digits: i64) -> doctor_syn::Result<f64> {
+    Expression::from(bound.clone())
+        .eval(num_digits)?
+        .try_into()
+        .map_err(|e: Error| {
+            Error::at(ErrorKind::CouldNotEvaluate, bound)
+                .with_operation("evaluating the range of the domain")
+                .caused_by(e)
+        })
+}
+
+/// Approximate `expr` over the domain as a mul_add chain using `num_bits` floats.
+fn approximate(
+    expr: &Expr,
+    num_bits: usize,
+    domain: &Domain,
+    options: &Options,
+) -> doctor_syn::Result<Expr> {
+    let num_digits = options.digits.unwrap_or_else(|| num_digits_for(num_bits));
+    let xmin = eval_bound(&domain.min, num_digits)?;
+    let xmax = eval_bound(&domain.max, num_digits)?;
+    if xmin >= xmax {
+        return Err(Error::at(ErrorKind::CouldNotEvaluate, &domain.min)
+            .with_operation("checking the range of the domain")
+            .with_message(format!("expected {} to be less than {}", xmin, xmax)));
+    }
+    let variable = Name::from(syn::Path::from(domain.variable.clone()));
+    Ok(Expression::from(expr.clone())
+        .approx(options.terms, xmin, xmax, variable, options.parity, num_digits)?
+        .use_number_type("decimal", num_bits)?
+        .into_inner())
+}
+
+pub fn expand_approx(args: ApproxArgs) -> syn::Result<TokenStream> {
+    let num_bits = num_bits(&args.ty)?;
+    let poly = approximate(&args.expr, num_bits, &args.domain, &args.options)?;
+    Ok(quote!( (#poly) ))
+}
+
+pub fn expand_approx_attr(args: AttrArgs, item: ItemFn) -> syn::Result<TokenStream> {
+    let ty = match &item.sig.output {
+        syn::ReturnType::Type(_, ty) => ty,
+        syn::ReturnType::Default => {
+            return Err(syn::Error::new(
+                item.sig.span(),
+                "expected a function returning `f32` or `f64`",
+            ))
+        }
+    };
+    let num_bits = num_bits(ty)?;
+    let variable = &args.domain.variable;
+    let is_parameter = item.sig.inputs.iter().any(|input| match input {
+        syn::FnArg::Typed(syn::PatType { pat, .. }) => {
+            matches!(&**pat, syn::Pat::Ident(p) if p.ident == *variable)
+        }
+        syn::FnArg::Receiver(_) => false,
+    });
+    if !is_parameter {
+        return Err(syn::Error::new(
+            variable.span(),
+            format!("expected `{}` to be a parameter of the function", variable),
+        ));
+    }
+    let expr = match item.block.stmts.as_slice() {
+        [syn::Stmt::Expr(expr)] => expr,
+        _ => {
+            return Err(syn::Error::new(
+                item.block.span(),
+                "expected the body to be a single expression",
+            ))
+        }
+    };
+    let poly = approximate(expr, num_bits, &args.domain, &args.options)?;
+    let ItemFn {
+        attrs, vis, sig, ..
+    } = &item;
+    Ok(quote!(
+        #(#attrs)*
+        #vis #sig {
+            #poly
+        }
+    ))
+}
+
+#[test]
+fn test_expand_approx() {
+    let args: ApproxArgs =
+        syn::parse_quote!(f32, (x * PI).sin(), x in -0.5..0.5, terms = 4, parity = odd);
+    let tokens = expand_approx(args).unwrap().to_string();
+    assert!(tokens.contains("mul_add"));
+    assert!(tokens.contains("f32"));
+
+    let args: ApproxArgs = syn::parse_quote!(f64, (x * y).sin(), x in -1..1, terms = 4);
+    let err = expand_approx(args).unwrap_err().to_string();
+    assert!(err.contains("undefined variable `y`"));
+}
+
+#[test]
+fn test_approx_args_errors() {
+    for terms in ["0", "1"] {
+        let src = format!("f32, x.sin(), x in -0.5..0.5, terms = {}", terms);
+        let err = syn::parse_str::<ApproxArgs>(&src).err().unwrap();
+        assert_eq!(err.to_string(), "expected at least 2 terms");
+    }
+
+    let args: ApproxArgs = syn::parse_quote!(f32, x.sin(), x in 0.5..-0.5, terms = 4);
+    let err = expand_approx(args).unwrap_err().to_string();
+    assert!(err.contains("expected 0.5 to be less than -0.5"));
+}
+
+#[test]
+fn test_expand_approx_attr_errors() {
+    let args: AttrArgs = syn::parse_quote!(x in -0.5..0.5, terms = 4);
+    let item: ItemFn = syn::parse_quote!(
+        fn f(t: f64) -> f64 {
+            t.sin()
+        }
+    );
+    let err = expand_approx_attr(args, item).unwrap_err().to_string();
+    assert_eq!(err, "expected `x` to be a parameter of the function");
+
+    let err = syn::parse_str::<AttrArgs>("x in -0.5..0.5").err().unwrap();
+    assert!(err.to_string().contains("missing `terms = N`"));
+}
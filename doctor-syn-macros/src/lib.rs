@@ -0,0 +1,83 @@
+//! Procedural macros for Doctor Syn.
+//!
+//! These compute polynomial approximations at compile time so that
+//! crates can embed custom approximations without a code generation step
+//! and generate portable SIMD versions of scalar functions.
+//!
+//! ```ignore
+//! use doctor_syn_macros::{approx, approximate};
+//!
+//! fn sinpi(x: f32) -> f32 {
+//!     approx!(f32, (x * PI).sin(), x in -0.5..0.5, terms = 8, parity = odd)
+//! }
+//!
+//! #[approximate(x in -0.5..0.5, terms = 16, parity = odd)]
+//! fn sinpi64(x: f64) -> f64 {
+//!     (x * PI).sin()
+//! }
+//! ```
+//!
+//! ```ignore
+//! #![feature(portable_simd)]
+//! use doctor_syn_macros::simd_version;
+//! use std::simd::{prelude::*, StdFloat};
+//!
+//! // Also generates `fn kernel_simd(x: Simd<f32, 8>) -> Simd<f32, 8>`.
+//! #[simd_version(lanes = 8)]
+//! fn kernel(x: f32) -> f32 {
+//!     if x < 0.0 { 0.0 } else { x.mul_add(x, 1.0) }
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+mod approx;
+mod simd;
+
+/// Expand to a polynomial approximation of an expression in one variable.
+///
+/// `approx!(f32, (x * PI).sin(), x in -0.5..0.5, terms = 8, parity = odd)`
+///
+/// The result is a `mul_add` chain in the variable, which must be in scope.
+#[proc_macro]
+pub fn approx(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as approx::ApproxArgs);
+    approx::expand_approx(args)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Replace the body of a function with a polynomial approximation of it.
+///
+/// `#[approximate(x in -0.5..0.5, terms = 16, parity = odd)]`
+///
+/// The body must be a single expression, the return type `f32` or `f64`
+/// and the variable of the domain one of the parameters.
+///
+/// This is not called `#[approx]` because attribute and function-like
+/// macros share a namespace, so it would clash with `approx!`.
+#[proc_macro_attribute]
+pub fn approximate(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as approx::AttrArgs);
+    let item = parse_macro_input!(item as syn::ItemFn);
+    approx::expand_approx_attr(args, item)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Add a portable SIMD twin of a scalar function.
+///
+/// `#[simd_version(lanes = 8)]` on `fn f(x: f32) -> f32` also emits
+/// `fn f_simd(x: Simd<f32, 8>) -> Simd<f32, 8>`. Use `name = ident` to name it.
+///
+/// The generated code requires `#![feature(portable_simd)]`,
+/// `std::simd::prelude::*` and `std::simd::StdFloat` in scope.
+#[proc_macro_attribute]
+pub fn simd_version(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as simd::SimdArgs);
+    let item = parse_macro_input!(item as syn::ItemFn);
+    simd::expand_simd_version(args, item)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
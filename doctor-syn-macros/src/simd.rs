@@ -0,0 +1,127 @@
+//! Vectorised twins of scalar functions using portable SIMD.
+//!
+//! `#[simd_version(lanes = N)]` keeps the function and adds `name_simd`
+//! taking and returning `std::simd::Simd<T, N>` values.
+//! The options are:
+//!
+//! * `lanes = N` the number of lanes (required).
+//! * `name = ident` the name of the new function (default `name_simd`).
+
+use doctor_syn::codegen::portable_simd::{to_simd_fn, FnOptions};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{Expr, Ident, ItemFn, Lit, Token};
+
+/// A single `name = value` option.
+struct Setting {
+    name: Ident,
+    value: Expr,
+}
+
+impl Parse for Setting {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(Setting { name, value })
+    }
+}
+
+/// Arguments to `#[simd_version(lanes = N, name = ident)]`.
+pub struct SimdArgs {
+    lanes: usize,
+    name: Option<Ident>,
+}
+
+impl Parse for SimdArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut lanes = None;
+        let mut name = None;
+        let settings = Punctuated::<Setting, Token![,]>::parse_terminated(input)?;
+        for Setting {
+            name: setting,
+            value,
+        } in settings
+        {
+            match (setting.to_string().as_str(), &value) {
+                (
+                    "lanes",
+                    Expr::Lit(syn::ExprLit {
+                        lit: Lit::Int(i), ..
+                    }),
+                ) => {
+                    let n: usize = i.base10_parse()?;
+                    if !n.is_power_of_two() || n > 64 {
+                        return Err(syn::Error::new(
+                            value.span(),
+                            "expected a power of two from 1 to 64",
+                        ));
+                    }
+                    lanes = Some(n);
+                }
+                ("name", Expr::Path(path)) if path.path.get_ident().is_some() => {
+                    name = path.path.get_ident().cloned();
+                }
+                ("lanes", _) => return Err(syn::Error::new(value.span(), "expected an integer")),
+                ("name", _) => return Err(syn::Error::new(value.span(), "expected an identifier")),
+                _ => {
+                    return Err(syn::Error::new(
+                        setting.span(),
+                        "expected `lanes` or `name`",
+                    ))
+                }
+            }
+        }
+        let lanes = lanes.ok_or_else(|| input.error("missing `lanes = N`"))?;
+        Ok(SimdArgs { lanes, name })
+    }
+}
+
+pub fn expand_simd_version(args: SimdArgs, item: ItemFn) -> syn::Result<TokenStream> {
+    let options = FnOptions {
+        lanes: args.lanes,
+        name: args.name,
+    };
+    let simd = to_simd_fn(&item, options)?;
+    Ok(quote!(
+        #item
+        #simd
+    ))
+}
+
+#[test]
+fn test_expand_simd_version() {
+    let args: SimdArgs = syn::parse_quote!(lanes = 8);
+    let item: ItemFn = syn::parse_quote!(
+        fn square(x: f32) -> f32 {
+            x * x + 1.0
+        }
+    );
+    let tokens = expand_simd_version(args, item).unwrap().to_string();
+    assert!(tokens.starts_with("fn square (x : f32) -> f32"));
+    assert!(tokens.contains("fn square_simd (x : std :: simd :: Simd < f32 , 8usize >)"));
+
+    let args: SimdArgs = syn::parse_quote!(lanes = 4, name = square4);
+    let item: ItemFn = syn::parse_quote!(
+        fn square(x: f64) -> f64 {
+            x * x
+        }
+    );
+    let tokens = expand_simd_version(args, item).unwrap().to_string();
+    assert!(tokens.contains("fn square4 (x : std :: simd :: Simd < f64 , 4usize >)"));
+
+    let err = syn::parse_str::<SimdArgs>("lanes = 3").err().unwrap();
+    assert_eq!(err.to_string(), "expected a power of two from 1 to 64");
+
+    let args: SimdArgs = syn::parse_quote!(lanes = 4);
+    let item: ItemFn = syn::parse_quote!(
+        fn first(x: &[f32]) -> f32 {
+            x[0]
+        }
+    );
+    let err = expand_simd_version(args, item).unwrap_err().to_string();
+    assert!(err.starts_with("unsupported in code generation `& [f32]`"));
+}
@@ -0,0 +1,37 @@
+use doctor_syn_macros::{approx, approximate};
+
+fn sinpi(x: f32) -> f32 {
+    approx!(f32, (x * PI).sin(), x in -0.5..0.5, terms = 8, parity = odd)
+}
+
+#[approximate(x in -0.5..0.5, terms = 16, parity = odd)]
+fn sinpi64(x: f64) -> f64 {
+    (x * PI).sin()
+}
+
+#[approximate(x in 0..1, terms = 12, digits = 30)]
+fn exp64(x: f64) -> f64 {
+    x.exp()
+}
+
+#[test]
+fn test_approx() {
+    for i in -50..=50 {
+        let x = i as f32 * 0.01;
+        let expected = (x * std::f32::consts::PI).sin();
+        assert!((sinpi(x) - expected).abs() < 1e-5, "sinpi({})", x);
+    }
+}
+
+#[test]
+fn test_approximate() {
+    for i in -50..=50 {
+        let x = i as f64 * 0.01;
+        let expected = (x * std::f64::consts::PI).sin();
+        assert!((sinpi64(x) - expected).abs() < 1e-12, "sinpi64({})", x);
+    }
+    for i in 0..=100 {
+        let x = i as f64 * 0.01;
+        assert!((exp64(x) - x.exp()).abs() < 1e-12, "exp64({})", x);
+    }
+}
@@ -4,7 +4,7 @@
 pub use bigdecimal::{BigDecimal, FromPrimitive, One, Signed, ToPrimitive, Zero};
 pub use num_bigint::BigInt;
 
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 
 // BigDecimal's round panics on large numbers.
 // example:
@@ -332,16 +332,25 @@ pub fn dnorm(x: BigDecimal, mean: BigDecimal, sd: BigDecimal, num_digits: i64) -
     k1 * exp(-&x * &x * half(), num_digits) / sd
 }
 
+fn overflow(x: &BigDecimal, bits: usize) -> Error {
+    Error::new(ErrorKind::Overflow)
+        .with_operation(format!("rounding {} to f{}", x, bits))
+}
+
 /// Round to f32 or f64.
 pub fn round_ieee(x: BigDecimal, bits: BigDecimal, _num_digits: i64) -> Result<BigDecimal> {
     match bits.to_i32() {
-        Some(32) => {
-            Ok(BigDecimal::from_f32(x.to_f32().ok_or(Error::Overflow)?).ok_or(Error::Overflow)?)
-        }
-        Some(64) => {
-            Ok(BigDecimal::from_f64(x.to_f64().ok_or(Error::Overflow)?).ok_or(Error::Overflow)?)
-        }
-        _ => Err(Error::Expected32or64bits),
+        Some(32) => x
+            .to_f32()
+            .and_then(BigDecimal::from_f32)
+            .ok_or_else(|| overflow(&x, 32)),
+        Some(64) => x
+            .to_f64()
+            .and_then(BigDecimal::from_f64)
+            .ok_or_else(|| overflow(&x, 64)),
+        _ => Err(Error::new(ErrorKind::Expected32or64bits)
+            .with_operation("rounding to an IEEE float")
+            .with_message(format!("got {} bits", bits))),
     }
 }
 
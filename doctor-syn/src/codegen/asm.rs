@@ -0,0 +1,1472 @@
+//! Translate Rust functions into x86-64 assembly using AVX2 and FMA.
+//!
+//! The output is for the GNU assembler in Intel syntax and functions follow
+//! the System V ABI: floats and vectors are passed in `xmm0`-`xmm7` or
+//! `ymm0`-`ymm7` and scalar integers and bools in `rdi`, `rsi` and so on.
+//!
+//! Functions are treated as straight-line code: `if`s evaluate both branches
+//! and blend, so branches must be cheap and must not fail. Values get
+//! registers in the order they are defined and give them up after their
+//! last use. When no register is free, the value used furthest in the
+//! future is spilled to the stack. Literals and constants are read from
+//! memory. Integers must be the size of the floats so that lanes line up,
+//! and bools are masks of that size.
+
+use crate::codegen::llvm_ir::special_value;
+use crate::ir::{
+    lower_file, BinaryOp, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Ty, UnaryOp,
+    Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::fmt::Write;
+
+pub struct Options {
+    /// Prepended to the names of functions, eg. `ds64_`.
+    pub prefix: String,
+    /// Make every value a 256 bit `ymm` vector of this many lanes.
+    pub lanes: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: "".to_string(),
+            lanes: None,
+        }
+    }
+}
+
+// Registers `xmm0` to `xmm11` hold values, the rest are scratch.
+const NUM_REGS: usize = 12;
+const NUM_SCRATCH: usize = 4;
+
+const INT_ARGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+
+// Predicates of `vcmp`, ordered except for `!=`.
+const CMP_EQ: u8 = 0x00;
+const CMP_UNORD: u8 = 0x03;
+const CMP_NEQ: u8 = 0x04;
+const CMP_LT: u8 = 0x11;
+const CMP_LE: u8 = 0x12;
+const CMP_GE: u8 = 0x1d;
+const CMP_GT: u8 = 0x1e;
+
+// Immediates of `vround`, which do not raise inexact.
+const ROUND_FLOOR: u8 = 0x9;
+const ROUND_CEIL: u8 = 0xa;
+const ROUND_TRUNC: u8 = 0xb;
+
+// Where a value is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Loc {
+    None,
+    Reg(usize),
+    Stack(usize),
+    Const(u64),
+}
+
+// The bits of a literal of `num_bits`, following constants.
+fn literal_bits(module: &Module, func: &Function, value: Value, num_bits: usize) -> Option<u64> {
+    let scalar = match func.ty(value) {
+        Ty::Scalar(scalar) => *scalar,
+        _ => return None,
+    };
+    let mask = u64::MAX >> (64 - num_bits);
+    let float = |x: f64| match num_bits {
+        32 => (x as f32).to_bits() as u64,
+        _ => x.to_bits(),
+    };
+    match &func.inst(value)?.op {
+        Op::Lit(Literal::Float(digits)) if num_bits == 32 => {
+            digits.parse::<f32>().ok().map(|x| x.to_bits() as u64)
+        }
+        Op::Lit(Literal::Float(digits)) => digits.parse::<f64>().ok().map(f64::to_bits),
+        Op::Lit(Literal::Int(i)) if scalar.is_float() => Some(float(*i as f64)),
+        Op::Lit(Literal::Int(i)) => Some(*i as u64 & mask),
+        Op::Lit(Literal::Bool(b)) => Some(if *b { mask } else { 0 }),
+        Op::Lit(Literal::Special(s)) => Some(float(special_value(*s, scalar))),
+        Op::Unary(UnaryOp::Neg, a) => {
+            let bits = literal_bits(module, func, *a, num_bits)?;
+            match scalar.is_float() {
+                true => Some(bits ^ (1 << (num_bits - 1))),
+                false => Some(bits.wrapping_neg() & mask),
+            }
+        }
+        Op::Const(name) => {
+            let c = module.constant(name)?;
+            literal_bits(module, c, c.body.result, num_bits)
+        }
+        Op::Convert(a) => {
+            let from = match func.ty(*a) {
+                Ty::Scalar(from) => *from,
+                _ => return None,
+            };
+            let bits = literal_bits(module, func, *a, 64)?;
+            match (from.is_float(), scalar.is_float()) {
+                (true, true) => Some(float(f64::from_bits(bits))),
+                (false, true) if from.is_signed() => Some(float(bits as i64 as f64)),
+                (false, true) => Some(float(bits as f64)),
+                (false, false) if scalar != Scalar::Bool => Some(bits & mask),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Instructions in the order they are printed, `if`s after their branches.
+fn linearize<'a>(insts: &'a [Inst], order: &mut Vec<&'a Inst>) {
+    for inst in insts {
+        if let Op::If(_, then_body, else_body) = &inst.op {
+            linearize(&then_body.insts, order);
+            linearize(&else_body.insts, order);
+        }
+        order.push(inst);
+    }
+}
+
+fn uses_of(inst: &Inst) -> Vec<Value> {
+    match &inst.op {
+        Op::If(c, then_body, else_body) => vec![*c, then_body.result, else_body.result],
+        op => op.operands(),
+    }
+}
+
+// Constant data shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    // The data of each constant, labelled `.LC<n>`.
+    pool: Vec<String>,
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    module: &'a Module,
+    options: &'a Options,
+    decls: &'a mut Decls,
+    // The size of floats, integers and lanes of masks.
+    num_bits: usize,
+    order: Vec<&'a Inst>,
+    // The positions in `order` of the uses of each value.
+    uses: Vec<Vec<usize>>,
+    loc: Vec<Loc>,
+    regs: [Option<Value>; NUM_REGS],
+    pos: usize,
+    scratch: usize,
+    slots: usize,
+    // Stack slots to convert vectors lane by lane.
+    buffer: Option<usize>,
+    has_call: bool,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(
+        func: &'a Function,
+        module: &'a Module,
+        options: &'a Options,
+        decls: &'a mut Decls,
+    ) -> Result<Self> {
+        let mut printer = Printer {
+            func,
+            module,
+            options,
+            decls,
+            num_bits: 64,
+            order: Vec::new(),
+            uses: vec![Vec::new(); func.values.len()],
+            loc: vec![Loc::None; func.values.len()],
+            regs: [None; NUM_REGS],
+            pos: 0,
+            scratch: 0,
+            slots: 0,
+            buffer: None,
+            has_call: false,
+            text: String::new(),
+        };
+        printer.num_bits = printer.check_types()?;
+        linearize(&func.body.insts, &mut printer.order);
+        for (pos, inst) in printer.order.iter().enumerate() {
+            // Literals are folded into one constant.
+            if literal_bits(module, func, inst.value, 64).is_some() {
+                continue;
+            }
+            for v in uses_of(inst) {
+                printer.uses[v.0].push(pos);
+            }
+        }
+        printer.uses[func.body.result.0].push(printer.order.len());
+        Ok(printer)
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating assembly for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            ty => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    // The size of the values, which must all be the same apart from literals.
+    fn check_types(&self) -> Result<usize> {
+        use Scalar::*;
+        let mut values = self.func.params.clone();
+        self.func.body.walk(&mut |inst| values.push(inst.value));
+        let mut num_bits = None;
+        for value in values {
+            let scalar = self.scalar(value)?;
+            if scalar == Bool || literal_bits(self.module, self.func, value, 64).is_some() {
+                continue;
+            }
+            if matches!(scalar, I8 | U8 | I16 | U16) {
+                return Err(self.error(&format!(
+                    "the type {} is not supported",
+                    self.func.ty(value)
+                )));
+            }
+            if num_bits.is_some() && num_bits != Some(scalar.num_bits()) {
+                return Err(self.error("values of different sizes are not supported"));
+            }
+            num_bits = Some(scalar.num_bits());
+        }
+        let num_bits = num_bits.unwrap_or(64);
+        match self.options.lanes {
+            Some(lanes) if lanes * num_bits != 256 => Err(self.error(&format!(
+                "{} lanes of {} bits do not fill a ymm register",
+                lanes, num_bits
+            ))),
+            _ => Ok(num_bits),
+        }
+    }
+
+    fn is_vector(&self) -> bool {
+        self.options.lanes.is_some()
+    }
+
+    // A register as used by the function, `xmm` or `ymm`.
+    fn reg_name(&self, reg: usize) -> String {
+        match self.is_vector() {
+            true => format!("ymm{}", reg),
+            false => format!("xmm{}", reg),
+        }
+    }
+
+    // The suffix of float instructions, eg. `sd` or `ps`.
+    fn fsfx(&self) -> &'static str {
+        match (self.is_vector(), self.num_bits) {
+            (false, 64) => "sd",
+            (false, _) => "ss",
+            (true, 64) => "pd",
+            (true, _) => "ps",
+        }
+    }
+
+    // The suffix of packed float instructions, for bitwise operations.
+    fn psfx(&self) -> &'static str {
+        match self.num_bits {
+            64 => "pd",
+            _ => "ps",
+        }
+    }
+
+    // The suffix of integer instructions.
+    fn isfx(&self) -> &'static str {
+        match self.num_bits {
+            64 => "q",
+            _ => "d",
+        }
+    }
+
+    // A memory operand, `scalar` if a scalar instruction reads it.
+    fn mem(&self, addr: &str, scalar: bool) -> String {
+        match (scalar, self.is_vector()) {
+            (true, false) => self.lane_mem(addr),
+            (_, true) => format!("YMMWORD PTR {}", addr),
+            (false, false) => format!("XMMWORD PTR {}", addr),
+        }
+    }
+
+    // A memory operand of one element.
+    fn lane_mem(&self, addr: &str) -> String {
+        match self.num_bits {
+            64 => format!("QWORD PTR {}", addr),
+            _ => format!("DWORD PTR {}", addr),
+        }
+    }
+
+    fn slot_addr(&self, slot: usize, offset: usize) -> String {
+        format!("[rbp - {}]", 32 * (slot + 1) - offset)
+    }
+
+    fn addr(&mut self, loc: Loc, offset: usize) -> String {
+        match loc {
+            Loc::Stack(slot) => self.slot_addr(slot, offset),
+            Loc::Const(bits) => match (self.intern(bits), offset) {
+                (n, 0) => format!("[rip + .LC{}]", n),
+                (n, offset) => format!("[rip + .LC{} + {}]", n, offset),
+            },
+            _ => unreachable!("values in registers have no address"),
+        }
+    }
+
+    fn emit(&mut self, ins: &str, args: &[&str]) {
+        let _ = writeln!(self.text, "\t{}\t{}", ins, args.join(", "));
+    }
+
+    // A constant in every lane, added to the data when first addressed.
+    fn constant(&self, bits: u64) -> Loc {
+        Loc::Const(bits)
+    }
+
+    fn intern(&mut self, bits: u64) -> usize {
+        let lanes = if self.is_vector() { 256 } else { 128 } / self.num_bits;
+        let data = match self.num_bits {
+            64 => format!(
+                ".quad\t{}",
+                vec![format!("{:#018x}", bits); lanes].join(", ")
+            ),
+            _ => format!(
+                ".long\t{}",
+                vec![format!("{:#010x}", bits); lanes].join(", ")
+            ),
+        };
+        match self.decls.pool.iter().position(|d| *d == data) {
+            Some(n) => n,
+            None => {
+                self.decls.pool.push(data);
+                self.decls.pool.len() - 1
+            }
+        }
+    }
+
+    fn constant_mem(&mut self, bits: u64, scalar: bool) -> String {
+        let loc = self.constant(bits);
+        let addr = self.addr(loc, 0);
+        self.mem(&addr, scalar)
+    }
+
+    fn float_bits(&self, x: f64) -> u64 {
+        match self.num_bits {
+            64 => x.to_bits(),
+            _ => (x as f32).to_bits() as u64,
+        }
+    }
+
+    fn sign_bit(&self) -> u64 {
+        1 << (self.num_bits - 1)
+    }
+
+    fn ones(&self) -> u64 {
+        u64::MAX >> (64 - self.num_bits)
+    }
+
+    // A scratch register, up to four in each instruction.
+    fn scratch(&mut self) -> usize {
+        let reg = NUM_REGS + NUM_SCRATCH - 1 - self.scratch;
+        self.scratch += 1;
+        assert!(self.scratch <= NUM_SCRATCH, "out of scratch registers");
+        reg
+    }
+
+    fn next_use(&self, value: Value) -> Option<usize> {
+        self.uses[value.0].iter().copied().find(|p| *p > self.pos)
+    }
+
+    // A value as a register or memory operand.
+    fn rm(&mut self, value: Value, scalar: bool) -> String {
+        match self.loc[value.0] {
+            Loc::Reg(reg) => self.reg_name(reg),
+            loc => {
+                let addr = self.addr(loc, 0);
+                self.mem(&addr, scalar)
+            }
+        }
+    }
+
+    // A value in a register, loaded into a scratch register if need be.
+    fn reg(&mut self, value: Value) -> String {
+        match self.loc[value.0] {
+            Loc::Reg(reg) => self.reg_name(reg),
+            loc => {
+                let scratch = self.scratch();
+                let scratch = self.reg_name(scratch);
+                let addr = self.addr(loc, 0);
+                let mem = self.mem(&addr, false);
+                self.emit(&format!("vmovup{}", &self.psfx()[1..]), &[&scratch, &mem]);
+                scratch
+            }
+        }
+    }
+
+    fn spill(&mut self, value: Value) {
+        if let Loc::Reg(reg) = self.loc[value.0] {
+            let slot = self.slots;
+            self.slots += 1;
+            let mem = self.mem(&self.slot_addr(slot, 0), false);
+            let reg_name = self.reg_name(reg);
+            self.emit(&format!("vmovup{}", &self.psfx()[1..]), &[&mem, &reg_name]);
+            self.loc[value.0] = Loc::Stack(slot);
+            self.regs[reg] = None;
+        }
+    }
+
+    // Free the registers of operands that are not used again.
+    fn release(&mut self, operands: &[Value]) {
+        for v in operands {
+            if let Loc::Reg(reg) = self.loc[v.0] {
+                if self.next_use(*v).is_none() && self.regs[reg] == Some(*v) {
+                    self.regs[reg] = None;
+                }
+            }
+        }
+    }
+
+    // Give a value a register, preferring those of its operands.
+    fn define(&mut self, value: Value, operands: &[Value]) -> String {
+        self.release(operands);
+        let operand_regs = operands
+            .iter()
+            .filter_map(|v| match self.loc[v.0] {
+                Loc::Reg(reg) => Some(reg),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let reg = operand_regs
+            .iter()
+            .copied()
+            .find(|reg| self.regs[*reg].is_none())
+            .or_else(|| (0..NUM_REGS).find(|reg| self.regs[*reg].is_none()))
+            .unwrap_or_else(|| {
+                // Spill the value used furthest in the future.
+                let victim = (0..NUM_REGS)
+                    .filter(|reg| !operand_regs.contains(reg))
+                    .max_by_key(|reg| self.regs[*reg].and_then(|v| self.next_use(v)))
+                    .unwrap_or(0);
+                if let Some(v) = self.regs[victim] {
+                    self.spill(v);
+                }
+                victim
+            });
+        self.regs[reg] = Some(value);
+        self.loc[value.0] = Loc::Reg(reg);
+        self.reg_name(reg)
+    }
+
+    // `dst = ins a, b`, with `a` in a register.
+    fn binop(&mut self, value: Value, ins: &str, a: Value, b: Value, scalar: bool) -> String {
+        let x = self.reg(a);
+        let y = self.rm(b, scalar);
+        let dst = self.define(value, &[a, b]);
+        self.emit(ins, &[&dst, &x, &y]);
+        dst
+    }
+
+    // `dst = ins a, constant`.
+    fn binop_const(&mut self, value: Value, ins: &str, a: Value, bits: u64, scalar: bool) {
+        let x = self.reg(a);
+        let y = self.constant_mem(bits, scalar);
+        let dst = self.define(value, &[a]);
+        self.emit(ins, &[&dst, &x, &y]);
+    }
+
+    fn mov(&mut self, value: Value, a: Value) {
+        let x = self.rm(a, false);
+        let dst = self.define(value, &[a]);
+        if x != dst {
+            let ins = match x.contains("PTR") {
+                true => format!("vmovup{}", &self.psfx()[1..]),
+                false => format!("vmovap{}", &self.psfx()[1..]),
+            };
+            self.emit(&ins, &[&dst, &x]);
+        }
+    }
+
+    fn cmp(&mut self, value: Value, a: Value, b: Value, predicate: u8) {
+        let x = self.reg(a);
+        let y = self.rm(b, true);
+        let dst = self.define(value, &[a, b]);
+        let ins = format!("vcmp{}", self.fsfx());
+        self.emit(&ins, &[&dst, &x, &y, &format!("{:#x}", predicate)]);
+    }
+
+    // A register of zeros.
+    fn zero(&mut self) -> String {
+        let reg = self.scratch();
+        let reg = self.reg_name(reg);
+        self.emit("vpxor", &[&reg, &reg, &reg]);
+        reg
+    }
+
+    // Flip the sign bits of integers so that signed comparisons are unsigned.
+    fn flip(&mut self, value: Value, unsigned: bool) -> String {
+        if !unsigned {
+            return self.reg(value);
+        }
+        let x = self.rm(value, false);
+        let sign = self.sign_bit();
+        let sign = self.constant_mem(sign, false);
+        let reg = self.scratch();
+        let reg = self.reg_name(reg);
+        self.emit("vpxor", &[&reg, &x, &sign]);
+        reg
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<()> {
+        let value = inst.value;
+        let scalar = self.scalar(value)?;
+        let (p, i) = (self.psfx(), self.isfx());
+        let (sign, ones) = (self.sign_bit(), self.ones());
+        match &inst.op {
+            Op::Unary(UnaryOp::Neg, a) if scalar.is_float() => {
+                self.binop_const(value, &format!("vxor{}", p), *a, sign, false)
+            }
+            Op::Unary(UnaryOp::Neg, a) => {
+                let zero = self.zero();
+                let x = self.rm(*a, false);
+                let dst = self.define(value, &[*a]);
+                self.emit(&format!("vpsub{}", i), &[&dst, &zero, &x]);
+            }
+            Op::Unary(UnaryOp::Not, a) => self.binop_const(value, "vpxor", *a, ones, false),
+            Op::Binary(op, a, b) => self.binary(value, *op, *a, *b)?,
+            Op::Select(c, a, b) => self.select(value, *c, *a, *b),
+            Op::If(c, then_body, else_body) => {
+                self.select(value, *c, then_body.result, else_body.result)
+            }
+            Op::Convert(a) => self.convert(value, *a)?,
+            Op::Bitcast(a) => self.mov(value, *a),
+            Op::Splat(a) if self.is_vector() => self.mov(value, *a),
+            Op::Splat(_) => return Err(self.error("vectors need a lane count")),
+            Op::Intrinsic(intrinsic, args) => self.intrinsic(value, *intrinsic, args)?,
+            Op::Tuple(_) | Op::Extract(..) => return Err(self.error("tuples are not supported")),
+            Op::Lit(_) | Op::Const(_) => {
+                return Err(self.error(&format!("{} is not a literal", self.func.name(value))))
+            }
+            Op::Call(..) => unreachable!("printed by call"),
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, value: Value, c: Value, a: Value, b: Value) {
+        let x = self.reg(b);
+        let y = self.rm(a, false);
+        let mask = self.reg(c);
+        let dst = self.define(value, &[c, a, b]);
+        self.emit(&format!("vblendv{}", self.psfx()), &[&dst, &x, &y, &mask]);
+    }
+
+    fn binary(&mut self, value: Value, op: BinaryOp, a: Value, b: Value) -> Result<()> {
+        use BinaryOp::*;
+        let scalar = self.scalar(a)?;
+        let (f, i) = (self.fsfx(), self.isfx());
+        if scalar.is_float() {
+            let predicate = match op {
+                Eq => Some(CMP_EQ),
+                Ne => Some(CMP_NEQ),
+                Lt => Some(CMP_LT),
+                Le => Some(CMP_LE),
+                Gt => Some(CMP_GT),
+                Ge => Some(CMP_GE),
+                _ => None,
+            };
+            if let Some(predicate) = predicate {
+                self.cmp(value, a, b, predicate);
+                return Ok(());
+            }
+            let ins = match op {
+                Add => "vadd",
+                Sub => "vsub",
+                Mul => "vmul",
+                Div => "vdiv",
+                _ => return Err(self.error(&format!("float `{}` is not supported", op.symbol()))),
+            };
+            // Operands in memory come second.
+            let (a, b) = match (op, self.loc[a.0], self.loc[b.0]) {
+                (Add | Mul, Loc::Stack(_) | Loc::Const(_), Loc::Reg(_)) => (b, a),
+                _ => (a, b),
+            };
+            self.binop(value, &format!("{}{}", ins, f), a, b, true);
+            return Ok(());
+        }
+        let unsigned = !scalar.is_signed() && scalar != Scalar::Bool;
+        match op {
+            Add => {
+                self.binop(value, &format!("vpadd{}", i), a, b, false);
+            }
+            Sub => {
+                self.binop(value, &format!("vpsub{}", i), a, b, false);
+            }
+            Mul => self.mul(value, a, b),
+            BitAnd | And => {
+                self.binop(value, "vpand", a, b, false);
+            }
+            BitOr | Or => {
+                self.binop(value, "vpor", a, b, false);
+            }
+            BitXor => {
+                self.binop(value, "vpxor", a, b, false);
+            }
+            Shl | Shr => self.shift(value, op, scalar, a, b),
+            Eq | Ne if scalar == Scalar::Bool => {
+                self.binop(value, "vpxor", a, b, false);
+                if op == Eq {
+                    self.invert(value);
+                }
+            }
+            Eq | Ne => {
+                self.binop(value, &format!("vpcmpeq{}", i), a, b, false);
+                if op == Ne {
+                    self.invert(value);
+                }
+            }
+            Lt | Le | Gt | Ge => {
+                // Only `>` is signed, so compare flipped integers.
+                let (x, y) = match op {
+                    Gt | Le => (a, b),
+                    _ => (b, a),
+                };
+                let x = self.flip(x, unsigned);
+                let y = self.flip(y, unsigned);
+                let dst = self.define(value, &[a, b]);
+                self.emit(&format!("vpcmpgt{}", i), &[&dst, &x, &y]);
+                if matches!(op, Le | Ge) {
+                    self.invert(value);
+                }
+            }
+            _ => return Err(self.error(&format!("integer `{}` is not supported", op.symbol()))),
+        }
+        Ok(())
+    }
+
+    // Wrapping multiplication, from 32 bit halves for 64 bit integers.
+    fn mul(&mut self, value: Value, a: Value, b: Value) {
+        if self.num_bits == 32 {
+            self.binop(value, "vpmulld", a, b, false);
+            return;
+        }
+        let x = self.reg(a);
+        let y = self.rm(b, false);
+        let (low, cross, t) = (self.scratch(), self.scratch(), self.scratch());
+        let (low, cross, t) = (self.reg_name(low), self.reg_name(cross), self.reg_name(t));
+        self.emit("vpmuludq", &[&low, &x, &y]);
+        self.emit("vpsrlq", &[&cross, &x, "32"]);
+        self.emit("vpmuludq", &[&cross, &cross, &y]);
+        self.emit("vpsrlq", &[&t, &y, "32"]);
+        self.emit("vpmuludq", &[&t, &x, &t]);
+        self.emit("vpaddq", &[&cross, &cross, &t]);
+        self.emit("vpsllq", &[&cross, &cross, "32"]);
+        let dst = self.define(value, &[a, b]);
+        self.emit("vpaddq", &[&dst, &low, &cross]);
+    }
+
+    // Invert the bits of a value in place.
+    fn invert(&mut self, value: Value) {
+        let ones = self.ones();
+        let ones = self.constant_mem(ones, false);
+        let dst = self.rm(value, false);
+        self.emit("vpxor", &[&dst, &dst, &ones]);
+    }
+
+    fn shift(&mut self, value: Value, op: BinaryOp, scalar: Scalar, a: Value, b: Value) {
+        let i = self.isfx();
+        let n = self.num_bits as u64;
+        let count = literal_bits(self.module, self.func, b, 64);
+        let (logical, arithmetic) = match op {
+            BinaryOp::Shl => ("vpsll", false),
+            _ => ("vpsrl", scalar.is_signed()),
+        };
+        match count {
+            Some(count) if !arithmetic || self.num_bits == 32 => {
+                let ins = if arithmetic { "vpsra" } else { logical };
+                let x = self.reg(a);
+                let dst = self.define(value, &[a]);
+                self.emit(
+                    &format!("{}{}", ins, i),
+                    &[&dst, &x, &(count % n).to_string()],
+                );
+            }
+            None if !arithmetic || self.num_bits == 32 => {
+                let ins = if arithmetic { "vpsra" } else { logical };
+                self.binop(value, &format!("{}v{}", ins, i), a, b, false);
+            }
+            // There is no 64 bit arithmetic shift, so or in the sign.
+            count => {
+                let x = self.reg(a);
+                let fill = self.zero();
+                self.emit("vpcmpgtq", &[&fill, &fill, &x]);
+                let shifted = self.scratch();
+                let shifted = self.reg_name(shifted);
+                match count {
+                    Some(count) => {
+                        // Shifting by 64 gives zero.
+                        let count = count % n;
+                        self.emit("vpsrlq", &[&shifted, &x, &count.to_string()]);
+                        self.emit("vpsllq", &[&fill, &fill, &(64 - count).to_string()]);
+                    }
+                    None => {
+                        let y = self.rm(b, false);
+                        self.emit("vpsrlvq", &[&shifted, &x, &y]);
+                        let back = self.scratch();
+                        let back = self.reg_name(back);
+                        let sixty_four = self.constant_mem(64, false);
+                        self.emit("vmovdqu", &[&back, &sixty_four]);
+                        self.emit("vpsubq", &[&back, &back, &y]);
+                        self.emit("vpsllvq", &[&fill, &fill, &back]);
+                    }
+                }
+                let dst = self.define(value, &[a, b]);
+                self.emit("vpor", &[&dst, &fill, &shifted]);
+            }
+        }
+    }
+
+    fn convert(&mut self, value: Value, a: Value) -> Result<()> {
+        let (from, to) = (self.scalar(a)?, self.scalar(value)?);
+        match (from, to) {
+            _ if from == to => self.mov(value, a),
+            (_, Scalar::Bool) => return Err(self.error("conversions to bool are not supported")),
+            (Scalar::Bool, _) if to.is_float() => {
+                return Err(self.error("conversions of bools to floats are not supported"))
+            }
+            (Scalar::Bool, _) => self.binop_const(value, "vpand", a, 1, false),
+            _ if from.is_float() == to.is_float() => self.mov(value, a),
+            _ => self.convert_lanes(value, a, from, to),
+        }
+        Ok(())
+    }
+
+    // Convert between floats and integers lane by lane in general registers.
+    fn convert_lanes(&mut self, value: Value, a: Value, from: Scalar, to: Scalar) {
+        let lanes = match self.options.lanes {
+            Some(lanes) => lanes,
+            None if from.is_float() => {
+                let x = self.reg(a);
+                self.float_to_int(from, to, &x);
+                let dst = self.define(value, &[a]);
+                let ins = if self.num_bits == 64 {
+                    "vmovq"
+                } else {
+                    "vmovd"
+                };
+                self.emit(ins, &[&dst, self.gpr("rax")]);
+                return;
+            }
+            None => {
+                match self.loc[a.0] {
+                    Loc::Reg(reg) => {
+                        let ins = if self.num_bits == 64 {
+                            "vmovq"
+                        } else {
+                            "vmovd"
+                        };
+                        let x = format!("xmm{}", reg);
+                        self.emit(ins, &[self.gpr("rax"), &x]);
+                    }
+                    loc => {
+                        let addr = self.addr(loc, 0);
+                        let mem = self.mem(&addr, true);
+                        self.emit("mov", &[self.gpr("rax"), &mem]);
+                    }
+                }
+                let dst = self.define(value, &[a]);
+                self.int_to_float(from, to, &dst);
+                return;
+            }
+        };
+        let buffer = match self.buffer {
+            Some(slot) => slot,
+            None => {
+                self.slots += 2;
+                self.buffer = Some(self.slots - 2);
+                self.slots - 2
+            }
+        };
+        let input = match self.loc[a.0] {
+            Loc::Reg(reg) => {
+                let mem = self.mem(&self.slot_addr(buffer, 0), false);
+                let reg = self.reg_name(reg);
+                self.emit(&format!("vmovup{}", &self.psfx()[1..]), &[&mem, &reg]);
+                Loc::Stack(buffer)
+            }
+            loc => loc,
+        };
+        let bytes = self.num_bits / 8;
+        let lane_reg = self.scratch();
+        let lane_reg = format!("xmm{}", lane_reg);
+        let load = format!("vmov{}", self.fsfx().replace('p', "s"));
+        let scratch = self.scratch;
+        for lane in 0..lanes {
+            self.scratch = scratch;
+            let addr = self.addr(input, lane * bytes);
+            let lane_in = self.lane_mem(&addr);
+            let lane_out = self.lane_mem(&self.slot_addr(buffer + 1, lane * bytes));
+            if from.is_float() {
+                self.emit(&load, &[&lane_reg, &lane_in]);
+                self.float_to_int(from, to, &lane_reg);
+                self.emit("mov", &[&lane_out, self.gpr("rax")]);
+            } else {
+                self.emit("mov", &[self.gpr("rax"), &lane_in]);
+                self.int_to_float(from, to, &lane_reg);
+                self.emit(&load, &[&lane_out, &lane_reg]);
+            }
+        }
+        let mem = self.mem(&self.slot_addr(buffer + 1, 0), false);
+        let dst = self.define(value, &[a]);
+        self.emit(&format!("vmovup{}", &self.psfx()[1..]), &[&dst, &mem]);
+    }
+
+    // A general register of the size of the values, eg. `eax`.
+    fn gpr(&self, reg: &'static str) -> &'static str {
+        match (self.num_bits, reg) {
+            (64, reg) => reg,
+            (_, "rax") => "eax",
+            (_, "rcx") => "ecx",
+            (_, "rdx") => "edx",
+            (_, reg) => reg,
+        }
+    }
+
+    // Convert the float in `x` to an integer in `rax` as Rust does, saturating.
+    fn float_to_int(&mut self, from: Scalar, to: Scalar, x: &str) {
+        let s = if from == Scalar::F64 { "sd" } else { "ss" };
+        let x_mem = |this: &mut Self, value: f64| {
+            let bits = this.float_bits(value);
+            let loc = this.constant(bits);
+            let addr = this.addr(loc, 0);
+            this.lane_mem(&addr)
+        };
+        let limit = 2f64.powi(self.num_bits as i32 - 1);
+        let (rax, rcx) = (self.gpr("rax"), self.gpr("rcx"));
+        let ucomi = format!("vucomi{}", s);
+        let cvt = format!("vcvtt{}2si", s);
+        if to.is_signed() {
+            // Out of range values and NaNs give the minimum.
+            self.emit(&cvt, &[rax, x]);
+            self.emit("xor", &["ecx", "ecx"]);
+            self.emit(&ucomi, &[x, x]);
+            self.emit("cmovp", &[rax, rcx]);
+            let max = format!("{:#x}", self.ones() >> 1);
+            self.emit(
+                if self.num_bits == 64 { "movabs" } else { "mov" },
+                &[rcx, &max],
+            );
+            let limit = x_mem(self, limit);
+            self.emit(&ucomi, &[x, &limit]);
+            self.emit("cmovae", &[rax, rcx]);
+            return;
+        }
+        if self.num_bits == 64 {
+            // Values from 2^63 are converted less 2^63.
+            let t = self.scratch();
+            let t = format!("xmm{}", t);
+            let limit = x_mem(self, limit);
+            self.emit(&format!("vsub{}", s), &[&t, x, &limit]);
+            self.emit(&cvt, &["rax", x]);
+            self.emit(&cvt, &["rcx", &t]);
+            self.emit("btc", &["rcx", "63"]);
+            self.emit(&ucomi, &[x, &limit]);
+            self.emit("cmovae", &["rax", "rcx"]);
+        } else {
+            self.emit(&cvt, &["rax", x]);
+        }
+        let zero = self.scratch();
+        let zero = format!("xmm{}", zero);
+        self.emit(&format!("vxorp{}", &s[1..]), &[&zero, &zero, &zero]);
+        self.emit("xor", &["ecx", "ecx"]);
+        self.emit(&ucomi, &[x, &zero]);
+        self.emit("cmovbe", &["rax", "rcx"]);
+        self.emit("mov", &["rcx", "-1"]);
+        if self.num_bits == 32 {
+            self.emit("mov", &["ecx", "ecx"]);
+        }
+        let max = x_mem(self, limit * 2.0);
+        self.emit(&ucomi, &[x, &max]);
+        self.emit("cmovae", &["rax", "rcx"]);
+    }
+
+    // Convert the integer in `rax` to a float in `x`.
+    fn int_to_float(&mut self, from: Scalar, to: Scalar, x: &str) {
+        let s = if to == Scalar::F64 { "sd" } else { "ss" };
+        let x = x.replace("ymm", "xmm");
+        let x = x.as_str();
+        let cvt = format!("vcvtsi2{}", s);
+        self.emit(&format!("vxorp{}", &s[1..]), &[x, x, x]);
+        match (from.is_signed(), self.num_bits) {
+            (true, _) => self.emit(&cvt, &[x, x, self.gpr("rax")]),
+            (false, 32) => {
+                self.emit("mov", &["eax", "eax"]);
+                self.emit(&cvt, &[x, x, "rax"]);
+            }
+            (false, _) => {
+                // Halve large values, keeping the low bit for rounding, and double.
+                self.emit("mov", &["rcx", "rax"]);
+                self.emit("shr", &["rcx", "1"]);
+                self.emit("mov", &["edx", "eax"]);
+                self.emit("and", &["edx", "1"]);
+                self.emit("or", &["rcx", "rdx"]);
+                self.emit("test", &["rax", "rax"]);
+                self.emit("cmovns", &["rcx", "rax"]);
+                self.emit(&cvt, &[x, x, "rcx"]);
+                self.emit("test", &["rax", "rax"]);
+                self.emit("jns", &["1f"]);
+                self.emit(&format!("vadd{}", s), &[x, x, x]);
+                let _ = writeln!(self.text, "1:");
+            }
+        }
+    }
+
+    fn intrinsic(&mut self, value: Value, i: Intrinsic, args: &[Value]) -> Result<()> {
+        use Intrinsic::*;
+        let (f, p, isfx) = (self.fsfx(), self.psfx(), self.isfx());
+        let (sign, ones) = (self.sign_bit(), self.ones());
+        let abs = ones >> 1;
+        let scalar = self.scalar(args[0])?;
+        let x = args[0];
+        let round = |this: &mut Self, dst: &str, src: &str, imm: u8| {
+            let imm = format!("{:#x}", imm);
+            match this.is_vector() {
+                true => this.emit(&format!("vround{}", f), &[dst, src, &imm]),
+                false => this.emit(&format!("vround{}", f), &[dst, src, src, &imm]),
+            }
+        };
+        if scalar.is_int() {
+            match i {
+                WrappingAdd => {
+                    self.binop(value, &format!("vpadd{}", isfx), x, args[1], false);
+                }
+                WrappingSub => {
+                    self.binop(value, &format!("vpsub{}", isfx), x, args[1], false);
+                }
+                WrappingMul => self.mul(value, x, args[1]),
+                WrappingNeg => {
+                    let zero = self.zero();
+                    let y = self.rm(x, false);
+                    let dst = self.define(value, &[x]);
+                    self.emit(&format!("vpsub{}", isfx), &[&dst, &zero, &y]);
+                }
+                // Blend on the sign bit.
+                Abs => {
+                    let y = self.reg(x);
+                    let neg = self.zero();
+                    self.emit(&format!("vpsub{}", isfx), &[&neg, &neg, &y]);
+                    let dst = self.define(value, &[x]);
+                    self.emit(&format!("vblendv{}", p), &[&dst, &y, &neg, &y]);
+                }
+                Min | Max => {
+                    let unsigned = !scalar.is_signed();
+                    let (a, b) = (self.reg(x), self.reg(args[1]));
+                    let fa = self.flip(x, unsigned);
+                    let fb = self.flip(args[1], unsigned);
+                    let mask = self.scratch();
+                    let mask = self.reg_name(mask);
+                    let (l, r) = if i == Min { (&fa, &fb) } else { (&fb, &fa) };
+                    self.emit(&format!("vpcmpgt{}", isfx), &[&mask, l, r]);
+                    let dst = self.define(value, &[x, args[1]]);
+                    self.emit(&format!("vblendv{}", p), &[&dst, &a, &b, &mask]);
+                }
+                _ => {
+                    return Err(self.error(&format!("`{}` is not supported for integers", i.name())))
+                }
+            }
+            return Ok(());
+        }
+        match i {
+            Abs => self.binop_const(value, &format!("vand{}", p), x, abs, false),
+            Sqrt => {
+                let y = self.reg(x);
+                let dst = self.define(value, &[x]);
+                match self.is_vector() {
+                    true => self.emit(&format!("vsqrt{}", f), &[&dst, &y]),
+                    false => self.emit(&format!("vsqrt{}", f), &[&dst, &y, &y]),
+                }
+            }
+            Floor | Ceil | Trunc => {
+                let imm = match i {
+                    Floor => ROUND_FLOOR,
+                    Ceil => ROUND_CEIL,
+                    _ => ROUND_TRUNC,
+                };
+                let y = self.reg(x);
+                let dst = self.define(value, &[x]);
+                round(self, &dst, &y, imm);
+            }
+            // Add a half less an ulp away from zero and truncate.
+            Round => {
+                let y = self.reg(x);
+                let t = self.scratch();
+                let t = self.reg_name(t);
+                let sign = self.constant_mem(sign, false);
+                let half = self.float_bits(0.5) - 1;
+                let half = self.constant_mem(half, false);
+                self.emit(&format!("vand{}", p), &[&t, &y, &sign]);
+                self.emit(&format!("vor{}", p), &[&t, &t, &half]);
+                self.emit(&format!("vadd{}", f), &[&t, &y, &t]);
+                let dst = self.define(value, &[x]);
+                round(self, &dst, &t, ROUND_TRUNC);
+            }
+            Fract => {
+                let y = self.reg(x);
+                let t = self.scratch();
+                let t = self.reg_name(t);
+                round(self, &t, &y, ROUND_TRUNC);
+                let dst = self.define(value, &[x]);
+                self.emit(&format!("vsub{}", f), &[&dst, &y, &t]);
+            }
+            Recip => {
+                let one = self.float_bits(1.0);
+                let one_mem = self.constant_mem(one, false);
+                let t = self.scratch();
+                let t = self.reg_name(t);
+                self.emit(&format!("vmovup{}", &p[1..]), &[&t, &one_mem]);
+                let y = self.rm(x, true);
+                let dst = self.define(value, &[x]);
+                self.emit(&format!("vdiv{}", f), &[&dst, &t, &y]);
+            }
+            Copysign => {
+                let (a, b) = (self.reg(x), self.reg(args[1]));
+                let (ta, tb) = (self.scratch(), self.scratch());
+                let (ta, tb) = (self.reg_name(ta), self.reg_name(tb));
+                let abs = self.constant_mem(abs, false);
+                let sign = self.constant_mem(sign, false);
+                self.emit(&format!("vand{}", p), &[&ta, &a, &abs]);
+                self.emit(&format!("vand{}", p), &[&tb, &b, &sign]);
+                let dst = self.define(value, &[x, args[1]]);
+                self.emit(&format!("vor{}", p), &[&dst, &ta, &tb]);
+            }
+            Signum => {
+                let y = self.reg(x);
+                let (t, nan) = (self.scratch(), self.scratch());
+                let (t, nan) = (self.reg_name(t), self.reg_name(nan));
+                let sign = self.constant_mem(sign, false);
+                let one = self.float_bits(1.0);
+                let one = self.constant_mem(one, false);
+                self.emit(&format!("vand{}", p), &[&t, &y, &sign]);
+                self.emit(&format!("vor{}", p), &[&t, &t, &one]);
+                let unord = format!("{:#x}", CMP_UNORD);
+                self.emit(&format!("vcmp{}", f), &[&nan, &y, &y, &unord]);
+                let dst = self.define(value, &[x]);
+                self.emit(&format!("vblendv{}", p), &[&dst, &t, &y, &nan]);
+            }
+            MulAdd => self.mul_add(value, x, args[1], args[2]),
+            // `vmin` gives its second operand if either is NaN, Rust ignores NaNs.
+            Min | Max => {
+                let (a, b) = (self.reg(x), self.reg(args[1]));
+                let (t, nan) = (self.scratch(), self.scratch());
+                let (t, nan) = (self.reg_name(t), self.reg_name(nan));
+                let ins = if i == Min { "vmin" } else { "vmax" };
+                self.emit(&format!("{}{}", ins, f), &[&t, &b, &a]);
+                let unord = format!("{:#x}", CMP_UNORD);
+                self.emit(&format!("vcmp{}", f), &[&nan, &a, &a, &unord]);
+                let dst = self.define(value, &[x, args[1]]);
+                self.emit(&format!("vblendv{}", p), &[&dst, &t, &b, &nan]);
+            }
+            IsNan => self.cmp(value, x, x, CMP_UNORD),
+            IsInfinite | IsFinite => {
+                let y = self.reg(x);
+                let t = self.scratch();
+                let t = self.reg_name(t);
+                let abs = self.constant_mem(abs, false);
+                self.emit(&format!("vand{}", p), &[&t, &y, &abs]);
+                let inf = self.float_bits(f64::INFINITY);
+                let inf = self.constant_mem(inf, true);
+                let dst = self.define(value, &[x]);
+                let predicate = if i == IsInfinite { CMP_EQ } else { CMP_LT };
+                let predicate = format!("{:#x}", predicate);
+                self.emit(&format!("vcmp{}", f), &[&dst, &t, &inf, &predicate]);
+            }
+            IsSignNegative | IsSignPositive => {
+                let y = self.rm(x, false);
+                let zero = self.zero();
+                let dst = self.define(value, &[x]);
+                self.emit(&format!("vpcmpgt{}", isfx), &[&dst, &zero, &y]);
+                if i == IsSignPositive {
+                    let ones = self.constant_mem(ones, false);
+                    self.emit("vpxor", &[&dst, &dst, &ones]);
+                }
+            }
+            // The module's own function, such as `ln`, if it has one.
+            _ if self.module.function_for(i, self.func, args).is_some() => {
+                self.call(value, i.name(), args)?
+            }
+            _ => return Err(self.error(&format!("`{}` is not supported", i.name()))),
+        }
+        Ok(())
+    }
+
+    // `a * b + c` in one of the forms of `vfmadd`, which overwrite an operand.
+    fn mul_add(&mut self, value: Value, a: Value, b: Value, c: Value) {
+        let f = self.fsfx();
+        let (ra, rb, rc) = (self.rm(a, true), self.rm(b, true), self.rm(c, true));
+        let dst = self.define(value, &[a, b, c]);
+        if dst == ra || dst == rb {
+            let other = if dst == ra { b } else { a };
+            let other = self.reg(other);
+            self.emit(&format!("vfmadd213{}", f), &[&dst, &other, &rc]);
+        } else if dst == rc {
+            let x = self.reg(a);
+            self.emit(&format!("vfmadd231{}", f), &[&dst, &x, &rb]);
+        } else {
+            let y = self.reg(b);
+            let load = match ra.contains("PTR") {
+                true => format!("vmovup{}", &self.psfx()[1..]),
+                false => format!("vmovap{}", &self.psfx()[1..]),
+            };
+            let ra = self.rm(a, false);
+            self.emit(&load, &[&dst, &ra]);
+            self.emit(&format!("vfmadd213{}", f), &[&dst, &y, &rc]);
+        }
+    }
+
+    // Calls clobber every register, so spill every value first.
+    fn call(&mut self, value: Value, func: &str, args: &[Value]) -> Result<()> {
+        self.has_call = true;
+        for reg in 0..NUM_REGS {
+            if let Some(v) = self.regs[reg] {
+                if self.next_use(v).is_some() || args.contains(&v) {
+                    self.spill(v);
+                }
+                self.regs[reg] = None;
+            }
+        }
+        let (mut floats, mut ints) = (0, 0);
+        for a in args {
+            let scalar = self.scalar(*a)?;
+            let mem = self.rm(*a, false);
+            if self.is_vector() || scalar.is_float() {
+                let reg = self.reg_name(floats);
+                floats += 1;
+                self.emit(&format!("vmovup{}", &self.psfx()[1..]), &[&reg, &mem]);
+            } else {
+                let reg = INT_ARGS
+                    .get(ints)
+                    .ok_or_else(|| self.error("too many integer arguments"))?;
+                ints += 1;
+                let mem = self.rm(*a, true);
+                let reg = match self.num_bits {
+                    64 => reg.to_string(),
+                    _ => arg32(reg),
+                };
+                self.emit("mov", &[&reg, &mem]);
+                if scalar == Scalar::Bool {
+                    self.emit("and", &[&reg, "1"]);
+                }
+            }
+        }
+        if floats > 8 {
+            return Err(self.error("too many float arguments"));
+        }
+        self.emit("call", &[&format!("{}{}", self.options.prefix, func)]);
+        let scalar = self.scalar(value)?;
+        self.regs[0] = Some(value);
+        self.loc[value.0] = Loc::Reg(0);
+        if !self.is_vector() && !scalar.is_float() {
+            self.move_from_gpr("rax", scalar, "xmm0");
+        }
+        Ok(())
+    }
+
+    // Move an integer or bool from a general register to an `xmm` register.
+    fn move_from_gpr(&mut self, gpr: &str, scalar: Scalar, xmm: &str) {
+        let gpr = match self.num_bits {
+            64 => gpr.to_string(),
+            _ => arg32(gpr),
+        };
+        if scalar == Scalar::Bool {
+            self.emit("and", &[&gpr, "1"]);
+            self.emit("neg", &[&gpr]);
+        }
+        let ins = if self.num_bits == 64 {
+            "vmovq"
+        } else {
+            "vmovd"
+        };
+        self.emit(ins, &[xmm, &gpr]);
+    }
+
+    // Print the instructions of the function.
+    fn body(&mut self) -> Result<()> {
+        let mut floats = 0;
+        let mut ints = Vec::new();
+        for p in &self.func.params {
+            let scalar = self.scalar(*p)?;
+            if self.is_vector() || scalar.is_float() {
+                if floats >= 8 {
+                    return Err(self.error("too many float parameters"));
+                }
+                if !self.uses[p.0].is_empty() {
+                    self.regs[floats] = Some(*p);
+                    self.loc[p.0] = Loc::Reg(floats);
+                }
+                floats += 1;
+            } else {
+                let gpr = INT_ARGS
+                    .get(ints.len())
+                    .ok_or_else(|| self.error("too many integer parameters"))?;
+                ints.push((*p, *gpr, scalar));
+            }
+        }
+        for (p, gpr, scalar) in ints {
+            if !self.uses[p.0].is_empty() {
+                let dst = self.define(p, &[]);
+                self.move_from_gpr(gpr, scalar, &dst);
+            }
+        }
+
+        let order = self.order.clone();
+        for (pos, inst) in order.iter().enumerate() {
+            self.pos = pos;
+            self.scratch = 0;
+            let value = inst.value;
+            if self.uses[value.0].is_empty() {
+                self.release(&uses_of(inst));
+                continue;
+            }
+            if let Some(bits) = literal_bits(self.module, self.func, value, self.num_bits) {
+                self.loc[value.0] = self.constant(bits);
+                continue;
+            }
+            match &inst.op {
+                Op::Call(func, args) => self.call(value, func, args)?,
+                _ => self.op(inst)?,
+            }
+        }
+
+        self.pos = self.order.len();
+        self.scratch = 0;
+        let result = self.func.body.result;
+        let scalar = self.scalar(result)?;
+        if self.is_vector() || scalar.is_float() {
+            let x = self.rm(result, false);
+            let reg = self.reg_name(0);
+            if x != reg {
+                let ins = match x.contains("PTR") {
+                    true => format!("vmovup{}", &self.psfx()[1..]),
+                    false => format!("vmovap{}", &self.psfx()[1..]),
+                };
+                self.emit(&ins, &[&reg, &x]);
+            }
+        } else {
+            let rax = self.gpr("rax");
+            match self.loc[result.0] {
+                Loc::Reg(reg) => {
+                    let ins = if self.num_bits == 64 {
+                        "vmovq"
+                    } else {
+                        "vmovd"
+                    };
+                    self.emit(ins, &[rax, &format!("xmm{}", reg)]);
+                }
+                loc => {
+                    let addr = self.addr(loc, 0);
+                    let mem = self.mem(&addr, true);
+                    self.emit("mov", &[rax, &mem]);
+                }
+            }
+            if scalar == Scalar::Bool {
+                self.emit("and", &["eax", "1"]);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The 32 bit name of an argument register.
+fn arg32(reg: &str) -> String {
+    match reg {
+        "rdi" => "edi".to_string(),
+        "rsi" => "esi".to_string(),
+        "rdx" => "edx".to_string(),
+        "rcx" => "ecx".to_string(),
+        "rax" => "eax".to_string(),
+        reg => format!("{}d", reg),
+    }
+}
+
+fn print_fn(
+    func: &Function,
+    module: &Module,
+    options: &Options,
+    decls: &mut Decls,
+) -> Result<String> {
+    let mut printer = Printer::new(func, module, options, decls)?;
+    printer.body()?;
+
+    let name = format!("{}{}", options.prefix, func.name);
+    let mut text = String::new();
+    for doc in &func.docs {
+        let _ = writeln!(text, "#{}", doc);
+    }
+    if func.is_pub {
+        let _ = writeln!(text, "\t.globl\t{}", name);
+    }
+    let _ = writeln!(text, "\t.type\t{}, @function", name);
+    let _ = writeln!(text, "\t.p2align\t4");
+    let _ = writeln!(text, "{}:", name);
+    let frame = printer.slots > 0 || printer.has_call;
+    if frame {
+        let _ = writeln!(text, "\tpush\trbp\n\tmov\trbp, rsp");
+        if printer.slots > 0 {
+            let _ = writeln!(text, "\tsub\trsp, {}", 32 * printer.slots);
+        }
+    }
+    text.push_str(&printer.text);
+    if frame {
+        let _ = writeln!(text, "\tleave");
+    }
+    let _ = writeln!(text, "\tret");
+    let _ = writeln!(text, "\t.size\t{}, .-{}", name, name);
+    Ok(text)
+}
+
+/// Translate a module of the IR into x86-64 assembly.
+pub fn module_to_asm(module: &Module, options: &Options) -> Result<String> {
+    let mut decls = Decls::default();
+    let mut text = "\t.intel_syntax noprefix\n\t.text\n\n".to_string();
+    for func in &module.functions {
+        text.push_str(&print_fn(func, module, options, &mut decls)?);
+        text.push('\n');
+    }
+    if !decls.pool.is_empty() {
+        let _ = writeln!(text, "\t.section\t.rodata");
+        let _ = writeln!(text, "\t.p2align\t5");
+        for (n, data) in decls.pool.iter().enumerate() {
+            let _ = writeln!(text, ".LC{}:\n\t{}", n, data);
+        }
+        text.push('\n');
+    }
+    text.push_str("\t.section\t.note.GNU-stack,\"\",@progbits\n");
+    Ok(text)
+}
+
+/// Translate a Rust file into x86-64 assembly.
+pub fn to_asm(file: &syn::File, options: Options) -> Result<String> {
+    module_to_asm(&lower_file(file)?, &options)
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        const HALF: f64 = 0.5;
+
+        /// Add half.
+        pub fn f(x: f64) -> f64 {
+            let y = x * -2.0;
+            if x < 0.0 {
+                let z = y.floor();
+                z.mul_add(HALF, 1.0)
+            } else {
+                y
+            }
+        }
+
+        fn g(x: f64, n: i64) -> f64 {
+            let a = f(x) + x;
+            a * (n as f64)
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        ..Options::default()
+    };
+    let asm = to_asm(&code, options).unwrap();
+    assert!(asm.starts_with(
+        "\t.intel_syntax noprefix\n\t.text\n\n# Add half.\n\t.globl\tds_f\n\t.type\tds_f, @function\n"
+    ));
+    assert!(asm.contains(
+        "ds_f:\n\
+         \tvmulsd\txmm1, xmm0, QWORD PTR [rip + .LC0]\n\
+         \tvcmpsd\txmm0, xmm0, QWORD PTR [rip + .LC1], 0x11\n\
+         \tvroundsd\txmm2, xmm1, xmm1, 0x9\n\
+         \tvmovupd\txmm15, XMMWORD PTR [rip + .LC2]\n\
+         \tvfmadd213sd\txmm2, xmm15, QWORD PTR [rip + .LC3]\n\
+         \tvblendvpd\txmm0, xmm1, xmm2, xmm0\n\
+         \tret\n"
+    ));
+    assert!(asm.contains(".LC0:\n\t.quad\t0xc000000000000000, 0xc000000000000000\n"));
+
+    // Values live across the call are spilled and `n` arrives in `rdi`.
+    assert!(asm.contains("\tsub\trsp, 64\n\tvmovq\txmm1, rdi\n"));
+    assert!(asm.contains("\tcall\tds_f\n\tvaddsd\txmm0, xmm0, QWORD PTR [rbp - 32]\n"));
+    assert!(asm.contains("\tvcvtsi2sd\txmm1, xmm1, rax\n\tvmulsd\txmm0, xmm0, xmm1\n\tleave\n"));
+
+    let options = Options {
+        prefix: "ds_".to_string(),
+        lanes: Some(4),
+    };
+    let asm = to_asm(&code, options).unwrap();
+    assert!(asm.contains("\tvroundpd\tymm2, ymm1, 0x9\n"));
+    assert!(asm.contains("\tvfmadd213pd\tymm2, ymm15, YMMWORD PTR [rip + .LC3]\n"));
+    assert!(asm.contains("\tmov\trax, QWORD PTR [rbp - 56]\n"));
+    assert!(asm.contains("\tvmovupd\tymm1, YMMWORD PTR [rbp - 128]\n"));
+}
+
+#[test]
+fn test_spills_and_bits() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f64) -> f64 {
+            let a0 = x * 1.0; let a1 = x * 2.0; let a2 = x * 3.0; let a3 = x * 4.0;
+            let a4 = x * 5.0; let a5 = x * 6.0; let a6 = x * 7.0; let a7 = x * 8.0;
+            let a8 = x * 9.0; let a9 = x * 10.0; let a10 = x * 11.0; let a11 = x * 12.0;
+            let a12 = x * 13.0; let a13 = x * 14.0;
+            a0 + a1 + a2 + a3 + a4 + a5 + a6 + a7 + a8 + a9 + a10 + a11 + a12 + a13
+        }
+
+        fn g(x: i64, s: i64) -> i64 {
+            (x >> 3) + (x >> s)
+        }
+    };
+    let asm = to_asm(&code, Options::default()).unwrap();
+    // The value used last is spilled.
+    assert!(asm.contains(
+        "\tvmulsd\txmm11, xmm0, QWORD PTR [rip + .LC10]\n\
+         \tvmovupd\tXMMWORD PTR [rbp - 32], xmm11\n"
+    ));
+    assert!(asm.contains("\tvaddsd\txmm1, xmm1, QWORD PTR [rbp - 32]\n"));
+    assert!(asm.contains("\tvpsrlq\txmm14, xmm0, 3\n\tvpsllq\txmm15, xmm15, 61\n"));
+    assert!(asm.contains("\tvpsrlvq\txmm14, xmm0, xmm1\n"));
+    assert!(asm.contains("\tvpaddq\txmm2, xmm2, xmm0\n\tvmovq\trax, xmm2\n\tret\n"));
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            let bits = x.to_bits();
+            let e = (bits >> 23) as i32 - 127;
+            if x.is_nan() { f32::INFINITY } else { f32::from_bits(bits & 0x807fffff) * (e as f32) }
+        }
+    };
+    let options = Options {
+        lanes: Some(8),
+        ..Options::default()
+    };
+    let asm = to_asm(&code, options).unwrap();
+    assert!(asm.contains("\tvpsrld\tymm2, ymm1, 23\n"));
+    assert!(asm.contains("\tvcmpps\tymm0, ymm0, ymm0, 0x3\n"));
+    assert!(asm.contains("\tvcvtsi2ss\txmm15, xmm15, eax\n"));
+    assert!(asm.contains("\tvblendvps\tymm0, ymm1, YMMWORD PTR [rip + .LC2], ymm0\n"));
+    assert!(!asm.contains("0x00000017"));
+
+    let options = Options {
+        lanes: Some(4),
+        ..Options::default()
+    };
+    assert!(to_asm(&code, options).is_err());
+    let code: syn::File = parse_quote! {
+        fn f(x: f64) -> f64 { x.sin() }
+    };
+    assert!(to_asm(&code, Options::default()).is_err());
+
+    // The module's own `ln` is called in place of the method.
+    let code: syn::File = parse_quote! {
+        fn ln(x: f64) -> f64 { x - 1.0 }
+        fn ln_1p(x: f64) -> f64 { (1.0 + x).ln() }
+    };
+    let asm = to_asm(&code, Options::default()).unwrap();
+    assert!(asm.contains("\tcall\tln\n"));
+}
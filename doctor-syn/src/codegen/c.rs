@@ -1,1552 +1,1269 @@
-#![allow(unused_variables)]
-// use syn::visit::Visit;
-// use syn::visit;
-// use syn::ItemFn;
-use syn::*;
-use syn::visit::*;
-use syn::punctuated::Punctuated;
-use proc_macro2::Span;
+//! Translate Rust functions into C99 by printing the IR.
+//!
+//! Values that are used once are printed inline, other values become
+//! `const` locals named after their `let` binding.
+
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Special,
+    Ty, UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// How generated functions are linked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Linkage {
+    /// Functions are exported, eg. to build a shared library.
+    Extern,
+    /// Functions are `static inline` for a header-only library.
+    StaticInline,
+}
 
 pub struct Options {
+    /// Prepended to the names of functions and constants, eg. `ds32_`.
     pub prefix: String,
-    pub float_suffix: String,
+    pub linkage: Linkage,
+    /// A header made by `module_to_c_header` for the source to include
+    /// in place of its own declarations, eg. `libds.h`.
+    pub header: Option<String>,
+    /// Do not use libm or string.h, for `-ffreestanding -nostdlib` builds.
+    pub freestanding: bool,
+    /// Mark functions of scalars `#pragma omp declare simd` so that loops
+    /// calling them vectorise. Compile with `-fopenmp` or `-fopenmp-simd`.
+    pub declare_simd: bool,
+    /// Print `mul_add` as `a * b + c`, which rounds twice, rather than `fma`.
+    pub unfused_mul_add: bool,
 }
 
 impl Default for Options {
     fn default() -> Self {
-        Options { prefix: "".to_string(), float_suffix: "".to_string() }
+        Options {
+            prefix: "".to_string(),
+            linkage: Linkage::Extern,
+            header: None,
+            freestanding: false,
+            declare_simd: false,
+            unfused_mul_add: false,
+        }
     }
 }
 
-struct CVisitor {
-    options: Options,
-    stack: Vec<String>,
+// C keywords and library names that a Rust binding could shadow.
+pub(crate) const RESERVED: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while", "bool", "true", "false", "fabs", "fabsf",
+    "sqrt", "cbrt", "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "sinh", "cosh", "tanh",
+    "asinh", "acosh", "atanh", "exp", "exp2", "expm1", "log", "log1p", "log2", "log10", "round",
+    "floor", "ceil", "trunc", "copysign", "fmod", "pow", "fmin", "fmax", "hypot", "fma", "memcpy",
+];
+
+// Operator precedence, higher binds tighter.
+pub(crate) const PREC_TERNARY: u8 = 3;
+pub(crate) const PREC_UNARY: u8 = 14;
+pub(crate) const PREC_ATOM: u8 = 16;
+
+pub(crate) fn binary_prec(op: BinaryOp) -> u8 {
+    use BinaryOp::*;
+    match op {
+        Mul | Div | Rem => 13,
+        Add | Sub => 12,
+        Shl | Shr => 11,
+        Lt | Le | Gt | Ge => 10,
+        Eq | Ne => 9,
+        BitAnd => 8,
+        BitXor => 7,
+        BitOr => 6,
+        And => 5,
+        Or => 4,
+    }
 }
 
-impl CVisitor {
-    fn new(options: Options) -> Self {
-        CVisitor {
-            options,
-            stack: Vec::new(),
-        }
+pub(crate) fn scalar_type(scalar: Scalar) -> &'static str {
+    use Scalar::*;
+    match scalar {
+        Bool => "bool",
+        I8 => "int8_t",
+        I16 => "int16_t",
+        I32 => "int32_t",
+        I64 => "int64_t",
+        Isize => "ptrdiff_t",
+        U8 => "uint8_t",
+        U16 => "uint16_t",
+        U32 => "uint32_t",
+        U64 => "uint64_t",
+        Usize => "size_t",
+        F32 => "float",
+        F64 => "double",
     }
+}
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    bitcasts: BTreeSet<(Scalar, Scalar)>,
+    // Tuples of scalars become structs with fields `_0`, `_1`...
+    tuples: BTreeSet<Vec<Scalar>>,
+    // Indices into `FALLBACKS` of maths functions for freestanding C.
+    fallbacks: BTreeSet<(usize, Scalar)>,
+}
 
-    fn log(&mut self, msg: &str) {
-        // eprintln!("log: {}", msg);
+pub(crate) fn float_literal(digits: &str, scalar: Scalar) -> String {
+    let mut text = digits.to_string();
+    if !text.contains(['.', 'e', 'E']) {
+        text.push_str(".0");
+    }
+    if scalar == Scalar::F32 {
+        text.push('f');
     }
+    text
+}
 
-    fn tos(&mut self) -> String {
-        self.stack.pop().unwrap()
+pub(crate) fn int_literal(value: u128, scalar: Scalar) -> String {
+    use Scalar::*;
+    match scalar {
+        U32 | Usize | U8 | U16 => format!("{}u", value),
+        U64 => format!("UINT64_C({})", value),
+        I64 => format!("INT64_C({})", value),
+        F32 | F64 => float_literal(&value.to_string(), scalar),
+        _ => value.to_string(),
     }
+}
 
-    fn push<S: Into<String>>(&mut self, s: S) {
-        self.stack.push(s.into());
-        // eprintln!("  push {:?}", self.stack);
+pub(crate) fn special_literal(special: Special, scalar: Scalar, freestanding: bool) -> (String, u8) {
+    let limit = if scalar == Scalar::F32 { "FLT" } else { "DBL" };
+    // `NAN` and `INFINITY` are in math.h.
+    let (nan, inf) = match (freestanding, scalar) {
+        (false, _) => ("NAN", "INFINITY"),
+        (true, Scalar::F32) => ("__builtin_nanf(\"\")", "__builtin_inff()"),
+        (true, _) => ("__builtin_nan(\"\")", "__builtin_inf()"),
+    };
+    match special {
+        Special::Nan => (nan.to_string(), PREC_ATOM),
+        Special::Infinity => (inf.to_string(), PREC_ATOM),
+        Special::NegInfinity => (format!("-{}", inf), PREC_UNARY),
+        Special::MinPositive => (format!("{}_MIN", limit), PREC_ATOM),
+        Special::Max => (format!("{}_MAX", limit), PREC_ATOM),
+        Special::Min => (format!("-{}_MAX", limit), PREC_UNARY),
+        Special::Epsilon => (format!("{}_EPSILON", limit), PREC_ATOM),
     }
 }
 
-macro_rules! ret {
-    ($self: expr, $($args:tt)*) => {
-        $self.push(format!($($args)*))
+// The C name of a maths function, with an `f` suffix for floats.
+pub(crate) fn libm_name(name: &str, scalar: Scalar) -> String {
+    if scalar == Scalar::F32 {
+        format!("{}f", name)
+    } else {
+        name.to_string()
     }
 }
 
-impl<'ast> Visit<'ast> for CVisitor {
+struct Printer<'a> {
+    func: &'a Function,
+    module: &'a Module,
+    options: &'a Options,
+    use_counts: Vec<usize>,
+    decls: &'a mut Decls,
+    text: String,
+}
 
-    fn visit_abi(&mut self, node: &'ast Abi) {
-        self.log("visit_abi");
-        if let Some(it) = &node.name {
-            self.visit_lit_str(it);
-            let lit_str = self.tos();
-            ret!(self, "extern {:?}", lit_str);
-        } else {
-            ret!(self, "extern ");
-        }
+impl<'a> Printer<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating C for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        self.func
+            .ty(value)
+            .scalar()
+            .filter(|_| matches!(self.func.ty(value), Ty::Scalar(_)))
+            .ok_or_else(|| self.error(&format!("the type {} is not supported", self.func.ty(value))))
+    }
+
+    fn c_type(&mut self, ty: &Ty) -> Result<String> {
+        let unsupported = || self.error(&format!("the type {} is not supported", ty));
+        let scalars = match ty {
+            Ty::Scalar(scalar) => return Ok(scalar_type(*scalar).to_string()),
+            Ty::Tuple(elems) => elems
+                .iter()
+                .map(|e| match e {
+                    Ty::Scalar(scalar) => Some(*scalar),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(unsupported)?,
+            _ => return Err(unsupported()),
+        };
+        let name = tuple_name(&self.options.prefix, &scalars);
+        self.decls.tuples.insert(scalars);
+        Ok(name)
     }
 
-    fn visit_angle_bracketed_generic_arguments(&mut self, node: &'ast AngleBracketedGenericArguments) {
-        self.log("visit_angle_bracketed_generic_arguments");
-        let mut args = Vec::new();
-        for el in Punctuated::pairs(&node.args) {
-            let (it, _) = el.into_tuple();
-            self.visit_generic_argument(it);
-            args.push(self.tos());
+    fn name(&self, value: Value) -> String {
+        let name = self.func.name(value);
+        if RESERVED.contains(&name.as_str()) {
+            format!("{}_", name)
+        } else {
+            name
         }
-        ret!(self, "<{}>", args.join(", "));
     }
 
-    fn visit_arm(&mut self, node: &'ast Arm) {
-        self.log("visit_arm");
-        self.visit_pat(&node.pat);
-        let pat = self.tos();
-        if let Some(it) = &node.guard {
-            self.visit_expr(&*(it).1);
-            let _guard = self.tos();
-        };
-        self.visit_expr(&*node.body);
-        let expr = self.tos();
-        ret!(self, "if {} {{{}}}", pat, expr);
+    fn operand(&mut self, value: Value, prec: u8) -> Result<String> {
+        let (text, p) = self.expr(value)?;
+        Ok(if p < prec { format!("({})", text) } else { text })
     }
 
-    fn visit_attr_style(&mut self, node: &'ast AttrStyle) {
-        self.log("visit_attr_style");
-        self.push("<<attr_style>>".to_string());
-        //visit_attr_style(self, node);
+    fn args(&mut self, args: &[Value]) -> Result<String> {
+        let args = args
+            .iter()
+            .map(|a| self.operand(*a, PREC_TERNARY))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(args.join(", "))
     }
 
-    fn visit_attribute(&mut self, node: &'ast Attribute) {
-        self.log("visit_attribute");
-        self.push("<<attribute>>".to_string());
-        //visit_attribute(self, node);
+    // True if a value is printed at its use.
+    fn is_inline(&self, value: Value) -> bool {
+        match self.func.inst(value) {
+            Some(Inst {
+                op: Op::If(_, then_body, else_body),
+                ..
+            }) => {
+                self.func.values[value.0].name.is_none()
+                    && self.use_counts[value.0] == 1
+                    && self.is_simple(then_body)
+                    && self.is_simple(else_body)
+            }
+            _ => self.func.is_inline(value, &self.use_counts),
+        }
     }
 
-    fn visit_bare_fn_arg(&mut self, node: &'ast BareFnArg) {
-        self.log("visit_bare_fn_arg");
-        self.push("<<bare_fn_arg>>".to_string());
-        //visit_bare_fn_arg(self, node);
+    // A value as an expression and its precedence.
+    fn expr(&mut self, value: Value) -> Result<(String, u8)> {
+        match self.func.inst(value) {
+            Some(inst) if self.is_inline(value) => self.op(inst),
+            _ => Ok((self.name(value), PREC_ATOM)),
+        }
     }
 
-    fn visit_bin_op(&mut self, node: &'ast BinOp) {
-        self.log("visit_bin_op");
-        let op = match node {
-            BinOp::Add(_binding_0) => {
-                "+"
-            }
-            BinOp::Sub(_binding_0) => {
-                "-"
-            }
-            BinOp::Mul(_binding_0) => {
-                "*"
-            }
-            BinOp::Div(_binding_0) => {
-                "/"
-            }
-            BinOp::Rem(_binding_0) => {
-                "%"
-            }
-            BinOp::And(_binding_0) => {
-                "&&"
-            }
-            BinOp::Or(_binding_0) => {
-                "||"
-            }
-            BinOp::BitXor(_binding_0) => {
-                "^"
-            }
-            BinOp::BitAnd(_binding_0) => {
-                "&"
-            }
-            BinOp::BitOr(_binding_0) => {
-                "|"
-            }
-            BinOp::Shl(_binding_0) => {
-                "<<"
+    fn op(&mut self, inst: &Inst) -> Result<(String, u8)> {
+        // Operations whose values need not be scalars.
+        match &inst.op {
+            Op::Call(name, args) => {
+                let args = self.args(args)?;
+                return Ok((format!("{}{}({})", self.options.prefix, name, args), PREC_ATOM));
             }
-            BinOp::Shr(_binding_0) => {
-                ">>"
+            Op::Tuple(args) => {
+                let ty = self.c_type(self.func.ty(inst.value))?;
+                return Ok((format!("({}){{{}}}", ty, self.args(args)?), PREC_ATOM));
             }
-            BinOp::Eq(_binding_0) => {
-                "=="
+            Op::Extract(a, i) => {
+                let a = self.operand(*a, PREC_ATOM)?;
+                return Ok((format!("{}._{}", a, i), PREC_ATOM));
             }
-            BinOp::Lt(_binding_0) => {
-                "<"
-            }
-            BinOp::Le(_binding_0) => {
-                "<="
-            }
-            BinOp::Ne(_binding_0) => {
-                "!="
-            }
-            BinOp::Ge(_binding_0) => {
-                ">="
-            }
-            BinOp::Gt(_binding_0) => {
-                ">"
+            _ => (),
+        }
+        let scalar = self.scalar(inst.value)?;
+        let ty = scalar_type(scalar);
+        Ok(match &inst.op {
+            Op::Lit(Literal::Float(digits)) => (float_literal(digits, scalar), PREC_ATOM),
+            Op::Lit(Literal::Int(i)) => (int_literal(*i, scalar), PREC_ATOM),
+            Op::Lit(Literal::Bool(b)) => (b.to_string(), PREC_ATOM),
+            Op::Lit(Literal::Special(s)) => special_literal(*s, scalar, self.options.freestanding),
+            Op::Const(name) => (format!("{}{}", self.options.prefix, name), PREC_ATOM),
+            Op::Unary(op, a) => {
+                let sym = match (op, self.scalar(*a)?) {
+                    (UnaryOp::Neg, _) => "-",
+                    (UnaryOp::Not, Scalar::Bool) => "!",
+                    (UnaryOp::Not, _) => "~",
+                };
+                (format!("{}{}", sym, self.operand(*a, PREC_UNARY)?), PREC_UNARY)
             }
-            BinOp::AddEq(_binding_0) => {
-                "+="
+            Op::Binary(BinaryOp::Rem, a, b) if scalar.is_float() => {
+                let name = self.math("fmod", scalar)?;
+                (format!("{}({})", name, self.args(&[*a, *b])?), PREC_ATOM)
             }
-            BinOp::SubEq(_binding_0) => {
-                "-="
+            Op::Binary(op, a, b) => {
+                use BinaryOp::*;
+                // Parenthesise operands of bitwise operators as `-Wparentheses` asks.
+                let prec = binary_prec(*op);
+                let min = match op {
+                    Shl | Shr | BitAnd | BitXor | BitOr | And | Or => PREC_UNARY,
+                    _ => prec,
+                };
+                let a = self.operand(*a, min)?;
+                let b = self.operand(*b, min.max(prec + 1))?;
+                (format!("{} {} {}", a, op.symbol(), b), prec)
             }
-            BinOp::MulEq(_binding_0) => {
-                "*="
+            Op::Convert(a) => (format!("({}){}", ty, self.operand(*a, PREC_UNARY)?), PREC_UNARY),
+            Op::Bitcast(a) => {
+                let from = self.scalar(*a)?;
+                self.decls.bitcasts.insert((scalar, from));
+                let name = bitcast_name(&self.options.prefix, scalar, from);
+                (format!("{}({})", name, self.args(&[*a])?), PREC_ATOM)
             }
-            BinOp::DivEq(_binding_0) => {
-                "/="
+            Op::Select(c, a, b) => self.ternary(*c, *a, *b)?,
+            Op::If(c, then_body, else_body) => {
+                self.ternary(*c, then_body.result, else_body.result)?
             }
-            BinOp::RemEq(_binding_0) => {
-                "%="
+            Op::Intrinsic(i, args) => self.intrinsic(*i, args)?,
+            Op::Splat(_) => return Err(self.error("vectors are not supported")),
+            Op::Call(..) | Op::Tuple(_) | Op::Extract(..) => unreachable!("printed above"),
+        })
+    }
+
+    // A maths function from libm, or its fallback in freestanding C.
+    fn math(&mut self, name: &str, scalar: Scalar) -> Result<String> {
+        if !self.options.freestanding {
+            return Ok(libm_name(name, scalar));
+        }
+        let index = FALLBACKS.iter().position(|f| f.0 == name).ok_or_else(|| {
+            self.error(&format!("`{}` needs libm which freestanding C does not have", name))
+        })?;
+        for dep in FALLBACKS[index].1 {
+            self.math(dep, scalar)?;
+        }
+        self.decls.fallbacks.insert((index, scalar));
+        Ok(fallback_name(&self.options.prefix, name, scalar))
+    }
+
+    // A call of a classification macro from math.h.
+    fn classify(&mut self, name: &str, args: &[Value]) -> Result<String> {
+        let name = if self.options.freestanding {
+            format!("__builtin_{}", name)
+        } else {
+            name.to_string()
+        };
+        Ok(format!("{}({})", name, self.args(args)?))
+    }
+
+    fn ternary(&mut self, c: Value, a: Value, b: Value) -> Result<(String, u8)> {
+        let c = self.operand(c, PREC_TERNARY + 1)?;
+        let a = self.operand(a, PREC_TERNARY + 1)?;
+        let b = self.operand(b, PREC_TERNARY)?;
+        Ok((format!("{} ? {} : {}", c, a, b), PREC_TERNARY))
+    }
+
+    fn intrinsic(&mut self, i: Intrinsic, args: &[Value]) -> Result<(String, u8)> {
+        use Intrinsic::*;
+        let arg_scalar = self.scalar(args[0])?;
+        let call = |this: &mut Self, name: &str| -> Result<(String, u8)> {
+            // Freestanding C calls the module's own function, such as `ln`, if
+            // there is no fallback.
+            let has_fallback = FALLBACKS.iter().any(|f| f.0 == name);
+            let name = match this.module.function_for(i, this.func, args) {
+                Some(own) if this.options.freestanding && !has_fallback => {
+                    format!("{}{}", this.options.prefix, own.name)
+                }
+                _ => this.math(name, arg_scalar)?,
+            };
+            Ok((format!("{}({})", name, this.args(args)?), PREC_ATOM))
+        };
+        let one = float_literal("1.0", arg_scalar);
+        if arg_scalar.is_int() {
+            let ty = scalar_type(arg_scalar);
+            let uty = scalar_type(Scalar::uint(arg_scalar.num_bits()).unwrap_or(Scalar::U64));
+            let wrapping = |this: &mut Self, op: &str| -> Result<(String, u8)> {
+                if !arg_scalar.is_signed() {
+                    let prec = if op == "*" { 13 } else { 12 };
+                    let a = this.operand(args[0], prec)?;
+                    let b = this.operand(args[1], prec + 1)?;
+                    return Ok((format!("{} {} {}", a, op, b), prec));
+                }
+                let a = this.operand(args[0], PREC_UNARY)?;
+                let b = this.operand(args[1], PREC_UNARY)?;
+                let text = format!("({})(({}){} {} ({}){})", ty, uty, a, op, uty, b);
+                Ok((text, PREC_UNARY))
+            };
+            let builtin = |this: &mut Self, name: &str| -> Result<(String, u8)> {
+                let suffix = if arg_scalar.num_bits() > 32 { "ll" } else { "" };
+                let a = this.operand(args[0], PREC_UNARY)?;
+                let text = format!("(int32_t)__builtin_{}{}(({}){})", name, suffix, uty, a);
+                Ok((text, PREC_UNARY))
+            };
+            return match i {
+                WrappingAdd => wrapping(self, "+"),
+                WrappingSub => wrapping(self, "-"),
+                WrappingMul => wrapping(self, "*"),
+                WrappingNeg => {
+                    let a = self.operand(args[0], PREC_UNARY)?;
+                    Ok((format!("({})(0u - ({}){})", ty, uty, a), PREC_UNARY))
+                }
+                CountOnes => builtin(self, "popcount"),
+                LeadingZeros | TrailingZeros => {
+                    let name = if i == LeadingZeros { "clz" } else { "ctz" };
+                    let (count, _) = builtin(self, name)?;
+                    let a = self.operand(args[0], PREC_TERNARY + 1)?;
+                    let text = format!("{} ? {} : {}", a, count, arg_scalar.num_bits());
+                    Ok((text, PREC_TERNARY))
+                }
+                Abs => {
+                    let a = self.operand(args[0], PREC_TERNARY + 1)?;
+                    Ok((format!("{} < 0 ? -{} : {}", a, a, a), PREC_TERNARY))
+                }
+                Min | Max => {
+                    let op = if i == Min { "<" } else { ">" };
+                    let a = self.operand(args[0], PREC_TERNARY + 1)?;
+                    let b = self.operand(args[1], PREC_TERNARY + 1)?;
+                    Ok((format!("{} {} {} ? {} : {}", a, op, b, a, b), PREC_TERNARY))
+                }
+                _ => Err(self.error(&format!("`{}` is not supported for integers", i.name()))),
+            };
+        }
+        match i {
+            Abs => call(self, "fabs"),
+            Sqrt | Cbrt | Sin | Cos | Tan | Asin | Acos | Atan | Atan2 | Sinh | Cosh | Tanh
+            | Asinh | Acosh | Atanh | Exp | Exp2 | Log2 | Log10 | Round | Floor | Ceil | Trunc
+            | Copysign | Hypot => call(self, i.name()),
+            ExpM1 => call(self, "expm1"),
+            Ln => call(self, "log"),
+            Ln1p => call(self, "log1p"),
+            Powf => call(self, "pow"),
+            Min => call(self, "fmin"),
+            Max => call(self, "fmax"),
+            Log => {
+                let log = self.math("log", arg_scalar)?;
+                let x = self.args(&args[0..1])?;
+                let base = self.args(&args[1..2])?;
+                Ok((format!("{}({}) / {}({})", log, x, log, base), 13))
             }
-            BinOp::BitXorEq(_binding_0) => {
-                "^="
+            Powi => {
+                let pow = self.math("pow", arg_scalar)?;
+                let x = self.args(&args[0..1])?;
+                let n = self.operand(args[1], PREC_UNARY)?;
+                let ty = scalar_type(arg_scalar);
+                Ok((format!("{}({}, ({}){})", pow, x, ty, n), PREC_ATOM))
             }
-            BinOp::BitAndEq(_binding_0) => {
-                "&="
+            Recip => {
+                let a = self.operand(args[0], 14)?;
+                Ok((format!("{} / {}", one, a), 13))
             }
-            BinOp::BitOrEq(_binding_0) => {
-                "|="
+            Fract => {
+                let trunc = self.math("trunc", arg_scalar)?;
+                let a = self.operand(args[0], 13)?;
+                Ok((format!("{} - {}({})", a, trunc, self.args(&args[0..1])?), 12))
             }
-            BinOp::ShlEq(_binding_0) => {
-                "<<="
+            Signum => {
+                let copysign = self.math("copysign", arg_scalar)?;
+                Ok((format!("{}({}, {})", copysign, one, self.args(&args[0..1])?), PREC_ATOM))
             }
-            BinOp::ShrEq(_binding_0) => {
-                ">>="
+            MulAdd if self.options.unfused_mul_add => {
+                let a = self.operand(args[0], 13)?;
+                let b = self.operand(args[1], 14)?;
+                let c = self.operand(args[2], 13)?;
+                Ok((format!("{} * {} + {}", a, b, c), 12))
             }
-        };
-        ret!(self, "{}", op);
-    }
-
-    fn visit_binding(&mut self, node: &'ast Binding) {
-        self.log("visit_binding");
-        self.push("<<binding>>".to_string());
-        //visit_binding(self, node);
-    }
-
-    fn visit_block(&mut self, node: &'ast Block) {
-        self.log("visit_block");
-
-        let mut stmts = Vec::new();
-        for it in &node.stmts {
-            self.visit_stmt(it);
-            stmts.push(self.tos());
+            MulAdd => call(self, "fma"),
+            IsNan => Ok((self.classify("isnan", args)?, PREC_ATOM)),
+            IsInfinite => Ok((self.classify("isinf", args)?, PREC_ATOM)),
+            IsFinite => Ok((self.classify("isfinite", args)?, PREC_ATOM)),
+            IsSignNegative => Ok((format!("{} != 0", self.classify("signbit", args)?), 9)),
+            IsSignPositive => Ok((format!("{} == 0", self.classify("signbit", args)?), 9)),
+            _ => Err(self.error(&format!("`{}` is not supported", i.name()))),
         }
-        ret!(self, "{{\n{}\n}}", stmts.join(";\n"));
-    }
-
-    fn visit_bound_lifetimes(&mut self, node: &'ast BoundLifetimes) {
-        self.log("visit_bound_lifetimes");
-        self.push("<<bound_lifetimes>>".to_string());
-        //visit_bound_lifetimes(self, node);
-    }
-
-    fn visit_const_param(&mut self, node: &'ast ConstParam) {
-        self.log("visit_const_param");
-        self.push("<<const_param>>".to_string());
-        //visit_const_param(self, node);
-    }
-
-    fn visit_constraint(&mut self, node: &'ast Constraint) {
-        self.log("visit_constraint");
-        self.push("<<constraint>>".to_string());
-        //visit_constraint(self, node);
-    }
-
-    fn visit_data(&mut self, node: &'ast Data) {
-        self.log("visit_data");
-        self.push("<<data>>".to_string());
-        //visit_data(self, node);
-    }
-
-    fn visit_data_enum(&mut self, node: &'ast DataEnum) {
-        self.log("visit_data_enum");
-        self.push("<<data_enum>>".to_string());
-        //visit_data_enum(self, node);
-    }
-
-    fn visit_data_struct(&mut self, node: &'ast DataStruct) {
-        self.log("visit_data_struct");
-        self.push("<<data_struct>>".to_string());
-        //visit_data_struct(self, node);
-    }
-
-    fn visit_data_union(&mut self, node: &'ast DataUnion) {
-        self.log("visit_data_union");
-        self.push("<<data_union>>".to_string());
-        //visit_data_union(self, node);
     }
 
-    fn visit_derive_input(&mut self, node: &'ast DeriveInput) {
-        self.log("visit_derive_input");
-        self.push("<<derive_input>>".to_string());
-        //visit_derive_input(self, node);
+    fn line(&mut self, indent: usize, line: &str) {
+        let _ = writeln!(self.text, "{}{}", "    ".repeat(indent), line);
     }
 
-    fn visit_expr(&mut self, node: &'ast Expr) {
-        self.log("visit_expr");
-        visit_expr(self, node);
-    }
-
-    fn visit_expr_array(&mut self, node: &'ast ExprArray) {
-        self.log("visit_expr_array");
-        // self.push("<<expr_array>>".to_string());
-        // visit_expr_array(self, node);
-        let mut elems = Vec::new();
-        for el in Punctuated::pairs(&node.elems) {
-            let (it, _) = el.into_tuple();
-            self.visit_expr(it);
-            elems.push(self.tos());
+    // Print the materialised instructions of a body.
+    fn body(&mut self, body: &Body, indent: usize) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            if self.is_inline(value) {
+                continue;
+            }
+            let name = self.name(value);
+            let is_call = matches!(inst.op, Op::Call(..));
+            if self.use_counts[value.0] == 0 && !is_call {
+                continue;
+            }
+            let ty = self.c_type(self.func.ty(value))?;
+            match &inst.op {
+                Op::If(c, then_body, else_body) => {
+                    if self.is_simple(then_body) && self.is_simple(else_body) {
+                        let (expr, _) = self.op(inst)?;
+                        self.line(indent, &format!("const {} {} = {};", ty, name, expr));
+                    } else {
+                        let (cond, _) = self.expr(*c)?;
+                        self.line(indent, &format!("{} {};", ty, name));
+                        self.line(indent, &format!("if ({}) {{", cond));
+                        self.branch(then_body, &name, indent + 1)?;
+                        self.line(indent, "} else {");
+                        self.branch(else_body, &name, indent + 1)?;
+                        self.line(indent, "}");
+                    }
+                }
+                _ => {
+                    let (expr, _) = self.op(inst)?;
+                    if self.use_counts[value.0] == 0 {
+                        self.line(indent, &format!("{};", expr));
+                    } else {
+                        self.line(indent, &format!("const {} {} = {};", ty, name, expr));
+                    }
+                }
+            }
         }
-        ret!(self, "{}", elems.join(", "));
-    }
-
-    fn visit_expr_assign(&mut self, node: &'ast ExprAssign) {
-        self.log("visit_expr_assign");
-        // self.push("<<expr_assign>>".to_string());
-        self.visit_expr(&*node.left);
-        let left = self.tos();
-        self.visit_expr(&*node.right);
-        let right = self.tos();
-        ret!(self, "{} = {}", left, right);
-    }
-
-    fn visit_expr_assign_op(&mut self, node: &'ast ExprAssignOp) {
-        self.log("visit_expr_assign_op");
-        self.push("<<expr_assign_op>>".to_string());
-        // visit_expr_assign_op(self, node);
-        self.visit_expr(&*node.left);
-        let left = self.tos();
-        self.visit_bin_op(&node.op);
-        let op = self.tos();
-        self.visit_expr(&*node.right);
-        let right = self.tos();
-        ret!(self, "{} {} {}", left, op, right);
-    }
-
-    fn visit_expr_async(&mut self, node: &'ast ExprAsync) {
-        self.log("visit_expr_async");
-        self.push("<<expr_async>>".to_string());
-        //visit_expr_async(self, node);
-    }
-
-    fn visit_expr_await(&mut self, node: &'ast ExprAwait) {
-        self.log("visit_expr_await");
-        self.push("<<expr_await>>".to_string());
-        //visit_expr_await(self, node);
-    }
-
-    fn visit_expr_binary(&mut self, node: &'ast ExprBinary) {
-        self.log("visit_expr_binary");
-        // self.push("<<expr_binary>>".to_string());
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-        self.visit_expr(&*node.left);
-        let lhs = self.tos();
-        self.visit_bin_op(&node.op);
-        let op = self.tos();
-        self.visit_expr(&*node.right);
-        let rhs = self.tos();
-        ret!(self, "{} {} {}", lhs, op, rhs);
+        Ok(())
     }
 
-    fn visit_expr_block(&mut self, node: &'ast ExprBlock) {
-        self.log("visit_expr_block");
-        // self.push("<<expr_block>>".to_string());
-        // if let Some(it) = &node.label {
-        //     v.visit_label(it);
-        // };
-        self.visit_block(&node.block);
-        let block = self.tos();
-        ret!(self, "({})", block);
+    fn branch(&mut self, body: &Body, name: &str, indent: usize) -> Result<()> {
+        self.body(body, indent)?;
+        let (result, _) = self.expr(body.result)?;
+        self.line(indent, &format!("{} = {};", name, result));
+        Ok(())
     }
 
-    fn visit_expr_box(&mut self, node: &'ast ExprBox) {
-        self.log("visit_expr_box");
-        self.push("<<expr_box>>".to_string());
-        //visit_expr_box(self, node);
+    // True if a branch has no statements of its own.
+    fn is_simple(&self, body: &Body) -> bool {
+        body.insts.iter().all(|inst| self.is_inline(inst.value))
     }
+}
 
-    fn visit_expr_break(&mut self, node: &'ast ExprBreak) {
-        self.log("visit_expr_break");
-        // self.push("<<expr_break>>".to_string());
-        // visit_expr_break(self, node);
-        ret!(self, "break;");
-    }
+fn bitcast_name(prefix: &str, to: Scalar, from: Scalar) -> String {
+    format!("{}bitcast_{}_{}", prefix, to.name(), from.name())
+}
 
-    fn visit_expr_call(&mut self, node: &'ast ExprCall) {
-        self.log("visit_expr_call");
-        self.visit_expr(&*node.func);
-        let expr = self.tos();
+fn fallback_name(prefix: &str, name: &str, scalar: Scalar) -> String {
+    format!("{}{}_{}", prefix, name, scalar.name())
+}
 
-        let mut args = Vec::new();
-        for el in Punctuated::pairs(&node.args) {
-            let (it, _) = el.into_tuple();
-            self.visit_expr(it);
-            args.push(self.tos());
+// Maths functions for freestanding C, their dependencies and bodies. In the bodies
+// `$T` is the float type, `$U` the unsigned integer of the same size, `$W` one with
+// `$WBITS` bits, twice as many, `$MANT` the number of mantissa bits, `$EMASK` and
+// `$BIAS` describe the exponent and `$trunc` is the fallback for `trunc`. `$W` is
+// `unsigned __int128` for doubles, which GCC and Clang have for 64 bit targets.
+const FALLBACKS: &[(&str, &[&str], &str)] = &[
+    (
+        "fabs",
+        &[],
+        "    union { $T f; $U u; } v = {x};
+    v.u &= ~(($U)1 << ($MANT + $EBITS));
+    return v.f;",
+    ),
+    (
+        "copysign",
+        &[],
+        "    const $U sign = ($U)1 << ($MANT + $EBITS);
+    union { $T f; $U u; } a = {x}, b = {y};
+    a.u = (a.u & ~sign) | (b.u & sign);
+    return a.f;",
+    ),
+    (
+        "trunc",
+        &[],
+        "    union { $T f; $U u; } v = {x};
+    const int e = (int)((v.u >> $MANT) & $EMASK) - $BIAS;
+    if (e >= $MANT) {
+        // Integral, infinite or NaN.
+        return x;
+    }
+    if (e < 0) {
+        v.u &= ($U)1 << ($MANT + $EBITS);
+        return v.f;
+    }
+    v.u &= ~((($U)1 << ($MANT - e)) - 1);
+    return v.f;",
+    ),
+    (
+        "floor",
+        &["trunc"],
+        "    const $T t = $trunc(x);
+    return t > x ? t - $ONE : t;",
+    ),
+    (
+        "ceil",
+        &["trunc"],
+        "    const $T t = $trunc(x);
+    return t < x ? t + $ONE : t;",
+    ),
+    (
+        "round",
+        &["trunc"],
+        "    const $T t = $trunc(x);
+    const $T d = x - t;
+    return d >= $HALF ? t + $ONE : d <= -$HALF ? t - $ONE : t;",
+    ),
+    (
+        "fmin",
+        &[],
+        "    return (x < y || y != y) ? x : y;",
+    ),
+    (
+        "fmax",
+        &[],
+        "    return (x > y || y != y) ? x : y;",
+    ),
+    (
+        "sqrt",
+        &[],
+        "    union { $T f; $U u; } v = {x};
+    $U ix = v.u, q = 0, s = 0, r, t;
+    int m = (int)(ix >> $MANT);
+    if (x < 0) {
+        return (x - x) / (x - x);
+    }
+    if (x == 0 || x != x || x - x != 0) {
+        return x;
+    }
+    if (m == 0) {
+        // Normalise a subnormal.
+        while ((ix >> $MANT) == 0) {
+            ix <<= 1;
+            m--;
+        }
+        m++;
+    }
+    m -= $BIAS;
+    ix = (ix & ((($U)1 << $MANT) - 1)) | (($U)1 << $MANT);
+    if (m & 1) {
+        ix += ix;
+    }
+    m = (m - (m & 1)) / 2;
+    // Find the root a bit at a time then round to nearest.
+    ix += ix;
+    for (r = ($U)1 << ($MANT + 1); r != 0; r >>= 1) {
+        t = s + r;
+        if (t <= ix) {
+            s = t + r;
+            ix -= t;
+            q += r;
+        }
+        ix += ix;
+    }
+    if (ix != 0) {
+        q += q & 1;
+    }
+    v.u = (q >> 1) + (($U)($BIAS - 1) << $MANT) + (($U)m << $MANT);
+    return v.f;",
+    ),
+    (
+        "fma",
+        &[],
+        "    const $U sign = ($U)1 << ($MANT + $EBITS);
+    const int emin = 1 - $BIAS - $MANT;
+    union { $T f; $U u; } v[3] = {{x}, {y}, {z}};
+    $W m[3];
+    int s[3], e[3], i, k;
+    // The product is exact or does not matter.
+    if (x == 0 || y == 0 || x - x != 0 || y - y != 0) {
+        return x * y + z;
+    }
+    if (z - z != 0) {
+        return z;
+    }
+    if (z == 0) {
+        return x * y;
+    }
+    // The sign and magnitude of each as `m * 2^e`.
+    for (i = 0; i < 3; i++) {
+        const int biased = (int)((v[i].u & ~sign) >> $MANT);
+        s[i] = (int)(v[i].u >> ($MANT + $EBITS));
+        m[i] = v[i].u & ((($U)1 << $MANT) - 1);
+        e[i] = emin;
+        if (biased != 0) {
+            m[i] |= ($W)1 << $MANT;
+            e[i] += biased - 1;
         }
-        let args = args.join(", ");
-
-        self.push(match expr.as_str() {
-            "fty_from_bits" => {
-                format!("({{ union {{ fty f; uty u; }} u; u.u = ({}); u.f; }})", args)
-            }
-            _ => {
-                format!("{}{}({})", self.options.prefix, expr, args)
-            }
-        });
-
-    }
-
-    fn visit_expr_cast(&mut self, node: &'ast ExprCast) {
-        self.log("visit_expr_cast");
-        // self.push("<<expr_cast>>".to_string());
-        // visit_expr_cast(self, node);
-        self.visit_expr(&*node.expr);
-        let expr = self.tos();
-        // tokens_helper(v, &node.as_token.span);
-        self.visit_type(&*node.ty);
-        let ty = self.tos();
-        ret!(self, "({}){}", ty, expr);
-    }
-
-    fn visit_expr_closure(&mut self, node: &'ast ExprClosure) {
-        self.log("visit_expr_closure");
-        self.push("<<expr_closure>>".to_string());
-        //visit_expr_closure(self, node);
-    }
-
-    fn visit_expr_continue(&mut self, node: &'ast ExprContinue) {
-        self.log("visit_expr_continue");
-        self.push("<<expr_continue>>".to_string());
-        //visit_expr_continue(self, node);
-    }
-
-    fn visit_expr_field(&mut self, node: &'ast ExprField) {
-        self.log("visit_expr_field");
-        self.push("<<expr_field>>".to_string());
-        //visit_expr_field(self, node);
     }
-
-    fn visit_expr_for_loop(&mut self, node: &'ast ExprForLoop) {
-        self.log("visit_expr_for_loop");
-        self.push("<<expr_for_loop>>".to_string());
-        //visit_expr_for_loop(self, node);
+    // The exact product then both it and `z` with their top bits at `$WBITS - 2`.
+    m[0] *= m[1];
+    e[0] += e[1];
+    s[0] ^= s[1];
+    m[1] = m[2];
+    e[1] = e[2];
+    s[1] = s[2];
+    for (i = 0; i < 2; i++) {
+        for (k = $WBITS / 2; k != 0; k /= 2) {
+            if ((m[i] >> ($WBITS - 1 - k)) == 0) {
+                m[i] <<= k;
+                e[i] -= k;
+            }
+        }
     }
+    // The smaller aligned to the larger, the bits shifted out kept as a sticky bit.
+    i = e[0] > e[1] || (e[0] == e[1] && m[0] >= m[1]) ? 0 : 1;
+    k = e[i] - e[1 - i];
+    if (k >= $WBITS) {
+        m[1 - i] = 1;
+    } else if (k > 0) {
+        m[1 - i] = (m[1 - i] >> k) | ((m[1 - i] << ($WBITS - k)) != 0);
+    }
+    m[2] = s[0] == s[1] ? m[i] + m[1 - i] : m[i] - m[1 - i];
+    if (m[2] == 0) {
+        return 0;
+    }
+    // Round `m[2] * 2^e[i]` to the nearest float, ties to even.
+    k = $WBITS - 1;
+    while ((m[2] >> k) == 0) {
+        k--;
+    }
+    k += e[i] - $MANT;
+    k = (k > emin ? k : emin) - e[i];
+    const int lsb = k + e[i];
+    if (k <= 0) {
+        m[2] <<= -k;
+    } else if (k >= $WBITS) {
+        m[2] = k == $WBITS && m[2] > ($W)1 << ($WBITS - 1);
+    } else {
+        const $W rem = m[2] & ((($W)1 << k) - 1), half = ($W)1 << (k - 1);
+        m[2] >>= k;
+        m[2] += rem > half || (rem == half && (m[2] & 1));
+    }
+    m[2] += ($W)(lsb - emin) << $MANT;
+    if (m[2] > ($W)$EMASK << $MANT) {
+        m[2] = ($W)$EMASK << $MANT;
+    }
+    v[0].u = ($U)m[2] | (($U)s[i] << ($MANT + $EBITS));
+    return v[0].f;",
+    ),
+];
+
+fn print_fallback(prefix: &str, index: usize, scalar: Scalar) -> String {
+    let (name, _, body) = FALLBACKS[index];
+    let (mant, ebits, bias) = if scalar == Scalar::F32 { (23, 8, 127) } else { (52, 11, 1023) };
+    let uty = Scalar::uint(scalar.num_bits()).unwrap_or(Scalar::U64);
+    let ty = scalar_type(scalar);
+    let wide = if scalar == Scalar::F32 { "uint64_t" } else { "unsigned __int128" };
+    let params = match name {
+        "copysign" | "fmin" | "fmax" => format!("{} x, {} y", ty, ty),
+        "fma" => format!("{} x, {} y, {} z", ty, ty, ty),
+        _ => format!("{} x", ty),
+    };
+    let body = body
+        .replace("$trunc", &fallback_name(prefix, "trunc", scalar))
+        .replace("$T", ty)
+        .replace("$U", scalar_type(uty))
+        .replace("$WBITS", &(scalar.num_bits() * 2).to_string())
+        .replace("$W", wide)
+        .replace("$MANT", &mant.to_string())
+        .replace("$EBITS", &ebits.to_string())
+        .replace("$EMASK", &format!("{:#x}", (1 << ebits) - 1))
+        .replace("$BIAS", &bias.to_string())
+        .replace("$ONE", &float_literal("1.0", scalar))
+        .replace("$HALF", &float_literal("0.5", scalar));
+    format!(
+        "static inline {} {}({}) {{\n{}\n}}\n",
+        ty,
+        fallback_name(prefix, name, scalar),
+        params,
+        body
+    )
+}
 
-    fn visit_expr_group(&mut self, node: &'ast ExprGroup) {
-        self.log("visit_expr_group");
-        self.push("<<expr_group>>".to_string());
-        //visit_expr_group(self, node);
-    }
+fn tuple_name(prefix: &str, scalars: &[Scalar]) -> String {
+    let names = scalars.iter().map(|s| s.name()).collect::<Vec<_>>();
+    format!("{}tuple_{}", prefix, names.join("_"))
+}
 
-    fn visit_expr_if(&mut self, node: &'ast ExprIf) {
-        self.log("visit_expr_if");
-        // self.push("<<expr_if>>".to_string());
-        self.visit_expr(&*node.cond);
-        let cond = self.tos();
-        self.visit_block(&node.then_branch);
-        let t = self.tos();
-        if let Some(it) = &node.else_branch {
-            self.visit_expr(&*(it).1);
-            let f = self.tos();
-            ret!(self, "({}) ? ({}) : ({})", cond, t, f);
-        } else {
-            ret!(self, "<<if requires an else>>")
-        };
+pub(crate) fn docs(text: &mut String, func: &Function) {
+    for doc in &func.docs {
+        let _ = writeln!(text, "//{}", doc);
     }
+}
 
-    fn visit_expr_index(&mut self, node: &'ast ExprIndex) {
-        self.log("visit_expr_index");
-        self.push("<<expr_index>>".to_string());
-        //visit_expr_index(self, node);
-    }
+fn print_fn(
+    func: &Function,
+    module: &Module,
+    options: &Options,
+    decls: &mut Decls,
+) -> Result<(String, String)> {
+    let mut printer = Printer {
+        func,
+        module,
+        options,
+        use_counts: func.use_counts(),
+        decls,
+        text: String::new(),
+    };
+    let params = func
+        .params
+        .iter()
+        .map(|p| Ok(format!("{} {}", printer.c_type(func.ty(*p))?, printer.name(*p))))
+        .collect::<Result<Vec<_>>>()?;
+    let params = if params.is_empty() {
+        "void".to_string()
+    } else {
+        params.join(", ")
+    };
+    let ret = printer.c_type(&func.ret)?;
+    printer.body(&func.body, 1)?;
+    let (result, _) = printer.expr(func.body.result)?;
+    printer.line(1, &format!("return {};", result));
+
+    let linkage = match options.linkage {
+        Linkage::Extern => "",
+        Linkage::StaticInline => "static inline ",
+    };
+    let mut signature = format!("{}{} {}{}({})", linkage, ret, options.prefix, func.name, params);
+    // The pragma goes before every declaration so it is part of the signature.
+    let is_number = |ty: &Ty| matches!(ty, Ty::Scalar(s) if *s != Scalar::Bool);
+    if options.declare_simd && func.params.iter().all(|p| is_number(func.ty(*p))) && is_number(&func.ret) {
+        signature = format!("#pragma omp declare simd notinbranch\n{}", signature);
+    }
+    let mut text = String::new();
+    docs(&mut text, func);
+    let _ = writeln!(text, "{} {{", signature);
+    text.push_str(&printer.text);
+    text.push_str("}\n");
+    Ok((signature, text))
+}
 
-    fn visit_expr_let(&mut self, node: &'ast ExprLet) {
-        self.log("visit_expr_let");
-        self.push("<<expr_let>>".to_string());
-        //visit_expr_let(self, node);
-    }
+// Constants are macros as C does not allow one `static const` to be used
+// in the initialiser of another.
+fn print_const(
+    func: &Function,
+    module: &Module,
+    options: &Options,
+    decls: &mut Decls,
+) -> Result<String> {
+    let mut printer = Printer {
+        func,
+        module,
+        options,
+        use_counts: func.use_counts(),
+        decls,
+        text: String::new(),
+    };
+    if !printer.is_simple(&func.body) {
+        return Err(printer.error("constants must be expressions"));
+    }
+    let (expr, prec) = printer.expr(func.body.result)?;
+    let expr = if prec < PREC_ATOM {
+        format!("({})", expr)
+    } else {
+        expr
+    };
+    let mut text = String::new();
+    docs(&mut text, func);
+    let _ = writeln!(text, "#define {}{} {}", options.prefix, func.name, expr);
+    Ok(text)
+}
 
-    fn visit_expr_lit(&mut self, node: &'ast ExprLit) {
-        self.log("visit_expr_lit");
-        // self.push("<<expr_lit>>".to_string());
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-        self.visit_lit(&node.lit);
+// Constants as macros for the other C backends, which have no helpers.
+pub(crate) fn const_defines(module: &Module, prefix: &str) -> Result<String> {
+    let options = Options {
+        prefix: prefix.to_string(),
+        ..Options::default()
+    };
+    let mut decls = Decls::default();
+    let mut text = String::new();
+    for c in &module.consts {
+        text.push_str(&print_const(c, module, &options, &mut decls)?);
+        if !decls.bitcasts.is_empty() || !decls.tuples.is_empty() {
+            return Err(Error::new(ErrorKind::UnsupportedCodegen)
+                .with_operation(format!("generating C for {}", c.name))
+                .with_message("constants must be plain expressions"));
+        }
     }
+    Ok(text)
+}
 
-    fn visit_expr_loop(&mut self, node: &'ast ExprLoop) {
-        self.log("visit_expr_loop");
-        self.push("<<expr_loop>>".to_string());
-        //visit_expr_loop(self, node);
+fn print_tuple(prefix: &str, scalars: &[Scalar]) -> String {
+    let mut text = "typedef struct {\n".to_string();
+    for (i, scalar) in scalars.iter().enumerate() {
+        let _ = writeln!(text, "    {} _{};", scalar_type(*scalar), i);
     }
+    let _ = writeln!(text, "}} {};", tuple_name(prefix, scalars));
+    text
+}
 
-    fn visit_expr_macro(&mut self, node: &'ast ExprMacro) {
-        self.log("visit_expr_macro");
-        self.push("<<expr_macro>>".to_string());
-        //visit_expr_macro(self, node);
-    }
+fn print_bitcast(prefix: &str, to: Scalar, from: Scalar, freestanding: bool) -> String {
+    let (to_ty, from_ty) = (scalar_type(to), scalar_type(from));
+    if freestanding {
+        // Without string.h, type-pun through a union as C99 allows.
+        return format!(
+            "static inline {} {}({} x) {{\n    union {{ {} from; {} to; }} u = {{x}};\n    return u.to;\n}}\n",
+            to_ty,
+            bitcast_name(prefix, to, from),
+            from_ty,
+            from_ty,
+            to_ty
+        );
+    }
+    format!(
+        "static inline {} {}({} x) {{\n    {} y;\n    memcpy(&y, &x, sizeof(y));\n    return y;\n}}\n",
+        to_ty,
+        bitcast_name(prefix, to, from),
+        from_ty,
+        to_ty
+    )
+}
 
-    fn visit_expr_match(&mut self, node: &'ast ExprMatch) {
-        self.log("visit_expr_match");
-        self.push("<<expr_match>>".to_string());
-        //visit_expr_match(self, node);
-    }
+// A module printed as C.
+struct Printed {
+    decls: Decls,
+    consts: Vec<String>,
+    // Signatures and definitions.
+    functions: Vec<(String, String)>,
+}
 
-    fn visit_expr_method_call(&mut self, node: &'ast ExprMethodCall) {
-        self.log("visit_expr_method_call");
-        // self.push("<<expr_method_call>>".to_string());
-        // visit_expr_method_call(self, node);
-        let mut args = Vec::new();
-        self.visit_expr(&*node.receiver);
-        args.push(self.tos());
-
-        self.visit_ident(&node.method);
-        let ident = self.tos();
-
-        // if let Some(it) = &node.turbofish {
-        //     self.visit_method_turbofish(it);
-        // };
-
-        for el in Punctuated::pairs(&node.args) {
-            let (it, _) = el.into_tuple();
-            self.visit_expr(it);
-            args.push(self.tos());
+impl Printed {
+    fn new(module: &Module, options: &Options) -> Result<Self> {
+        let mut decls = Decls::default();
+        let mut consts = Vec::new();
+        for c in &module.consts {
+            consts.push(print_const(c, module, options, &mut decls)?);
         }
-
-        self.push(match ident.as_str() {
-            "round" => format!("{}{}({})", ident, self.options.float_suffix, args.join(", ")),
-            "mul_add" => format!("(({}\n) * ({}) + ({}))", args[0], args[1], args[2]),
-            _ => format!("{}({})", ident, args.join(", ")),
+        let mut functions = Vec::new();
+        for func in &module.functions {
+            functions.push(print_fn(func, module, options, &mut decls)?);
+        }
+        Ok(Printed {
+            decls,
+            consts,
+            functions,
         })
     }
 
-    fn visit_expr_paren(&mut self, node: &'ast ExprParen) {
-        self.log("visit_expr_paren");
-        self.visit_expr(&*node.expr);
-        let expr = self.tos();
-        ret!(self, "({})", expr);
-    }
-
-    fn visit_expr_path(&mut self, node: &'ast ExprPath) {
-        self.log("visit_expr_path");
-        // self.push("<<expr_path>>".to_string());
-        // visit_expr_path(self, node);
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-        if let Some(it) = &node.qself {
-            self.visit_qself(it);
-            return;
-        };
-        self.visit_path(&node.path);
-    }
-
-    fn visit_expr_range(&mut self, node: &'ast ExprRange) {
-        self.log("visit_expr_range");
-        self.push("<<expr_range>>".to_string());
-        //visit_expr_range(self, node);
-    }
-
-    fn visit_expr_reference(&mut self, node: &'ast ExprReference) {
-        self.log("visit_expr_reference");
-        self.push("<<expr_reference>>".to_string());
-        //visit_expr_reference(self, node);
-    }
-
-    fn visit_expr_repeat(&mut self, node: &'ast ExprRepeat) {
-        self.log("visit_expr_repeat");
-        self.push("<<expr_repeat>>".to_string());
-        //visit_expr_repeat(self, node);
-    }
-
-    fn visit_expr_return(&mut self, node: &'ast ExprReturn) {
-        self.log("visit_expr_return");
-        self.push("<<expr_return>>".to_string());
-        //visit_expr_return(self, node);
-    }
-
-    fn visit_expr_struct(&mut self, node: &'ast ExprStruct) {
-        self.log("visit_expr_struct");
-        self.push("<<expr_struct>>".to_string());
-        //visit_expr_struct(self, node);
-    }
-
-    fn visit_expr_try(&mut self, node: &'ast ExprTry) {
-        self.log("visit_expr_try");
-        self.push("<<expr_try>>".to_string());
-        //visit_expr_try(self, node);
-    }
-
-    fn visit_expr_try_block(&mut self, node: &'ast ExprTryBlock) {
-        self.log("visit_expr_try_block");
-        self.push("<<expr_try_block>>".to_string());
-        //visit_expr_try_block(self, node);
-    }
-
-    fn visit_expr_tuple(&mut self, node: &'ast ExprTuple) {
-        self.log("visit_expr_tuple");
-        self.push("<<expr_tuple>>".to_string());
-        //visit_expr_tuple(self, node);
-    }
-
-    fn visit_expr_type(&mut self, node: &'ast ExprType) {
-        self.log("visit_expr_type");
-        self.push("<<expr_type>>".to_string());
-        //visit_expr_type(self, node);
-    }
-
-    fn visit_expr_unary(&mut self, node: &'ast ExprUnary) {
-        self.log("visit_expr_unary");
-        self.visit_un_op(&node.op);
-        let op = self.tos();
-        self.visit_expr(&*node.expr);
-        let expr = self.tos();
-        ret!(self, "{}{}", op, expr);
-    }
-
-    fn visit_expr_unsafe(&mut self, node: &'ast ExprUnsafe) {
-        self.log("visit_expr_unsafe");
-        self.push("<<expr_unsafe>>".to_string());
-        //visit_expr_unsafe(self, node);
-    }
-
-    fn visit_expr_while(&mut self, node: &'ast ExprWhile) {
-        self.log("visit_expr_while");
-        self.push("<<expr_while>>".to_string());
-        //visit_expr_while(self, node);
-    }
-
-    fn visit_expr_yield(&mut self, node: &'ast ExprYield) {
-        self.log("visit_expr_yield");
-        self.push("<<expr_yield>>".to_string());
-        //visit_expr_yield(self, node);
-    }
-
-    fn visit_field(&mut self, node: &'ast Field) {
-        self.log("visit_field");
-        self.push("<<field>>".to_string());
-        //visit_field(self, node);
-    }
-
-    fn visit_field_pat(&mut self, node: &'ast FieldPat) {
-        self.log("visit_field_pat");
-        self.push("<<field_pat>>".to_string());
-        //visit_field_pat(self, node);
-    }
-
-    fn visit_field_value(&mut self, node: &'ast FieldValue) {
-        self.log("visit_field_value");
-        self.push("<<field_value>>".to_string());
-        //visit_field_value(self, node);
-    }
-
-    fn visit_fields(&mut self, node: &'ast Fields) {
-        self.log("visit_fields");
-        self.push("<<fields>>".to_string());
-        //visit_fields(self, node);
-    }
-
-    fn visit_fields_named(&mut self, node: &'ast FieldsNamed) {
-        self.log("visit_fields_named");
-        self.push("<<fields_named>>".to_string());
-        //visit_fields_named(self, node);
-    }
-
-    fn visit_fields_unnamed(&mut self, node: &'ast FieldsUnnamed) {
-        self.log("visit_fields_unnamed");
-        self.push("<<fields_unnamed>>".to_string());
-        //visit_fields_unnamed(self, node);
-    }
-
-    fn visit_file(&mut self, node: &'ast File) {
-        self.log("visit_file");
-
-        // skip!(node.shebang);
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-
-        let mut items = Vec::new();
-        items.push("#include <math.h>".to_string());
-        for it in &node.items {
-            self.visit_item(it);
-            items.push(self.tos());
+    // Include the headers for declarations, and for definitions if asked.
+    fn includes(&self, text: &mut String, options: &Options, definitions: bool) {
+        for header in &["float.h", "math.h", "stdbool.h", "stddef.h", "stdint.h", "string.h"] {
+            let hosted = *header == "math.h" || *header == "string.h";
+            if (hosted && options.freestanding) || (*header == "string.h" && !definitions) {
+                continue;
+            }
+            let _ = writeln!(text, "#include <{}>", header);
         }
-        self.push(items.join("\n\n"));
-    }
-
-    fn visit_fn_arg(&mut self, node: &'ast FnArg) {
-        self.log("visit_fn_arg");
-        visit_fn_arg(self, node);
-    }
-
-    fn visit_foreign_item(&mut self, node: &'ast ForeignItem) {
-        self.log("visit_foreign_item");
-        self.push("<<foreign_item>>".to_string());
-        //visit_foreign_item(self, node);
-    }
-
-    fn visit_foreign_item_fn(&mut self, node: &'ast ForeignItemFn) {
-        self.log("visit_foreign_item_fn");
-        self.push("<<foreign_item_fn>>".to_string());
-        //visit_foreign_item_fn(self, node);
-    }
-
-    fn visit_foreign_item_macro(&mut self, node: &'ast ForeignItemMacro) {
-        self.log("visit_foreign_item_macro");
-        self.push("<<foreign_item_macro>>".to_string());
-        //visit_foreign_item_macro(self, node);
-    }
-
-    fn visit_foreign_item_static(&mut self, node: &'ast ForeignItemStatic) {
-        self.log("visit_foreign_item_static");
-        self.push("<<foreign_item_static>>".to_string());
-        //visit_foreign_item_static(self, node);
-    }
-
-    fn visit_foreign_item_type(&mut self, node: &'ast ForeignItemType) {
-        self.log("visit_foreign_item_type");
-        self.push("<<foreign_item_type>>".to_string());
-        //visit_foreign_item_type(self, node);
-    }
-
-    fn visit_generic_argument(&mut self, node: &'ast GenericArgument) {
-        self.log("visit_generic_argument");
-        self.push("<<generic_argument>>".to_string());
-        //visit_generic_argument(self, node);
-    }
-
-    fn visit_generic_method_argument(&mut self, node: &'ast GenericMethodArgument) {
-        self.log("visit_generic_method_argument");
-        self.push("<<generic_method_argument>>".to_string());
-        //visit_generic_method_argument(self, node);
-    }
-
-    fn visit_generic_param(&mut self, node: &'ast GenericParam) {
-        self.log("visit_generic_param");
-        self.push("<<generic_param>>".to_string());
-        //visit_generic_param(self, node);
-    }
-
-    fn visit_generics(&mut self, node: &'ast Generics) {
-        self.log("visit_generics");
-        self.push("<<generics>>".to_string());
-        //visit_generics(self, node);
-    }
-    fn visit_ident(&mut self, node: &'ast Ident) {
-        self.log("visit_ident");
-        let name = node.to_string();
-        self.push(name);
-    }
-
-    fn visit_impl_item(&mut self, node: &'ast ImplItem) {
-        self.log("visit_impl_item");
-        self.push("<<impl_item>>".to_string());
-        //visit_impl_item(self, node);
-    }
-
-    fn visit_impl_item_const(&mut self, node: &'ast ImplItemConst) {
-        self.log("visit_impl_item_const");
-        self.push("<<impl_item_const>>".to_string());
-        //visit_impl_item_const(self, node);
-    }
-
-    fn visit_impl_item_macro(&mut self, node: &'ast ImplItemMacro) {
-        self.log("visit_impl_item_macro");
-        self.push("<<impl_item_macro>>".to_string());
-        //visit_impl_item_macro(self, node);
-    }
-
-    fn visit_impl_item_method(&mut self, node: &'ast ImplItemMethod) {
-        self.log("visit_impl_item_method");
-        self.push("<<impl_item_method>>".to_string());
-        //visit_impl_item_method(self, node);
-    }
-
-    fn visit_impl_item_type(&mut self, node: &'ast ImplItemType) {
-        self.log("visit_impl_item_type");
-        self.push("<<impl_item_type>>".to_string());
-        //visit_impl_item_type(self, node);
-    }
-
-    fn visit_index(&mut self, node: &'ast Index) {
-        self.log("visit_index");
-        self.push("<<index>>".to_string());
-        //visit_index(self, node);
-    }
-
-    fn visit_item(&mut self, node: &'ast Item) {
-        self.log("visit_item");
-        visit_item(self, node);
-    }
-
-    fn visit_item_const(&mut self, node: &'ast ItemConst) {
-        self.log("visit_item_const");
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-        // self.visit_visibility(&node.vis);
-        // tokens_helper(v, &node.const_token.span);
-        self.visit_ident(&node.ident);
-        let ident = self.tos();
-        // tokens_helper(v, &node.colon_token.spans);
-        self.visit_type(&*node.ty);
-        let ty = self.tos();
-        // tokens_helper(v, &node.eq_token.spans);
-        self.visit_expr(&*node.expr);
-        let expr = self.tos();
-        // tokens_helper(v, &node.semi_token.spans);
-        ret!(self, "const {} {} = {};", ty, ident, expr);
-    }
-
-    fn visit_item_enum(&mut self, node: &'ast ItemEnum) {
-        self.log("visit_item_enum");
-        self.push("<<item_enum>>".to_string());
-        //visit_item_enum(self, node);
-    }
-
-    fn visit_item_extern_crate(&mut self, node: &'ast ItemExternCrate) {
-        self.log("visit_item_extern_crate");
-        self.push("<<item_extern_crate>>".to_string());
-        //visit_item_extern_crate(self, node);
-    }
-
-    fn visit_item_fn(&mut self, node: &'ast ItemFn) {
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-        // self.visit_visibility(&node.vis);
-    
-        self.visit_signature(&node.sig);
-        let sig = self.tos();
-    
-        self.visit_block(&*node.block);
-        let block = self.tos();
-    
-        ret!(self, "{} {{ return ({}); }}", sig, block);
-    }
-
-    fn visit_item_foreign_mod(&mut self, node: &'ast ItemForeignMod) {
-        self.log("visit_item_foreign_mod");
-        self.push("<<item_foreign_mod>>".to_string());
-        //visit_item_foreign_mod(self, node);
-    }
-
-    fn visit_item_impl(&mut self, node: &'ast ItemImpl) {
-        self.log("visit_item_impl");
-        self.push("<<item_impl>>".to_string());
-        //visit_item_impl(self, node);
-    }
-
-    fn visit_item_macro(&mut self, node: &'ast ItemMacro) {
-        self.log("visit_item_macro");
-        self.push("<<item_macro>>".to_string());
-        //visit_item_macro(self, node);
-    }
-
-    fn visit_item_macro2(&mut self, node: &'ast ItemMacro2) {
-        self.log("visit_item_macro2");
-        self.push("<<item_macro2>>".to_string());
-        //visit_item_macro2(self, node);
-    }
-
-    fn visit_item_mod(&mut self, node: &'ast ItemMod) {
-        self.log("visit_item_mod");
-        self.push("<<item_mod>>".to_string());
-        //visit_item_mod(self, node);
-    }
-
-    fn visit_item_static(&mut self, node: &'ast ItemStatic) {
-        self.log("visit_item_static");
-        self.push("<<item_static>>".to_string());
-        //visit_item_static(self, node);
-    }
-
-    fn visit_item_struct(&mut self, node: &'ast ItemStruct) {
-        self.log("visit_item_struct");
-        self.push("<<item_struct>>".to_string());
-        //visit_item_struct(self, node);
-    }
-
-    fn visit_item_trait(&mut self, node: &'ast ItemTrait) {
-        self.log("visit_item_trait");
-        self.push("<<item_trait>>".to_string());
-        //visit_item_trait(self, node);
-    }
-
-    fn visit_item_trait_alias(&mut self, node: &'ast ItemTraitAlias) {
-        self.log("visit_item_trait_alias");
-        self.push("<<item_trait_alias>>".to_string());
-        //visit_item_trait_alias(self, node);
-    }
-
-    fn visit_item_type(&mut self, node: &'ast ItemType) {
-        self.log("visit_item_type");
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-        // self.visit_visibility(&node.vis);
-        // tokens_helper(v, &node.type_token.span);
-        self.visit_ident(&node.ident);
-        let name = self.tos();
-        // self.visit_generics(&node.generics);
-        // tokens_helper(v, &node.eq_token.spans);
-        self.visit_type(&*node.ty);
-        let ty = self.tos();
-
-        ret!(self, "typedef {} {};", ty, name);
-    }
-
-    fn visit_item_union(&mut self, node: &'ast ItemUnion) {
-        self.log("visit_item_union");
-        self.push("<<item_union>>".to_string());
-        //visit_item_union(self, node);
-    }
-
-    fn visit_item_use(&mut self, node: &'ast ItemUse) {
-        self.log("visit_item_use");
-        self.push("<<item_use>>".to_string());
-        //visit_item_use(self, node);
-    }
-
-    fn visit_label(&mut self, node: &'ast Label) {
-        self.log("visit_label");
-        self.push("<<label>>".to_string());
-        //visit_label(self, node);
-    }
-    fn visit_lifetime(&mut self, node: &'ast Lifetime) {
-        self.log("visit_lifetime");
-        self.push("<<lifetime>>".to_string());
-        //visit_lifetime(self, node);
+        text.push('\n');
     }
 
-    fn visit_lifetime_def(&mut self, node: &'ast LifetimeDef) {
-        self.log("visit_lifetime_def");
-        self.push("<<lifetime_def>>".to_string());
-        //visit_lifetime_def(self, node);
-    }
-    fn visit_lit(&mut self, node: &'ast Lit) {
-        self.log("visit_lit");
-        // self.push("<<lit>>".to_string());
-        visit_lit(self, node);
-    }
-    fn visit_lit_bool(&mut self, node: &'ast LitBool) {
-        self.log("visit_lit_bool");
-        self.push("<<lit_bool>>".to_string());
-        //visit_lit_bool(self, node);
-    }
-    fn visit_lit_byte(&mut self, node: &'ast LitByte) {
-        self.log("visit_lit_byte");
-        self.push("<<lit_byte>>".to_string());
-        //visit_lit_byte(self, node);
-    }
-    fn visit_lit_byte_str(&mut self, node: &'ast LitByteStr) {
-        self.log("visit_lit_byte_str");
-        self.push("<<lit_byte_str>>".to_string());
-        //visit_lit_byte_str(self, node);
-    }
-    fn visit_lit_char(&mut self, node: &'ast LitChar) {
-        self.log("visit_lit_char");
-        self.push("<<lit_char>>".to_string());
-        //visit_lit_char(self, node);
-    }
-    fn visit_lit_float(&mut self, node: &'ast LitFloat) {
-        self.log("visit_lit_float");
-        let suffix = node.suffix();
-        match suffix {
-            _ => ret!(self, "{}", node.base10_digits())
+    // Tuple types and constants.
+    fn types_and_consts(&self, text: &mut String, prefix: &str) {
+        for scalars in &self.decls.tuples {
+            text.push_str(&print_tuple(prefix, scalars));
+            text.push('\n');
         }
-    }
-
-    fn visit_lit_int(&mut self, node: &'ast LitInt) {
-        self.log("visit_lit_int");
-        // self.push("<<lit_int>>".to_string());
-        // visit_lit_int(self, node);
-        let suffix = node.suffix();
-        match suffix {
-            _ => ret!(self, "{}", node.base10_digits())
+        for c in &self.consts {
+            text.push_str(c);
         }
-    }
-    fn visit_lit_str(&mut self, node: &'ast LitStr) {
-        self.log("visit_lit_str");
-        self.push("<<lit_str>>".to_string());
-        //visit_lit_str(self, node);
+        text.push('\n');
     }
 
-    fn visit_local(&mut self, node: &'ast Local) {
-        self.log("visit_local");
-        // self.push("<<local>>".to_string());
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-        // tokens_helper(v, &node.let_token.span);
-
-        self.visit_pat(&node.pat);
-        let pat = self.tos();
-        if let Some(it) = &node.init {
-            self.visit_expr(&*(it).1);
-            let expr = self.tos();
-            ret!(self, "{} = {}", pat, expr);
-        } else {
-            ret!(self, "{}", pat);
+    // Bit casts and maths fallbacks used by the definitions.
+    fn helpers(&self, text: &mut String, options: &Options) {
+        for (to, from) in &self.decls.bitcasts {
+            text.push_str(&print_bitcast(&options.prefix, *to, *from, options.freestanding));
+            text.push('\n');
+        }
+        for (index, scalar) in &self.decls.fallbacks {
+            text.push_str(&print_fallback(&options.prefix, *index, *scalar));
+            text.push('\n');
         }
     }
 
-    fn visit_macro(&mut self, node: &'ast Macro) {
-        self.log("visit_macro");
-        self.push("<<macro>>".to_string());
-        //visit_macro(self, node);
-    }
-
-    fn visit_macro_delimiter(&mut self, node: &'ast MacroDelimiter) {
-        self.log("visit_macro_delimiter");
-        self.push("<<macro_delimiter>>".to_string());
-        //visit_macro_delimiter(self, node);
-    }
-
-    fn visit_member(&mut self, node: &'ast Member) {
-        self.log("visit_member");
-        self.push("<<member>>".to_string());
-        //visit_member(self, node);
-    }
-
-    fn visit_meta(&mut self, node: &'ast Meta) {
-        self.log("visit_meta");
-        self.push("<<meta>>".to_string());
-        //visit_meta(self, node);
-    }
-
-    fn visit_meta_list(&mut self, node: &'ast MetaList) {
-        self.log("visit_meta_list");
-        self.push("<<meta_list>>".to_string());
-        //visit_meta_list(self, node);
-    }
-
-    fn visit_meta_name_value(&mut self, node: &'ast MetaNameValue) {
-        self.log("visit_meta_name_value");
-        self.push("<<meta_name_value>>".to_string());
-        //visit_meta_name_value(self, node);
-    }
-
-    fn visit_method_turbofish(&mut self, node: &'ast MethodTurbofish) {
-        self.log("visit_method_turbofish");
-        self.push("<<method_turbofish>>".to_string());
-        //visit_method_turbofish(self, node);
-    }
-
-    fn visit_nested_meta(&mut self, node: &'ast NestedMeta) {
-        self.log("visit_nested_meta");
-        self.push("<<nested_meta>>".to_string());
-        //visit_nested_meta(self, node);
-    }
-
-    fn visit_parenthesized_generic_arguments(&mut self, node: &'ast ParenthesizedGenericArguments) {
-        self.log("visit_parenthesized_generic_arguments");
-        self.push("<<parenthesized_generic_arguments>>".to_string());
-        //visit_parenthesized_generic_arguments(self, node);
-    }
-
-    fn visit_pat(&mut self, node: &'ast Pat) {
-        self.log("visit_pat");
-        visit_pat(self, node);
-    }
-
-    fn visit_pat_box(&mut self, node: &'ast PatBox) {
-        self.log("visit_pat_box");
-        self.push("<<pat_box>>".to_string());
-        //visit_pat_box(self, node);
-    }
-
-    fn visit_pat_ident(&mut self, node: &'ast PatIdent) {
-        self.log("visit_pat_ident");
-        // visit_pat_ident(self, node);
-
-        self.visit_ident(&node.ident);
-    }
-
-    fn visit_pat_lit(&mut self, node: &'ast PatLit) {
-        self.log("visit_pat_lit");
-        self.push("<<pat_lit>>".to_string());
-        //visit_pat_lit(self, node);
-    }
-
-    fn visit_pat_macro(&mut self, node: &'ast PatMacro) {
-        self.log("visit_pat_macro");
-        self.push("<<pat_macro>>".to_string());
-        //visit_pat_macro(self, node);
-    }
-
-    fn visit_pat_or(&mut self, node: &'ast PatOr) {
-        self.log("visit_pat_or");
-        self.push("<<pat_or>>".to_string());
-        //visit_pat_or(self, node);
-    }
-
-    fn visit_pat_path(&mut self, node: &'ast PatPath) {
-        self.log("visit_pat_path");
-        self.push("<<pat_path>>".to_string());
-        //visit_pat_path(self, node);
-    }
-
-    fn visit_pat_range(&mut self, node: &'ast PatRange) {
-        self.log("visit_pat_range");
-        self.push("<<pat_range>>".to_string());
-        //visit_pat_range(self, node);
-    }
-
-    fn visit_pat_reference(&mut self, node: &'ast PatReference) {
-        self.log("visit_pat_reference");
-        self.push("<<pat_reference>>".to_string());
-        //visit_pat_reference(self, node);
-    }
-
-    fn visit_pat_rest(&mut self, node: &'ast PatRest) {
-        self.log("visit_pat_rest");
-        self.push("<<pat_rest>>".to_string());
-        //visit_pat_rest(self, node);
-    }
-
-    fn visit_pat_slice(&mut self, node: &'ast PatSlice) {
-        self.log("visit_pat_slice");
-        self.push("<<pat_slice>>".to_string());
-        //visit_pat_slice(self, node);
-    }
-
-    fn visit_pat_struct(&mut self, node: &'ast PatStruct) {
-        self.log("visit_pat_struct");
-        self.push("<<pat_struct>>".to_string());
-        //visit_pat_struct(self, node);
-    }
-
-    fn visit_pat_tuple(&mut self, node: &'ast PatTuple) {
-        self.log("visit_pat_tuple");
-        self.push("<<pat_tuple>>".to_string());
-        //visit_pat_tuple(self, node);
-    }
-
-    fn visit_pat_tuple_struct(&mut self, node: &'ast PatTupleStruct) {
-        self.log("visit_pat_tuple_struct");
-        self.push("<<pat_tuple_struct>>".to_string());
-        //visit_pat_tuple_struct(self, node);
-    }
-
-    fn visit_pat_type(&mut self, node: &'ast PatType) {
-        self.log("visit_pat_type");
-
-        // for it in &node.attrs {
-        //     self.visit_attribute(it);
-        // }
-
-        self.visit_pat(&*node.pat);
-        let pat = self.tos();
-        self.visit_type(&*node.ty);
-        let ty = self.tos();
-        ret!(self, "{} {}", ty, pat);
-    }
-
-    fn visit_pat_wild(&mut self, node: &'ast PatWild) {
-        self.log("visit_pat_wild");
-        self.push("<<pat_wild>>".to_string());
-        //visit_pat_wild(self, node);
-    }
-
-    fn visit_path(&mut self, node: &'ast Path) {
-        self.log("visit_path");
-        use quote::ToTokens;
-        let path = node.to_token_stream().to_string().replace(" :: ", "_");
-        self.push(path);
-    }
-
-    fn visit_path_arguments(&mut self, node: &'ast PathArguments) {
-        self.log("visit_path_arguments");
-        self.push("<<path_arguments>>".to_string());
-        //visit_path_arguments(self, node);
-    }
-
-    fn visit_path_segment(&mut self, node: &'ast PathSegment) {
-        self.log("visit_path_segment");
-        self.push("<<path_segment>>".to_string());
-        //visit_path_segment(self, node);
-    }
-
-    fn visit_predicate_eq(&mut self, node: &'ast PredicateEq) {
-        self.log("visit_predicate_eq");
-        self.push("<<predicate_eq>>".to_string());
-        //visit_predicate_eq(self, node);
-    }
-
-    fn visit_predicate_lifetime(&mut self, node: &'ast PredicateLifetime) {
-        self.log("visit_predicate_lifetime");
-        self.push("<<predicate_lifetime>>".to_string());
-        //visit_predicate_lifetime(self, node);
-    }
-
-    fn visit_predicate_type(&mut self, node: &'ast PredicateType) {
-        self.log("visit_predicate_type");
-        self.push("<<predicate_type>>".to_string());
-        //visit_predicate_type(self, node);
-    }
-
-    fn visit_qself(&mut self, node: &'ast QSelf) {
-        self.log("visit_qself");
-        self.push("<<qself>>".to_string());
-        //visit_qself(self, node);
+    fn prototypes(&self, text: &mut String) {
+        for (signature, _) in &self.functions {
+            let _ = writeln!(text, "{};", signature);
+        }
     }
 
-    fn visit_range_limits(&mut self, node: &'ast RangeLimits) {
-        self.log("visit_range_limits");
-        self.push("<<range_limits>>".to_string());
-        //visit_range_limits(self, node);
+    fn definitions(&self, text: &mut String) {
+        for (_, func) in &self.functions {
+            text.push('\n');
+            text.push_str(func);
+        }
     }
+}
 
-    fn visit_receiver(&mut self, node: &'ast Receiver) {
-        self.log("visit_receiver");
-        self.push("<<receiver>>".to_string());
-        //visit_receiver(self, node);
+/// Translate a module of the IR into C.
+///
+/// If `options.header` is set, the source includes that header
+/// in place of the types, constants and prototypes.
+pub fn module_to_c(module: &Module, options: &Options) -> Result<String> {
+    let printed = Printed::new(module, options)?;
+    let prefix = &options.prefix;
+    let mut text = String::new();
+    match (&options.header, options.linkage) {
+        (Some(header), Linkage::StaticInline) => {
+            let _ = writeln!(text, "#include \"{}\"", header);
+        }
+        (Some(header), Linkage::Extern) => {
+            let _ = writeln!(text, "#include \"{}\"", header);
+            printed.includes(&mut text, options, true);
+            printed.helpers(&mut text, options);
+            printed.definitions(&mut text);
+        }
+        (None, _) => {
+            printed.includes(&mut text, options, true);
+            printed.types_and_consts(&mut text, prefix);
+            printed.helpers(&mut text, options);
+            printed.prototypes(&mut text);
+            printed.definitions(&mut text);
+        }
     }
+    Ok(text)
+}
 
-    fn visit_return_type(&mut self, node: &'ast ReturnType) {
-        self.log("visit_return_type");
-        match node {
-            ReturnType::Default => ret!(self, "void"),
-            ReturnType::Type(_, ty) => {
-                self.visit_type(&**ty);
+/// Translate a module of the IR into a C header usable from C and C++.
+///
+/// With `Linkage::StaticInline` the header also holds the definitions.
+pub fn module_to_c_header(module: &Module, options: &Options) -> Result<String> {
+    let printed = Printed::new(module, options)?;
+    let prefix = &options.prefix;
+    let name = match &options.header {
+        Some(header) => header.clone(),
+        None => format!("{}doctor_syn.h", prefix),
+    };
+    let guard = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+
+    let mut text = String::new();
+    let _ = writeln!(text, "#ifndef {}\n#define {}\n", guard, guard);
+    printed.includes(&mut text, options, options.linkage == Linkage::StaticInline);
+    text.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    printed.types_and_consts(&mut text, prefix);
+    match options.linkage {
+        Linkage::Extern => {
+            for (func, (signature, _)) in module.functions.iter().zip(&printed.functions) {
+                docs(&mut text, func);
+                let _ = writeln!(text, "{};\n", signature);
             }
-        };
-    }
-
-    fn visit_signature(&mut self, node: &'ast Signature) {
-        self.log("visit_signature");
-        // self.push("<<signature>>".to_string());
-        //// visit_signature(self, node);
-        // if let Some(it) = &node.constness {
-        //     tokens_helper(v, &it.span);
-        // };
-        // if let Some(it) = &node.asyncness {
-        //     tokens_helper(v, &it.span);
-        // };
-        // if let Some(it) = &node.unsafety {
-        //     tokens_helper(v, &it.span);
-        // };
-        // if let Some(it) = &node.abi {
-        //     self.visit_abi(it);
-        // };
-        // tokens_helper(v, &node.fn_token.span);
-
-        self.visit_ident(&node.ident);
-        let ident = self.tos();
-        let ident = format!("{}{}", self.options.prefix, ident);
-
-        // self.visit_generics(&node.generics);
-        // tokens_helper(v, &node.paren_token.span);
-
-        let mut args = Vec::new();
-        for el in Punctuated::pairs(&node.inputs) {
-            let (it, p) = el.into_tuple();
-            self.visit_fn_arg(it);
-            args.push(self.tos());
-            // if let Some(p) = p {
-            //     tokens_helper(v, &p.spans);
-            // }
         }
-        let args = args.join(", ");
-
-        // if let Some(it) = &node.variadic {
-        //     self.visit_variadic(it);
-        // };
-
-        self.visit_return_type(&node.output);
-        let ret = self.tos();
-
-        ret!(self, "{} {}({})", ret, ident, args);
-    }
-
-    fn visit_span(&mut self, node: &Span) {
-        self.log("visit_span");
-        self.push("<<span>>".to_string());
-        //visit_span(self, node);
-    }
-
-    fn visit_stmt(&mut self, node: &'ast Stmt) {
-        self.log("visit_stmt");
-        if let Stmt::Expr(e) = node {
-            self.visit_expr(e);
-            let expr = self.tos();
-            ret!(self, "{};", expr);
-        } else {
-            visit_stmt(self, node);
+        Linkage::StaticInline => {
+            printed.helpers(&mut text, options);
+            printed.prototypes(&mut text);
+            printed.definitions(&mut text);
+            text.push('\n');
         }
     }
+    text.push_str("#ifdef __cplusplus\n}\n#endif\n\n");
+    let _ = writeln!(text, "#endif // {}", guard);
+    Ok(text)
+}
 
-    fn visit_trait_bound(&mut self, node: &'ast TraitBound) {
-        self.log("visit_trait_bound");
-        self.push("<<trait_bound>>".to_string());
-        //visit_trait_bound(self, node);
-    }
-
-    fn visit_trait_bound_modifier(&mut self, node: &'ast TraitBoundModifier) {
-        self.log("visit_trait_bound_modifier");
-        self.push("<<trait_bound_modifier>>".to_string());
-        //visit_trait_bound_modifier(self, node);
-    }
-
-    fn visit_trait_item(&mut self, node: &'ast TraitItem) {
-        self.log("visit_trait_item");
-        self.push("<<trait_item>>".to_string());
-        //visit_trait_item(self, node);
-    }
-
-    fn visit_trait_item_const(&mut self, node: &'ast TraitItemConst) {
-        self.log("visit_trait_item_const");
-        self.push("<<trait_item_const>>".to_string());
-        //visit_trait_item_const(self, node);
-    }
-
-    fn visit_trait_item_macro(&mut self, node: &'ast TraitItemMacro) {
-        self.log("visit_trait_item_macro");
-        self.push("<<trait_item_macro>>".to_string());
-        //visit_trait_item_macro(self, node);
-    }
-
-    fn visit_trait_item_method(&mut self, node: &'ast TraitItemMethod) {
-        self.log("visit_trait_item_method");
-        self.push("<<trait_item_method>>".to_string());
-        //visit_trait_item_method(self, node);
-    }
-
-    fn visit_trait_item_type(&mut self, node: &'ast TraitItemType) {
-        self.log("visit_trait_item_type");
-        self.push("<<trait_item_type>>".to_string());
-        //visit_trait_item_type(self, node);
-    }
-
-    fn visit_type(&mut self, node: &'ast Type) {
-        self.log("visit_type");
-        visit_type(self, node);
-    }
-
-    fn visit_type_array(&mut self, node: &'ast TypeArray) {
-        self.log("visit_type_array");
-        self.push("<<type_array>>".to_string());
-        //visit_type_array(self, node);
-    }
-
-    fn visit_type_bare_fn(&mut self, node: &'ast TypeBareFn) {
-        self.log("visit_type_bare_fn");
-        self.push("<<type_bare_fn>>".to_string());
-        //visit_type_bare_fn(self, node);
-    }
-
-    fn visit_type_group(&mut self, node: &'ast TypeGroup) {
-        self.log("visit_type_group");
-        self.push("<<type_group>>".to_string());
-        //visit_type_group(self, node);
-    }
-
-    fn visit_type_impl_trait(&mut self, node: &'ast TypeImplTrait) {
-        self.log("visit_type_impl_trait");
-        self.push("<<type_impl_trait>>".to_string());
-        //visit_type_impl_trait(self, node);
-    }
-
-    fn visit_type_infer(&mut self, node: &'ast TypeInfer) {
-        self.log("visit_type_infer");
-        self.push("<<type_infer>>".to_string());
-        //visit_type_infer(self, node);
-    }
-
-    fn visit_type_macro(&mut self, node: &'ast TypeMacro) {
-        self.log("visit_type_macro");
-        self.push("<<type_macro>>".to_string());
-        //visit_type_macro(self, node);
-    }
-
-    fn visit_type_never(&mut self, node: &'ast TypeNever) {
-        self.log("visit_type_never");
-        self.push("<<type_never>>".to_string());
-        //visit_type_never(self, node);
-    }
-
-    fn visit_type_param(&mut self, node: &'ast TypeParam) {
-        self.log("visit_type_param");
-        self.push("<<type_param>>".to_string());
-        //visit_type_param(self, node);
-    }
-
-    fn visit_type_param_bound(&mut self, node: &'ast TypeParamBound) {
-        self.log("visit_type_param_bound");
-        self.push("<<type_param_bound>>".to_string());
-        //visit_type_param_bound(self, node);
-    }
-
-    fn visit_type_paren(&mut self, node: &'ast TypeParen) {
-        self.log("visit_type_paren");
-        self.push("<<type_paren>>".to_string());
-        //visit_type_paren(self, node);
-    }
+/// Translate a Rust file into C.
+pub fn to_c(file: &syn::File, options: Options) -> Result<String> {
+    module_to_c(&lower_file(file)?, &options)
+}
 
-    fn visit_type_path(&mut self, node: &'ast TypePath) {
-        self.log("visit_type_path");
-        // if let Some(it) = &node.qself {
-        //     self.visit_qself(it);
-        // };
-        self.visit_path(&node.path);
-        let path = self.tos();
-        self.push(translate_basic_types(path));
-    }
+/// Translate a Rust file into a C header.
+pub fn to_c_header(file: &syn::File, options: Options) -> Result<String> {
+    module_to_c_header(&lower_file(file)?, &options)
+}
 
-    fn visit_type_ptr(&mut self, node: &'ast TypePtr) {
-        self.log("visit_type_ptr");
-        self.push("<<type_ptr>>".to_string());
-        //visit_type_ptr(self, node);
-    }
+#[test]
+fn test() {
+    use syn::parse_quote;
 
-    fn visit_type_reference(&mut self, node: &'ast TypeReference) {
-        self.log("visit_type_reference");
-        self.push("<<type_reference>>".to_string());
-        //visit_type_reference(self, node);
-    }
+    let code: syn::File = parse_quote! {
+        #[allow (non_camel_case_types)]
+        type fty =f64 ;
 
-    fn visit_type_slice(&mut self, node: &'ast TypeSlice) {
-        self.log("visit_type_slice");
-        self.push("<<type_slice>>".to_string());
-        //visit_type_slice(self, node);
-    }
+        const RECIP_2PI :fty =0.1591549430918953357688837633725143620345;
 
-    fn visit_type_trait_object(&mut self, node: &'ast TypeTraitObject) {
-        self.log("visit_type_trait_object");
-        self.push("<<type_trait_object>>".to_string());
-        //visit_type_trait_object(self, node);
-    }
+        pub fn sin (arg : fty)->fty {
+          let scaled :fty =arg *RECIP_2PI ;
+          let x :fty =scaled -scaled .round ();
+          (- 0.0000795978135564681619446994463825844449 as f64).mul_add (x * x , 0.0011251039233483632093906670512638370694 as f64).mul_add (x * x , - 0.0120293093815837587083929079549229916291 as f64).mul_add (x * x , 0.1042285941703196255136347927732329854039 as f64).mul_add (x * x , - 0.7181222077484850721256518873820977766730 as f64).mul_add (x * x , 3.8199525744232107661057457125514942650559 as f64).mul_add (x * x , - 15.0946425760590780811041894566331393312522 as f64).mul_add (x * x , 42.0586939448620164904399014801571486455824 as f64).mul_add (x * x , - 76.7058597530604003747392381683185548195638 as f64).mul_add (x * x , 81.6052492760750400501824780624242989421231 as f64).mul_add (x * x , - 41.3417022403997601538762424010531941752307 as f64).mul_add (x * x , 6.2831853071795864768497321650524941104931 as f64)*x
+        }
+    };
 
-    fn visit_type_tuple(&mut self, node: &'ast TypeTuple) {
-        self.log("visit_type_tuple");
-        self.push("<<type_tuple>>".to_string());
-        //visit_type_tuple(self, node);
-    }
+    let options = Options::default();
+    let c = to_c(&code, options).unwrap();
+    assert!(c.contains("#define RECIP_2PI 0.1591549430918953357688837633725143620345\n"));
+    assert!(c.contains("double sin(double arg) {\n    const double scaled = arg * RECIP_2PI;\n"));
+    assert!(c.contains("const double x = scaled - round(scaled);\n"));
+    assert!(c.contains("fma(fma(-0.0000795978135564681619446994463825844449, x * x, 0.0011251039233483632093906670512638370694), x * x, "));
+    assert!(c.contains(", x * x, 6.2831853071795864768497321650524941104931) * x;\n}\n"));
+}
 
-    fn visit_un_op(&mut self, node: &'ast UnOp) {
-        self.log("visit_un_op");
-        let op = match node {
-            UnOp::Deref(_) => {
-                "*"
-            }
-            UnOp::Not(_) => {
-                "!"
-            }
-            UnOp::Neg(_) => {
-                "-"
+#[test]
+fn test_if_and_bitcast() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            let bits = x.to_bits();
+            if x < 0.0 {
+                let y = f32::from_bits(bits & 0x7fffffff);
+                y * 2.0
+            } else {
+                x
             }
-        };
-        ret!(self, "{}", op);
-    }
-
-    fn visit_use_glob(&mut self, node: &'ast UseGlob) {
-        self.log("visit_use_glob");
-        self.push("<<use_glob>>".to_string());
-        //visit_use_glob(self, node);
-    }
-
-    fn visit_use_group(&mut self, node: &'ast UseGroup) {
-        self.log("visit_use_group");
-        self.push("<<use_group>>".to_string());
-        //visit_use_group(self, node);
-    }
-
-    fn visit_use_name(&mut self, node: &'ast UseName) {
-        self.log("visit_use_name");
-        self.push("<<use_name>>".to_string());
-        //visit_use_name(self, node);
-    }
-
-    fn visit_use_path(&mut self, node: &'ast UsePath) {
-        self.log("visit_use_path");
-        self.push("<<use_path>>".to_string());
-        //visit_use_path(self, node);
-    }
-
-    fn visit_use_rename(&mut self, node: &'ast UseRename) {
-        self.log("visit_use_rename");
-        self.push("<<use_rename>>".to_string());
-        //visit_use_rename(self, node);
-    }
-
-    fn visit_use_tree(&mut self, node: &'ast UseTree) {
-        self.log("visit_use_tree");
-        self.push("<<use_tree>>".to_string());
-        //visit_use_tree(self, node);
-    }
-
-    fn visit_variadic(&mut self, node: &'ast Variadic) {
-        self.log("visit_variadic");
-        self.push("<<variadic>>".to_string());
-        //visit_variadic(self, node);
-    }
-
-    fn visit_variant(&mut self, node: &'ast Variant) {
-        self.log("visit_variant");
-        self.push("<<variant>>".to_string());
-        //visit_variant(self, node);
-    }
-
-    fn visit_vis_crate(&mut self, node: &'ast VisCrate) {
-        self.log("visit_vis_crate");
-        self.push("<<vis_crate>>".to_string());
-        //visit_vis_crate(self, node);
-    }
-
-    fn visit_vis_public(&mut self, node: &'ast VisPublic) {
-        self.log("visit_vis_public");
-        self.push("<<vis_public>>".to_string());
-        //visit_vis_public(self, node);
-    }
-
-    fn visit_vis_restricted(&mut self, node: &'ast VisRestricted) {
-        self.log("visit_vis_restricted");
-        self.push("<<vis_restricted>>".to_string());
-        //visit_vis_restricted(self, node);
-    }
-
-    fn visit_visibility(&mut self, node: &'ast Visibility) {
-        self.log("visit_visibility");
-        self.push("<<visibility>>".to_string());
-        //visit_visibility(self, node);
-    }
+        }
+    };
+    let c = to_c(&code, Options {
+            prefix: "ds_".to_string(),
+            ..Options::default()
+        }).unwrap();
+    assert!(c.contains("static inline uint32_t ds_bitcast_u32_f32(float x) {"));
+    assert!(c.contains("const uint32_t bits = ds_bitcast_u32_f32(x);\n"));
+    assert!(c.contains("    float v9;\n    if (x < 0.0f) {\n"));
+    assert!(c.contains("        const float y = ds_bitcast_f32_u32(bits & 2147483647u);\n"));
+    assert!(c.contains("        v9 = y * 2.0f;\n    } else {\n        v9 = x;\n    }\n    return v9;\n"));
+}
 
-    fn visit_where_clause(&mut self, node: &'ast WhereClause) {
-        self.log("visit_where_clause");
-        self.push("<<where_clause>>".to_string());
-        //visit_where_clause(self, node);
-    }
+#[test]
+fn test_tuple() {
+    use syn::parse_quote;
 
-    fn visit_where_predicate(&mut self, node: &'ast WherePredicate) {
-        self.log("visit_where_predicate");
-        self.push("<<where_predicate>>".to_string());
-        //visit_where_predicate(self, node);
-    }
+    let code: syn::File = parse_quote! {
+        fn f(x: f64) -> (f64, f64) {
+            (x.sin(), x.cos())
+        }
+        fn g(x: f64) -> f64 {
+            let (s, c) = f(x);
+            s * c
+        }
+    };
+    let c = to_c(&code, Options::default()).unwrap();
+    assert!(c.contains("typedef struct {\n    double _0;\n    double _1;\n} tuple_f64_f64;\n"));
+    assert!(c.contains("    return (tuple_f64_f64){sin(x), cos(x)};\n"));
+    assert!(c.contains("    const tuple_f64_f64 v1 = f(x);\n    const double s = v1._0;\n"));
 }
 
-fn translate_basic_types(name: String) -> String {
-    match name.as_str() {
-        "bool" => "int".to_string(),
-        "i32" => "int".to_string(),
-        "i64" => "long long".to_string(),
-        "u32" => "unsigned int".to_string(),
-        "u64" => "unsigned long long".to_string(),
-        "f32" => "float".to_string(),
-        "f64" => "double".to_string(),
-        _ => name,
-    }
+#[test]
+fn test_header() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        const TWO: f32 = 2.0;
+        /// Twice x.
+        pub fn twice(x: f32) -> f32 {
+            x * TWO
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        header: Some("twice.h".to_string()),
+        ..Options::default()
+    };
+    let h = to_c_header(&code, options).unwrap();
+    assert!(h.starts_with("#ifndef TWICE_H\n#define TWICE_H\n"));
+    assert!(h.contains("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n#define ds_TWO 2.0f\n"));
+    assert!(h.contains("// Twice x.\nfloat ds_twice(float x);\n"));
+    assert!(!h.contains("return"));
+
+    let options = Options {
+        prefix: "ds_".to_string(),
+        header: Some("twice.h".to_string()),
+        ..Options::default()
+    };
+    let c = to_c(&code, options).unwrap();
+    assert!(c.starts_with("#include \"twice.h\"\n"));
+    assert!(!c.contains("#define"));
+    assert!(c.contains("float ds_twice(float x) {\n"));
+
+    let options = Options {
+        linkage: Linkage::StaticInline,
+        ..Options::default()
+    };
+    let h = to_c_header(&code, options).unwrap();
+    assert!(h.contains("static inline float twice(float x) {\n    return x * TWO;\n}\n"));
+    assert!(h.ends_with("#endif // DOCTOR_SYN_H\n"));
 }
 
+#[test]
+fn test_freestanding() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            if x.is_nan() {
+                f32::INFINITY
+            } else {
+                f32::from_bits(x.floor().to_bits() | 1)
+            }
+        }
+    };
+    let options = Options {
+        freestanding: true,
+        ..Options::default()
+    };
+    let c = to_c(&code, options).unwrap();
+    assert!(!c.contains("math.h") && !c.contains("string.h"));
+    assert!(c.contains("union { float from; uint32_t to; } u = {x};"));
+    assert!(c.contains("static inline float trunc_f32(float x) {"));
+    assert!(c.contains("    return __builtin_isnan(x) ? __builtin_inff() : "));
+    assert!(c.contains("bitcast_f32_u32(bitcast_u32_f32(floor_f32(x)) | 1u)"));
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            x.sin()
+        }
+    };
+    let options = Options {
+        freestanding: true,
+        ..Options::default()
+    };
+    let e = to_c(&code, options).unwrap_err();
+    assert_eq!(e.message(), Some("`sin` needs libm which freestanding C does not have"));
 
-/// Translate a Rust file into C.
-pub fn to_c(file: &syn::File, options: Options) -> String {
-    let mut cv = CVisitor::new(options);
-    cv.visit_file(file);
-    cv.tos()
+    // The module's own `ln` replaces libm's.
+    let code: syn::File = parse_quote! {
+        fn ln(x: f64) -> f64 {
+            x - 1.0
+        }
+        fn ln_1p(x: f64) -> f64 {
+            (1.0 + x).ln()
+        }
+    };
+    let options = Options {
+        prefix: "ds64_".to_string(),
+        freestanding: true,
+        ..Options::default()
+    };
+    let c = to_c(&code, options).unwrap();
+    assert!(c.contains("    return ds64_ln(1.0 + x);\n"));
+    let c = to_c(&code, Options::default()).unwrap();
+    assert!(c.contains("    return log(1.0 + x);\n"));
 }
 
 #[test]
-fn test() {
-    let code : syn::File = parse_quote! {
-        #[allow (non_camel_case_types)]
-        type fty =f64 ;
-        
-        const RECIP_2PI :fty =0.1591549430918953357688837633725143620345;
-        
-        pub fn sin (arg : fty)->fty {
-          let scaled :fty =arg *RECIP_2PI ;
-          let x :fty =scaled -scaled .round ();
-          (- 0.0000795978135564681619446994463825844449 as f64).mul_add (x * x , 0.0011251039233483632093906670512638370694 as f64).mul_add (x * x , - 0.0120293093815837587083929079549229916291 as f64).mul_add (x * x , 0.1042285941703196255136347927732329854039 as f64).mul_add (x * x , - 0.7181222077484850721256518873820977766730 as f64).mul_add (x * x , 3.8199525744232107661057457125514942650559 as f64).mul_add (x * x , - 15.0946425760590780811041894566331393312522 as f64).mul_add (x * x , 42.0586939448620164904399014801571486455824 as f64).mul_add (x * x , - 76.7058597530604003747392381683185548195638 as f64).mul_add (x * x , 81.6052492760750400501824780624242989421231 as f64).mul_add (x * x , - 41.3417022403997601538762424010531941752307 as f64).mul_add (x * x , 6.2831853071795864768497321650524941104931 as f64)*x 
+fn test_declare_simd() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f64, n: i32) -> f64 {
+            x * n as f64
+        }
+        fn g(x: f64) -> (f64, f64) {
+            (x, x)
         }
     };
-
-    let options = Options::default();
-    println!("res\n{}", to_c(&code, options));
+    let options = Options {
+        prefix: "ds64_".to_string(),
+        declare_simd: true,
+        ..Options::default()
+    };
+    let c = to_c(&code, options).unwrap();
+    assert!(c.contains("#pragma omp declare simd notinbranch\ndouble ds64_f(double x, int32_t n);\n"));
+    assert!(c.contains("#pragma omp declare simd notinbranch\ndouble ds64_f(double x, int32_t n) {\n"));
+    // Vector variants can not return structs.
+    assert!(c.contains("\nds64_tuple_f64_f64 ds64_g(double x) {\n"));
 }
 
+#[test]
+fn test_mul_add() {
+    use syn::parse_quote;
 
+    let code: syn::File = parse_quote! {
+        fn f(x: f32, y: f32) -> f32 {
+            x.mul_add(y, 1.0)
+        }
+    };
+    let c = to_c(&code, Options::default()).unwrap();
+    assert!(c.contains("    return fmaf(x, y, 1.0f);\n"));
+    let options = Options {
+        freestanding: true,
+        ..Options::default()
+    };
+    let c = to_c(&code, options).unwrap();
+    assert!(c.contains("static inline float fma_f32(float x, float y, float z) {"));
+    assert!(!c.contains('$'));
+    assert!(c.contains("uint64_t m[3];"));
+    assert!(c.contains("    return fma_f32(x, y, 1.0f);\n"));
+    let options = Options {
+        unfused_mul_add: true,
+        ..Options::default()
+    };
+    let c = to_c(&code, options).unwrap();
+    assert!(c.contains("    return x * y + 1.0f;\n"));
+}
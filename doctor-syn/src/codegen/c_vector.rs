@@ -0,0 +1,965 @@
+//! Translate Rust functions into C using GCC and Clang vector extensions.
+//!
+//! Every value becomes a vector of `lanes` elements, eg. `f64x4`, except
+//! inline literals and constants which the compiler broadcasts. `if`
+//! expressions evaluate both branches and blend the results. Maths functions
+//! without an operator loop over the lanes or, for a chosen flavour, use x86
+//! intrinsics on vectors of the right size.
+
+use super::c::{
+    binary_prec, const_defines, docs, float_literal, int_literal, libm_name, scalar_type,
+    special_literal, PREC_ATOM, PREC_UNARY, RESERVED,
+};
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Ty,
+    UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// The x86 intrinsics used where vector extensions have no operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavour {
+    /// Vector extensions only, for any target.
+    Generic,
+    /// SSE4.1 for 128 bit vectors.
+    Sse,
+    /// AVX2 and FMA for 256 bit vectors.
+    Avx2,
+    /// AVX-512F and AVX-512DQ for 512 bit vectors.
+    Avx512,
+}
+
+impl Flavour {
+    // The ISA letter of vector function ABI names.
+    fn isa(self) -> Option<char> {
+        match self {
+            Flavour::Generic => None,
+            Flavour::Sse => Some('b'),
+            Flavour::Avx2 => Some('d'),
+            Flavour::Avx512 => Some('e'),
+        }
+    }
+
+    // The size of vector the intrinsics take and their prefix.
+    fn vector(self) -> Option<(usize, &'static str)> {
+        match self {
+            Flavour::Generic => None,
+            Flavour::Sse => Some((128, "_mm")),
+            Flavour::Avx2 => Some((256, "_mm256")),
+            Flavour::Avx512 => Some((512, "_mm512")),
+        }
+    }
+}
+
+pub struct Options {
+    /// Prepended to the names of types, functions and constants, eg. `ds64x4_`.
+    pub prefix: String,
+    /// The number of elements in each vector, a power of two.
+    pub lanes: usize,
+    pub flavour: Flavour,
+    /// Also define the functions under the x86 vector function ABI names of
+    /// scalar functions with this prefix, eg. `_ZGVdN4v_ds64_sin` for `ds64_`,
+    /// so that loops calling them can be vectorised. Only functions whose
+    /// vectors are the size of the flavour's are given a variant.
+    pub vector_abi: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: "".to_string(),
+            lanes: 4,
+            flavour: Flavour::Generic,
+            vector_abi: None,
+        }
+    }
+}
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    vectors: BTreeSet<Scalar>,
+    tuples: BTreeSet<Vec<Scalar>>,
+    // Helper functions by name.
+    helpers: BTreeMap<String, String>,
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    options: &'a Options,
+    use_counts: Vec<usize>,
+    decls: &'a mut Decls,
+    // The element size of masks with no size of their own.
+    mask_bits: usize,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(func: &'a Function, options: &'a Options, decls: &'a mut Decls) -> Self {
+        let mask_bits = func
+            .values
+            .iter()
+            .filter_map(|v| v.ty.scalar())
+            .filter(|s| s.is_float())
+            .map(|s| s.num_bits())
+            .max()
+            .unwrap_or(32);
+        Printer {
+            func,
+            options,
+            use_counts: func.use_counts(),
+            decls,
+            mask_bits,
+            text: String::new(),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating C vectors for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            ty => Err(self.error(&format!("expected a scalar, found {}", ty))),
+        }
+    }
+
+    // The element of a value's vector, masks are signed integers.
+    fn elem(&self, value: Value) -> Result<Scalar> {
+        match self.scalar(value)? {
+            Scalar::Bool => Ok(Scalar::int(self.bool_bits(value)).unwrap_or(Scalar::I32)),
+            scalar => Ok(scalar),
+        }
+    }
+
+    // The element size of a mask, from the values compared.
+    fn bool_bits(&self, value: Value) -> usize {
+        match self.func.inst(value).map(|inst| &inst.op) {
+            Some(Op::Binary(_, a, _)) | Some(Op::Unary(_, a)) => match self.func.ty(*a) {
+                Ty::Scalar(Scalar::Bool) => self.bool_bits(*a),
+                ty => ty.scalar().map_or(self.mask_bits, |s| s.num_bits()),
+            },
+            Some(Op::Intrinsic(_, args)) => self
+                .func
+                .ty(args[0])
+                .scalar()
+                .map_or(self.mask_bits, |s| s.num_bits()),
+            Some(Op::Select(_, a, _)) => self.bool_bits(*a),
+            Some(Op::If(_, then_body, _)) => self.bool_bits(then_body.result),
+            _ => self.mask_bits,
+        }
+    }
+
+    // The vector type of a scalar type.
+    fn vector(&mut self, scalar: Scalar) -> String {
+        let scalar = match scalar {
+            Scalar::Bool => Scalar::int(self.mask_bits).unwrap_or(Scalar::I32),
+            scalar => scalar,
+        };
+        self.decls.vectors.insert(scalar);
+        vector_name(self.options, scalar)
+    }
+
+    fn c_type(&mut self, value: Value) -> Result<String> {
+        match self.func.ty(value) {
+            Ty::Scalar(_) => {
+                let elem = self.elem(value)?;
+                Ok(self.vector(elem))
+            }
+            ty => self.ty(&ty.clone()),
+        }
+    }
+
+    fn ty(&mut self, ty: &Ty) -> Result<String> {
+        match ty {
+            Ty::Scalar(scalar) => Ok(self.vector(*scalar)),
+            Ty::Tuple(elems) => {
+                let scalars = elems
+                    .iter()
+                    .map(|e| e.scalar().filter(|_| matches!(e, Ty::Scalar(_))))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| self.error(&format!("the type {} is not supported", ty)))?;
+                for scalar in &scalars {
+                    self.vector(*scalar);
+                }
+                let name = tuple_name(self.options, &scalars);
+                self.decls.tuples.insert(scalars);
+                Ok(name)
+            }
+            _ => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    fn name(&self, value: Value) -> String {
+        let name = self.func.name(value);
+        if RESERVED.contains(&name.as_str()) {
+            format!("{}_", name)
+        } else {
+            name
+        }
+    }
+
+    // Literals and constants used once, and their negations, are printed as scalars.
+    fn is_uniform(&self, value: Value) -> bool {
+        self.func.is_inline(value, &self.use_counts)
+            && match self.func.inst(value).map(|inst| &inst.op) {
+                Some(Op::Lit(_) | Op::Const(_)) => true,
+                Some(Op::Unary(_, a)) => self.is_uniform(*a),
+                _ => false,
+            }
+    }
+
+    // A value as an expression and its precedence.
+    fn expr(&mut self, value: Value) -> Result<(String, u8)> {
+        match self.func.inst(value) {
+            Some(inst) if self.func.is_inline(value, &self.use_counts) => self.op(inst),
+            _ => Ok((self.name(value), PREC_ATOM)),
+        }
+    }
+
+    fn operand(&mut self, value: Value, prec: u8) -> Result<String> {
+        let (text, p) = self.expr(value)?;
+        Ok(if p < prec {
+            format!("({})", text)
+        } else {
+            text
+        })
+    }
+
+    // A value as a vector, broadcasting scalars.
+    fn vector_operand(&mut self, value: Value, prec: u8) -> Result<String> {
+        if self.is_uniform(value) {
+            let (text, _) = self.expr(value)?;
+            let ty = self.c_type(value)?;
+            Ok(splat(&ty, &text, self.options.lanes))
+        } else {
+            self.operand(value, prec)
+        }
+    }
+
+    fn args(&mut self, args: &[Value]) -> Result<String> {
+        let args = args
+            .iter()
+            .map(|a| self.vector_operand(*a, 0))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(args.join(", "))
+    }
+
+    // A mask with elements the size of `elem`.
+    fn mask(&mut self, value: Value, elem: Scalar) -> Result<String> {
+        let bits = elem.num_bits();
+        let mask = self.vector_operand(value, 0)?;
+        if self.is_uniform(value) || self.bool_bits(value) == bits {
+            return Ok(mask);
+        }
+        let ty = self.vector(Scalar::int(bits).unwrap_or(Scalar::I32));
+        Ok(format!("__builtin_convertvector({}, {})", mask, ty))
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: Value, b: Value) -> Result<(String, u8)> {
+        use BinaryOp::*;
+        let prec = binary_prec(op);
+        let min = match op {
+            Shl | Shr | BitAnd | BitXor | BitOr | And | Or => PREC_UNARY,
+            _ => prec,
+        };
+        // One side may be a scalar, which is broadcast.
+        let scalar_lhs = !matches!(op, Shl | Shr) && !self.is_uniform(b);
+        let lhs = if scalar_lhs {
+            self.operand(a, min)?
+        } else {
+            self.vector_operand(a, min)?
+        };
+        let rhs = self.operand(b, min.max(prec + 1))?;
+        let sym = match op {
+            And => "&",
+            Or => "|",
+            op => op.symbol(),
+        };
+        Ok((format!("{} {} {}", lhs, sym, rhs), prec))
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<(String, u8)> {
+        let value = inst.value;
+        Ok(match &inst.op {
+            Op::Lit(Literal::Float(digits)) => {
+                (float_literal(digits, self.scalar(value)?), PREC_ATOM)
+            }
+            Op::Lit(Literal::Int(i)) => (int_literal(*i, self.scalar(value)?), PREC_ATOM),
+            Op::Lit(Literal::Bool(b)) => ((if *b { "-1" } else { "0" }).to_string(), PREC_ATOM),
+            Op::Lit(Literal::Special(s)) => special_literal(*s, self.scalar(value)?, false),
+            Op::Const(name) => (format!("{}{}", self.options.prefix, name), PREC_ATOM),
+            Op::Unary(op, a) => {
+                let sym = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "~",
+                };
+                (
+                    format!("{}{}", sym, self.operand(*a, PREC_UNARY)?),
+                    PREC_UNARY,
+                )
+            }
+            Op::Binary(BinaryOp::Rem, a, b) if self.scalar(value)?.is_float() => {
+                self.lanes_call("fmod", value, &[*a, *b])?
+            }
+            Op::Binary(op, a, b) => self.binary(*op, *a, *b)?,
+            Op::Convert(a) => {
+                if self.func.ty(value).is_bool() || self.func.ty(*a).is_bool() {
+                    return Err(self.error("conversions to and from bool are not supported"));
+                }
+                let ty = self.c_type(value)?;
+                let a = self.vector_operand(*a, 0)?;
+                (format!("__builtin_convertvector({}, {})", a, ty), PREC_ATOM)
+            }
+            Op::Bitcast(a) => {
+                let ty = self.c_type(value)?;
+                (
+                    format!("({}){}", ty, self.vector_operand(*a, PREC_UNARY)?),
+                    PREC_UNARY,
+                )
+            }
+            Op::Select(c, a, b) => self.blend(value, *c, *a, *b)?,
+            Op::If(c, then_body, else_body) => {
+                self.blend(value, *c, then_body.result, else_body.result)?
+            }
+            Op::Call(name, args) => {
+                let args = self.args(args)?;
+                (
+                    format!("{}{}({})", self.options.prefix, name, args),
+                    PREC_ATOM,
+                )
+            }
+            Op::Intrinsic(i, args) => self.intrinsic(value, *i, args)?,
+            Op::Tuple(args) => {
+                let ty = self.c_type(value)?;
+                (format!("({}){{{}}}", ty, self.args(args)?), PREC_ATOM)
+            }
+            Op::Extract(a, i) => (
+                format!("{}._{}", self.operand(*a, PREC_ATOM)?, i),
+                PREC_ATOM,
+            ),
+            Op::Splat(_) => return Err(self.error("the function already uses vectors")),
+        })
+    }
+
+    // Select lanes of `a` where `c` is set and of `b` elsewhere.
+    fn blend(&mut self, value: Value, c: Value, a: Value, b: Value) -> Result<(String, u8)> {
+        if !matches!(self.func.ty(value), Ty::Scalar(_)) {
+            return Err(self.error("only scalars can be selected"));
+        }
+        let elem = self.elem(value)?;
+        let ty = self.vector(elem);
+        let mask_ty = self.vector(Scalar::int(elem.num_bits()).unwrap_or(Scalar::I32));
+        let name = format!(
+            "{}select_{}",
+            self.options.prefix,
+            vector_suffix(elem, self.options.lanes)
+        );
+        let prefix = self.intrinsics(elem);
+        let body = match prefix {
+            Some(prefix) if elem.is_float() && self.options.flavour == Flavour::Avx512 => {
+                let (p, bits) = (ps_pd(elem), elem.num_bits());
+                format!(
+                    "    return ({ty}){prefix}_mask_blend_{p}({prefix}_movepi{bits}_mask((__m512i)m), (__m512{s})b, (__m512{s})a);",
+                    ty = ty,
+                    prefix = prefix,
+                    p = p,
+                    bits = bits,
+                    s = vector_letter(elem)
+                )
+            }
+            Some(prefix) if elem.is_float() => {
+                let cast = intrinsic_type(elem, self.options);
+                format!(
+                    "    return ({ty}){prefix}_blendv_{p}(({c})b, ({c})a, ({c})m);",
+                    ty = ty,
+                    prefix = prefix,
+                    p = ps_pd(elem),
+                    c = cast
+                )
+            }
+            _ if elem.is_float() || !elem.is_signed() => {
+                format!(
+                    "    return ({ty})((({m})a & m) | (({m})b & ~m));",
+                    ty = ty,
+                    m = mask_ty
+                )
+            }
+            _ => "    return (a & m) | (b & ~m);".to_string(),
+        };
+        let text = format!(
+            "static inline {ty} {name}({m} m, {ty} a, {ty} b) {{\n{body}\n}}\n",
+            ty = ty,
+            name = name,
+            m = mask_ty,
+            body = body
+        );
+        self.decls.helpers.insert(name.clone(), text);
+        let m = self.mask(c, elem)?;
+        let a = self.vector_operand(a, 0)?;
+        let b = self.vector_operand(b, 0)?;
+        Ok((format!("{}({}, {}, {})", name, m, a, b), PREC_ATOM))
+    }
+
+    // The intrinsic prefix if the flavour has intrinsics for vectors of this element.
+    fn intrinsics(&self, elem: Scalar) -> Option<&'static str> {
+        let (bits, prefix) = self.options.flavour.vector()?;
+        if elem.num_bits() * self.options.lanes == bits {
+            Some(prefix)
+        } else {
+            None
+        }
+    }
+
+    // A helper that applies `expr` to each lane, `a[i]`, `b[i]`... are the arguments.
+    fn lanes_helper(
+        &mut self,
+        name: &str,
+        value: Value,
+        args: &[Value],
+        expr: &str,
+    ) -> Result<String> {
+        let ret = self.c_type(value)?;
+        let mut params = Vec::new();
+        let mut suffixes = Vec::new();
+        for (arg, param) in args.iter().zip(&["a", "b", "c"]) {
+            let elem = self.elem(*arg)?;
+            params.push(format!("{} {}", self.vector(elem), param));
+            suffixes.push(vector_suffix(elem, self.options.lanes));
+        }
+        let name = format!("{}{}_{}", self.options.prefix, name, suffixes.join("_"));
+        let text = format!(
+            "static inline {ret} {name}({params}) {{\n    {ret} r = {{0}};\n    int i;\n    for (i = 0; i < {lanes}; i++) {{\n        r[i] = {expr};\n    }}\n    return r;\n}}\n",
+            ret = ret,
+            name = name,
+            params = params.join(", "),
+            lanes = self.options.lanes,
+            expr = expr
+        );
+        self.decls.helpers.insert(name.clone(), text);
+        Ok(name)
+    }
+
+    // A call of a helper that applies a libm function to each lane.
+    fn lanes_call(&mut self, libm: &str, value: Value, args: &[Value]) -> Result<(String, u8)> {
+        let scalar = self.elem(args[0])?;
+        let func = libm_name(libm, scalar);
+        let lane_args = ["a[i]", "b[i]", "c[i]"][0..args.len()].join(", ");
+        let expr = format!("{}({})", func, lane_args);
+        let name = self.lanes_helper(libm, value, args, &expr)?;
+        Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+    }
+
+    // A call of an intrinsic on a whole vector.
+    fn vector_call(
+        &mut self,
+        name: &str,
+        value: Value,
+        args: &[Value],
+        body: String,
+    ) -> Result<(String, u8)> {
+        let elem = self.elem(value)?;
+        let ty = self.vector(elem);
+        let name = format!(
+            "{}{}_{}",
+            self.options.prefix,
+            name,
+            vector_suffix(elem, self.options.lanes)
+        );
+        let params = ["a", "b", "c"][0..args.len()]
+            .iter()
+            .map(|p| format!("{} {}", ty, p))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let text = format!(
+            "static inline {} {}({}) {{\n    return ({}){};\n}}\n",
+            ty, name, params, ty, body
+        );
+        self.decls.helpers.insert(name.clone(), text);
+        Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+    }
+
+    fn intrinsic(&mut self, value: Value, i: Intrinsic, args: &[Value]) -> Result<(String, u8)> {
+        use Intrinsic::*;
+        let elem = self.elem(args[0])?;
+        if elem.is_int() {
+            return self.int_intrinsic(value, i, args, elem);
+        }
+        let cast = intrinsic_type(elem, self.options);
+        let p = ps_pd(elem);
+        let one = float_literal("1.0", elem);
+        let round_mode = match i {
+            Floor => Some("_MM_FROUND_TO_NEG_INF"),
+            Ceil => Some("_MM_FROUND_TO_POS_INF"),
+            Trunc => Some("_MM_FROUND_TO_ZERO"),
+            _ => None,
+        };
+        match (i, self.intrinsics(elem)) {
+            (Sqrt, Some(prefix)) => {
+                let body = format!("{}_sqrt_{}(({})a)", prefix, p, cast);
+                self.vector_call("sqrt", value, args, body)
+            }
+            (Floor | Ceil | Trunc, Some(prefix)) => {
+                let round = if prefix == "_mm512" {
+                    "roundscale"
+                } else {
+                    "round"
+                };
+                let mode = round_mode.unwrap_or_default();
+                let body = format!(
+                    "{}_{}_{}(({})a, {} | _MM_FROUND_NO_EXC)",
+                    prefix, round, p, cast, mode
+                );
+                self.vector_call(i.name(), value, args, body)
+            }
+            (MulAdd, Some(prefix)) if self.options.flavour != Flavour::Sse => {
+                let body = format!("{}_fmadd_{}(({c})a, ({c})b, ({c})c)", prefix, p, c = cast);
+                self.vector_call("mul_add", value, args, body)
+            }
+            // Rust's `mul_add` is fused, contraction is left to the C compiler.
+            (MulAdd, _) => {
+                let a = if self.is_uniform(args[1]) {
+                    self.vector_operand(args[0], 13)?
+                } else {
+                    self.operand(args[0], 13)?
+                };
+                let b = self.operand(args[1], 14)?;
+                let c = self.operand(args[2], 13)?;
+                Ok((format!("{} * {} + {}", a, b, c), 12))
+            }
+            (Abs, _) => self.lanes_call("fabs", value, args),
+            (
+                Sqrt | Cbrt | Sin | Cos | Tan | Asin | Acos | Atan | Atan2 | Sinh | Cosh | Tanh,
+                _,
+            )
+            | (Asinh | Acosh | Atanh | Exp | Exp2 | Log2 | Log10 | Round | Floor | Ceil, _)
+            | (Trunc | Copysign | Hypot, _) => self.lanes_call(i.name(), value, args),
+            (ExpM1, _) => self.lanes_call("expm1", value, args),
+            (Ln, _) => self.lanes_call("log", value, args),
+            (Ln1p, _) => self.lanes_call("log1p", value, args),
+            (Powf, _) => self.lanes_call("pow", value, args),
+            (Min, _) => self.lanes_call("fmin", value, args),
+            (Max, _) => self.lanes_call("fmax", value, args),
+            (Recip, _) => Ok((format!("{} / {}", one, self.operand(args[0], 14)?), 13)),
+            (Log, _) => {
+                let log = libm_name("log", elem);
+                let expr = format!("{}(a[i]) / {}(b[i])", log, log);
+                let name = self.lanes_helper("log", value, args, &expr)?;
+                Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+            }
+            (Powi, _) => {
+                let expr = format!(
+                    "{}(a[i], ({})b[i])",
+                    libm_name("pow", elem),
+                    scalar_type(elem)
+                );
+                let name = self.lanes_helper("powi", value, args, &expr)?;
+                Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+            }
+            (Fract, _) => {
+                let expr = format!("a[i] - {}(a[i])", libm_name("trunc", elem));
+                let name = self.lanes_helper("fract", value, args, &expr)?;
+                Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+            }
+            (Signum, _) => {
+                let expr = format!("{}({}, a[i])", libm_name("copysign", elem), one);
+                let name = self.lanes_helper("signum", value, args, &expr)?;
+                Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+            }
+            (IsNan, _) => {
+                let a = self.operand(args[0], 10)?;
+                Ok((format!("{} != {}", a, a), 9))
+            }
+            (IsInfinite | IsFinite | IsSignNegative | IsSignPositive, _) => {
+                let test = match i {
+                    IsInfinite => "isinf(a[i])",
+                    IsFinite => "isfinite(a[i])",
+                    IsSignNegative => "signbit(a[i])",
+                    _ => "!signbit(a[i])",
+                };
+                let expr = format!("{} ? -1 : 0", test);
+                let name = self.lanes_helper(i.name(), value, args, &expr)?;
+                Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+            }
+            _ => Err(self.error(&format!("`{}` is not supported", i.name()))),
+        }
+    }
+
+    fn int_intrinsic(
+        &mut self,
+        value: Value,
+        i: Intrinsic,
+        args: &[Value],
+        elem: Scalar,
+    ) -> Result<(String, u8)> {
+        use Intrinsic::*;
+        let ty = self.vector(elem);
+        let uty = self.vector(Scalar::uint(elem.num_bits()).unwrap_or(Scalar::U64));
+        // Signed vector arithmetic is done unsigned so that it wraps.
+        let wrapping = |this: &mut Self, op: &str| -> Result<(String, u8)> {
+            let a = this.vector_operand(args[0], PREC_UNARY)?;
+            let b = this.vector_operand(args[1], PREC_UNARY)?;
+            if elem.is_signed() {
+                Ok((
+                    format!("({})(({}){} {} ({}){})", ty, uty, a, op, uty, b),
+                    PREC_UNARY,
+                ))
+            } else {
+                Ok((
+                    format!("{} {} {}", a, op, b),
+                    if op == "*" { 13 } else { 12 },
+                ))
+            }
+        };
+        let suffix = if elem.num_bits() > 32 { "ll" } else { "" };
+        let uscalar = scalar_type(Scalar::uint(elem.num_bits()).unwrap_or(Scalar::U64));
+        let expr = match i {
+            WrappingAdd => return wrapping(self, "+"),
+            WrappingSub => return wrapping(self, "-"),
+            WrappingMul => return wrapping(self, "*"),
+            WrappingNeg => {
+                let a = self.vector_operand(args[0], PREC_UNARY)?;
+                return Ok((format!("({})-({}){}", ty, uty, a), PREC_UNARY));
+            }
+            Abs => "a[i] < 0 ? -a[i] : a[i]".to_string(),
+            Min => "a[i] < b[i] ? a[i] : b[i]".to_string(),
+            Max => "a[i] > b[i] ? a[i] : b[i]".to_string(),
+            CountOnes => format!("__builtin_popcount{}(({})a[i])", suffix, uscalar),
+            LeadingZeros | TrailingZeros => {
+                let name = if i == LeadingZeros { "clz" } else { "ctz" };
+                format!(
+                    "a[i] ? __builtin_{}{}(({})a[i]) : {}",
+                    name,
+                    suffix,
+                    uscalar,
+                    elem.num_bits()
+                )
+            }
+            _ => return Err(self.error(&format!("`{}` is not supported for integers", i.name()))),
+        };
+        let name = self.lanes_helper(i.name(), value, args, &expr)?;
+        Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+    }
+
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.text, "    {}", line);
+    }
+
+    // Print the values that are not inline, both branches of an `if` first.
+    fn body(&mut self, body: &Body) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            if self.func.is_inline(value, &self.use_counts)
+                || self.use_counts[value.0] == 0 && !matches!(inst.op, Op::Call(..))
+            {
+                continue;
+            }
+            if let Op::If(_, then_body, else_body) = &inst.op {
+                self.body(then_body)?;
+                self.body(else_body)?;
+            }
+            let (expr, _) = match &inst.op {
+                Op::Lit(_) | Op::Const(_) => {
+                    let (text, _) = self.op(inst)?;
+                    let ty = self.c_type(value)?;
+                    (splat(&ty, &text, self.options.lanes), PREC_ATOM)
+                }
+                _ => self.op(inst)?,
+            };
+            if self.use_counts[value.0] == 0 {
+                self.line(&format!("{};", expr));
+            } else {
+                let ty = self.c_type(value)?;
+                let name = self.name(value);
+                self.line(&format!("const {} {} = {};", ty, name, expr));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn vector_suffix(scalar: Scalar, lanes: usize) -> String {
+    format!("{}x{}", scalar.name(), lanes)
+}
+
+fn vector_name(options: &Options, scalar: Scalar) -> String {
+    format!("{}{}", options.prefix, vector_suffix(scalar, options.lanes))
+}
+
+fn tuple_name(options: &Options, scalars: &[Scalar]) -> String {
+    let names = scalars
+        .iter()
+        .map(|s| vector_suffix(*s, options.lanes))
+        .collect::<Vec<_>>();
+    format!("{}tuple_{}", options.prefix, names.join("_"))
+}
+
+// A vector with every lane set to a scalar.
+fn splat(ty: &str, scalar: &str, lanes: usize) -> String {
+    format!("({}){{{}}}", ty, vec![scalar; lanes].join(", "))
+}
+
+fn ps_pd(elem: Scalar) -> &'static str {
+    if elem == Scalar::F32 {
+        "ps"
+    } else {
+        "pd"
+    }
+}
+
+fn vector_letter(elem: Scalar) -> &'static str {
+    if elem == Scalar::F32 {
+        ""
+    } else {
+        "d"
+    }
+}
+
+// The x86 type of a float vector, eg. `__m256d`.
+fn intrinsic_type(elem: Scalar, options: &Options) -> String {
+    let bits = elem.num_bits() * options.lanes;
+    format!("__m{}{}", bits, vector_letter(elem))
+}
+
+fn print_fn(func: &Function, options: &Options, decls: &mut Decls) -> Result<(String, String)> {
+    let mut printer = Printer::new(func, options, decls);
+    let params = func
+        .params
+        .iter()
+        .map(|p| Ok(format!("{} {}", printer.c_type(*p)?, printer.name(*p))))
+        .collect::<Result<Vec<_>>>()?;
+    let params = if params.is_empty() {
+        "void".to_string()
+    } else {
+        params.join(", ")
+    };
+    let ret = printer.ty(&func.ret)?;
+    printer.body(&func.body)?;
+    let result = printer.vector_operand(func.body.result, 0)?;
+    printer.line(&format!("return {};", result));
+
+    let signature = format!("{} {}{}({})", ret, options.prefix, func.name, params);
+    let mut text = String::new();
+    docs(&mut text, func);
+    let _ = writeln!(text, "{} {{", signature);
+    text.push_str(&printer.text);
+    text.push_str("}\n");
+    Ok((signature, text))
+}
+
+// A function with a vector function ABI name that calls `func`.
+fn print_vector_abi(func: &Function, options: &Options, scalar_prefix: &str) -> Option<String> {
+    let (bits, _) = options.flavour.vector()?;
+    let isa = options.flavour.isa()?;
+    let vector = |ty: &Ty| match ty {
+        Ty::Scalar(scalar) if *scalar != Scalar::Bool && scalar.num_bits() * options.lanes == bits => {
+            Some(vector_name(options, *scalar))
+        }
+        _ => None,
+    };
+    let ret = vector(&func.ret)?;
+    let mut params = Vec::new();
+    let mut args = Vec::new();
+    for (i, param) in func.params.iter().enumerate() {
+        params.push(format!("{} x{}", vector(func.ty(*param))?, i));
+        args.push(format!("x{}", i));
+    }
+    let name = format!(
+        "_ZGV{}N{}{}_{}{}",
+        isa,
+        options.lanes,
+        "v".repeat(params.len()),
+        scalar_prefix,
+        func.name
+    );
+    Some(format!(
+        "{} {}({}) {{\n    return {}{}({});\n}}\n",
+        ret,
+        name,
+        params.join(", "),
+        options.prefix,
+        func.name,
+        args.join(", ")
+    ))
+}
+
+/// Translate a module of the IR into C with vector extensions.
+pub fn module_to_c_vector(module: &Module, options: &Options) -> Result<String> {
+    if !options.lanes.is_power_of_two() {
+        return Err(Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation("generating C vectors")
+            .with_message(format!("{} lanes is not a power of two", options.lanes)));
+    }
+    if options.vector_abi.is_some() && options.flavour == Flavour::Generic {
+        return Err(Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation("generating C vectors")
+            .with_message("the vector function ABI needs a flavour other than generic"));
+    }
+    let mut decls = Decls::default();
+    let mut functions = Vec::new();
+    for func in &module.functions {
+        functions.push(print_fn(func, options, &mut decls)?);
+    }
+
+    let mut text = String::new();
+    for header in &["float.h", "math.h", "stddef.h", "stdint.h"] {
+        let _ = writeln!(text, "#include <{}>", header);
+    }
+    if options.flavour != Flavour::Generic {
+        text.push_str("#include <immintrin.h>\n");
+    }
+    text.push('\n');
+    for scalar in &decls.vectors {
+        let _ = writeln!(
+            text,
+            "typedef {} {} __attribute__((vector_size({})));",
+            scalar_type(*scalar),
+            vector_name(options, *scalar),
+            scalar.num_bits() / 8 * options.lanes
+        );
+    }
+    text.push('\n');
+    for scalars in &decls.tuples {
+        text.push_str("typedef struct {\n");
+        for (i, scalar) in scalars.iter().enumerate() {
+            let _ = writeln!(text, "    {} _{};", vector_name(options, *scalar), i);
+        }
+        let _ = writeln!(text, "}} {};\n", tuple_name(options, scalars));
+    }
+    let consts = const_defines(module, &options.prefix)?;
+    if !consts.is_empty() {
+        text.push_str(&consts);
+        text.push('\n');
+    }
+    for helper in decls.helpers.values() {
+        text.push_str(helper);
+        text.push('\n');
+    }
+    for (signature, _) in &functions {
+        let _ = writeln!(text, "{};", signature);
+    }
+    for (_, func) in functions {
+        text.push('\n');
+        text.push_str(&func);
+    }
+    if let Some(scalar_prefix) = &options.vector_abi {
+        for func in &module.functions {
+            if let Some(variant) = print_vector_abi(func, options, scalar_prefix) {
+                text.push('\n');
+                text.push_str(&variant);
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// Translate a Rust file into C with vector extensions.
+pub fn to_c_vector(file: &syn::File, options: Options) -> Result<String> {
+    module_to_c_vector(&lower_file(file)?, &options)
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            if x < 0.0 {
+                let y = x * 2.0;
+                y.mul_add(x, -1.0)
+            } else {
+                x.sqrt()
+            }
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        lanes: 8,
+        ..Options::default()
+    };
+    let c = to_c_vector(&code, options).unwrap();
+    assert!(c.contains("typedef int32_t ds_i32x8 __attribute__((vector_size(32)));\n"));
+    assert!(c.contains("typedef float ds_f32x8 __attribute__((vector_size(32)));\n"));
+    assert!(c.contains("    return (ds_f32x8)(((ds_i32x8)a & m) | ((ds_i32x8)b & ~m));\n"));
+    assert!(c.contains("        r[i] = sqrtf(a[i]);\n"));
+    assert!(c.contains("ds_f32x8 ds_f(ds_f32x8 x) {\n    const ds_f32x8 y = x * 2.0f;\n"));
+    assert!(c.contains("ds_select_f32x8(x < 0.0f, y * x + -1.0f, ds_sqrt_f32x8(x));\n"));
+}
+
+#[test]
+fn test_flavours() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f64) -> f64 {
+            if x.is_nan() { 1.0 } else { x.sqrt().mul_add(x, 2.0) }
+        }
+    };
+    let options = Options {
+        flavour: Flavour::Avx2,
+        ..Options::default()
+    };
+    let c = to_c_vector(&code, options).unwrap();
+    assert!(c.contains("#include <immintrin.h>\n"));
+    assert!(c.contains("    return (f64x4)_mm256_blendv_pd((__m256d)b, (__m256d)a, (__m256d)m);\n"));
+    assert!(c.contains("    return (f64x4)_mm256_sqrt_pd((__m256d)a);\n"));
+    assert!(c.contains("select_f64x4(x != x, (f64x4){1.0, 1.0, 1.0, 1.0}, mul_add_f64x4(sqrt_f64x4(x), x, (f64x4){2.0, 2.0, 2.0, 2.0}))"));
+
+    // Intrinsics are only used for vectors of the flavour's size.
+    let options = Options {
+        lanes: 8,
+        flavour: Flavour::Avx512,
+        ..Options::default()
+    };
+    let c = to_c_vector(&code, options).unwrap();
+    assert!(c.contains("_mm512_mask_blend_pd(_mm512_movepi64_mask((__m512i)m), (__m512d)b, (__m512d)a)"));
+    let options = Options {
+        lanes: 2,
+        flavour: Flavour::Avx512,
+        ..Options::default()
+    };
+    let c = to_c_vector(&code, options).unwrap();
+    assert!(!c.contains("_mm"));
+
+    let options = Options {
+        lanes: 3,
+        ..Options::default()
+    };
+    let e = to_c_vector(&code, options).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnsupportedCodegen);
+}
+
+#[test]
+fn test_vector_abi() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f64, y: f64) -> f64 {
+            x * y
+        }
+        fn g(x: f64, i: u32) -> f64 {
+            x * i as f64
+        }
+    };
+    let options = Options {
+        prefix: "ds64x4_".to_string(),
+        flavour: Flavour::Avx2,
+        vector_abi: Some("ds64_".to_string()),
+        ..Options::default()
+    };
+    let c = to_c_vector(&code, options).unwrap();
+    assert!(c.contains("ds64x4_f64x4 _ZGVdN4vv_ds64_f(ds64x4_f64x4 x0, ds64x4_f64x4 x1) {\n    return ds64x4_f(x0, x1);\n}\n"));
+    // `u32x4` is not a 256 bit vector.
+    assert!(!c.contains("_ds64_g"));
+
+    let options = Options {
+        vector_abi: Some("ds64_".to_string()),
+        ..Options::default()
+    };
+    assert!(to_c_vector(&code, options).is_err());
+}
@@ -0,0 +1,1069 @@
+//! Translate Rust functions into a header-only C++17 library.
+//!
+//! The f32 and f64 versions of a module become one set of templates on
+//! `V`, which is `float`, `double` or a `std::experimental::simd` of them.
+//! Each template picks the body for the size of its float with `if constexpr`
+//! and the public functions are overloaded for scalars and simd types.
+
+use super::c::{binary_prec, float_literal, int_literal, scalar_type, PREC_ATOM, PREC_TERNARY, PREC_UNARY};
+use super::fortran::sorted_consts;
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Special,
+    Ty, UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+pub struct Options {
+    /// The namespace of the library, which also names the include guard.
+    pub namespace: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            namespace: "doctor_syn".to_string(),
+        }
+    }
+}
+
+// C++ keywords, macros and names used by the generated code that a Rust
+// binding could shadow.
+const RESERVED: &[&str] = &[
+    "alignas", "alignof", "and", "and_eq", "asm", "auto", "bitand", "bitor", "bool", "break",
+    "case", "catch", "char", "class", "compl", "concept", "const", "constexpr", "const_cast",
+    "continue", "decltype", "default", "delete", "do", "double", "dynamic_cast", "else", "enum",
+    "explicit", "export", "extern", "false", "float", "for", "friend", "goto", "if", "inline",
+    "int", "long", "mutable", "namespace", "new", "noexcept", "not", "not_eq", "nullptr",
+    "operator", "or", "or_eq", "private", "protected", "public", "register", "reinterpret_cast",
+    "requires", "return", "short", "signed", "sizeof", "static", "static_assert", "static_cast",
+    "struct", "switch", "template", "this", "thread_local", "throw", "true", "try", "typedef",
+    "typeid", "typename", "union", "unsigned", "using", "virtual", "void", "volatile", "while",
+    "xor", "xor_eq", "INFINITY", "NAN", "HUGE_VAL", "assert", "errno", "std", "stdx", "detail",
+    "impl", "T", "V", "U", "I", "M", "i8", "i16", "i32", "i64", "isize", "u8", "u16", "u32",
+    "u64", "usize", "f32", "f64",
+];
+
+// Maths functions which return masks rather than floats.
+const CLASSIFY: &[&str] = &["isnan", "isinf", "isfinite", "signbit"];
+
+// Traits and helpers which work for scalars and simd types. `$SIMD` is
+// defined if `std::experimental::simd` is available.
+const SUPPORT: &str = "// The lane type of V, V with lanes of U and the type of comparisons of V.
+template <typename V>
+struct traits {
+    using scalar = V;
+    template <typename U>
+    using rebind = U;
+    using mask = bool;
+};
+
+#ifdef $SIMD
+template <typename T, typename Abi>
+struct traits<stdx::simd<T, Abi>> {
+    using scalar = T;
+    template <typename U>
+    using rebind = stdx::rebind_simd_t<U, stdx::simd<T, Abi>>;
+    using mask = stdx::simd_mask<T, Abi>;
+};
+#endif
+
+template <typename V>
+using scalar_t = typename traits<V>::scalar;
+template <typename U, typename V>
+using rebind_t = typename traits<V>::template rebind<U>;
+template <typename V>
+using mask_t = typename traits<V>::mask;
+
+// Integers with lanes the size of those of V.
+template <typename V>
+using int_v = rebind_t<std::conditional_t<sizeof(scalar_t<V>) == 4, std::int32_t, std::int64_t>, V>;
+template <typename V>
+using uint_v = rebind_t<std::conditional_t<sizeof(scalar_t<V>) == 4, std::uint32_t, std::uint64_t>, V>;
+
+template <typename T>
+using if_float = std::enable_if_t<std::is_same_v<T, float> || std::is_same_v<T, double>, int>;
+
+template <typename To, typename From>
+constexpr To convert(const From &x) {
+    return static_cast<To>(x);
+}
+
+template <typename To, typename From>
+To bit_cast(const From &x) {
+#if __cpp_lib_bit_cast
+    return std::bit_cast<To>(x);
+#else
+    To y;
+    std::memcpy(static_cast<void *>(&y), &x, sizeof(y));
+    return y;
+#endif
+}
+
+template <typename R, typename A, typename B>
+constexpr R select(bool c, const A &a, const B &b) {
+    return c ? R(a) : R(b);
+}
+
+// Only the branch taken is evaluated.
+template <typename R, typename A, typename B>
+R if_else(bool c, const A &a, const B &b) {
+    return c ? a() : b();
+}
+
+#ifdef $SIMD
+template <typename To, typename T, typename Abi>
+To convert(const stdx::simd<T, Abi> &x) {
+    return stdx::static_simd_cast<To>(x);
+}
+
+template <typename R, typename T, typename Abi, typename A, typename B>
+R select(const stdx::simd_mask<T, Abi> &c, const A &a, const B &b) {
+    using M = typename R::mask_type;
+    R r(b);
+    if constexpr (std::is_same_v<M, stdx::simd_mask<T, Abi>>) {
+        where(c, r) = R(a);
+    } else {
+        M m(false);
+        for (std::size_t i = 0; i < c.size(); ++i) {
+            m[i] = c[i];
+        }
+        where(m, r) = R(a);
+    }
+    return r;
+}
+
+// Both branches are evaluated and the lanes of each are selected.
+template <typename R, typename T, typename Abi, typename A, typename B>
+R if_else(const stdx::simd_mask<T, Abi> &c, const A &a, const B &b) {
+    return select<R>(c, a(), b());
+}
+#endif
+
+// A polynomial in x with coefficients from the highest power down.
+template <typename V, typename T, std::size_t N>
+V horner(const V &x, const T (&c)[N]) {
+    V r = c[0];
+    for (std::size_t i = 1; i < N; ++i) {
+        r = r * x + c[i];
+    }
+    return r;
+}
+";
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    // Maths functions from <cmath> and <experimental/simd> and their arity.
+    maths: BTreeSet<(&'static str, usize)>,
+}
+
+// The C++ type of a scalar in the library's interface.
+fn std_type(scalar: Scalar) -> String {
+    match scalar {
+        Scalar::Bool | Scalar::F32 | Scalar::F64 => scalar_type(scalar).to_string(),
+        _ => format!("std::{}", scalar_type(scalar)),
+    }
+}
+
+// The type of a parameter or result in terms of the float type `v`,
+// or `None` if the f32 and f64 types do not correspond.
+fn generic_type(t32: &Ty, t64: &Ty, v: &str) -> Option<String> {
+    use Scalar::*;
+    match (t32, t64) {
+        (Ty::Scalar(a), Ty::Scalar(b)) => Some(match (a, b) {
+            (F32, F64) => v.to_string(),
+            (I32, I64) => format!("detail::int_v<{}>", v),
+            (U32, U64) => format!("detail::uint_v<{}>", v),
+            (Bool, Bool) => format!("detail::mask_t<{}>", v),
+            (a, b) if a == b && v == "T" => std_type(*a),
+            (a, b) if a == b => format!("detail::rebind_t<{}, {}>", std_type(*a), v),
+            _ => return None,
+        }),
+        (Ty::Tuple(a), Ty::Tuple(b)) if a.len() == b.len() => {
+            let elems = a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| generic_type(a, b, v))
+                .collect::<Option<Vec<_>>>()?;
+            Some(format!("std::tuple<{}>", elems.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+// The declaration of a type alias used in a function body.
+fn alias(name: &str) -> String {
+    let ty = match name {
+        "T" => "detail::scalar_t<V>".to_string(),
+        "U" => "detail::uint_v<V>".to_string(),
+        "I" => "detail::int_v<V>".to_string(),
+        "M" => "detail::mask_t<V>".to_string(),
+        _ => {
+            let scalar = Scalar::from_name(name).unwrap_or(Scalar::U64);
+            format!("detail::rebind_t<{}, V>", std_type(scalar))
+        }
+    };
+    format!("using {} = {};", name, ty)
+}
+
+fn cpp_name(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    options: &'a Options,
+    // The size of the float type, 32 or 64.
+    bits: usize,
+    // Constants have the float type `T` and no other types.
+    is_const: bool,
+    use_counts: Vec<usize>,
+    decls: &'a mut Decls,
+    // Type aliases used by the body.
+    aliases: BTreeSet<String>,
+    // Lines of coefficient tables to print before the next statement.
+    tables: Vec<String>,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(func: &'a Function, bits: usize, options: &'a Options, decls: &'a mut Decls) -> Self {
+        Printer {
+            func,
+            options,
+            bits,
+            is_const: false,
+            use_counts: func.use_counts(),
+            decls,
+            aliases: BTreeSet::new(),
+            tables: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating C++ for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            ty => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    // The float type of the lanes.
+    fn t(&mut self) -> &'static str {
+        if !self.is_const {
+            self.aliases.insert("T".to_string());
+        }
+        "T"
+    }
+
+    // A scalar type as the type with the lanes of `V`.
+    fn type_name(&mut self, scalar: Scalar) -> Result<String> {
+        use Scalar::*;
+        let float = Scalar::float(self.bits).unwrap_or(F64);
+        let name = match scalar {
+            s if s == float && self.is_const => return Ok(self.t().to_string()),
+            s if s == float => return Ok("V".to_string()),
+            _ if self.is_const => return Err(self.error("constants must be plain expressions")),
+            I32 | I64 if scalar.num_bits() == self.bits => "I",
+            U32 | U64 if scalar.num_bits() == self.bits => "U",
+            Bool => "M",
+            s => s.name(),
+        };
+        self.aliases.insert(name.to_string());
+        Ok(name.to_string())
+    }
+
+    fn cpp_type(&mut self, ty: &Ty) -> Result<String> {
+        match ty {
+            Ty::Scalar(scalar) => self.type_name(*scalar),
+            Ty::Tuple(elems) => {
+                let elems = elems
+                    .iter()
+                    .map(|e| self.cpp_type(e))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(format!("std::tuple<{}>", elems.join(", ")))
+            }
+            _ => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    fn name(&self, value: Value) -> String {
+        cpp_name(&self.func.name(value))
+    }
+
+    fn operand(&mut self, value: Value, prec: u8) -> Result<String> {
+        let (text, p) = self.expr(value)?;
+        Ok(if p < prec { format!("({})", text) } else { text })
+    }
+
+    fn args(&mut self, args: &[Value]) -> Result<String> {
+        let args = args
+            .iter()
+            .map(|a| self.operand(*a, PREC_TERNARY))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(args.join(", "))
+    }
+
+    // True if a value is printed at its use.
+    fn is_inline(&self, value: Value) -> bool {
+        match self.func.inst(value) {
+            Some(Inst {
+                op: Op::If(_, then_body, else_body),
+                ..
+            }) => {
+                self.func.values[value.0].name.is_none()
+                    && self.use_counts[value.0] == 1
+                    && self.is_simple(then_body)
+                    && self.is_simple(else_body)
+            }
+            _ => self.func.is_inline(value, &self.use_counts),
+        }
+    }
+
+    // A value as an expression and its precedence.
+    fn expr(&mut self, value: Value) -> Result<(String, u8)> {
+        match self.func.inst(value) {
+            Some(inst) if self.is_inline(value) => self.op(inst),
+            _ => Ok((self.name(value), PREC_ATOM)),
+        }
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<(String, u8)> {
+        // Constants are initialised at compile time.
+        if self.is_const && matches!(inst.op, Op::Call(..) | Op::Intrinsic(..) | Op::Bitcast(_)) {
+            return Err(self.error("constants must be plain expressions"));
+        }
+        // Operations whose values need not be scalars.
+        match &inst.op {
+            Op::Call(name, args) => {
+                let args = self.args(args)?;
+                return Ok((format!("impl::{}<V>({})", cpp_name(name), args), PREC_ATOM));
+            }
+            Op::Tuple(args) => {
+                let ty = self.cpp_type(self.func.ty(inst.value))?;
+                return Ok((format!("{}({})", ty, self.args(args)?), PREC_ATOM));
+            }
+            Op::Extract(a, i) => {
+                let a = self.args(&[*a])?;
+                return Ok((format!("std::get<{}>({})", i, a), PREC_ATOM));
+            }
+            _ => (),
+        }
+        let scalar = self.scalar(inst.value)?;
+        Ok(match &inst.op {
+            Op::Lit(Literal::Float(digits)) => (float_literal(digits, scalar), PREC_ATOM),
+            Op::Lit(Literal::Int(i)) => (int_literal(*i, scalar), PREC_ATOM),
+            // Masks are not made from bools implicitly.
+            Op::Lit(Literal::Bool(b)) => (format!("{}({})", self.type_name(scalar)?, b), PREC_ATOM),
+            Op::Lit(Literal::Special(s)) => self.special(*s, scalar),
+            Op::Const(name) => {
+                let t = self.t();
+                let text = format!("{}::{}<{}>", self.options.namespace, cpp_name(name), t);
+                (text, PREC_ATOM)
+            }
+            Op::Unary(op, a) => {
+                let sym = match (op, self.scalar(*a)?) {
+                    (UnaryOp::Neg, _) => "-",
+                    (UnaryOp::Not, Scalar::Bool) => "!",
+                    (UnaryOp::Not, _) => "~",
+                };
+                (format!("{}{}", sym, self.operand(*a, PREC_UNARY)?), PREC_UNARY)
+            }
+            Op::Binary(BinaryOp::Rem, a, b) if scalar.is_float() => self.call("fmod", &[*a, *b])?,
+            Op::Binary(op, a, b) => {
+                use BinaryOp::*;
+                // Parenthesise operands of bitwise operators as `-Wparentheses` asks.
+                let prec = binary_prec(*op);
+                let min = match op {
+                    Shl | Shr | BitAnd | BitXor | BitOr | And | Or => PREC_UNARY,
+                    _ => prec,
+                };
+                let a = self.operand(*a, min)?;
+                let b = self.operand(*b, min.max(prec + 1))?;
+                (format!("{} {} {}", a, op.symbol(), b), prec)
+            }
+            Op::Convert(a) => self.convert(scalar, *a)?,
+            Op::Bitcast(a) => {
+                let ty = self.type_name(scalar)?;
+                (format!("detail::bit_cast<{}>({})", ty, self.args(&[*a])?), PREC_ATOM)
+            }
+            Op::Select(c, a, b) => self.select(scalar, *c, *a, *b)?,
+            Op::If(c, then_body, else_body) => {
+                self.select(scalar, *c, then_body.result, else_body.result)?
+            }
+            Op::Intrinsic(Intrinsic::MulAdd, args) => match self.polynomial(inst.value, args)? {
+                Some(horner) => horner,
+                None => self.intrinsic(Intrinsic::MulAdd, args)?,
+            },
+            Op::Intrinsic(i, args) => self.intrinsic(*i, args)?,
+            Op::Splat(_) => return Err(self.error("vectors are not supported")),
+            Op::Call(..) | Op::Tuple(_) | Op::Extract(..) => unreachable!("printed above"),
+        })
+    }
+
+    fn special(&mut self, special: Special, scalar: Scalar) -> (String, u8) {
+        let t = if scalar.num_bits() == self.bits {
+            self.t()
+        } else {
+            scalar_type(scalar)
+        };
+        let limit = |name: &str| format!("std::numeric_limits<{}>::{}()", t, name);
+        match special {
+            Special::Nan => (limit("quiet_NaN"), PREC_ATOM),
+            Special::Infinity => (limit("infinity"), PREC_ATOM),
+            Special::NegInfinity => (format!("-{}", limit("infinity")), PREC_UNARY),
+            Special::MinPositive => (limit("min"), PREC_ATOM),
+            Special::Max => (limit("max"), PREC_ATOM),
+            Special::Min => (limit("lowest"), PREC_ATOM),
+            Special::Epsilon => (limit("epsilon"), PREC_ATOM),
+        }
+    }
+
+    fn convert(&mut self, to: Scalar, a: Value) -> Result<(String, u8)> {
+        let from = self.scalar(a)?;
+        if from == to {
+            return self.expr(a);
+        }
+        if to == Scalar::Bool {
+            return Err(self.error("conversions to bool are not supported"));
+        }
+        let ty = self.type_name(to)?;
+        let x = self.args(&[a])?;
+        if from == Scalar::Bool {
+            return Ok((format!("detail::select<{}>({}, 1, 0)", ty, x), PREC_ATOM));
+        }
+        Ok((format!("detail::convert<{}>({})", ty, x), PREC_ATOM))
+    }
+
+    fn select(&mut self, scalar: Scalar, c: Value, a: Value, b: Value) -> Result<(String, u8)> {
+        let ty = self.type_name(scalar)?;
+        Ok((format!("detail::select<{}>({})", ty, self.args(&[c, a, b])?), PREC_ATOM))
+    }
+
+    // A maths function of the type of the first argument.
+    fn call(&mut self, name: &'static str, args: &[Value]) -> Result<(String, u8)> {
+        let ty = self.type_name(self.scalar(args[0])?)?;
+        self.decls.maths.insert((name, args.len()));
+        Ok((format!("detail::{}<{}>({})", name, ty, self.args(args)?), PREC_ATOM))
+    }
+
+    // True if a value is a coefficient of a polynomial in the float type.
+    fn is_coefficient(&self, value: Value) -> bool {
+        let is_float = matches!(self.func.ty(value), Ty::Scalar(s) if s.is_float() && s.num_bits() == self.bits);
+        is_float
+            && self.is_inline(value)
+            && match self.func.inst(value).map(|i| &i.op) {
+                Some(Op::Lit(Literal::Float(_))) | Some(Op::Lit(Literal::Int(_))) => true,
+                Some(Op::Const(_)) => true,
+                Some(Op::Unary(UnaryOp::Neg, a)) => self.is_coefficient(*a),
+                _ => false,
+            }
+    }
+
+    // A chain of `mul_add`s of the same `x` with constant addends, as a call of
+    // `horner` with a table of coefficients.
+    fn polynomial(&mut self, value: Value, args: &[Value]) -> Result<Option<(String, u8)>> {
+        let mut coeffs = vec![args[2]];
+        let mut xs = vec![args[1]];
+        let mut a = args[0];
+        while let Some(Inst {
+            op: Op::Intrinsic(Intrinsic::MulAdd, inner),
+            ..
+        }) = self.func.inst(a)
+        {
+            if !self.is_inline(a) {
+                break;
+            }
+            coeffs.push(inner[2]);
+            xs.push(inner[1]);
+            a = inner[0];
+        }
+        coeffs.push(a);
+        if coeffs.len() < 4 || !coeffs.iter().all(|c| self.is_coefficient(*c)) {
+            return Ok(None);
+        }
+        let x = self.operand(xs[0], PREC_TERNARY)?;
+        for other in &xs[1..] {
+            if self.operand(*other, PREC_TERNARY)? != x {
+                return Ok(None);
+            }
+        }
+        let table = format!("poly{}", value.0);
+        let t = self.t();
+        self.tables.push(format!("static constexpr {} {}[] = {{", t, table));
+        for c in coeffs.iter().rev() {
+            let c = self.operand(*c, PREC_TERNARY)?;
+            self.tables.push(format!("    {},", c));
+        }
+        self.tables.push("};".to_string());
+        Ok(Some((format!("detail::horner({}, {})", x, table), PREC_ATOM)))
+    }
+
+    fn intrinsic(&mut self, i: Intrinsic, args: &[Value]) -> Result<(String, u8)> {
+        use Intrinsic::*;
+        let arg_scalar = self.scalar(args[0])?;
+        let one = float_literal("1.0", arg_scalar);
+        if arg_scalar.is_int() {
+            let ty = self.type_name(arg_scalar)?;
+            let uty = self.type_name(Scalar::uint(arg_scalar.num_bits()).unwrap_or(Scalar::U64))?;
+            // Signed overflow is undefined so wrap in unsigned integers.
+            let wrapping = |this: &mut Self, op: &str| -> Result<(String, u8)> {
+                let prec = if op == "*" { 13 } else { 12 };
+                if !arg_scalar.is_signed() {
+                    let a = this.operand(args[0], prec)?;
+                    let b = this.operand(args[1], prec + 1)?;
+                    return Ok((format!("{} {} {}", a, op, b), prec));
+                }
+                let a = this.args(&args[0..1])?;
+                let b = this.args(&args[1..2])?;
+                let text = format!(
+                    "detail::convert<{}>(detail::convert<{}>({}) {} detail::convert<{}>({}))",
+                    ty, uty, a, op, uty, b
+                );
+                Ok((text, PREC_ATOM))
+            };
+            return match i {
+                WrappingAdd => wrapping(self, "+"),
+                WrappingSub => wrapping(self, "-"),
+                WrappingMul => wrapping(self, "*"),
+                WrappingNeg if arg_scalar.is_signed() => {
+                    let a = self.args(args)?;
+                    let text = format!("detail::convert<{}>(-detail::convert<{}>({}))", ty, uty, a);
+                    Ok((text, PREC_ATOM))
+                }
+                WrappingNeg => Ok((format!("-{}", self.operand(args[0], PREC_UNARY)?), PREC_UNARY)),
+                Abs => {
+                    let a = self.operand(args[0], PREC_TERNARY)?;
+                    let neg = self.operand(args[0], PREC_UNARY)?;
+                    Ok((format!("detail::select<{}>({} < 0, -{}, {})", ty, a, neg, a), PREC_ATOM))
+                }
+                Min | Max => {
+                    let op = if i == Min { "<" } else { ">" };
+                    let a = self.operand(args[0], 11)?;
+                    let b = self.operand(args[1], 11)?;
+                    let text = format!("detail::select<{}>({} {} {}, {}, {})", ty, a, op, b, a, b);
+                    Ok((text, PREC_ATOM))
+                }
+                _ => Err(self.error(&format!("`{}` is not supported for integers", i.name()))),
+            };
+        }
+        let ty = self.type_name(arg_scalar)?;
+        match i {
+            Abs => self.call("fabs", args),
+            Sqrt => self.call("sqrt", args),
+            Cbrt => self.call("cbrt", args),
+            Sin => self.call("sin", args),
+            Cos => self.call("cos", args),
+            Tan => self.call("tan", args),
+            Asin => self.call("asin", args),
+            Acos => self.call("acos", args),
+            Atan => self.call("atan", args),
+            Atan2 => self.call("atan2", args),
+            Sinh => self.call("sinh", args),
+            Cosh => self.call("cosh", args),
+            Tanh => self.call("tanh", args),
+            Asinh => self.call("asinh", args),
+            Acosh => self.call("acosh", args),
+            Atanh => self.call("atanh", args),
+            Exp => self.call("exp", args),
+            Exp2 => self.call("exp2", args),
+            ExpM1 => self.call("expm1", args),
+            Ln => self.call("log", args),
+            Ln1p => self.call("log1p", args),
+            Log2 => self.call("log2", args),
+            Log10 => self.call("log10", args),
+            Round => self.call("round", args),
+            Floor => self.call("floor", args),
+            Ceil => self.call("ceil", args),
+            Trunc => self.call("trunc", args),
+            Copysign => self.call("copysign", args),
+            Hypot => self.call("hypot", args),
+            Powf => self.call("pow", args),
+            Min => self.call("fmin", args),
+            Max => self.call("fmax", args),
+            Log => {
+                let (x, _) = self.call("log", &args[0..1])?;
+                let (base, _) = self.call("log", &args[1..2])?;
+                Ok((format!("{} / {}", x, base), 13))
+            }
+            Powi => {
+                self.decls.maths.insert(("pow", 2));
+                let x = self.args(&args[0..1])?;
+                let n = self.args(&args[1..2])?;
+                let text = format!("detail::pow<{}>({}, detail::convert<{}>({}))", ty, x, ty, n);
+                Ok((text, PREC_ATOM))
+            }
+            Recip => {
+                let a = self.operand(args[0], 14)?;
+                Ok((format!("{} / {}", one, a), 13))
+            }
+            Fract => {
+                let a = self.operand(args[0], 13)?;
+                let (trunc, _) = self.call("trunc", &args[0..1])?;
+                Ok((format!("{} - {}", a, trunc), 12))
+            }
+            Signum => {
+                self.decls.maths.insert(("copysign", 2));
+                let a = self.args(&args[0..1])?;
+                Ok((format!("detail::copysign<{}>({}, {})", ty, one, a), PREC_ATOM))
+            }
+            // Rust's `mul_add` is fused, contraction is left to the C++ compiler.
+            MulAdd => {
+                let a = self.operand(args[0], 13)?;
+                let b = self.operand(args[1], 14)?;
+                let c = self.operand(args[2], 13)?;
+                Ok((format!("{} * {} + {}", a, b, c), 12))
+            }
+            IsNan => self.call("isnan", args),
+            IsInfinite => self.call("isinf", args),
+            IsFinite => self.call("isfinite", args),
+            IsSignNegative => self.call("signbit", args),
+            IsSignPositive => {
+                let (signbit, _) = self.call("signbit", args)?;
+                Ok((format!("!{}", signbit), PREC_UNARY))
+            }
+            _ => Err(self.error(&format!("`{}` is not supported", i.name()))),
+        }
+    }
+
+    fn line(&mut self, indent: usize, line: &str) {
+        let indent = "    ".repeat(indent);
+        for table in std::mem::take(&mut self.tables) {
+            let _ = writeln!(self.text, "{}{}", indent, table);
+        }
+        let _ = writeln!(self.text, "{}{}", indent, line);
+    }
+
+    // Print the materialised instructions of a body.
+    fn body(&mut self, body: &Body, indent: usize) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            if self.is_inline(value) {
+                continue;
+            }
+            let name = self.name(value);
+            let is_call = matches!(inst.op, Op::Call(..));
+            if self.use_counts[value.0] == 0 && !is_call {
+                continue;
+            }
+            let ty = self.cpp_type(self.func.ty(value))?;
+            match &inst.op {
+                // Lambdas keep scalar branches lazy.
+                Op::If(c, then_body, else_body)
+                    if !self.is_simple(then_body) || !self.is_simple(else_body) =>
+                {
+                    let (cond, _) = self.expr(*c)?;
+                    let line = format!(
+                        "const {} {} = detail::if_else<{}>({}, [&]() -> {} {{",
+                        ty, name, ty, cond, ty
+                    );
+                    self.line(indent, &line);
+                    self.branch(then_body, indent + 1)?;
+                    self.line(indent, &format!("}}, [&]() -> {} {{", ty));
+                    self.branch(else_body, indent + 1)?;
+                    self.line(indent, "});");
+                }
+                _ => {
+                    let (expr, _) = self.op(inst)?;
+                    if self.use_counts[value.0] == 0 {
+                        self.line(indent, &format!("{};", expr));
+                    } else {
+                        self.line(indent, &format!("const {} {} = {};", ty, name, expr));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn branch(&mut self, body: &Body, indent: usize) -> Result<()> {
+        self.body(body, indent)?;
+        let (result, _) = self.expr(body.result)?;
+        self.line(indent, &format!("return {};", result));
+        Ok(())
+    }
+
+    // True if a branch has no statements of its own.
+    fn is_simple(&self, body: &Body) -> bool {
+        body.insts.iter().all(|inst| self.is_inline(inst.value))
+    }
+}
+
+fn mismatch(name: &str) -> Error {
+    Error::new(ErrorKind::UnsupportedCodegen)
+        .with_operation(format!("generating C++ for {}", name))
+        .with_message("the f32 and f64 versions do not correspond")
+}
+
+// Docs of both versions, marking the lines that differ.
+fn docs(text: &mut String, f32: &Function, f64: &Function) {
+    for doc in &f32.docs {
+        if f64.docs.contains(doc) {
+            let _ = writeln!(text, "//{}", doc);
+        } else {
+            let _ = writeln!(text, "// f32:{}", doc);
+        }
+    }
+    for doc in f64.docs.iter().filter(|doc| !f32.docs.contains(doc)) {
+        let _ = writeln!(text, "// f64:{}", doc);
+    }
+}
+
+// A function body for one float size and the type aliases it uses.
+fn print_body(
+    func: &Function,
+    bits: usize,
+    indent: usize,
+    options: &Options,
+    decls: &mut Decls,
+) -> Result<(String, BTreeSet<String>)> {
+    let mut printer = Printer::new(func, bits, options, decls);
+    printer.body(&func.body, indent)?;
+    let (result, _) = printer.expr(func.body.result)?;
+    printer.line(indent, &format!("return {};", result));
+    Ok((printer.text, printer.aliases))
+}
+
+// The declaration and definition of a function template in `impl`.
+fn print_fn(
+    f32: &Function,
+    f64: &Function,
+    options: &Options,
+    decls: &mut Decls,
+) -> Result<(String, String)> {
+    if f32.param_names() != f64.param_names() {
+        return Err(mismatch(&f32.name));
+    }
+    let params = f32
+        .params
+        .iter()
+        .zip(&f64.params)
+        .map(|(a, b)| {
+            let ty = generic_type(f32.ty(*a), f64.ty(*b), "V")?;
+            Some(format!("{} {}", ty, cpp_name(&f32.name(*a))))
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| mismatch(&f32.name))?;
+    let ret = generic_type(&f32.ret, &f64.ret, "V").ok_or_else(|| mismatch(&f32.name))?;
+    let signature = format!(
+        "template <typename V>\n{} {}({})",
+        ret,
+        cpp_name(&f32.name),
+        params.join(", ")
+    );
+
+    let (text32, aliases32) = print_body(f32, 32, 2, options, decls)?;
+    let (text64, aliases64) = print_body(f64, 64, 2, options, decls)?;
+    let (body, mut aliases) = if text32 == text64 {
+        print_body(f64, 64, 1, options, decls)?
+    } else {
+        let body = format!(
+            "    if constexpr (std::is_same_v<T, float>) {{\n{}    }} else {{\n{}    }}\n",
+            text32, text64
+        );
+        let mut aliases = &aliases32 | &aliases64;
+        aliases.insert("T".to_string());
+        (body, aliases)
+    };
+
+    let mut text = String::new();
+    let _ = writeln!(text, "{} {{", signature);
+    for name in ["T", "U", "I", "M"] {
+        if aliases.remove(name) {
+            let _ = writeln!(text, "    {}", alias(name));
+        }
+    }
+    for name in &aliases {
+        let _ = writeln!(text, "    {}", alias(name));
+    }
+    text.push_str(&body);
+    text.push_str("}\n");
+    Ok((signature, text))
+}
+
+// The public overloads of a function for scalars and simd types.
+fn print_overloads(f32: &Function, f64: &Function) -> Result<(String, String)> {
+    let name = cpp_name(&f32.name);
+    let args = f32
+        .params
+        .iter()
+        .map(|p| cpp_name(&f32.name(*p)))
+        .collect::<Vec<_>>();
+    let overload = |v: &str| -> Result<String> {
+        let params = f32
+            .params
+            .iter()
+            .zip(&f64.params)
+            .zip(&args)
+            .map(|((a, b), arg)| Some(format!("{} {}", generic_type(f32.ty(*a), f64.ty(*b), v)?, arg)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| mismatch(&f32.name))?;
+        let ret = generic_type(&f32.ret, &f64.ret, v).ok_or_else(|| mismatch(&f32.name))?;
+        Ok(format!(
+            "{} {}({}) {{\n    return impl::{}<{}>({});\n}}\n",
+            ret,
+            name,
+            params.join(", "),
+            name,
+            v,
+            args.join(", ")
+        ))
+    };
+    let mut scalar = String::new();
+    docs(&mut scalar, f32, f64);
+    scalar.push_str("template <typename T, detail::if_float<T> = 0>\n");
+    scalar.push_str(&overload("T")?);
+    let mut simd = "template <typename T, typename Abi, detail::if_float<T> = 0>\n".to_string();
+    simd.push_str(&overload("stdx::simd<T, Abi>")?);
+    Ok((scalar, simd))
+}
+
+// A constant as a variable template on the float type.
+fn print_const(
+    f32: Option<&Function>,
+    f64: Option<&Function>,
+    options: &Options,
+    decls: &mut Decls,
+) -> Result<String> {
+    let mut exprs = Vec::new();
+    let mut funcs = Vec::new();
+    for (func, bits) in [(f32, 32), (f64, 64)] {
+        if let Some(func) = func {
+            let mut printer = Printer::new(func, bits, options, decls);
+            printer.is_const = true;
+            if !printer.is_simple(&func.body) {
+                return Err(printer.error("constants must be expressions"));
+            }
+            let (expr, _) = printer.expr(func.body.result)?;
+            exprs.push(expr);
+            funcs.push(func);
+        }
+    }
+    let mut text = String::new();
+    docs(&mut text, funcs[0], funcs[funcs.len() - 1]);
+    let name = cpp_name(&funcs[0].name);
+    text.push_str("template <typename T>\n");
+    if exprs.len() == 1 || exprs[0] == exprs[1] {
+        let _ = writeln!(text, "inline constexpr auto {} = {};", name, exprs[0]);
+    } else {
+        let _ = writeln!(
+            text,
+            "inline constexpr auto {} = [] {{\n    if constexpr (std::is_same_v<T, float>) {{\n        return {};\n    }} else {{\n        return {};\n    }}\n}}();",
+            name, exprs[0], exprs[1]
+        );
+    }
+    Ok(text)
+}
+
+fn print_math(name: &str, arity: usize) -> String {
+    let ret = if CLASSIFY.contains(&name) { "mask_t<V>" } else { "V" };
+    let (params, args) = if arity == 1 {
+        ("const V &x", "x")
+    } else {
+        ("const V &x, const V &y", "x, y")
+    };
+    format!(
+        "template <typename V>\n{} {}({}) {{\n    using std::{};\n    return {}({});\n}}\n",
+        ret, name, params, name, name, args
+    )
+}
+
+/// Translate the f32 and f64 versions of a module of the IR into a C++ header.
+///
+/// Both modules must have the same functions, with `f32`, `i32` and `u32`
+/// in one where the other has `f64`, `i64` and `u64`.
+pub fn modules_to_cpp(f32: &Module, f64: &Module, options: &Options) -> Result<String> {
+    let mut decls = Decls::default();
+    let mut consts = Vec::new();
+    for c in sorted_consts(f64) {
+        consts.push(print_const(f32.constant(&c.name), Some(c), options, &mut decls)?);
+    }
+    for c in f32.consts.iter().filter(|c| f64.constant(&c.name).is_none()) {
+        consts.push(print_const(Some(c), None, options, &mut decls)?);
+    }
+    let mut functions = Vec::new();
+    let mut overloads = Vec::new();
+    for func in &f32.functions {
+        let other = f64.function(&func.name).ok_or_else(|| mismatch(&func.name))?;
+        functions.push(print_fn(func, other, options, &mut decls)?);
+        if func.is_pub {
+            overloads.push(print_overloads(func, other)?);
+        }
+    }
+    if let Some(func) = f64.functions.iter().find(|f| f32.function(&f.name).is_none()) {
+        return Err(mismatch(&func.name));
+    }
+
+    let guard = options
+        .namespace
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+    let simd = format!("{}_HAVE_SIMD", guard);
+    let mut text = String::new();
+    let _ = writeln!(text, "#ifndef {}_HPP\n#define {}_HPP\n", guard, guard);
+    for header in &["cmath", "cstddef", "cstdint", "cstring", "limits", "tuple", "type_traits"] {
+        let _ = writeln!(text, "#include <{}>", header);
+    }
+    text.push_str("#if __has_include(<bit>)\n#include <bit>\n#endif\n");
+    let _ = writeln!(
+        text,
+        "#if __has_include(<experimental/simd>)\n#include <experimental/simd>\n#define {} 1\n#endif\n",
+        simd
+    );
+    let _ = writeln!(text, "namespace {} {{\n", options.namespace);
+    let _ = writeln!(text, "#ifdef {}\nnamespace stdx = std::experimental;\n#endif\n", simd);
+    text.push_str("namespace detail {\n\n");
+    text.push_str(&SUPPORT.replace("$SIMD", &simd));
+    for (name, arity) in &decls.maths {
+        text.push('\n');
+        text.push_str(&print_math(name, *arity));
+    }
+    text.push_str("\n} // namespace detail\n");
+    for c in &consts {
+        text.push('\n');
+        text.push_str(c);
+    }
+    text.push_str("\nnamespace impl {\n\n");
+    for (signature, _) in &functions {
+        let _ = writeln!(text, "{};", signature);
+    }
+    for (_, func) in &functions {
+        text.push('\n');
+        text.push_str(func);
+    }
+    text.push_str("\n} // namespace impl\n");
+    for (scalar, _) in &overloads {
+        text.push('\n');
+        text.push_str(scalar);
+    }
+    if !overloads.is_empty() {
+        let _ = writeln!(text, "\n#ifdef {}", simd);
+        for (_, simd) in &overloads {
+            text.push('\n');
+            text.push_str(simd);
+        }
+        text.push_str("\n#endif\n");
+    }
+    let _ = writeln!(text, "\n}} // namespace {}\n", options.namespace);
+    let _ = writeln!(text, "#endif // {}_HPP", guard);
+    Ok(text)
+}
+
+/// Translate the f32 and f64 versions of a Rust file into a C++ header.
+pub fn to_cpp(f32: &syn::File, f64: &syn::File, options: Options) -> Result<String> {
+    modules_to_cpp(&lower_file(f32)?, &lower_file(f64)?, &options)
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let f32: syn::File = parse_quote! {
+        const HALF: f32 = 0.5;
+        const NAN: f32 = f32::NAN;
+
+        /// Half of x.
+        pub fn half(x: f32) -> f32 {
+            x * HALF
+        }
+
+        pub fn poly(x: f32) -> f32 {
+            let y = x * x;
+            (1.0 as f32).mul_add(y, 2.0).mul_add(y, 3.0).mul_add(y, -4.0) * x
+        }
+    };
+    let f64: syn::File = parse_quote! {
+        const HALF: f64 = 0.5;
+        const NAN: f64 = f64::NAN;
+
+        /// Half of x.
+        pub fn half(x: f64) -> f64 {
+            x * HALF
+        }
+
+        pub fn poly(x: f64) -> f64 {
+            let y = x * x;
+            (1.0 as f64).mul_add(y, 2.0).mul_add(y, 3.0).mul_add(y, -4.0).mul_add(y, 5.0) * x
+        }
+    };
+    let options = Options {
+        namespace: "ds".to_string(),
+    };
+    let cpp = to_cpp(&f32, &f64, options).unwrap();
+    assert!(cpp.starts_with("#ifndef DS_HPP\n#define DS_HPP\n"));
+    assert!(cpp.contains("#define DS_HAVE_SIMD 1\n"));
+    assert!(cpp.contains("template <typename T>\ninline constexpr auto HALF = [] {\n    if constexpr (std::is_same_v<T, float>) {\n        return 0.5f;\n    } else {\n        return 0.5;\n    }\n}();\n"));
+    assert!(cpp.contains("template <typename T>\ninline constexpr auto NAN_ = std::numeric_limits<T>::quiet_NaN();\n"));
+    // Bodies which are the same for both sizes are not repeated.
+    assert!(cpp.contains("template <typename V>\nV half(V x) {\n    using T = detail::scalar_t<V>;\n    return x * ds::HALF<T>;\n}\n"));
+    assert!(cpp.contains("    if constexpr (std::is_same_v<T, float>) {\n        const V y = x * x;\n        static constexpr T poly10[] = {\n            1.0f,\n            2.0f,\n            3.0f,\n            -4.0f,\n        };\n        return detail::horner(y, poly10) * x;\n    } else {\n"));
+    assert!(cpp.contains("            5.0,\n        };\n        return detail::horner(y, poly12) * x;\n"));
+    assert!(cpp.contains("// Half of x.\ntemplate <typename T, detail::if_float<T> = 0>\nT half(T x) {\n    return impl::half<T>(x);\n}\n"));
+    assert!(cpp.contains("template <typename T, typename Abi, detail::if_float<T> = 0>\nstdx::simd<T, Abi> half(stdx::simd<T, Abi> x) {\n    return impl::half<stdx::simd<T, Abi>>(x);\n}\n"));
+    assert!(cpp.ends_with("} // namespace ds\n\n#endif // DS_HPP\n"));
+}
+
+#[test]
+fn test_types_and_branches() {
+    use syn::parse_quote;
+
+    let f32: syn::File = parse_quote! {
+        fn f(x: f32, n: usize) -> (f32, bool) {
+            let bits = x.to_bits() ^ n as u32;
+            let y = if x < 0.0 {
+                let z = f32::from_bits(bits);
+                z.sqrt()
+            } else {
+                x
+            };
+            (y, x.is_nan())
+        }
+        pub fn g(x: f32) -> f32 {
+            let (a, b) = f(x, 1);
+            if b { a } else { -a }
+        }
+    };
+    let f64: syn::File = parse_quote! {
+        fn f(x: f64, n: usize) -> (f64, bool) {
+            let bits = x.to_bits() ^ n as u64;
+            let y = if x < 0.0 {
+                let z = f64::from_bits(bits);
+                z.sqrt()
+            } else {
+                x
+            };
+            (y, x.is_nan())
+        }
+        pub fn g(x: f64) -> f64 {
+            let (a, b) = f(x, 1);
+            if b { a } else { -a }
+        }
+    };
+    let cpp = to_cpp(&f32, &f64, Options::default()).unwrap();
+    assert!(cpp.contains("template <typename V>\nV sqrt(const V &x) {\n    using std::sqrt;\n    return sqrt(x);\n}\n"));
+    assert!(cpp.contains("template <typename V>\nmask_t<V> isnan(const V &x) {\n    using std::isnan;\n    return isnan(x);\n}\n"));
+    assert!(cpp.contains("template <typename V>\nstd::tuple<V, detail::mask_t<V>> f(V x, detail::rebind_t<std::size_t, V> n) {\n    using T = detail::scalar_t<V>;\n    using U = detail::uint_v<V>;\n    using M = detail::mask_t<V>;\n"));
+    assert!(cpp.contains("    const U bits = detail::bit_cast<U>(x) ^ detail::convert<U>(n);\n"));
+    assert!(cpp.contains("        const V y = detail::if_else<V>(x < 0.0, [&]() -> V {\n            const V z = detail::bit_cast<V>(bits);\n            return detail::sqrt<V>(z);\n        }, [&]() -> V {\n            return x;\n        });\n"));
+    assert!(cpp.contains("    return std::tuple<V, M>(y, detail::isnan<V>(x));\n"));
+    assert!(cpp.contains("    const std::tuple<V, M> v2 = impl::f<V>(x, 1u);\n    const V a = std::get<0>(v2);\n"));
+    // Only public functions are overloaded.
+    assert!(!cpp.contains("T f(T x"));
+    assert!(cpp.contains("template <typename T, detail::if_float<T> = 0>\nT g(T x) {\n"));
+
+    let e = to_cpp(&f32, &parse_quote!(pub fn g(x: f64) -> f64 { x }), Options::default()).unwrap_err();
+    assert_eq!(e.message(), Some("the f32 and f64 versions do not correspond"));
+}
@@ -0,0 +1,940 @@
+//! Translate Rust functions into a Fortran 2008 module.
+//!
+//! Functions become `elemental pure` so that they apply to whole arrays.
+//! Fortran has no unsigned integers, so they are held in signed integers
+//! of the same size and compared with `blt`, `bge` etc. Signed overflow is
+//! not allowed in Fortran, so wrapping arithmetic and the arithmetic of
+//! unsigned integers call helpers that work 16 bits at a time.
+
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Special,
+    Ty, UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+pub struct Options {
+    /// Prepended to the names of functions, constants and types, eg. `ds64_`.
+    pub prefix: String,
+    /// The name of the module.
+    pub module: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: "".to_string(),
+            module: "doctor_syn".to_string(),
+        }
+    }
+}
+
+// Intrinsics and kinds that a Rust binding could shadow, in lower case
+// as Fortran names are not case sensitive.
+const RESERVED: &[&str] = &[
+    "abs", "acos", "acosh", "aint", "anint", "asin", "asinh", "atan", "atan2", "atanh", "bge",
+    "bgt", "ble", "blt", "btest", "cos", "cosh", "epsilon", "exp", "huge", "hypot", "iand", "ieor",
+    "ieee_is_finite", "ieee_is_nan", "int", "int8", "int16", "int32", "int64", "ior", "leadz",
+    "log", "log10", "max", "merge", "min", "mod", "not", "popcnt", "real", "real32", "real64",
+    "shifta", "shiftl", "shiftr", "sign", "sin", "sinh", "sqrt", "tan", "tanh", "tiny", "trailz",
+    "transfer",
+];
+
+// Operator precedence, higher binds tighter. Unary minus binds as loosely
+// as binary minus, so `a * (-b)` needs parentheses.
+const PREC_EQV: u8 = 6;
+const PREC_OR: u8 = 7;
+const PREC_AND: u8 = 8;
+const PREC_NOT: u8 = 9;
+const PREC_REL: u8 = 10;
+const PREC_ADD: u8 = 12;
+const PREC_MUL: u8 = 13;
+const PREC_POW: u8 = 14;
+const PREC_ATOM: u8 = 16;
+
+// Free form lines are at most 132 characters.
+const MAX_LINE: usize = 100;
+
+fn kind(scalar: Scalar) -> &'static str {
+    use Scalar::*;
+    match scalar {
+        Bool => "",
+        I8 | U8 => "int8",
+        I16 | U16 => "int16",
+        I32 | U32 => "int32",
+        I64 | U64 | Isize | Usize => "int64",
+        F32 => "real32",
+        F64 => "real64",
+    }
+}
+
+fn scalar_type(scalar: Scalar) -> String {
+    match scalar {
+        Scalar::Bool => "logical".to_string(),
+        s if s.is_float() => format!("real(kind={})", kind(s)),
+        s => format!("integer(kind={})", kind(s)),
+    }
+}
+
+fn float_literal(digits: &str, scalar: Scalar) -> String {
+    let mut text = digits.to_string();
+    if !text.contains(['.', 'e', 'E']) {
+        text.push_str(".0");
+    }
+    format!("{}_{}", text, kind(scalar))
+}
+
+// An integer literal of any sign and its precedence, unsigned values are wrapped.
+fn int_literal(value: i128, scalar: Scalar) -> (String, u8) {
+    let bits = scalar.num_bits() as u32;
+    let value = if scalar.is_signed() || value < 1 << (bits - 1) {
+        value
+    } else {
+        value - (1 << bits)
+    };
+    let kind = kind(scalar);
+    if value == -(1 << (bits - 1)) {
+        (format!("-huge(0_{}) - 1_{}", kind, kind), PREC_ADD)
+    } else if value < 0 {
+        (format!("-{}_{}", -value, kind), PREC_ADD)
+    } else {
+        (format!("{}_{}", value, kind), PREC_ATOM)
+    }
+}
+
+// Infinities and NaN as bit patterns, which are allowed in constant expressions.
+fn special_literal(special: Special, scalar: Scalar) -> (String, u8) {
+    let zero = float_literal("0.0", scalar);
+    let bits = |value: u64| {
+        let (bits, _) = int_literal(
+            value as i128,
+            Scalar::uint(scalar.num_bits()).unwrap_or(Scalar::U64),
+        );
+        (format!("transfer({}, {})", bits, zero), PREC_ATOM)
+    };
+    let (nan, inf, neg_inf) = if scalar == Scalar::F32 {
+        (0x7fc0_0000, 0x7f80_0000, 0xff80_0000)
+    } else {
+        (0x7ff8_0000_0000_0000, 0x7ff0_0000_0000_0000, 0xfff0_0000_0000_0000)
+    };
+    match special {
+        Special::Nan => bits(nan),
+        Special::Infinity => bits(inf),
+        Special::NegInfinity => bits(neg_inf),
+        Special::MinPositive => (format!("tiny({})", zero), PREC_ATOM),
+        Special::Max => (format!("huge({})", zero), PREC_ATOM),
+        Special::Min => (format!("-huge({})", zero), PREC_ADD),
+        Special::Epsilon => (format!("epsilon({})", zero), PREC_ATOM),
+    }
+}
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    // Tuples of scalars become derived types with components `v0`, `v1`...
+    tuples: BTreeSet<Vec<Scalar>>,
+    // Indices into `HELPERS` of maths functions without an intrinsic.
+    helpers: BTreeSet<(usize, Scalar)>,
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    options: &'a Options,
+    use_counts: Vec<usize>,
+    // Tuples whose components are taken, which must be variables.
+    extracted: BTreeSet<Value>,
+    decls: &'a mut Decls,
+    // Variables in order of definition.
+    locals: Vec<(String, String)>,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(func: &'a Function, options: &'a Options, decls: &'a mut Decls) -> Self {
+        let mut extracted = BTreeSet::new();
+        func.body.walk(&mut |inst| {
+            if let Op::Extract(a, _) = &inst.op {
+                extracted.insert(*a);
+            }
+        });
+        Printer {
+            func,
+            options,
+            use_counts: func.use_counts(),
+            extracted,
+            decls,
+            locals: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating Fortran for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            ty => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    fn f_type(&mut self, ty: &Ty) -> Result<String> {
+        match ty {
+            Ty::Scalar(scalar) => Ok(scalar_type(*scalar)),
+            _ => Ok(format!("type({})", self.tuple(ty)?)),
+        }
+    }
+
+    // The name of the derived type of a tuple.
+    fn tuple(&mut self, ty: &Ty) -> Result<String> {
+        let unsupported = || self.error(&format!("the type {} is not supported", ty));
+        let scalars = match ty {
+            Ty::Tuple(elems) => elems
+                .iter()
+                .map(|e| match e {
+                    Ty::Scalar(scalar) => Some(*scalar),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(unsupported)?,
+            _ => return Err(unsupported()),
+        };
+        let name = tuple_name(&self.options.prefix, &scalars);
+        self.decls.tuples.insert(scalars);
+        Ok(name)
+    }
+
+    fn name(&self, value: Value) -> String {
+        fortran_name(&self.func.name(value))
+    }
+
+    fn operand(&mut self, value: Value, prec: u8) -> Result<String> {
+        let (text, p) = self.expr(value)?;
+        Ok(if p < prec {
+            format!("({})", text)
+        } else {
+            text
+        })
+    }
+
+    fn args(&mut self, args: &[Value]) -> Result<String> {
+        let args = args
+            .iter()
+            .map(|a| self.operand(*a, 0))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(args.join(", "))
+    }
+
+    // True if a value is printed at its use.
+    fn is_inline(&self, value: Value) -> bool {
+        match self.func.inst(value) {
+            _ if self.extracted.contains(&value) => false,
+            Some(Inst {
+                op: Op::If(_, then_body, else_body),
+                ..
+            }) => {
+                self.func.values[value.0].name.is_none()
+                    && self.use_counts[value.0] == 1
+                    && self.is_simple(then_body)
+                    && self.is_simple(else_body)
+            }
+            _ => self.func.is_inline(value, &self.use_counts),
+        }
+    }
+
+    // A value as an expression and its precedence.
+    fn expr(&mut self, value: Value) -> Result<(String, u8)> {
+        match self.func.inst(value) {
+            Some(inst) if self.is_inline(value) => self.op(inst),
+            _ => Ok((self.name(value), PREC_ATOM)),
+        }
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<(String, u8)> {
+        // Operations whose values need not be scalars.
+        match &inst.op {
+            Op::Call(name, args) => {
+                let args = self.args(args)?;
+                return Ok((
+                    format!("{}{}({})", self.options.prefix, name, args),
+                    PREC_ATOM,
+                ));
+            }
+            Op::Tuple(args) => {
+                let name = self.tuple(self.func.ty(inst.value))?;
+                return Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM));
+            }
+            Op::Extract(a, i) => {
+                let a = self.operand(*a, PREC_ATOM)?;
+                return Ok((format!("{}%v{}", a, i), PREC_ATOM));
+            }
+            _ => (),
+        }
+        let scalar = self.scalar(inst.value)?;
+        let k = kind(scalar);
+        Ok(match &inst.op {
+            Op::Lit(Literal::Float(digits)) => (float_literal(digits, scalar), PREC_ATOM),
+            Op::Lit(Literal::Int(i)) if scalar.is_float() => {
+                (float_literal(&i.to_string(), scalar), PREC_ATOM)
+            }
+            Op::Lit(Literal::Int(i)) => int_literal(*i as i128, scalar),
+            Op::Lit(Literal::Bool(b)) => (format!(".{}.", b), PREC_ATOM),
+            Op::Lit(Literal::Special(s)) => special_literal(*s, scalar),
+            Op::Const(name) => (format!("{}{}", self.options.prefix, name), PREC_ATOM),
+            Op::Unary(UnaryOp::Neg, a) => {
+                (format!("-{}", self.operand(*a, PREC_ADD + 1)?), PREC_ADD)
+            }
+            Op::Unary(UnaryOp::Not, a) if scalar == Scalar::Bool => {
+                (format!(".not. {}", self.operand(*a, PREC_NOT)?), PREC_NOT)
+            }
+            Op::Unary(UnaryOp::Not, a) => (format!("not({})", self.args(&[*a])?), PREC_ATOM),
+            Op::Binary(op, a, b) => self.binary(*op, *a, *b)?,
+            Op::Convert(a) => self.convert(scalar, *a)?,
+            Op::Bitcast(a) => {
+                let zero = if scalar.is_float() {
+                    float_literal("0.0", scalar)
+                } else {
+                    format!("0_{}", k)
+                };
+                (
+                    format!("transfer({}, {})", self.args(&[*a])?, zero),
+                    PREC_ATOM,
+                )
+            }
+            Op::Select(c, a, b) => self.merge(*c, *a, *b)?,
+            Op::If(c, then_body, else_body) => {
+                self.merge(*c, then_body.result, else_body.result)?
+            }
+            Op::Intrinsic(i, args) => self.intrinsic(*i, args)?,
+            Op::Splat(_) => return Err(self.error("vectors are not supported")),
+            Op::Call(..) | Op::Tuple(_) | Op::Extract(..) => unreachable!("printed above"),
+        })
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: Value, b: Value) -> Result<(String, u8)> {
+        use BinaryOp::*;
+        let scalar = self.scalar(a)?;
+        let unsigned = scalar.is_int() && !scalar.is_signed();
+        let call = |this: &mut Self, name: &str| -> Result<(String, u8)> {
+            Ok((format!("{}({})", name, this.args(&[a, b])?), PREC_ATOM))
+        };
+        let infix = |this: &mut Self, sym: &str, prec: u8, min_b: u8| -> Result<(String, u8)> {
+            let min_a = if prec == PREC_REL { prec + 1 } else { prec };
+            let a = this.operand(a, min_a)?;
+            let b = this.operand(b, min_b)?;
+            Ok((format!("{} {} {}", a, sym, b), prec))
+        };
+        match op {
+            Div | Rem if unsigned => Err(self.error("unsigned division is not supported")),
+            Add | Sub | Mul if unsigned => {
+                let name = match op {
+                    Add => "wrapping_add",
+                    Sub => "wrapping_sub",
+                    _ => "wrapping_mul",
+                };
+                let name = self.helper(name, scalar);
+                call(self, &name)
+            }
+            Add => infix(self, "+", PREC_ADD, PREC_ADD + 1),
+            Sub => infix(self, "-", PREC_ADD, PREC_ADD + 1),
+            Mul => infix(self, "*", PREC_MUL, PREC_MUL + 1),
+            Div => infix(self, "/", PREC_MUL, PREC_MUL + 1),
+            Rem => call(self, "mod"),
+            Shl => call(self, "shiftl"),
+            Shr if unsigned => call(self, "shiftr"),
+            Shr => call(self, "shifta"),
+            Lt | Le | Gt | Ge if unsigned => {
+                let name = match op {
+                    Lt => "blt",
+                    Le => "ble",
+                    Gt => "bgt",
+                    _ => "bge",
+                };
+                call(self, name)
+            }
+            Eq if scalar == Scalar::Bool => infix(self, ".eqv.", PREC_EQV, PREC_EQV + 1),
+            Ne | BitXor if scalar == Scalar::Bool => infix(self, ".neqv.", PREC_EQV, PREC_EQV + 1),
+            BitAnd | And if scalar == Scalar::Bool => infix(self, ".and.", PREC_AND, PREC_AND + 1),
+            BitOr | Or if scalar == Scalar::Bool => infix(self, ".or.", PREC_OR, PREC_OR + 1),
+            BitAnd => call(self, "iand"),
+            BitXor => call(self, "ieor"),
+            BitOr => call(self, "ior"),
+            Eq => infix(self, "==", PREC_REL, PREC_REL + 1),
+            Ne => infix(self, "/=", PREC_REL, PREC_REL + 1),
+            Lt | Le | Gt | Ge => infix(self, op.symbol(), PREC_REL, PREC_REL + 1),
+            And | Or => Err(self.error("`&&` and `||` need bools")),
+        }
+    }
+
+    fn convert(&mut self, to: Scalar, a: Value) -> Result<(String, u8)> {
+        let from = self.scalar(a)?;
+        let k = kind(to);
+        let x = self.args(&[a])?;
+        Ok(match (from, to) {
+            (Scalar::Bool, _) => (format!("merge(1_{}, 0_{}, {})", k, k, x), PREC_ATOM),
+            (_, Scalar::Bool) => return Err(self.error("conversions to bool are not supported")),
+            (from, to) if to.is_float() && from.is_int() && !from.is_signed() => {
+                // Values with the top bit set are negative in Fortran.
+                let wrap = format!("2.0_{}**{}", k, from.num_bits());
+                let zero = float_literal("0.0", to);
+                let x_lt_0 = format!("{} < 0", self.operand(a, PREC_REL + 1)?);
+                let text = format!(
+                    "real({}, kind={}) + merge({}, {}, {})",
+                    x, k, wrap, zero, x_lt_0
+                );
+                (text, PREC_ADD)
+            }
+            (_, to) if to.is_float() => (format!("real({}, kind={})", x, k), PREC_ATOM),
+            (from, to)
+                if from.is_float() || from.num_bits() < to.num_bits() && from.is_signed() =>
+            {
+                (format!("int({}, kind={})", x, k), PREC_ATOM)
+            }
+            (from, to) if from.num_bits() < to.num_bits() => {
+                // Zero extend by masking the sign extended value.
+                let (mask, _) = int_literal((1 << from.num_bits()) - 1, to);
+                (format!("iand(int({}, kind={}), {})", x, k, mask), PREC_ATOM)
+            }
+            (from, to) if from.num_bits() > to.num_bits() => {
+                // Sign extend the low bits so that `int` is in range.
+                let shift = from.num_bits() - to.num_bits();
+                let text = format!(
+                    "int(shifta(shiftl({}, {}), {}), kind={})",
+                    x, shift, shift, k
+                );
+                (text, PREC_ATOM)
+            }
+            _ => return self.expr(a),
+        })
+    }
+
+    fn merge(&mut self, c: Value, a: Value, b: Value) -> Result<(String, u8)> {
+        Ok((format!("merge({})", self.args(&[a, b, c])?), PREC_ATOM))
+    }
+
+    // A maths function without an intrinsic.
+    fn helper(&mut self, name: &str, scalar: Scalar) -> String {
+        let index = HELPERS.iter().position(|h| h.0 == name).unwrap();
+        self.decls.helpers.insert((index, scalar));
+        helper_name(&self.options.prefix, name, scalar)
+    }
+
+    fn intrinsic(&mut self, i: Intrinsic, args: &[Value]) -> Result<(String, u8)> {
+        use Intrinsic::*;
+        let scalar = self.scalar(args[0])?;
+        let call = |this: &mut Self, name: &str| -> Result<(String, u8)> {
+            Ok((format!("{}({})", name, this.args(args)?), PREC_ATOM))
+        };
+        let one = float_literal("1.0", scalar);
+        if scalar.is_int() {
+            let unsigned = !scalar.is_signed();
+            return match i {
+                WrappingAdd | WrappingSub | WrappingMul => {
+                    let name = self.helper(i.name(), scalar);
+                    call(self, &name)
+                }
+                WrappingNeg => {
+                    let name = self.helper("wrapping_sub", scalar);
+                    let x = self.args(args)?;
+                    Ok((format!("{}(0_{}, {})", name, kind(scalar), x), PREC_ATOM))
+                }
+                CountOnes => call(self, "popcnt"),
+                LeadingZeros => call(self, "leadz"),
+                TrailingZeros => call(self, "trailz"),
+                Abs => call(self, "abs"),
+                Min | Max if unsigned => {
+                    let args = self.args(args)?;
+                    let op = if i == Min { "blt" } else { "bgt" };
+                    Ok((format!("merge({}, {}({}))", args, op, args), PREC_ATOM))
+                }
+                Min => call(self, "min"),
+                Max => call(self, "max"),
+                _ => Err(self.error(&format!("`{}` is not supported for integers", i.name()))),
+            };
+        }
+        match i {
+            Abs | Sqrt | Sin | Cos | Tan | Asin | Acos | Atan | Atan2 | Sinh | Cosh | Tanh
+            | Asinh | Acosh | Atanh | Exp | Log10 | Hypot | Min | Max => call(self, i.name()),
+            Ln => call(self, "log"),
+            Round => call(self, "anint"),
+            Trunc => call(self, "aint"),
+            Copysign => call(self, "sign"),
+            Floor | Ceil | Cbrt => {
+                let name = self.helper(i.name(), scalar);
+                call(self, &name)
+            }
+            Exp2 => Ok((
+                format!(
+                    "2.0_{}**{}",
+                    kind(scalar),
+                    self.operand(args[0], PREC_POW + 1)?
+                ),
+                PREC_POW,
+            )),
+            ExpM1 => Ok((format!("exp({}) - {}", self.args(args)?, one), PREC_ADD)),
+            Ln1p => Ok((
+                format!("log({} + {})", one, self.operand(args[0], PREC_ADD + 1)?),
+                PREC_ATOM,
+            )),
+            Log2 => Ok((
+                format!("log({}) / log(2.0_{})", self.args(args)?, kind(scalar)),
+                PREC_MUL,
+            )),
+            Log => {
+                let x = self.args(&args[0..1])?;
+                let base = self.args(&args[1..2])?;
+                Ok((format!("log({}) / log({})", x, base), PREC_MUL))
+            }
+            Powf | Powi => {
+                let a = self.operand(args[0], PREC_POW + 1)?;
+                let b = self.operand(args[1], PREC_POW)?;
+                Ok((format!("{}**{}", a, b), PREC_POW))
+            }
+            Recip => Ok((
+                format!("{} / {}", one, self.operand(args[0], PREC_MUL + 1)?),
+                PREC_MUL,
+            )),
+            Fract => {
+                let a = self.operand(args[0], PREC_ADD)?;
+                Ok((format!("{} - aint({})", a, self.args(args)?), PREC_ADD))
+            }
+            Signum => Ok((format!("sign({}, {})", one, self.args(args)?), PREC_ATOM)),
+            // Rust's `mul_add` is fused, contraction is left to the compiler.
+            MulAdd => {
+                let a = self.operand(args[0], PREC_MUL)?;
+                let b = self.operand(args[1], PREC_MUL + 1)?;
+                let c = self.operand(args[2], PREC_ADD + 1)?;
+                Ok((format!("{} * {} + {}", a, b, c), PREC_ADD))
+            }
+            IsNan => call(self, "ieee_is_nan"),
+            IsFinite => call(self, "ieee_is_finite"),
+            IsInfinite => {
+                let a = self.args(args)?;
+                Ok((format!("abs({}) > huge({})", a, one), PREC_REL))
+            }
+            IsSignNegative | IsSignPositive => {
+                let int = kind(Scalar::int(scalar.num_bits()).unwrap_or(Scalar::I64));
+                let test = format!(
+                    "btest(transfer({}, 0_{}), {})",
+                    self.args(args)?,
+                    int,
+                    scalar.num_bits() - 1
+                );
+                if i == IsSignNegative {
+                    Ok((test, PREC_ATOM))
+                } else {
+                    Ok((format!(".not. {}", test), PREC_NOT))
+                }
+            }
+            _ => Err(self.error(&format!("`{}` is not supported", i.name()))),
+        }
+    }
+
+    fn line(&mut self, indent: usize, line: &str) {
+        let indent = "  ".repeat(indent);
+        self.text.push_str(&wrap(&indent, line));
+    }
+
+    fn local(&mut self, value: Value) -> Result<String> {
+        let name = self.name(value);
+        let ty = self.f_type(self.func.ty(value))?;
+        self.locals.push((ty, name.clone()));
+        Ok(name)
+    }
+
+    // Print the materialised instructions of a body.
+    fn body(&mut self, body: &Body, indent: usize) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            // Functions are pure so unused calls can be dropped.
+            if self.is_inline(value) || self.use_counts[value.0] == 0 {
+                continue;
+            }
+            match &inst.op {
+                Op::If(c, then_body, else_body)
+                    if !self.is_simple(then_body) || !self.is_simple(else_body) =>
+                {
+                    let name = self.local(value)?;
+                    let (cond, _) = self.expr(*c)?;
+                    self.line(indent, &format!("if ({}) then", cond));
+                    self.branch(then_body, &name, indent + 1)?;
+                    self.line(indent, "else");
+                    self.branch(else_body, &name, indent + 1)?;
+                    self.line(indent, "end if");
+                }
+                _ => {
+                    let name = self.local(value)?;
+                    let (expr, _) = self.op(inst)?;
+                    self.line(indent, &format!("{} = {}", name, expr));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn branch(&mut self, body: &Body, name: &str, indent: usize) -> Result<()> {
+        self.body(body, indent)?;
+        let (result, _) = self.expr(body.result)?;
+        self.line(indent, &format!("{} = {}", name, result));
+        Ok(())
+    }
+
+    // True if a branch has no statements of its own.
+    fn is_simple(&self, body: &Body) -> bool {
+        body.insts.iter().all(|inst| self.is_inline(inst.value))
+    }
+}
+
+// A Rust name as a Fortran name, which can not start with `_`.
+fn fortran_name(name: &str) -> String {
+    if name.starts_with('_') {
+        format!("u{}", name)
+    } else if RESERVED.contains(&name.to_lowercase().as_str()) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// Split a statement with `&` continuations at spaces between tokens.
+fn wrap(indent: &str, line: &str) -> String {
+    let comment = line.starts_with('!');
+    let mut text = String::new();
+    let mut current = format!("{}{}", indent, line);
+    while current.len() > MAX_LINE {
+        let split = match current[..MAX_LINE].rfind(' ') {
+            Some(split) if split > indent.len() + 5 => split,
+            _ => break,
+        };
+        let (head, tail) = current.split_at(split);
+        if comment {
+            let _ = writeln!(text, "{}", head);
+            current = format!("{}!{}", indent, tail);
+        } else {
+            let _ = writeln!(text, "{} &", head);
+            current = format!("{}    &{}", indent, tail);
+        }
+    }
+    let _ = writeln!(text, "{}", current);
+    text
+}
+
+fn tuple_name(prefix: &str, scalars: &[Scalar]) -> String {
+    let names = scalars.iter().map(|s| s.name()).collect::<Vec<_>>();
+    format!("{}tuple_{}", prefix, names.join("_"))
+}
+
+fn helper_name(prefix: &str, name: &str, scalar: Scalar) -> String {
+    format!("{}{}_{}", prefix, name, scalar.name())
+}
+
+// Maths functions without an intrinsic and their bodies, in which `$K` is the kind.
+// The integer helpers sum or multiply 16 bit pieces of `x` and `y` into `s`, the
+// low 64 bits, and `$NARROW` sets `r` to the low bits of `s` that fit the kind.
+const HELPERS: &[(&str, &str)] = &[
+    ("floor", "r = aint(x)\nif (r > x) r = r - 1.0_$K"),
+    ("ceil", "r = aint(x)\nif (r < x) r = r + 1.0_$K"),
+    ("cbrt", "r = sign(abs(x)**(1.0_$K / 3.0_$K), x)"),
+    (
+        "wrapping_add",
+        "integer(kind=int64) :: s, t
+integer :: i
+s = 0
+t = 0
+do i = 0, 48, 16
+  t = t + ibits(int(x, int64), i, 16) + ibits(int(y, int64), i, 16)
+  s = ior(s, shiftl(iand(t, 65535_int64), i))
+  t = shifta(t, 16)
+end do
+$NARROW",
+    ),
+    (
+        "wrapping_sub",
+        "integer(kind=int64) :: s, t
+integer :: i
+s = 0
+t = 0
+do i = 0, 48, 16
+  t = t + ibits(int(x, int64), i, 16) - ibits(int(y, int64), i, 16)
+  s = ior(s, shiftl(iand(t, 65535_int64), i))
+  t = shifta(t, 16)
+end do
+$NARROW",
+    ),
+    (
+        "wrapping_mul",
+        "integer(kind=int64) :: s, t
+integer :: i, j
+s = 0
+t = 0
+do i = 0, 48, 16
+  do j = 0, i, 16
+    t = t + ibits(int(x, int64), j, 16) * ibits(int(y, int64), i - j, 16)
+  end do
+  s = ior(s, shiftl(iand(t, 65535_int64), i))
+  t = shiftr(t, 16)
+end do
+$NARROW",
+    ),
+];
+
+fn print_helper(prefix: &str, index: usize, scalar: Scalar) -> String {
+    let (name, body) = HELPERS[index];
+    let name = helper_name(prefix, name, scalar);
+    let ty = scalar_type(scalar);
+    let params = if scalar.is_int() { "x, y" } else { "x" };
+    let mut text = format!(
+        "  elemental pure function {}({}) result(r)\n    {}, intent(in) :: {}\n    {} :: r\n",
+        name, params, ty, params, ty
+    );
+    let bits = scalar.num_bits();
+    let narrow = match bits {
+        64 => "r = s".to_string(),
+        _ => format!(
+            "s = iand(s, 2_int64**{} - 1)\nr = int(merge(s - 2_int64**{}, s, btest(s, {})), kind={})",
+            bits,
+            bits,
+            bits - 1,
+            kind(scalar)
+        ),
+    };
+    let body = body.replace("$NARROW", &narrow).replace("$K", kind(scalar));
+    for line in body.lines() {
+        let _ = writeln!(text, "    {}", line);
+    }
+    let _ = writeln!(text, "  end function {}", name);
+    text
+}
+
+fn docs(text: &mut String, func: &Function, indent: &str) {
+    for doc in &func.docs {
+        text.push_str(&wrap(indent, &format!("!{}", doc)));
+    }
+}
+
+fn print_fn(func: &Function, options: &Options, decls: &mut Decls) -> Result<String> {
+    let mut printer = Printer::new(func, options, decls);
+    let mut params = Vec::new();
+    for p in &func.params {
+        let ty = printer.f_type(func.ty(*p))?;
+        params.push((ty, printer.name(*p)));
+    }
+    let ret = printer.f_type(&func.ret)?;
+    printer.body(&func.body, 2)?;
+    let name = format!("{}{}", options.prefix, func.name);
+    let (result, _) = printer.expr(func.body.result)?;
+    printer.line(2, &format!("{} = {}", name, result));
+
+    let mut text = String::new();
+    docs(&mut text, func, "  ");
+    let names = params
+        .iter()
+        .map(|(_, name)| name.as_str())
+        .collect::<Vec<_>>();
+    let _ = writeln!(
+        text,
+        "  elemental pure function {}({})",
+        name,
+        names.join(", ")
+    );
+    for (ty, param) in &params {
+        let _ = writeln!(text, "    {}, intent(in) :: {}", ty, param);
+    }
+    let _ = writeln!(text, "    {} :: {}", ret, name);
+    for (ty, local) in &printer.locals {
+        let _ = writeln!(text, "    {} :: {}", ty, local);
+    }
+    text.push_str(&printer.text);
+    let _ = writeln!(text, "  end function {}", name);
+    Ok(text)
+}
+
+fn print_const(func: &Function, options: &Options, decls: &mut Decls) -> Result<String> {
+    let mut printer = Printer::new(func, options, decls);
+    if !printer.is_simple(&func.body) {
+        return Err(printer.error("constants must be expressions"));
+    }
+    let ty = printer.f_type(&func.ret)?;
+    let (expr, _) = printer.expr(func.body.result)?;
+    if !printer.decls.helpers.is_empty() {
+        return Err(printer.error("constants must be constant expressions"));
+    }
+    let mut text = String::new();
+    docs(&mut text, func, "  ");
+    let line = format!(
+        "{}, parameter :: {}{} = {}",
+        ty, options.prefix, func.name, expr
+    );
+    text.push_str(&wrap("  ", &line));
+    Ok(text)
+}
+
+// Constants in an order where each is defined before it is used.
+pub(crate) fn sorted_consts(module: &Module) -> Vec<&Function> {
+    let mut sorted: Vec<&Function> = Vec::new();
+    let mut pending = module.consts.iter().collect::<Vec<_>>();
+    while !pending.is_empty() {
+        let before = pending.len();
+        pending.retain(|c| {
+            let ready = c.consts_used().iter().all(|used| {
+                sorted.iter().any(|s| &s.name == used) || module.constant(used).is_none()
+            });
+            if ready {
+                sorted.push(c);
+            }
+            !ready
+        });
+        // Cycles are left for the compiler to report.
+        if pending.len() == before {
+            sorted.append(&mut pending);
+        }
+    }
+    sorted
+}
+
+/// Translate a module of the IR into a Fortran module.
+pub fn module_to_fortran(module: &Module, options: &Options) -> Result<String> {
+    let mut decls = Decls::default();
+    let mut consts = Vec::new();
+    for c in sorted_consts(module) {
+        consts.push(print_const(c, options, &mut decls)?);
+    }
+    let mut functions = Vec::new();
+    for func in &module.functions {
+        functions.push(print_fn(func, options, &mut decls)?);
+    }
+
+    let mut text = String::new();
+    let _ = writeln!(text, "module {}", options.module);
+    text.push_str(
+        "  use, intrinsic :: iso_fortran_env, only: int8, int16, int32, int64, real32, real64\n",
+    );
+    text.push_str("  use, intrinsic :: ieee_arithmetic, only: ieee_is_finite, ieee_is_nan\n");
+    text.push_str("  implicit none\n");
+    let private = decls
+        .helpers
+        .iter()
+        .map(|(index, scalar)| helper_name(&options.prefix, HELPERS[*index].0, *scalar))
+        .chain(
+            module
+                .functions
+                .iter()
+                .filter(|f| !f.is_pub)
+                .map(|f| format!("{}{}", options.prefix, f.name)),
+        )
+        .collect::<Vec<_>>();
+    if !private.is_empty() {
+        text.push_str(&wrap("  ", &format!("private :: {}", private.join(", "))));
+    }
+    for scalars in &decls.tuples {
+        let name = tuple_name(&options.prefix, scalars);
+        let _ = writeln!(text, "\n  type :: {}", name);
+        for (i, scalar) in scalars.iter().enumerate() {
+            let _ = writeln!(text, "    {} :: v{}", scalar_type(*scalar), i);
+        }
+        let _ = writeln!(text, "  end type {}", name);
+    }
+    if !consts.is_empty() {
+        text.push('\n');
+    }
+    for c in consts {
+        text.push_str(&c);
+    }
+    text.push_str("\ncontains\n");
+    for (index, scalar) in &decls.helpers {
+        text.push('\n');
+        text.push_str(&print_helper(&options.prefix, *index, *scalar));
+    }
+    for func in functions {
+        text.push('\n');
+        text.push_str(&func);
+    }
+    let _ = writeln!(text, "\nend module {}", options.module);
+    Ok(text)
+}
+
+/// Translate a Rust file into a Fortran module.
+pub fn to_fortran(file: &syn::File, options: Options) -> Result<String> {
+    module_to_fortran(&lower_file(file)?, &options)
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        const HALF: f64 = 0.5;
+
+        /// Add half.
+        pub fn f(x: f64) -> f64 {
+            let y = x * -2.0;
+            if x < 0.0 {
+                let z = y.floor();
+                z * HALF
+            } else {
+                x.mul_add(y, HALF)
+            }
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        module: "ds".to_string(),
+    };
+    let f = to_fortran(&code, options).unwrap();
+    assert!(f.starts_with("module ds\n"));
+    assert!(f.contains("  private :: ds_floor_f64\n"));
+    assert!(f.contains("  real(kind=real64), parameter :: ds_HALF = 0.5_real64\n"));
+    assert!(f.contains("    if (r > x) r = r - 1.0_real64\n"));
+    assert!(f.contains("  ! Add half.\n  elemental pure function ds_f(x)\n    real(kind=real64), intent(in) :: x\n"));
+    assert!(f.contains("    y = x * (-2.0_real64)\n    if (x < 0.0_real64) then\n      z = ds_floor_f64(y)\n"));
+    assert!(f.contains("    else\n      v11 = x * y + ds_HALF\n    end if\n    ds_f = v11\n"));
+    assert!(f.ends_with("end module ds\n"));
+}
+
+#[test]
+fn test_bits_and_tuples() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> (f32, bool) {
+            let bits = x.to_bits();
+            let y = f32::from_bits((bits >> 1) & 0xff800000);
+            (if x.is_nan() { f32::INFINITY } else { y }, bits < 3)
+        }
+        fn g(x: f32) -> f32 {
+            let (a, b) = f(x);
+            if b { a } else { -a }
+        }
+    };
+    let f = to_fortran(&code, Options::default()).unwrap();
+    assert!(f.contains("  type :: tuple_f32_bool\n    real(kind=real32) :: v0\n    logical :: v1\n"));
+    assert!(f.contains("    bits = transfer(x, 0_int32)\n"));
+    assert!(f.contains("transfer(iand(shiftr(bits, 1_int32), -8388608_int32), 0.0_real32)"));
+    assert!(f.contains("merge(transfer(2139095040_int32, 0.0_real32), y, ieee_is_nan(x))"));
+    // Long lines are continued.
+    assert!(f.contains("ieee_is_nan(x)), blt(bits, &\n        & 3_int32))\n"));
+    assert!(f.contains("    v1 = f(x)\n    a = v1%v0\n    b = v1%v1\n    g = merge(a, -a, b)\n"));
+}
+
+#[test]
+fn test_wrapping() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(index: u64, n: i32) -> u64 {
+            (index + 1).wrapping_mul(0x9e3779b97f4a7c15) - n.wrapping_neg() as u64
+        }
+    };
+    let f = to_fortran(&code, Options::default()).unwrap();
+    assert!(f.contains("private :: wrapping_add_u64, wrapping_sub_i32, wrapping_sub_u64, wrapping_mul_u64, f\n"));
+    assert!(f.contains("wrapping_mul_u64(wrapping_add_u64(index, 1_int64), &\n        & -7046029254386353131_int64)"));
+    assert!(f.contains("wrapping_sub_i32(0_int32, n)"));
+    assert!(f.contains("  elemental pure function wrapping_sub_i32(x, y) result(r)\n    integer(kind=int32), intent(in) :: x, y\n"));
+    assert!(f.contains("    s = iand(s, 2_int64**32 - 1)\n    r = int(merge(s - 2_int64**32, s, btest(s, 31)), kind=int32)\n"));
+    assert!(f.contains("        t = t + ibits(int(x, int64), j, 16) * ibits(int(y, int64), i - j, 16)\n"));
+    assert!(!f.contains(" + 1_int64"));
+}
@@ -0,0 +1,865 @@
+//! Translate Rust functions into textual LLVM IR.
+//!
+//! Every IR value becomes an SSA value named after its `let` binding.
+//! Scalar `if` expressions become branches and a `phi`, with a lane count
+//! every value is a vector and both branches are evaluated and `select`ed.
+//! Constants become internal functions so that no pointers are needed.
+//! Float literals are printed in hex as LLVM wants them exact.
+
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Special,
+    Ty, UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+pub struct Options {
+    /// Prepended to the names of functions and constants, eg. `ds64_`.
+    /// Maths functions without an intrinsic call libm, eg. `@tan`, so
+    /// this should not be empty if those names are generated.
+    pub prefix: String,
+    /// Make every value a vector of this many elements, eg. `<4 x double>`.
+    pub lanes: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: "".to_string(),
+            lanes: None,
+        }
+    }
+}
+
+fn scalar_type(scalar: Scalar) -> &'static str {
+    use Scalar::*;
+    match scalar {
+        Bool => "i1",
+        I8 | U8 => "i8",
+        I16 | U16 => "i16",
+        I32 | U32 => "i32",
+        I64 | U64 | Isize | Usize => "i64",
+        F32 => "float",
+        F64 => "double",
+    }
+}
+
+// The number of bits of a scalar in LLVM.
+fn llvm_bits(scalar: Scalar) -> usize {
+    match scalar {
+        Scalar::Bool => 1,
+        Scalar::Isize | Scalar::Usize => 64,
+        scalar => scalar.num_bits(),
+    }
+}
+
+// A float as the hex of a double, the form that is always exact.
+fn float_constant(value: f64, scalar: Scalar) -> String {
+    let value = if scalar == Scalar::F32 {
+        value as f32 as f64
+    } else {
+        value
+    };
+    format!("0x{:016X}", value.to_bits())
+}
+
+// An integer wrapped to the size of the scalar, as a signed value.
+fn int_constant(value: u128, scalar: Scalar) -> String {
+    if scalar == Scalar::Bool {
+        return (value != 0).to_string();
+    }
+    let shift = 128 - llvm_bits(scalar);
+    (((value as i128) << shift) >> shift).to_string()
+}
+
+pub(crate) fn special_value(special: Special, scalar: Scalar) -> f64 {
+    let f32 = scalar == Scalar::F32;
+    match special {
+        Special::Nan => f64::NAN,
+        Special::Infinity => f64::INFINITY,
+        Special::NegInfinity => f64::NEG_INFINITY,
+        Special::MinPositive if f32 => f32::MIN_POSITIVE as f64,
+        Special::Max if f32 => f32::MAX as f64,
+        Special::Min if f32 => f32::MIN as f64,
+        Special::Epsilon if f32 => f32::EPSILON as f64,
+        Special::MinPositive => f64::MIN_POSITIVE,
+        Special::Max => f64::MAX,
+        Special::Min => f64::MIN,
+        Special::Epsilon => f64::EPSILON,
+    }
+}
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    // `declare` lines of intrinsics and libm functions.
+    declares: BTreeSet<String>,
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    options: &'a Options,
+    // Constants are scalars even when functions are vectors.
+    lanes: Option<usize>,
+    decls: &'a mut Decls,
+    // The operand that each value is printed as.
+    operands: Vec<Option<String>>,
+    // Local names in use, which must be unique in a function.
+    names: BTreeSet<String>,
+    // The label of the current block and the number of `if`s so far.
+    block: String,
+    ifs: usize,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(
+        func: &'a Function,
+        options: &'a Options,
+        lanes: Option<usize>,
+        decls: &'a mut Decls,
+    ) -> Self {
+        Printer {
+            func,
+            options,
+            lanes,
+            decls,
+            operands: vec![None; func.values.len()],
+            names: BTreeSet::new(),
+            block: "entry".to_string(),
+            ifs: 0,
+            text: String::new(),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating LLVM IR for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            ty => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    // The type of a scalar, a vector if there are lanes.
+    fn vector(&self, scalar: Scalar) -> String {
+        match self.lanes {
+            Some(lanes) => format!("<{} x {}>", lanes, scalar_type(scalar)),
+            None => scalar_type(scalar).to_string(),
+        }
+    }
+
+    fn llvm_type(&self, ty: &Ty) -> Result<String> {
+        match ty {
+            Ty::Scalar(scalar) => Ok(self.vector(*scalar)),
+            Ty::Tuple(elems) => {
+                let elems = elems
+                    .iter()
+                    .map(|e| self.llvm_type(e))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(format!("{{ {} }}", elems.join(", ")))
+            }
+            _ => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    fn value_type(&self, value: Value) -> Result<String> {
+        self.llvm_type(self.func.ty(value))
+    }
+
+    // The suffix of an overloaded intrinsic, eg. `f64` or `v4f32`.
+    fn mangle(&self, scalar: Scalar) -> String {
+        let elem = if scalar.is_float() {
+            format!("f{}", scalar.num_bits())
+        } else {
+            format!("i{}", llvm_bits(scalar))
+        };
+        match self.lanes {
+            Some(lanes) => format!("v{}{}", lanes, elem),
+            None => elem,
+        }
+    }
+
+    // A constant, splatted if there are lanes.
+    fn constant(&self, scalar: Scalar, text: &str) -> String {
+        match self.lanes {
+            Some(lanes) => {
+                let elem = format!("{} {}", scalar_type(scalar), text);
+                format!("<{}>", vec![elem; lanes].join(", "))
+            }
+            None => text.to_string(),
+        }
+    }
+
+    fn operand(&self, value: Value) -> String {
+        self.operands[value.0]
+            .clone()
+            .unwrap_or_else(|| format!("%{}", self.func.name(value)))
+    }
+
+    // A typed operand, eg. `double %x`.
+    fn typed(&self, value: Value) -> Result<String> {
+        Ok(format!(
+            "{} {}",
+            self.value_type(value)?,
+            self.operand(value)
+        ))
+    }
+
+    // A new local name based on a Rust name.
+    fn fresh(&mut self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut n = 0;
+        while self.names.contains(&name) {
+            n += 1;
+            name = format!("{}.{}", base, n);
+        }
+        self.names.insert(name.clone());
+        format!("%{}", name)
+    }
+
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.text, "  {}", line);
+    }
+
+    fn label(&mut self, label: String) {
+        let _ = writeln!(self.text, "{}:", label);
+        self.block = label;
+    }
+
+    // Print an instruction and return its result.
+    fn emit(&mut self, base: &str, inst: &str) -> String {
+        let name = self.fresh(base);
+        self.line(&format!("{} = {}", name, inst));
+        name
+    }
+
+    // Call an intrinsic or libm function, declaring it.
+    fn call(&mut self, base: &str, func: &str, ret: &str, args: &[(String, String)]) -> String {
+        let types = args.iter().map(|(ty, _)| ty.as_str()).collect::<Vec<_>>();
+        self.decls
+            .declares
+            .insert(format!("declare {} @{}({})", ret, func, types.join(", ")));
+        let args = args
+            .iter()
+            .map(|(ty, a)| format!("{} {}", ty, a))
+            .collect::<Vec<_>>();
+        self.emit(
+            base,
+            &format!("call {} @{}({})", ret, func, args.join(", ")),
+        )
+    }
+
+    // Call an overloaded intrinsic on values of one type, eg. `llvm.fma.f64`.
+    fn intrinsic_call(
+        &mut self,
+        base: &str,
+        name: &str,
+        scalar: Scalar,
+        args: &[String],
+    ) -> String {
+        let ty = self.vector(scalar);
+        let func = format!("llvm.{}.{}", name, self.mangle(scalar));
+        let args = args
+            .iter()
+            .map(|a| (ty.clone(), a.clone()))
+            .collect::<Vec<_>>();
+        self.call(base, &func, &ty, &args)
+    }
+
+    // Call a libm function, lane by lane for vectors.
+    fn libm_call(&mut self, base: &str, name: &str, scalar: Scalar, args: &[String]) -> String {
+        let sty = scalar_type(scalar);
+        let name = if scalar == Scalar::F32 {
+            format!("{}f", name)
+        } else {
+            name.to_string()
+        };
+        let lanes = match self.lanes {
+            Some(lanes) => lanes,
+            None => {
+                let args = args
+                    .iter()
+                    .map(|a| (sty.to_string(), a.clone()))
+                    .collect::<Vec<_>>();
+                return self.call(base, &name, sty, &args);
+            }
+        };
+        let ty = self.vector(scalar);
+        let mut result = "undef".to_string();
+        for lane in 0..lanes {
+            let mut lane_args = Vec::new();
+            for a in args {
+                let x = self.emit(
+                    "lane",
+                    &format!("extractelement {} {}, i32 {}", ty, a, lane),
+                );
+                lane_args.push((sty.to_string(), x));
+            }
+            let y = self.call("lane", &name, sty, &lane_args);
+            let inst = format!(
+                "insertelement {} {}, {} {}, i32 {}",
+                ty, result, sty, y, lane
+            );
+            result = self.emit(if lane + 1 == lanes { base } else { "lane" }, &inst);
+        }
+        result
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<String> {
+        let value = inst.value;
+        let name = self.func.name(value);
+        let ty = self.value_type(value)?;
+        // Operations whose values need not be scalars.
+        match &inst.op {
+            Op::Call(func, args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.typed(*a))
+                    .collect::<Result<Vec<_>>>()?;
+                let call = format!(
+                    "call {} @{}{}({})",
+                    ty,
+                    self.options.prefix,
+                    func,
+                    args.join(", ")
+                );
+                return Ok(self.emit(&name, &call));
+            }
+            Op::Tuple(args) => {
+                let mut tuple = "undef".to_string();
+                for (i, a) in args.iter().enumerate() {
+                    let a = self.typed(*a)?;
+                    let base = if i + 1 == args.len() {
+                        name.as_str()
+                    } else {
+                        "tuple"
+                    };
+                    tuple = self.emit(base, &format!("insertvalue {} {}, {}, {}", ty, tuple, a, i));
+                }
+                return Ok(tuple);
+            }
+            Op::Extract(a, i) => {
+                let a = self.typed(*a)?;
+                return Ok(self.emit(&name, &format!("extractvalue {}, {}", a, i)));
+            }
+            Op::If(c, then_body, else_body) => return self.branch(&name, *c, then_body, else_body),
+            Op::Select(c, a, b) => {
+                let (c, a, b) = (self.typed(*c)?, self.typed(*a)?, self.typed(*b)?);
+                return Ok(self.emit(&name, &format!("select {}, {}, {}", c, a, b)));
+            }
+            _ => (),
+        }
+        let scalar = self.scalar(value)?;
+        Ok(match &inst.op {
+            Op::Lit(Literal::Float(digits)) => {
+                let x = self
+                    .float_value(value)
+                    .ok_or_else(|| self.error(&format!("bad float {}", digits)))?;
+                self.constant(scalar, &float_constant(x, scalar))
+            }
+            Op::Lit(Literal::Int(i)) if scalar.is_float() => {
+                self.constant(scalar, &float_constant(*i as f64, scalar))
+            }
+            Op::Lit(Literal::Int(i)) => self.constant(scalar, &int_constant(*i, scalar)),
+            Op::Lit(Literal::Bool(b)) => self.constant(scalar, &b.to_string()),
+            Op::Lit(Literal::Special(_)) => {
+                let x = self.float_value(value).unwrap_or(f64::NAN);
+                self.constant(scalar, &float_constant(x, scalar))
+            }
+            Op::Const(c) => {
+                let sty = scalar_type(scalar);
+                let call = format!("call {} @{}{}()", sty, self.options.prefix, c);
+                match self.lanes {
+                    Some(_) => {
+                        let x = self.emit("const", &call);
+                        self.splat(&name, scalar, &x)
+                    }
+                    None => self.emit(&name, &call),
+                }
+            }
+            // Negative float literals are constants.
+            Op::Unary(UnaryOp::Neg, a) if self.float_value(*a).is_some() => {
+                let x = -self.float_value(*a).unwrap_or(0.0);
+                self.constant(scalar, &float_constant(x, scalar))
+            }
+            Op::Unary(op, a) => {
+                let a = self.operand(*a);
+                let inst = match (op, scalar) {
+                    (UnaryOp::Neg, s) if s.is_float() => format!("fneg {} {}", ty, a),
+                    (UnaryOp::Neg, s) => format!("sub {} {}, {}", ty, self.constant(s, "0"), a),
+                    (UnaryOp::Not, Scalar::Bool) => {
+                        format!("xor {} {}, {}", ty, a, self.constant(scalar, "true"))
+                    }
+                    (UnaryOp::Not, s) => format!("xor {} {}, {}", ty, a, self.constant(s, "-1")),
+                };
+                self.emit(&name, &inst)
+            }
+            Op::Binary(op, a, b) => self.binary(&name, *op, *a, *b)?,
+            Op::Convert(a) => {
+                let from = self.scalar(*a)?;
+                if scalar == Scalar::Bool && from != Scalar::Bool {
+                    return Err(self.error("conversions to bool are not supported"));
+                }
+                let a = self.operand(*a);
+                self.convert(&name, from, scalar, &a)
+            }
+            Op::Bitcast(a) => {
+                let from = self.scalar(*a)?;
+                if llvm_bits(from) != llvm_bits(scalar) {
+                    return Err(self.error("bit casts must be between scalars of the same size"));
+                }
+                if from.is_float() || scalar.is_float() {
+                    let a = self.typed(*a)?;
+                    self.emit(&name, &format!("bitcast {} to {}", a, ty))
+                } else {
+                    self.operand(*a)
+                }
+            }
+            Op::Intrinsic(i, args) => self.intrinsic(&name, scalar, *i, args)?,
+            // Values are already vectors.
+            Op::Splat(a) if self.lanes.is_some() => self.operand(*a),
+            Op::Splat(_) => return Err(self.error("vectors need a lane count")),
+            Op::Call(..) | Op::Tuple(_) | Op::Extract(..) | Op::If(..) | Op::Select(..) => {
+                unreachable!("printed above")
+            }
+        })
+    }
+
+    // The value of a float literal, rounded to its type.
+    fn float_value(&self, value: Value) -> Option<f64> {
+        let scalar = self.scalar(value).ok().filter(|s| s.is_float())?;
+        match &self.func.inst(value)?.op {
+            Op::Lit(Literal::Float(digits)) if scalar == Scalar::F32 => {
+                digits.parse::<f32>().ok().map(|x| x as f64)
+            }
+            Op::Lit(Literal::Float(digits)) => digits.parse::<f64>().ok(),
+            Op::Lit(Literal::Int(i)) => Some(*i as f64),
+            Op::Lit(Literal::Special(s)) => Some(special_value(*s, scalar)),
+            _ => None,
+        }
+    }
+
+    // Broadcast a scalar to every lane.
+    fn splat(&mut self, base: &str, scalar: Scalar, x: &str) -> String {
+        let ty = self.vector(scalar);
+        let lanes = self.lanes.unwrap_or(1);
+        let inst = format!(
+            "insertelement {} undef, {} {}, i32 0",
+            ty,
+            scalar_type(scalar),
+            x
+        );
+        let first = self.emit("splat", &inst);
+        let inst = format!(
+            "shufflevector {} {}, {} undef, <{} x i32> zeroinitializer",
+            ty, first, ty, lanes
+        );
+        self.emit(base, &inst)
+    }
+
+    // Scalar `if`s branch, vector ones evaluate both sides.
+    fn branch(
+        &mut self,
+        name: &str,
+        c: Value,
+        then_body: &Body,
+        else_body: &Body,
+    ) -> Result<String> {
+        let ty = self.value_type(then_body.result)?;
+        if self.lanes.is_some() {
+            if !matches!(self.func.ty(then_body.result), Ty::Scalar(_)) {
+                return Err(self.error("selecting tuples of vectors is not supported"));
+            }
+            self.body(then_body)?;
+            self.body(else_body)?;
+            let c = self.typed(c)?;
+            let (a, b) = (
+                self.operand(then_body.result),
+                self.operand(else_body.result),
+            );
+            return Ok(self.emit(name, &format!("select {}, {} {}, {} {}", c, ty, a, ty, b)));
+        }
+        let n = self.ifs;
+        self.ifs += 1;
+        let c = self.operand(c);
+        self.line(&format!(
+            "br i1 {}, label %if{}.then, label %if{}.else",
+            c, n, n
+        ));
+        let mut incoming = Vec::new();
+        for (body, part) in [(then_body, "then"), (else_body, "else")] {
+            self.label(format!("if{}.{}", n, part));
+            self.body(body)?;
+            incoming.push(format!(
+                "[ {}, %{} ]",
+                self.operand(body.result),
+                self.block
+            ));
+            self.line(&format!("br label %if{}.end", n));
+        }
+        self.label(format!("if{}.end", n));
+        Ok(self.emit(name, &format!("phi {} {}", ty, incoming.join(", "))))
+    }
+
+    fn binary(&mut self, name: &str, op: BinaryOp, a: Value, b: Value) -> Result<String> {
+        use BinaryOp::*;
+        let scalar = self.scalar(a)?;
+        let ty = self.vector(scalar);
+        let float = scalar.is_float();
+        let signed = scalar.is_signed();
+        let x = self.operand(a);
+        let mut y = self.operand(b);
+        let inst = match op {
+            Add | Sub | Mul | Div | Rem if float => match op {
+                Add => "fadd",
+                Sub => "fsub",
+                Mul => "fmul",
+                Div => "fdiv",
+                _ => "frem",
+            },
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+            Div if signed => "sdiv",
+            Div => "udiv",
+            Rem if signed => "srem",
+            Rem => "urem",
+            Shl | Shr => {
+                // Both operands of a shift have the same type in LLVM.
+                let by = self.scalar(b)?;
+                y = match self.func.inst(b).map(|inst| &inst.op) {
+                    Some(Op::Lit(Literal::Int(i))) => {
+                        self.constant(scalar, &int_constant(*i, scalar))
+                    }
+                    _ => self.convert("shift", by, scalar, &y),
+                };
+                match op {
+                    Shl => "shl",
+                    _ if signed => "ashr",
+                    _ => "lshr",
+                }
+            }
+            BitAnd | And => "and",
+            BitOr | Or => "or",
+            BitXor => "xor",
+            Eq | Ne | Lt | Le | Gt | Ge if float => {
+                // `!=` is true for NaNs.
+                let cond = match op {
+                    Eq => "oeq",
+                    Ne => "une",
+                    Lt => "olt",
+                    Le => "ole",
+                    Gt => "ogt",
+                    _ => "oge",
+                };
+                return Ok(self.emit(name, &format!("fcmp {} {} {}, {}", cond, ty, x, y)));
+            }
+            Eq | Ne | Lt | Le | Gt | Ge => {
+                let cond = match (op, signed) {
+                    (Eq, _) => "eq",
+                    (Ne, _) => "ne",
+                    (Lt, true) => "slt",
+                    (Le, true) => "sle",
+                    (Gt, true) => "sgt",
+                    (Ge, true) => "sge",
+                    (Lt, false) => "ult",
+                    (Le, false) => "ule",
+                    (Gt, false) => "ugt",
+                    _ => "uge",
+                };
+                return Ok(self.emit(name, &format!("icmp {} {} {}, {}", cond, ty, x, y)));
+            }
+        };
+        Ok(self.emit(name, &format!("{} {} {}, {}", inst, ty, x, y)))
+    }
+
+    // Convert as Rust's `as` does, float to integer conversions saturate.
+    fn convert(&mut self, name: &str, from: Scalar, to: Scalar, x: &str) -> String {
+        let (from_ty, to_ty) = (self.vector(from), self.vector(to));
+        let (from_bits, to_bits) = (llvm_bits(from), llvm_bits(to));
+        let cast = match (from.is_float(), to.is_float()) {
+            (true, true) if from_bits < to_bits => "fpext",
+            (true, true) if from_bits > to_bits => "fptrunc",
+            (false, true) if from.is_signed() => "sitofp",
+            (false, true) => "uitofp",
+            (true, false) => {
+                let sat = if to.is_signed() {
+                    "fptosi.sat"
+                } else {
+                    "fptoui.sat"
+                };
+                let func = format!("llvm.{}.{}.{}", sat, self.mangle(to), self.mangle(from));
+                return self.call(name, &func, &to_ty, &[(from_ty, x.to_string())]);
+            }
+            (false, false) if from_bits < to_bits && from.is_signed() => "sext",
+            (false, false) if from_bits < to_bits => "zext",
+            (false, false) if from_bits > to_bits => "trunc",
+            _ => return x.to_string(),
+        };
+        self.emit(name, &format!("{} {} {} to {}", cast, from_ty, x, to_ty))
+    }
+
+    fn intrinsic(
+        &mut self,
+        name: &str,
+        scalar: Scalar,
+        i: Intrinsic,
+        args: &[Value],
+    ) -> Result<String> {
+        use Intrinsic::*;
+        let arg_scalar = self.scalar(args[0])?;
+        let ty = self.vector(arg_scalar);
+        let xs = args.iter().map(|a| self.operand(*a)).collect::<Vec<_>>();
+        let x = xs[0].clone();
+        if arg_scalar.is_int() {
+            let signed = arg_scalar.is_signed();
+            let zero = self.constant(arg_scalar, "0");
+            let binary = |this: &mut Self, inst: &str| {
+                this.emit(name, &format!("{} {} {}, {}", inst, ty, xs[0], xs[1]))
+            };
+            return Ok(match i {
+                WrappingAdd => binary(self, "add"),
+                WrappingSub => binary(self, "sub"),
+                WrappingMul => binary(self, "mul"),
+                WrappingNeg => self.emit(name, &format!("sub {} {}, {}", ty, zero, x)),
+                // Counts are `u32` in Rust.
+                CountOnes | LeadingZeros | TrailingZeros => {
+                    let count = match i {
+                        CountOnes => {
+                            let func = format!("llvm.ctpop.{}", self.mangle(arg_scalar));
+                            self.call("count", &func, &ty, &[(ty.clone(), x)])
+                        }
+                        _ => {
+                            let op = if i == LeadingZeros { "ctlz" } else { "cttz" };
+                            let func = format!("llvm.{}.{}", op, self.mangle(arg_scalar));
+                            let args = [(ty.clone(), x), ("i1".to_string(), "false".to_string())];
+                            self.call("count", &func, &ty, &args)
+                        }
+                    };
+                    let unsigned = Scalar::uint(arg_scalar.num_bits()).unwrap_or(Scalar::U64);
+                    self.convert(name, unsigned, scalar, &count)
+                }
+                Abs => {
+                    let func = format!("llvm.abs.{}", self.mangle(arg_scalar));
+                    let args = [(ty.clone(), x), ("i1".to_string(), "false".to_string())];
+                    self.call(name, &func, &ty, &args)
+                }
+                Min | Max => {
+                    let op = match (i == Min, signed) {
+                        (true, true) => "smin",
+                        (true, false) => "umin",
+                        (false, true) => "smax",
+                        (false, false) => "umax",
+                    };
+                    self.intrinsic_call(name, op, arg_scalar, &xs)
+                }
+                _ => {
+                    return Err(self.error(&format!("`{}` is not supported for integers", i.name())))
+                }
+            });
+        }
+        let one = self.constant(arg_scalar, &float_constant(1.0, arg_scalar));
+        let inf = self.constant(arg_scalar, &float_constant(f64::INFINITY, arg_scalar));
+        let cmp = self.vector(Scalar::Bool);
+        Ok(match i {
+            Abs => self.intrinsic_call(name, "fabs", arg_scalar, &xs),
+            Sqrt | Sin | Cos | Exp | Exp2 | Log2 | Log10 | Floor | Ceil | Trunc | Round
+            | Copysign => self.intrinsic_call(name, i.name(), arg_scalar, &xs),
+            Ln => self.intrinsic_call(name, "log", arg_scalar, &xs),
+            Powf => self.intrinsic_call(name, "pow", arg_scalar, &xs),
+            MulAdd => self.intrinsic_call(name, "fma", arg_scalar, &xs),
+            Min => self.intrinsic_call(name, "minnum", arg_scalar, &xs),
+            Max => self.intrinsic_call(name, "maxnum", arg_scalar, &xs),
+            Tan | Asin | Acos | Atan | Atan2 | Sinh | Cosh | Tanh | Asinh | Acosh | Atanh
+            | Cbrt | Hypot => self.libm_call(name, i.name(), arg_scalar, &xs),
+            ExpM1 => self.libm_call(name, "expm1", arg_scalar, &xs),
+            Ln1p => self.libm_call(name, "log1p", arg_scalar, &xs),
+            Log => {
+                let lx = self.intrinsic_call("log", "log", arg_scalar, &xs[0..1]);
+                let lb = self.intrinsic_call("log", "log", arg_scalar, &xs[1..2]);
+                self.emit(name, &format!("fdiv {} {}, {}", ty, lx, lb))
+            }
+            Powi => {
+                let n = self.scalar(args[1])?;
+                let n = self.convert("powi", n, arg_scalar, &xs[1]);
+                self.intrinsic_call(name, "pow", arg_scalar, &[x, n])
+            }
+            Recip => self.emit(name, &format!("fdiv {} {}, {}", ty, one, x)),
+            // Rust's `fract` is relative to `trunc`.
+            Fract => {
+                let t = self.intrinsic_call("trunc", "trunc", arg_scalar, &xs);
+                self.emit(name, &format!("fsub {} {}, {}", ty, x, t))
+            }
+            Signum => {
+                let s = self.intrinsic_call("sign", "copysign", arg_scalar, &[one, x.clone()]);
+                let nan = self.emit("nan", &format!("fcmp uno {} {}, {}", ty, x, x));
+                self.emit(
+                    name,
+                    &format!("select {} {}, {} {}, {} {}", cmp, nan, ty, x, ty, s),
+                )
+            }
+            IsNan => self.emit(name, &format!("fcmp uno {} {}, {}", ty, x, x)),
+            IsInfinite | IsFinite => {
+                let a = self.intrinsic_call("abs", "fabs", arg_scalar, &xs);
+                let cond = if i == IsInfinite { "oeq" } else { "one" };
+                self.emit(name, &format!("fcmp {} {} {}, {}", cond, ty, a, inf))
+            }
+            IsSignNegative | IsSignPositive => {
+                let int = Scalar::int(arg_scalar.num_bits()).unwrap_or(Scalar::I64);
+                let ity = self.vector(int);
+                let bits = self.emit("bits", &format!("bitcast {} {} to {}", ty, x, ity));
+                let cond = if i == IsSignNegative { "slt" } else { "sge" };
+                let zero = self.constant(int, "0");
+                self.emit(name, &format!("icmp {} {} {}, {}", cond, ity, bits, zero))
+            }
+            _ => return Err(self.error(&format!("`{}` is not supported", i.name()))),
+        })
+    }
+
+    // Print the instructions of a body.
+    fn body(&mut self, body: &Body) -> Result<()> {
+        for inst in &body.insts {
+            let operand = self.op(inst)?;
+            self.operands[inst.value.0] = Some(operand);
+        }
+        Ok(())
+    }
+}
+
+fn docs(text: &mut String, func: &Function) {
+    for doc in &func.docs {
+        let _ = writeln!(text, ";{}", doc);
+    }
+}
+
+fn print_fn(
+    func: &Function,
+    options: &Options,
+    lanes: Option<usize>,
+    decls: &mut Decls,
+) -> Result<String> {
+    let mut printer = Printer::new(func, options, lanes, decls);
+    let mut params = Vec::new();
+    for p in &func.params {
+        let ty = printer.value_type(*p)?;
+        let name = printer.fresh(&func.name(*p));
+        printer.operands[p.0] = Some(name.clone());
+        params.push(format!("{} {}", ty, name));
+    }
+    let ret = printer.llvm_type(&func.ret)?;
+    printer.body(&func.body)?;
+    let result = printer.operand(func.body.result);
+    printer.line(&format!("ret {} {}", ret, result));
+
+    let linkage = if func.is_pub { "" } else { "internal " };
+    let mut text = String::new();
+    docs(&mut text, func);
+    let _ = writeln!(
+        text,
+        "define {}{} @{}{}({}) {{\nentry:",
+        linkage,
+        ret,
+        options.prefix,
+        func.name,
+        params.join(", ")
+    );
+    text.push_str(&printer.text);
+    text.push_str("}\n");
+    Ok(text)
+}
+
+/// Translate a module of the IR into LLVM IR.
+pub fn module_to_llvm_ir(module: &Module, options: &Options) -> Result<String> {
+    let mut decls = Decls::default();
+    let mut text = String::new();
+    for c in &module.consts {
+        text.push_str(&print_fn(c, options, None, &mut decls)?);
+        text.push('\n');
+    }
+    for func in &module.functions {
+        text.push_str(&print_fn(func, options, options.lanes, &mut decls)?);
+        text.push('\n');
+    }
+    for declare in &decls.declares {
+        let _ = writeln!(text, "{}", declare);
+    }
+    Ok(text)
+}
+
+/// Translate a Rust file into LLVM IR.
+pub fn to_llvm_ir(file: &syn::File, options: Options) -> Result<String> {
+    module_to_llvm_ir(&lower_file(file)?, &options)
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        const HALF: f64 = 0.5;
+
+        /// Add half.
+        pub fn f(x: f64) -> f64 {
+            let y = x * -2.0;
+            if x < 0.0 {
+                let z = y.floor();
+                z.mul_add(HALF, 1.0)
+            } else {
+                y
+            }
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        lanes: None,
+    };
+    let ll = to_llvm_ir(&code, options).unwrap();
+    assert!(ll.contains(
+        "define internal double @ds_HALF() {\nentry:\n  ret double 0x3FE0000000000000\n}\n"
+    ));
+    assert!(ll.contains("; Add half.\ndefine double @ds_f(double %x) {\nentry:\n"));
+    assert!(ll.contains("  %y = fmul double %x, 0xC000000000000000\n"));
+    assert!(ll.contains("  br i1 %v5, label %if0.then, label %if0.else\nif0.then:\n"));
+    assert!(ll.contains(
+        "  %z = call double @llvm.floor.f64(double %y)\n  %v7 = call double @ds_HALF()\n"
+    ));
+    assert!(
+        ll.contains("call double @llvm.fma.f64(double %z, double %v7, double 0x3FF0000000000000)")
+    );
+    assert!(ll.contains(
+        "  %v10 = phi double [ %v9, %if0.then ], [ %y, %if0.else ]\n  ret double %v10\n"
+    ));
+    assert!(ll.contains("declare double @llvm.fma.f64(double, double, double)\n"));
+}
+
+#[test]
+fn test_vectors_and_bits() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            let bits = x.to_bits();
+            let e = (bits >> 23) as i32 - 127;
+            if x.is_nan() { f32::INFINITY } else { f32::from_bits(bits & 0x807fffff) * (e as f32) }
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        lanes: Some(4),
+    };
+    let ll = to_llvm_ir(&code, options).unwrap();
+    assert!(ll.contains("define internal <4 x float> @ds_f(<4 x float> %x) {\n"));
+    assert!(ll.contains("  %bits = bitcast <4 x float> %x to <4 x i32>\n"));
+    assert!(ll.contains("lshr <4 x i32> %bits, <i32 23, i32 23, i32 23, i32 23>\n"));
+    assert!(ll.contains("and <4 x i32> %bits, <i32 -2139095041, "));
+    assert!(ll.contains("fcmp uno <4 x float> %x, %x\n"));
+    assert!(ll.contains("sitofp <4 x i32> %e to <4 x float>\n"));
+    assert!(ll.contains("  %v14 = select <4 x i1> %v7, "));
+    assert!(ll.contains(", <4 x float> <float 0x7FF0000000000000, "));
+}
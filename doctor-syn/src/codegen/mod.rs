@@ -2,6 +2,15 @@
 //!
 //!
 
+pub mod asm;
 pub mod c;
+pub mod c_vector;
+pub mod cpp;
+pub mod fortran;
+pub mod llvm_ir;
+pub mod no_std;
+pub mod numpy;
 pub mod rust;
+pub mod shader;
+pub mod wasm;
 pub mod portable_simd;
\ No newline at end of file
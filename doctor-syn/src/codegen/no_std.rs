@@ -0,0 +1,440 @@
+//! Make generated Rust depend only on `core`.
+//!
+//! Float methods that need `std`, such as `mul_add` and `sqrt`, and the ones
+//! older versions of `core` lack, such as `abs`, become calls of functions in
+//! a `core_math` module added to the file. These work on the bits of the
+//! floats and round correctly, so they give the same results as `std`.
+//! `Fma` chooses faster versions of `mul_add` and `sqrt`. Other methods that
+//! need `std`, such as `ln`, call the file's own function of the same name if
+//! it has one.
+//!
+//! The methods are assumed to be called on floats of the file's `fty` type.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use std::collections::{BTreeMap, BTreeSet};
+use syn::visit_mut::VisitMut;
+use syn::{parse_quote, Expr};
+
+/// How `mul_add` and `sqrt` are computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fma {
+    /// Correctly rounded integer arithmetic.
+    Software,
+    /// The x86-64 FMA and SSE2 instructions when the `fma` and `sse2` target
+    /// features are enabled, otherwise software.
+    Hardware,
+    /// A multiply then an add, which rounds twice, and a software `sqrt`.
+    Unfused,
+}
+
+pub struct Options {
+    /// The size of `fty`, 32 or 64.
+    pub num_bits: usize,
+    pub fma: Fma,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            num_bits: 64,
+            fma: Fma::Software,
+        }
+    }
+}
+
+// The methods replaced, their number of arguments including the receiver and
+// the `core_math` functions they need.
+const METHODS: &[(&str, usize, &[&str])] = &[
+    ("mul_add", 3, &[]),
+    ("sqrt", 1, &[]),
+    ("abs", 1, &[]),
+    ("recip", 1, &[]),
+    ("copysign", 2, &[]),
+    ("trunc", 1, &[]),
+    ("floor", 1, &["trunc"]),
+    ("ceil", 1, &["trunc"]),
+    ("round", 1, &["trunc"]),
+];
+
+// Methods without a `core_math` function that the file may define itself.
+const STD_ONLY: &[&str] = &[
+    "ln", "log", "log2", "log10", "ln_1p", "exp", "exp2", "exp_m1", "powf", "powi", "cbrt",
+    "hypot", "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "sinh", "cosh", "tanh", "asinh",
+    "acosh", "atanh",
+];
+
+struct CoreMath {
+    used: BTreeSet<&'static str>,
+    // The number of parameters of each function of the file.
+    own: BTreeMap<String, usize>,
+    // The function being visited, which must not call itself.
+    current: Option<syn::Ident>,
+}
+
+impl CoreMath {
+    // A call of `core_math::name`, or of the file's function, if the method is one of ours.
+    fn call(&mut self, method: &syn::Ident, args: Vec<Expr>) -> Option<Expr> {
+        let name = method.to_string();
+        if STD_ONLY.contains(&name.as_str())
+            && self.own.get(&name) == Some(&args.len())
+            && self.current.as_ref() != Some(method)
+        {
+            return Some(parse_quote!(#method(#(#args),*)));
+        }
+        let (name, _, deps) = METHODS
+            .iter()
+            .find(|(name, len, _)| method == name && *len == args.len())?;
+        self.used.insert(name);
+        self.used.extend(deps.iter());
+        let name = format_ident!("{}", name);
+        Some(parse_quote!(core_math::#name(#(#args),*)))
+    }
+}
+
+impl VisitMut for CoreMath {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        syn::visit_mut::visit_expr_mut(self, expr);
+        let call = match expr {
+            // eg. `x.mul_add(y, z)`
+            Expr::MethodCall(call) => {
+                // Receivers such as `(a * b)` need no parentheses as arguments.
+                let receiver = match &*call.receiver {
+                    Expr::Paren(paren) => (*paren.expr).clone(),
+                    receiver => receiver.clone(),
+                };
+                let args = std::iter::once(receiver)
+                    .chain(call.args.iter().cloned())
+                    .collect();
+                self.call(&call.method, args)
+            }
+            // eg. `fty::sqrt(x)`
+            Expr::Call(call) => match &*call.func {
+                Expr::Path(path) if path.path.segments.len() == 2 => {
+                    let ty = &path.path.segments[0].ident;
+                    if ty == "fty" || ty == "f32" || ty == "f64" {
+                        let args = call.args.iter().cloned().collect();
+                        self.call(&path.path.segments[1].ident, args)
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(call) = call {
+            *expr = call;
+        }
+    }
+
+    fn visit_item_fn_mut(&mut self, item: &mut syn::ItemFn) {
+        self.current = Some(item.sig.ident.clone());
+        syn::visit_mut::visit_item_fn_mut(self, item);
+        self.current = None;
+    }
+
+    // Tests run with `std`.
+    fn visit_item_mod_mut(&mut self, _: &mut syn::ItemMod) {}
+}
+
+// The software versions, written for any size of `fty`.
+fn software(name: &str) -> TokenStream {
+    match name {
+        "mul_add" => quote! {
+            pub fn mul_add(a: fty, b: fty, c: fty) -> fty {
+                // The product is exact or does not matter.
+                if a == 0.0 || b == 0.0 || !a.is_finite() || !b.is_finite() {
+                    return a * b + c;
+                }
+                if !c.is_finite() {
+                    return c;
+                }
+                if c == 0.0 {
+                    return a * b;
+                }
+                let (sa, ma, ea) = split(a);
+                let (sb, mb, eb) = split(b);
+                // The larger of the product and `c` with the other aligned to it
+                // and the bits shifted out kept as a sticky bit.
+                let p = normalise(sa ^ sb, ma * mb, ea + eb);
+                let c = split(c);
+                let c = normalise(c.0, c.1, c.2);
+                let ((sx, mx, ex), (sy, my, ey)) = if (p.2, p.1) >= (c.2, c.1) { (p, c) } else { (c, p) };
+                let shift = (ex - ey) as u32;
+                let my = if shift >= 128 {
+                    1
+                } else if shift == 0 {
+                    my
+                } else {
+                    (my >> shift) | ((my << (128 - shift) != 0) as u128)
+                };
+                let m = if sx == sy { mx + my } else { mx - my };
+                if m == 0 {
+                    return 0.0;
+                }
+                pack(sx, m, ex)
+            }
+        },
+        "sqrt" => quote! {
+            pub fn sqrt(x: fty) -> fty {
+                if x < 0.0 {
+                    return fty::NAN;
+                }
+                if x == 0.0 || !x.is_finite() {
+                    return x;
+                }
+                let (_, m, e) = split(x);
+                let (_, m, e) = normalise(0, m, e);
+                // An even exponent, leaving 64 bits in the root for rounding.
+                let (m, e) = if e & 1 == 0 { (m, e) } else { (m << 1, e - 1) };
+                let (mut root, mut rem) = (0u128, m);
+                let mut bit = 1u128 << 126;
+                while bit != 0 {
+                    if rem >= root + bit {
+                        rem -= root + bit;
+                        root = (root >> 1) + bit;
+                    } else {
+                        root >>= 1;
+                    }
+                    bit >>= 2;
+                }
+                pack(0, root << 1 | (rem != 0) as u128, e / 2 - 1)
+            }
+        },
+        "abs" => quote! {
+            pub fn abs(x: fty) -> fty {
+                fty::from_bits((x.to_bits() as u128 & !SIGN) as _)
+            }
+        },
+        "recip" => quote! {
+            pub fn recip(x: fty) -> fty {
+                1.0 / x
+            }
+        },
+        "copysign" => quote! {
+            pub fn copysign(x: fty, y: fty) -> fty {
+                fty::from_bits(((x.to_bits() as u128 & !SIGN) | (y.to_bits() as u128 & SIGN)) as _)
+            }
+        },
+        "trunc" => quote! {
+            pub fn trunc(x: fty) -> fty {
+                let bits = x.to_bits() as u128;
+                let e = ((bits & !SIGN) >> MANT) as i32 - BIAS;
+                if e >= MANT as i32 {
+                    // Integral, infinite or NaN.
+                    return x;
+                }
+                if e < 0 {
+                    return fty::from_bits((bits & SIGN) as _);
+                }
+                fty::from_bits((bits & !((1 << (MANT as i32 - e)) - 1)) as _)
+            }
+        },
+        "floor" => quote! {
+            pub fn floor(x: fty) -> fty {
+                let t = trunc(x);
+                if t > x { t - 1.0 } else { t }
+            }
+        },
+        "ceil" => quote! {
+            pub fn ceil(x: fty) -> fty {
+                let t = trunc(x);
+                if t < x { t + 1.0 } else { t }
+            }
+        },
+        // Halfway cases round away from zero.
+        _ => quote! {
+            pub fn round(x: fty) -> fty {
+                let t = trunc(x);
+                let d = x - t;
+                if d >= 0.5 {
+                    t + 1.0
+                } else if d <= -0.5 {
+                    t - 1.0
+                } else {
+                    t
+                }
+            }
+        },
+    }
+}
+
+// The x86-64 instructions, if the target has them, or the software version.
+fn hardware(name: &str, num_bits: usize) -> TokenStream {
+    let body = match (name, num_bits) {
+        ("mul_add", 32) => quote!(_mm_cvtss_f32(_mm_fmadd_ss(
+            _mm_set_ss(a),
+            _mm_set_ss(b),
+            _mm_set_ss(c)
+        ))),
+        ("mul_add", _) => quote!(_mm_cvtsd_f64(_mm_fmadd_sd(
+            _mm_set_sd(a),
+            _mm_set_sd(b),
+            _mm_set_sd(c)
+        ))),
+        ("sqrt", 32) => quote!(_mm_cvtss_f32(_mm_sqrt_ss(_mm_set_ss(x)))),
+        ("sqrt", _) => quote!(_mm_cvtsd_f64(_mm_sqrt_sd(_mm_setzero_pd(), _mm_set_sd(x)))),
+        _ => return software(name),
+    };
+    let (feature, params) = match name {
+        "mul_add" => ("fma", quote!(a: fty, b: fty, c: fty)),
+        _ => ("sse2", quote!(x: fty)),
+    };
+    let name = format_ident!("{}", name);
+    let software = software(&name.to_string());
+    quote! {
+        #[cfg(all(target_arch = "x86_64", target_feature = #feature))]
+        #[allow(unused_unsafe)]
+        pub fn #name(#params) -> fty {
+            use core::arch::x86_64::*;
+            unsafe { #body }
+        }
+
+        #[cfg(not(all(target_arch = "x86_64", target_feature = #feature)))]
+        #software
+    }
+}
+
+fn core_math(used: &BTreeSet<&'static str>, options: &Options) -> TokenStream {
+    let functions = used.iter().map(|name| match (options.fma, *name) {
+        (Fma::Hardware, _) => hardware(name, options.num_bits),
+        (Fma::Unfused, "mul_add") => quote! {
+            pub fn mul_add(a: fty, b: fty, c: fty) -> fty {
+                a * b + c
+            }
+        },
+        _ => software(name),
+    });
+    quote! {
+        /// Maths functions that only need `core`.
+        #[allow(dead_code, clippy::all)]
+        mod core_math {
+            use super::fty;
+
+            const BITS: u32 = core::mem::size_of::<fty>() as u32 * 8;
+            const MANT: u32 = fty::MANTISSA_DIGITS - 1;
+            const BIAS: i32 = fty::MAX_EXP - 1;
+            // The exponent of the least significant bit of subnormals.
+            const EMIN: i32 = 1 - BIAS - MANT as i32;
+            const SIGN: u128 = 1 << (BITS - 1);
+
+            // The sign and magnitude of a finite float as `m * 2^e`.
+            fn split(x: fty) -> (u128, u128, i32) {
+                let bits = x.to_bits() as u128;
+                let biased = ((bits & !SIGN) >> MANT) as i32;
+                let m = bits & ((1 << MANT) - 1);
+                if biased == 0 {
+                    (bits >> (BITS - 1), m, EMIN)
+                } else {
+                    (bits >> (BITS - 1), m | 1 << MANT, biased - 1 + EMIN)
+                }
+            }
+
+            // Shift a non-zero `m` up to bit 126.
+            fn normalise(sign: u128, m: u128, e: i32) -> (u128, u128, i32) {
+                let shift = m.leading_zeros() - 1;
+                (sign, m << shift, e - shift as i32)
+            }
+
+            // Round `m * 2^e` to the nearest float, ties to even.
+            fn pack(sign: u128, m: u128, e: i32) -> fty {
+                let top = 127 - m.leading_zeros() as i32 + e;
+                let lsb = if top - (MANT as i32) > EMIN { top - MANT as i32 } else { EMIN };
+                let shift = lsb - e;
+                let m = if shift <= 0 {
+                    m << -shift
+                } else if shift >= 128 {
+                    (shift == 128 && m > 1 << 127) as u128
+                } else {
+                    let (q, rem, half) = (m >> shift, m & ((1 << shift) - 1), 1 << (shift - 1));
+                    q + (rem > half || rem == half && q & 1 == 1) as u128
+                };
+                let inf = (SIGN - 1) >> MANT << MANT;
+                let bits = (((lsb - EMIN) as u128) << MANT) + m;
+                fty::from_bits((sign << (BITS - 1) | bits.min(inf)) as _)
+            }
+
+            #(#functions)*
+        }
+    }
+}
+
+/// Replace the float methods that need `std` with functions of a `core_math` module.
+pub fn to_no_std(file: &syn::File, options: &Options) -> syn::File {
+    let mut file = file.clone();
+    let own = file
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Fn(f) => Some((f.sig.ident.to_string(), f.sig.inputs.len())),
+            _ => None,
+        })
+        .collect();
+    let mut visitor = CoreMath {
+        used: BTreeSet::new(),
+        own,
+        current: None,
+    };
+    visitor.visit_file_mut(&mut file);
+    if !visitor.used.is_empty() {
+        file.items
+            .push(syn::parse2(core_math(&visitor.used, options)).unwrap());
+    }
+    file
+}
+
+#[test]
+fn test_to_no_std() {
+    use quote::ToTokens;
+
+    let code: syn::File = parse_quote! {
+        type fty = f64;
+        pub fn f(x: fty) -> fty {
+            let y: fty = x.abs().sqrt().mul_add(2.0, x.recip());
+            fty::round(y).copysign(x)
+        }
+        pub fn ln(x: fty) -> fty {
+            x.ln()
+        }
+        pub fn ln_1p(x: fty) -> fty {
+            (1.0 + x).ln() + x.sin()
+        }
+        mod tests {
+            fn g(x: f64) -> f64 { x.sqrt() }
+        }
+    };
+    let text = to_no_std(&code, &Options::default())
+        .to_token_stream()
+        .to_string();
+    assert!(text.contains("let y : fty = core_math :: mul_add (core_math :: sqrt (core_math :: abs (x)) , 2.0 , core_math :: recip (x)) ;"));
+    assert!(text.contains("core_math :: copysign (core_math :: round (y) , x)"));
+    assert!(text.contains("fn g (x : f64) -> f64 { x . sqrt () }"));
+    assert!(text.contains("pub fn ln (x : fty) -> fty { x . ln () }"));
+    assert!(text.contains("pub fn ln_1p (x : fty) -> fty { ln (1.0 + x) + x . sin () }"));
+    assert!(text.contains("pub fn trunc (x : fty) -> fty"));
+    assert!(!text.contains("pub fn floor"));
+    assert!(!text.contains("target_feature"));
+
+    let options = Options {
+        num_bits: 32,
+        fma: Fma::Hardware,
+    };
+    let text = to_no_std(&code, &options).to_token_stream().to_string();
+    assert!(text.contains("# [cfg (all (target_arch = \"x86_64\" , target_feature = \"fma\"))]"));
+    assert!(text.contains("_mm_fmadd_ss"));
+    assert!(text.contains("_mm_sqrt_ss"));
+
+    let options = Options {
+        num_bits: 64,
+        fma: Fma::Unfused,
+    };
+    let text = to_no_std(&code, &options).to_token_stream().to_string();
+    assert!(text.contains("pub fn mul_add (a : fty , b : fty , c : fty) -> fty { a * b + c }"));
+
+    let code: syn::File = parse_quote! { type fty = f32; pub fn f(x: fty) -> fty { x * 2.0 } };
+    let text = to_no_std(&code, &Options::default())
+        .to_token_stream()
+        .to_string();
+    assert!(!text.contains("core_math"));
+}
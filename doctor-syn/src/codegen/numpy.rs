@@ -0,0 +1,675 @@
+//! Translate Rust functions into a Python module of NumPy array functions.
+//!
+//! Every function takes arrays, or anything `np.asarray` accepts, and
+//! applies to all of their elements. `if` expressions evaluate both branches
+//! and choose with `np.where`, bit casts use `view` and `mul_add` is a
+//! separate multiply and add as NumPy has no fused multiply-add. Warnings
+//! from overflow and the unused branch of an `if` are silenced.
+
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Special,
+    Ty, UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+pub struct Options {
+    /// Prepended to the names of functions and constants.
+    pub prefix: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: "".to_string(),
+        }
+    }
+}
+
+// Python keywords and the name of the NumPy module.
+const RESERVED: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "np", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+// Operator precedence, higher binds tighter. Comparisons chain in Python
+// so their operands always bind tighter than a comparison.
+const PREC_CMP: u8 = 5;
+const PREC_OR: u8 = 6;
+const PREC_XOR: u8 = 7;
+const PREC_AND: u8 = 8;
+const PREC_SHIFT: u8 = 9;
+const PREC_ADD: u8 = 10;
+const PREC_MUL: u8 = 11;
+const PREC_UNARY: u8 = 12;
+const PREC_ATOM: u8 = 16;
+
+fn dtype(scalar: Scalar) -> &'static str {
+    use Scalar::*;
+    match scalar {
+        Bool => "np.bool_",
+        I8 => "np.int8",
+        I16 => "np.int16",
+        I32 => "np.int32",
+        I64 | Isize => "np.int64",
+        U8 => "np.uint8",
+        U16 => "np.uint16",
+        U32 => "np.uint32",
+        U64 | Usize => "np.uint64",
+        F32 => "np.float32",
+        F64 => "np.float64",
+    }
+}
+
+// The digits are rounded to the type in Rust and printed as the shortest
+// f64 that round trips, which is exact for f32 too.
+fn float_literal(digits: &str, scalar: Scalar) -> String {
+    let value = if scalar == Scalar::F32 {
+        digits.parse::<f32>().map(f64::from)
+    } else {
+        digits.parse::<f64>()
+    };
+    match value {
+        Ok(value) => format!("{}({:?})", dtype(scalar), value),
+        Err(_) => format!("{}(\"{}\")", dtype(scalar), digits),
+    }
+}
+
+// An integer literal in range for its type, unsigned values are wrapped.
+fn int_literal(value: u128, scalar: Scalar) -> String {
+    let bits = scalar.num_bits() as u32;
+    let value = value & (u128::MAX >> (128 - bits));
+    let value = if scalar.is_signed() && value >> (bits - 1) != 0 {
+        value as i128 - (1 << bits)
+    } else {
+        value as i128
+    };
+    format!("{}({})", dtype(scalar), value)
+}
+
+fn special_literal(special: Special, scalar: Scalar) -> String {
+    let finfo = format!("np.finfo({})", dtype(scalar));
+    match special {
+        Special::Nan => format!("{}(np.nan)", dtype(scalar)),
+        Special::Infinity => format!("{}(np.inf)", dtype(scalar)),
+        Special::NegInfinity => format!("{}(-np.inf)", dtype(scalar)),
+        Special::MinPositive => format!("{}.tiny", finfo),
+        Special::Max => format!("{}.max", finfo),
+        Special::Min => format!("{}.min", finfo),
+        Special::Epsilon => format!("{}.eps", finfo),
+    }
+}
+
+// Functions for operations without a NumPy equivalent and their bodies.
+const HELPERS: &[(&str, &str)] = &[
+    (
+        "_trunc_div",
+        "\"\"\"Integer division rounding towards zero as in Rust.\"\"\"
+q = np.abs(a) // np.abs(b)
+return np.where((a < 0) != (b < 0), -q, q)",
+    ),
+    (
+        "_cast_int",
+        "\"\"\"Convert floats to integers as `as` does in Rust, saturating with NaN as zero.\"\"\"
+info = np.iinfo(ty)
+lo = a.dtype.type(info.min)
+hi = a.dtype.type(info.max)
+y = np.where(np.isnan(a) | (a <= lo) | (a >= hi), a.dtype.type(0), a).astype(ty)
+return np.where(a >= hi, ty(info.max), np.where(a <= lo, ty(info.min), y))",
+    ),
+    (
+        "_round",
+        "\"\"\"Round half way cases away from zero as in Rust.\"\"\"
+t = np.trunc(a)
+return np.where(np.abs(a - t) >= a.dtype.type(0.5), t + np.copysign(a.dtype.type(1), a), t)",
+    ),
+    (
+        "_signum",
+        "\"\"\"One with the sign of `a`, or NaN.\"\"\"
+return np.where(np.isnan(a), a, np.copysign(a.dtype.type(1), a))",
+    ),
+];
+
+fn helper_params(name: &str) -> &'static str {
+    match name {
+        "_trunc_div" => "a, b",
+        "_cast_int" => "a, ty",
+        _ => "a",
+    }
+}
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    // Indices into `HELPERS`.
+    helpers: BTreeSet<usize>,
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    options: &'a Options,
+    use_counts: Vec<usize>,
+    decls: &'a mut Decls,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(func: &'a Function, options: &'a Options, decls: &'a mut Decls) -> Self {
+        Printer {
+            func,
+            options,
+            use_counts: func.use_counts(),
+            decls,
+            text: String::new(),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating NumPy for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            ty => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    fn name(&self, value: Value) -> String {
+        python_name(&self.func.name(value))
+    }
+
+    fn operand(&mut self, value: Value, prec: u8) -> Result<String> {
+        let (text, p) = self.expr(value)?;
+        Ok(if p < prec {
+            format!("({})", text)
+        } else {
+            text
+        })
+    }
+
+    fn args(&mut self, args: &[Value]) -> Result<String> {
+        let args = args
+            .iter()
+            .map(|a| self.operand(*a, 0))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(args.join(", "))
+    }
+
+    // A value as an expression and its precedence.
+    fn expr(&mut self, value: Value) -> Result<(String, u8)> {
+        match self.func.inst(value) {
+            Some(inst) if self.func.is_inline(value, &self.use_counts) => self.op(inst),
+            _ => Ok((self.name(value), PREC_ATOM)),
+        }
+    }
+
+    // A value converted to another type, as needed for shift amounts
+    // which NumPy would otherwise promote with the shifted value.
+    fn cast_operand(&mut self, value: Value, to: Scalar) -> Result<String> {
+        if self.scalar(value)? == to {
+            return self.operand(value, PREC_ATOM);
+        }
+        match self.func.inst(value) {
+            Some(Inst {
+                op: Op::Lit(Literal::Int(i)),
+                ..
+            }) if self.func.is_inline(value, &self.use_counts) => Ok(if to.is_float() {
+                float_literal(&i.to_string(), to)
+            } else {
+                int_literal(*i, to)
+            }),
+            _ => Ok(format!(
+                "{}.astype({})",
+                self.operand(value, PREC_ATOM)?,
+                dtype(to)
+            )),
+        }
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<(String, u8)> {
+        // Operations whose values need not be scalars.
+        match &inst.op {
+            Op::Call(name, args) => {
+                let args = self.args(args)?;
+                return Ok((
+                    format!("{}{}({})", self.options.prefix, name, args),
+                    PREC_ATOM,
+                ));
+            }
+            Op::Tuple(args) => {
+                let args = self.args(args)?;
+                let comma = if args.contains(", ") { "" } else { "," };
+                return Ok((format!("({}{})", args, comma), PREC_ATOM));
+            }
+            Op::Extract(a, i) => {
+                let a = self.operand(*a, PREC_ATOM)?;
+                return Ok((format!("{}[{}]", a, i), PREC_ATOM));
+            }
+            Op::If(c, then_body, else_body) if matches!(self.func.ty(inst.value), Ty::Tuple(_)) => {
+                let c = self.operand(*c, 0)?;
+                let a = self.operand(then_body.result, 0)?;
+                let b = self.operand(else_body.result, 0)?;
+                let text = format!(
+                    "tuple(np.where({}, _a, _b) for _a, _b in zip({}, {}))",
+                    c, a, b
+                );
+                return Ok((text, PREC_ATOM));
+            }
+            Op::Intrinsic(Intrinsic::SinCos, args) => {
+                let a = self.args(args)?;
+                return Ok((format!("(np.sin({}), np.cos({}))", a, a), PREC_ATOM));
+            }
+            _ => (),
+        }
+        let scalar = self.scalar(inst.value)?;
+        Ok(match &inst.op {
+            Op::Lit(Literal::Float(digits)) => (float_literal(digits, scalar), PREC_ATOM),
+            Op::Lit(Literal::Int(i)) if scalar.is_float() => {
+                (float_literal(&i.to_string(), scalar), PREC_ATOM)
+            }
+            Op::Lit(Literal::Int(i)) => (int_literal(*i, scalar), PREC_ATOM),
+            Op::Lit(Literal::Bool(b)) => {
+                let b = if *b { "True" } else { "False" };
+                (format!("np.bool_({})", b), PREC_ATOM)
+            }
+            Op::Lit(Literal::Special(s)) => (special_literal(*s, scalar), PREC_ATOM),
+            Op::Const(name) => (format!("{}{}", self.options.prefix, name), PREC_ATOM),
+            Op::Unary(UnaryOp::Neg, a) => {
+                (format!("-{}", self.operand(*a, PREC_UNARY)?), PREC_UNARY)
+            }
+            Op::Unary(UnaryOp::Not, a) => {
+                (format!("~{}", self.operand(*a, PREC_UNARY)?), PREC_UNARY)
+            }
+            Op::Binary(op, a, b) => self.binary(*op, *a, *b)?,
+            Op::Convert(a) => self.convert(scalar, *a)?,
+            Op::Bitcast(a) => (
+                format!("{}.view({})", self.operand(*a, PREC_ATOM)?, dtype(scalar)),
+                PREC_ATOM,
+            ),
+            Op::Select(c, a, b) => (
+                format!("np.where({})", self.args(&[*c, *a, *b])?),
+                PREC_ATOM,
+            ),
+            Op::If(c, then_body, else_body) => {
+                let args = self.args(&[*c, then_body.result, else_body.result])?;
+                (format!("np.where({})", args), PREC_ATOM)
+            }
+            Op::Intrinsic(i, args) => self.intrinsic(*i, args)?,
+            Op::Splat(_) => return Err(self.error("vectors are not supported")),
+            Op::Call(..) | Op::Tuple(_) | Op::Extract(..) => unreachable!("printed above"),
+        })
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: Value, b: Value) -> Result<(String, u8)> {
+        use BinaryOp::*;
+        let scalar = self.scalar(a)?;
+        let infix = |this: &mut Self, sym: &str, prec: u8| -> Result<(String, u8)> {
+            let min_a = if prec == PREC_CMP { prec + 1 } else { prec };
+            let a = this.operand(a, min_a)?;
+            let b = this.operand(b, prec + 1)?;
+            Ok((format!("{} {} {}", a, sym, b), prec))
+        };
+        match op {
+            Add => infix(self, "+", PREC_ADD),
+            Sub => infix(self, "-", PREC_ADD),
+            Mul => infix(self, "*", PREC_MUL),
+            Div if scalar.is_float() => infix(self, "/", PREC_MUL),
+            Div if scalar.is_signed() => {
+                let name = self.helper("_trunc_div");
+                Ok((format!("{}({})", name, self.args(&[a, b])?), PREC_ATOM))
+            }
+            Div => infix(self, "//", PREC_MUL),
+            // `fmod` truncates like Rust's `%`, unlike Python's `%`.
+            Rem => Ok((format!("np.fmod({})", self.args(&[a, b])?), PREC_ATOM)),
+            BitAnd | And => infix(self, "&", PREC_AND),
+            BitOr | Or => infix(self, "|", PREC_OR),
+            BitXor => infix(self, "^", PREC_XOR),
+            Shl | Shr => {
+                let lhs = self.operand(a, PREC_SHIFT)?;
+                let rhs = self.cast_operand(b, scalar)?;
+                Ok((format!("{} {} {}", lhs, op.symbol(), rhs), PREC_SHIFT))
+            }
+            Eq | Ne | Lt | Le | Gt | Ge => infix(self, op.symbol(), PREC_CMP),
+        }
+    }
+
+    fn convert(&mut self, to: Scalar, a: Value) -> Result<(String, u8)> {
+        let from = self.scalar(a)?;
+        Ok(match (from, to) {
+            (from, to) if from == to => return self.expr(a),
+            (_, Scalar::Bool) => return Err(self.error("conversions to bool are not supported")),
+            (from, to) if from.is_float() && to.is_int() => {
+                let name = self.helper("_cast_int");
+                let x = self.args(&[a])?;
+                (format!("{}({}, {})", name, x, dtype(to)), PREC_ATOM)
+            }
+            _ => (
+                format!("{}.astype({})", self.operand(a, PREC_ATOM)?, dtype(to)),
+                PREC_ATOM,
+            ),
+        })
+    }
+
+    fn helper(&mut self, name: &str) -> String {
+        let index = HELPERS.iter().position(|h| h.0 == name).unwrap();
+        self.decls.helpers.insert(index);
+        name.to_string()
+    }
+
+    fn intrinsic(&mut self, i: Intrinsic, args: &[Value]) -> Result<(String, u8)> {
+        use Intrinsic::*;
+        let scalar = self.scalar(args[0])?;
+        let call = |this: &mut Self, name: &str| -> Result<(String, u8)> {
+            Ok((format!("{}({})", name, this.args(args)?), PREC_ATOM))
+        };
+        if scalar.is_int() {
+            return match i {
+                WrappingAdd => self.binary(BinaryOp::Add, args[0], args[1]),
+                WrappingSub => self.binary(BinaryOp::Sub, args[0], args[1]),
+                WrappingMul => self.binary(BinaryOp::Mul, args[0], args[1]),
+                WrappingNeg => Ok((
+                    format!("-{}", self.operand(args[0], PREC_UNARY)?),
+                    PREC_UNARY,
+                )),
+                Abs => call(self, "np.abs"),
+                Min => call(self, "np.minimum"),
+                Max => call(self, "np.maximum"),
+                _ => Err(self.error(&format!("`{}` is not supported for integers", i.name()))),
+            };
+        }
+        let one = float_literal("1.0", scalar);
+        match i {
+            Abs | Sqrt | Cbrt | Sin | Cos | Tan | Sinh | Cosh | Tanh | Exp | Exp2 | Log2
+            | Log10 | Floor | Ceil | Trunc | Copysign | Hypot => {
+                call(self, &format!("np.{}", i.name()))
+            }
+            Asin => call(self, "np.arcsin"),
+            Acos => call(self, "np.arccos"),
+            Atan => call(self, "np.arctan"),
+            Atan2 => call(self, "np.arctan2"),
+            Asinh => call(self, "np.arcsinh"),
+            Acosh => call(self, "np.arccosh"),
+            Atanh => call(self, "np.arctanh"),
+            ExpM1 => call(self, "np.expm1"),
+            Ln => call(self, "np.log"),
+            Ln1p => call(self, "np.log1p"),
+            Powf => call(self, "np.power"),
+            // Rust's `min` and `max` ignore NaN as `fmin` and `fmax` do.
+            Min => call(self, "np.fmin"),
+            Max => call(self, "np.fmax"),
+            IsNan => call(self, "np.isnan"),
+            IsInfinite => call(self, "np.isinf"),
+            IsFinite => call(self, "np.isfinite"),
+            IsSignNegative => call(self, "np.signbit"),
+            IsSignPositive => Ok((format!("~np.signbit({})", self.args(args)?), PREC_UNARY)),
+            Round => {
+                let name = self.helper("_round");
+                call(self, &name)
+            }
+            Signum => {
+                let name = self.helper("_signum");
+                call(self, &name)
+            }
+            Powi => {
+                let x = self.args(&args[0..1])?;
+                let n = self.cast_operand(args[1], scalar)?;
+                Ok((format!("np.power({}, {})", x, n), PREC_ATOM))
+            }
+            Log => {
+                let x = self.args(&args[0..1])?;
+                let base = self.operand(args[1], 0)?;
+                Ok((format!("np.log({}) / np.log({})", x, base), PREC_MUL))
+            }
+            Recip => Ok((
+                format!("{} / {}", one, self.operand(args[0], PREC_MUL + 1)?),
+                PREC_MUL,
+            )),
+            Fract => {
+                let a = self.operand(args[0], PREC_ADD + 1)?;
+                Ok((format!("{} - np.trunc({})", a, self.args(args)?), PREC_ADD))
+            }
+            // NumPy has no fused multiply-add, so this rounds twice.
+            MulAdd => {
+                let a = self.operand(args[0], PREC_MUL)?;
+                let b = self.operand(args[1], PREC_MUL + 1)?;
+                let c = self.operand(args[2], PREC_ADD + 1)?;
+                Ok((format!("{} * {} + {}", a, b, c), PREC_ADD))
+            }
+            _ => Err(self.error(&format!("`{}` is not supported", i.name()))),
+        }
+    }
+
+    fn line(&mut self, indent: usize, line: &str) {
+        let _ = writeln!(self.text, "{}{}", "    ".repeat(indent), line);
+    }
+
+    // Print the materialised instructions of a body. Both branches of an
+    // `if` are evaluated before choosing between them.
+    fn body(&mut self, body: &Body, indent: usize) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            // Functions are pure so unused calls can be dropped.
+            if self.func.is_inline(value, &self.use_counts) || self.use_counts[value.0] == 0 {
+                continue;
+            }
+            if let Op::If(_, then_body, else_body) = &inst.op {
+                self.body(then_body, indent)?;
+                self.body(else_body, indent)?;
+            }
+            let name = self.name(value);
+            let (expr, _) = self.op(inst)?;
+            self.line(indent, &format!("{} = {}", name, expr));
+        }
+        Ok(())
+    }
+}
+
+// A Rust name as a Python name.
+fn python_name(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn docstring(text: &mut String, func: &Function, indent: &str) {
+    let lines = func
+        .docs
+        .iter()
+        .map(|doc| doc.strip_prefix(' ').unwrap_or(doc).replace('\\', "\\\\"))
+        .collect::<Vec<_>>();
+    match lines.as_slice() {
+        [] => (),
+        [line] => {
+            let _ = writeln!(text, "{}\"\"\"{}\"\"\"", indent, line);
+        }
+        [first, rest @ ..] => {
+            let _ = writeln!(text, "{}\"\"\"{}", indent, first);
+            for line in rest {
+                if line.is_empty() {
+                    text.push('\n');
+                } else {
+                    let _ = writeln!(text, "{}{}", indent, line);
+                }
+            }
+            let _ = writeln!(text, "{}\"\"\"", indent);
+        }
+    }
+}
+
+fn print_helper(index: usize) -> String {
+    let (name, body) = HELPERS[index];
+    let mut text = format!("def {}({}):\n", name, helper_params(name));
+    for line in body.lines() {
+        let _ = writeln!(text, "    {}", line);
+    }
+    text
+}
+
+fn print_fn(func: &Function, options: &Options, decls: &mut Decls) -> Result<String> {
+    let mut printer = Printer::new(func, options, decls);
+    // Parameters are converted to arrays of their type.
+    let mut params = Vec::new();
+    for p in &func.params {
+        let name = printer.name(*p);
+        if let Ty::Scalar(scalar) = func.ty(*p) {
+            printer.line(
+                1,
+                &format!("{} = np.asarray({}, dtype={})", name, name, dtype(*scalar)),
+            );
+        }
+        params.push(name);
+    }
+    printer.line(1, "with np.errstate(all=\"ignore\"):");
+    printer.body(&func.body, 2)?;
+    let (result, _) = printer.expr(func.body.result)?;
+    printer.line(2, &format!("return {}", result));
+
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "def {}{}({}):",
+        options.prefix,
+        func.name,
+        params.join(", ")
+    );
+    docstring(&mut text, func, "    ");
+    text.push_str(&printer.text);
+    Ok(text)
+}
+
+fn print_const(func: &Function, options: &Options, decls: &mut Decls) -> Result<String> {
+    let mut printer = Printer::new(func, options, decls);
+    if !func
+        .body
+        .insts
+        .iter()
+        .all(|inst| func.is_inline(inst.value, &printer.use_counts))
+    {
+        return Err(printer.error("constants must be expressions"));
+    }
+    let (expr, _) = printer.expr(func.body.result)?;
+    let mut text = String::new();
+    for doc in &func.docs {
+        let _ = writeln!(text, "#{}", doc);
+    }
+    let _ = writeln!(text, "{}{} = {}", options.prefix, func.name, expr);
+    Ok(text)
+}
+
+/// Translate a module of the IR into a Python module using NumPy.
+pub fn module_to_numpy(module: &Module, options: &Options) -> Result<String> {
+    let mut decls = Decls::default();
+    let mut consts = Vec::new();
+    for c in super::fortran::sorted_consts(module) {
+        consts.push(print_const(c, options, &mut decls)?);
+    }
+    let mut functions = Vec::new();
+    for func in &module.functions {
+        functions.push(print_fn(func, options, &mut decls)?);
+    }
+
+    let mut text = String::new();
+    text.push_str("import numpy as np\n");
+    let public = module
+        .consts
+        .iter()
+        .chain(&module.functions)
+        .filter(|f| f.is_pub)
+        .map(|f| format!("\"{}{}\"", options.prefix, f.name))
+        .collect::<Vec<_>>();
+    let _ = writeln!(text, "\n__all__ = [{}]", public.join(", "));
+    if !consts.is_empty() {
+        text.push('\n');
+    }
+    for c in consts {
+        text.push_str(&c);
+    }
+    for index in &decls.helpers {
+        text.push_str("\n\n");
+        text.push_str(&print_helper(*index));
+    }
+    for func in functions {
+        text.push_str("\n\n");
+        text.push_str(&func);
+    }
+    Ok(text)
+}
+
+/// Translate a Rust file into a Python module using NumPy.
+pub fn to_numpy(file: &syn::File, options: Options) -> Result<String> {
+    module_to_numpy(&lower_file(file)?, &options)
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        const HALF: f64 = 0.5;
+
+        /// Add half.
+        pub fn f(x: f64) -> f64 {
+            let y = x * -2.0;
+            if x < 0.0 {
+                let z = y.round();
+                z * HALF
+            } else {
+                x.mul_add(y, HALF)
+            }
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+    };
+    let f = to_numpy(&code, options).unwrap();
+    assert!(
+        f.starts_with("import numpy as np\n\n__all__ = [\"ds_f\"]\n\nds_HALF = np.float64(0.5)\n")
+    );
+    assert!(f.contains(
+        "\n\ndef _round(a):\n    \"\"\"Round half way cases away from zero as in Rust.\"\"\"\n"
+    ));
+    assert!(f.contains(
+        "\n\ndef ds_f(x):\n    \"\"\"Add half.\"\"\"\n    x = np.asarray(x, dtype=np.float64)\n"
+    ));
+    assert!(f.contains("    with np.errstate(all=\"ignore\"):\n        y = x * -np.float64(2.0)\n"));
+    assert!(f.contains("        z = _round(y)\n        v11 = np.where(x < np.float64(0.0), z * ds_HALF, x * y + ds_HALF)\n        return v11\n"));
+}
+
+#[test]
+fn test_bits_and_tuples() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32, n: i32) -> (f32, bool) {
+            let bits = x.to_bits();
+            let y = f32::from_bits((bits >> 1) & 0xff800000);
+            let m = (x as i32) / n;
+            (if x.is_nan() { f32::INFINITY } else { y.powi(m) }, bits < 3 || n % 2 == 0)
+        }
+        fn g(x: f32) -> f32 {
+            let (a, b) = f(x, -3);
+            if b { a } else { -a }
+        }
+    };
+    let f = to_numpy(&code, Options::default()).unwrap();
+    assert!(f.starts_with("import numpy as np\n\n__all__ = []\n\n\ndef _trunc_div(a, b):\n"));
+    assert!(f.contains("\n\ndef _cast_int(a, ty):\n"));
+    assert!(f.contains("        bits = x.view(np.uint32)\n"));
+    assert!(
+        f.contains("        y = (bits >> np.uint32(1) & np.uint32(4286578688)).view(np.float32)\n")
+    );
+    assert!(f.contains("        m = _trunc_div(_cast_int(x, np.int32), n)\n"));
+    assert!(
+        f.contains("np.where(np.isnan(x), np.float32(np.inf), np.power(y, m.astype(np.float32)))")
+    );
+    assert!(f.contains("(bits < np.uint32(3)) | (np.fmod(n, np.int32(2)) == np.int32(0)))\n"));
+    assert!(f.contains("        v3 = f(x, -np.int32(3))\n        a = v3[0]\n        b = v3[1]\n        v7 = np.where(b, a, -a)\n        return v7\n"));
+}
@@ -1,299 +1,578 @@
 //! Convert a file of functions into a set of methods
 //! suitable for rust-lang/portable-simd
-
-use quote::{quote, format_ident, ToTokens};
-use syn::punctuated::Punctuated;
-use syn::visit_mut::{visit_expr_mut, visit_ident_mut, VisitMut, visit_signature_mut, visit_local_mut};
-use syn::{parse_quote, Expr, Ident, Item, ItemConst, Token, Visibility};
+//!
+//! `to_simd_fn` converts a single scalar function into a free function
+//! on `Simd<f32, N>` or `Simd<f64, N>` values.
+//!
+//! Both print the IR with every value a vector. `if` expressions evaluate
+//! both branches and select between them lane-wise.
+//!
+//! `to_simd` targets the portable-simd crate unless `Options::std_simd`
+//! asks for the current `std::simd`.
+//!
+//! Functions whose first parameter is not a float, such as `runif`, become
+//! associated functions called as `Self::name(..)`. Integer parameters and
+//! results are `Self::IntType` or `Self::UintType`, cast on entry and exit
+//! when the function uses other sizes, which are `Simd<T, N>`.
+
+use crate::ir::{
+    lower_file, lower_fn, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar,
+    Special, Ty, UnaryOp, Value,
+};
+use crate::typeinference::Env;
+use crate::{Error, ErrorKind, Result};
+use proc_macro2::{Literal as LitToken, TokenStream};
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse_quote, Ident, ItemFn, Type};
 
 pub struct Options {
     pub num_bits: usize,
+    /// Use the current `std::simd`, with `simd_lt` and `simd_min` from its
+    /// prelude and no `LaneCount` bound, rather than the portable-simd crate.
+    /// Float intrinsics are called as `StdFloat::sqrt(x)` so that methods of
+    /// the same name in `StdLibm` do not make them ambiguous.
+    pub std_simd: bool,
 }
 
 impl Default for Options {
     fn default() -> Self {
-        Options { num_bits: 32 }
+        Options { num_bits: 32, std_simd: false }
     }
 }
 
-#[allow(dead_code)]
-pub struct SimdVisitor {
-    options: Options,
-    consts: Vec<ItemConst>,
-    idents_used: Vec<Ident>,
+/// Options for `to_simd_fn`.
+pub struct FnOptions {
+    /// The number of lanes in the vector types.
+    pub lanes: usize,
+    /// The name of the new function, by default the original with `_simd` appended.
+    pub name: Option<Ident>,
 }
 
-impl SimdVisitor {
-    fn new(options: Options) -> Self {
-        let consts = Vec::new();
-        let idents_used = Vec::new();
-        SimdVisitor {
-            options,
-            consts,
-            idents_used,
-        }
+impl Default for FnOptions {
+    fn default() -> Self {
+        FnOptions { lanes: 4, name: None }
     }
 }
 
-fn deblock1(block: &syn::Block) -> &Expr {
-    if block.stmts.len() == 1 {
-        if let syn::Stmt::Expr(expr) = &block.stmts[0] {
-            return expr;
-        }
+// Rust operator precedence, higher binds tighter.
+const PREC_UNARY: u8 = 14;
+const PREC_ATOM: u8 = 16;
+
+fn binary_prec(op: BinaryOp) -> u8 {
+    use BinaryOp::*;
+    match op {
+        Mul | Div | Rem => 12,
+        Add | Sub => 11,
+        Shl | Shr => 10,
+        BitAnd | And => 9,
+        BitXor => 8,
+        BitOr | Or => 7,
+        Eq | Ne | Lt | Le | Gt | Ge => 6,
     }
-    unreachable!();
 }
 
-fn deblock2(expr: &Expr) -> &Expr {
-    if let syn::Expr::Block(block) = expr {
-        if block.block.stmts.len() == 1 {
-            if let syn::Stmt::Expr(expr) = &block.block.stmts[0] {
-                return expr;
-            }
+struct Printer<'a> {
+    func: &'a Function,
+    // Lanes of the vector types for free functions, None for `impl StdLibm` methods.
+    lanes: Option<usize>,
+    num_bits: usize,
+    // Method names of std::simd, which free functions always use.
+    std_simd: bool,
+    use_counts: Vec<usize>,
+    stmts: Vec<TokenStream>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(func: &'a Function, lanes: Option<usize>, num_bits: usize) -> Self {
+        Printer {
+            func,
+            lanes,
+            num_bits,
+            std_simd: lanes.is_some(),
+            use_counts: func.use_counts(),
+            stmts: Vec::new(),
         }
     }
-    expr
-}
 
-impl VisitMut for SimdVisitor {
-    fn visit_ident_mut(&mut self, i: &mut Ident) {
-        self.idents_used.push(i.clone());
-        visit_ident_mut(self, i);
-        match i {
-            i if i == "fty" => *i = Ident::new("Self", i.span()),
-            // i if i == "uty" => *i = Ident::new("Self::UintType", i.span()),
-            // i if i == "ity" => *i = Ident::new("Self::IntType", i.span()),
-            _ => {
-                self.idents_used.push(i.clone());
-                visit_ident_mut(self, i);
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("vectorising {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, ty: &Ty) -> Result<Scalar> {
+        match ty {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            _ => Err(self.error(&format!("expected a scalar, found {}", ty))),
+        }
+    }
+
+    // The element type, sized to match the float type for methods.
+    fn elem(&self, scalar: Scalar) -> Ident {
+        match self.lanes {
+            None if scalar.is_float() => format_ident!("f{}", self.num_bits),
+            _ => format_ident!("{}", scalar.name()),
+        }
+    }
+
+    // `Self` and its associated types for the lanes of methods that have them.
+    fn assoc(&self, scalar: Scalar) -> Option<TokenStream> {
+        match self.lanes {
+            Some(_) => None,
+            None if scalar.is_float() => Some(quote!(Self)),
+            None if Some(scalar) == Scalar::int(self.num_bits) => Some(quote!(Self::IntType)),
+            None if Some(scalar) == Scalar::uint(self.num_bits) => Some(quote!(Self::UintType)),
+            None => None,
+        }
+    }
+
+    // `Simd<elem, N>` for free functions or the `Self` types for methods.
+    fn vector(&self, ty: &Ty) -> Result<TokenStream> {
+        let scalar = match ty {
+            Ty::Tuple(tys) => {
+                let tys = tys.iter().map(|ty| self.vector(ty)).collect::<Result<Vec<_>>>()?;
+                return Ok(quote!((#(#tys),*)));
             }
+            ty => self.scalar(ty)?,
+        };
+        if let Some(assoc) = self.assoc(scalar) {
+            return Ok(assoc);
         }
+        let elem = match scalar {
+            Scalar::Bool => format_ident!("i{}", self.num_bits),
+            scalar => self.elem(scalar),
+        };
+        let kind = format_ident!("{}", if scalar == Scalar::Bool { "Mask" } else { "Simd" });
+        Ok(match self.lanes {
+            Some(lanes) => quote!(std::simd::#kind<#elem, #lanes>),
+            None => quote!(super::simd::#kind<#elem, N>),
+        })
     }
 
-    fn visit_signature_mut(&mut self, sig: &mut syn::Signature) {
-        visit_signature_mut(self, sig);
-        // Patch the first argument to self
-        let arg0 = sig.inputs.iter_mut().next().unwrap();
-        *arg0 = parse_quote! {self};
-        sig.output = parse_quote! {-> Self};
+    // `ty::splat(value)` using a qualified path for generic types.
+    fn splat(&self, ty: &Ty, value: TokenStream) -> Result<TokenStream> {
+        match self.assoc(self.scalar(ty)?) {
+            Some(assoc) => Ok(quote!(#assoc::splat(#value))),
+            None => {
+                let vector = self.vector(ty)?;
+                Ok(quote!(<#vector>::splat(#value)))
+            }
+        }
     }
 
-    fn visit_type_path_mut(&mut self, type_path: &mut syn::TypePath) {
-        match type_path.to_token_stream().to_string().as_str() {
-            "uty" => *type_path = parse_quote!(Self::UintType),
-            "ity" => *type_path = parse_quote!(Self::IntType),
-            "fty" => *type_path = parse_quote!(Self),
-            _ => {},
+    // The type of a method parameter or result, which the trait must be able to
+    // declare, and the integer type to cast to and from if the sizes differ.
+    fn interface(&self, ty: &Ty) -> Result<(TokenStream, Option<Scalar>)> {
+        let scalar = match ty {
+            Ty::Tuple(tys) => {
+                let tys = tys
+                    .iter()
+                    .map(|ty| match self.interface(ty)? {
+                        (ty, None) => Ok(ty),
+                        _ => Err(self.error("tuples of integers of other sizes are not supported")),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                return Ok((quote!((#(#tys),*)), None));
+            }
+            ty => self.scalar(ty)?,
+        };
+        if scalar == Scalar::Bool {
+            return Err(self.error("masks are not supported in methods"));
         }
+        let sized = if scalar.is_float() {
+            scalar
+        } else if scalar.is_signed() {
+            Scalar::int(self.num_bits).unwrap_or(scalar)
+        } else {
+            Scalar::uint(self.num_bits).unwrap_or(scalar)
+        };
+        let ty = self.vector(&Ty::Scalar(sized))?;
+        Ok((ty, if sized == scalar { None } else { Some(sized) }))
     }
 
-    // Convert `lit as f32` etc. to splats.
-    fn visit_expr_mut(&mut self, expr: &mut syn::Expr) {
-        // println!("{} {:?}", expr.to_token_stream(), expr);
-        visit_expr_mut(self, expr);
+    // Methods take a float as `self`, other functions are called on `Self`.
+    fn is_receiver(&self, value: Value) -> bool {
+        matches!(self.func.ty(value), Ty::Scalar(scalar) if scalar.is_float())
+    }
 
-        match &*expr {
-            syn::Expr::Cast(cast) => {
-                *expr = convert_cast(cast, self.options.num_bits)
+    fn literal(&self, lit: &Literal, ty: &Ty) -> Result<TokenStream> {
+        let scalar = self.scalar(ty)?;
+        let value = match lit {
+            Literal::Float(digits) if scalar.is_float() => {
+                syn::LitFloat::new(digits, proc_macro2::Span::call_site()).to_token_stream()
             }
-            syn::Expr::Binary(binary) => {
-                *expr = convert_binary(binary)
+            Literal::Float(digits) => {
+                let message = format!("expected an integer, found {}", digits);
+                return Err(self.error(&message));
             }
-            syn::Expr::Call(call) => {
-                *expr = convert_call(call);
+            Literal::Int(i) if scalar.is_float() => {
+                syn::LitFloat::new(&format!("{}.0", i), proc_macro2::Span::call_site())
+                    .to_token_stream()
             }
-            syn::Expr::Lit(syn::ExprLit { lit, ..} ) => {
-                match lit {
-                    syn::Lit::Float(f) => {
-                        *expr = convert_lit_float(f);
-                    }
-                    syn::Lit::Int(f) => {
-                        *expr = convert_lit_int(f);
-                    }
-                    _ => {}
-                }
-            },
-            syn::Expr::If(exprif) => {
-                let cond = &*exprif.cond;
-                let then_branch = deblock1(&exprif.then_branch);
-                if let Some((_, else_branch)) = &exprif.else_branch {
-                    let else_branch = deblock2(&*else_branch);
-                    *expr = parse_quote! { (#cond).select(#then_branch, #else_branch) };
-                }
-            },
-            syn::Expr::Path(exprpath) => {
-                // println!("HERE: {}", exprpath.to_token_stream().to_string().as_str())
-                match exprpath.to_token_stream().to_string().as_str() {
-                    "f32 :: NAN" => *expr = parse_quote! { Self::splat(f32::NAN) },
-                    "f64 :: NAN" => *expr = parse_quote! { Self::splat(f64::NAN) },
-                    "f32 :: INFINITY" => *expr = parse_quote! { Self::splat(f32::INFINITY) },
-                    "f64 :: INFINITY" => *expr = parse_quote! { Self::splat(f64::INFINITY) },
-                    "f32 :: MIN_POSITIVE" => *expr = parse_quote! { Self::splat(f32::MIN_POSITIVE) },
-                    "f64 :: MIN_POSITIVE" => *expr = parse_quote! { Self::splat(f64::MIN_POSITIVE) },
-                    _ => (),
-                }
-            },
-            _ => {}
-        }
+            Literal::Int(i) => LitToken::u128_unsuffixed(*i).to_token_stream(),
+            Literal::Bool(b) => quote!(#b),
+            Literal::Special(special) => {
+                let elem = self.elem(scalar);
+                let name = match special {
+                    Special::Nan => "NAN",
+                    Special::Infinity => "INFINITY",
+                    Special::NegInfinity => "NEG_INFINITY",
+                    Special::MinPositive => "MIN_POSITIVE",
+                    Special::Max => "MAX",
+                    Special::Min => "MIN",
+                    Special::Epsilon => "EPSILON",
+                };
+                let name = format_ident!("{}", name);
+                quote!(#elem::#name)
+            }
+        };
+        self.splat(ty, value)
     }
 
-    fn visit_local_mut(&mut self, local: &mut syn::Local) {
-        visit_local_mut(self, local);
-        if let syn::Pat::Type(pat_type) = &local.pat {
-            local.pat = *pat_type.pat.clone();
-        }
+    fn name(&self, value: Value) -> Ident {
+        format_ident!("{}", self.func.name(value))
     }
-}
 
-fn convert_cast(cast: &syn::ExprCast, num_bits: usize) -> Expr {
-    let expr = &*cast.expr;
-    let ty = &*cast.ty;
-    let (uty, ity, fty) = if num_bits == 32 {
-        (quote!(u32), quote!(i32), quote!(f32))
-    } else {
-        (quote!(u64), quote!(i64), quote!(f64))
-    };
-    match ty.to_token_stream().to_string().as_str() {
-        "uty" | "Self :: UintType" => parse_quote!{ #expr.cast::<#uty>() },
-        "ity" | "Self :: IntType" => parse_quote!{ #expr.cast::<#ity>() },
-        "fty" | "Self" => parse_quote!{ #expr.cast::<#fty>() },
-        _ => cast.clone().into()
+    fn operand(&mut self, value: Value, prec: u8) -> Result<TokenStream> {
+        let (tokens, p) = self.expr(value)?;
+        Ok(if p < prec { quote!((#tokens)) } else { tokens })
     }
-}
 
-fn convert_binary(binary: &syn::ExprBinary) -> Expr {
-    use syn::BinOp::*;
-    if let Some(op) = match binary.op {
-        Eq(_) => Some("lanes_eq"),
-        Lt(_) => Some("lanes_lt"),
-        Le(_) => Some("lanes_le"),
-        Ne(_) => Some("lanes_ne"),
-        Ge(_) => Some("lanes_ge"),
-        Gt(_) => Some("lanes_gt"),
-        _ => None,
-    } {
-        let id = format_ident!("{}", op);
-        let lhs = binary.left.clone();
-        let rhs = binary.right.clone();
-        parse_quote! { (#lhs).#id(#rhs) }
-    } else {
-        binary.clone().into()
+    fn args(&mut self, args: &[Value]) -> Result<Vec<TokenStream>> {
+        args.iter().map(|a| self.operand(*a, 0)).collect()
     }
-}
 
-fn convert_call(call: &syn::ExprCall) -> Expr {
-    let mut args = call.args.iter();
-    let arg0 = args.next().unwrap();
-    match call.func.to_token_stream().to_string().as_str() {
-        "sin" | "cos" | "tan" | "exp2" | "powf" | "log2" | "ln" => {
-            let func = &*call.func;
-            let rest = args.cloned().collect::<Punctuated<Expr, Token![,]>>();
-            parse_quote! { (#arg0).#func(#rest) }
+    // A value as an expression and its precedence.
+    fn expr(&mut self, value: Value) -> Result<(TokenStream, u8)> {
+        match self.func.inst(value) {
+            Some(inst) if self.func.is_inline(value, &self.use_counts) => self.op(inst),
+            _ => Ok((self.name(value).to_token_stream(), PREC_ATOM)),
         }
+    }
 
-        _ => call.clone().into()
+    // A method call on the first argument.
+    fn method(&mut self, name: &str, args: &[Value]) -> Result<(TokenStream, u8)> {
+        let (first, rest) = match args.split_first() {
+            Some(split) => split,
+            None => return Err(self.error(&format!("{} has no arguments to call it on", name))),
+        };
+        let receiver = self.operand(*first, PREC_ATOM)?;
+        let rest = self.args(rest)?;
+        let name = format_ident!("{}", name);
+        Ok((quote!(#receiver.#name(#(#rest),*)), PREC_ATOM))
+    }
+
+    // A call to another method with integers cast to the sizes of its interface.
+    fn call(&mut self, name: &str, args: &[Value], ty: &Ty) -> Result<(TokenStream, u8)> {
+        let receiver = match args.first() {
+            Some(first) if self.is_receiver(*first) => Some(self.operand(*first, PREC_ATOM)?),
+            _ => None,
+        };
+        let rest = args[receiver.iter().count()..]
+            .iter()
+            .map(|a| match self.interface(self.func.ty(*a))? {
+                (_, Some(sized)) => {
+                    let elem = format_ident!("{}", sized.name());
+                    let a = self.operand(*a, PREC_ATOM)?;
+                    Ok(quote!(#a.cast::<#elem>()))
+                }
+                _ => self.operand(*a, 0),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Vectors have their own methods with the names of intrinsics such as `round`.
+        let clash = Intrinsic::from_name(name).is_some();
+        let name = format_ident!("{}", name);
+        let call = match receiver {
+            Some(receiver) if clash => quote!(<Self as StdLibm>::#name(#receiver #(, #rest)*)),
+            Some(receiver) => quote!(#receiver.#name(#(#rest),*)),
+            None => quote!(Self::#name(#(#rest),*)),
+        };
+        Ok(match self.interface(ty)? {
+            (_, Some(_)) => {
+                let elem = self.elem(self.scalar(ty)?);
+                (quote!(#call.cast::<#elem>()), PREC_ATOM)
+            }
+            _ => (call, PREC_ATOM),
+        })
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: Value, b: Value) -> Result<(TokenStream, u8)> {
+        let prec = binary_prec(op);
+        let lhs = self.operand(a, prec)?;
+        // Shift amounts are vectors of the shifted type.
+        let rhs = match (op, self.func.inst(b)) {
+            (BinaryOp::Shl | BinaryOp::Shr, Some(Inst { op: Op::Lit(lit), .. })) => {
+                self.literal(lit, self.func.ty(a))?
+            }
+            _ => self.operand(b, prec + 1)?,
+        };
+        let sym: TokenStream = match op {
+            BinaryOp::And => quote!(&),
+            BinaryOp::Or => quote!(|),
+            op => op.symbol().parse().unwrap(),
+        };
+        Ok((quote!(#lhs #sym #rhs), prec))
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<(TokenStream, u8)> {
+        let ty = self.func.ty(inst.value).clone();
+        Ok(match &inst.op {
+            Op::Lit(lit) => (self.literal(lit, &ty)?, PREC_ATOM),
+            Op::Const(name) => (format_ident!("{}", name).to_token_stream(), PREC_ATOM),
+            Op::Unary(op, a) => {
+                let a = self.operand(*a, PREC_UNARY)?;
+                match op {
+                    UnaryOp::Neg => (quote!(-#a), PREC_UNARY),
+                    UnaryOp::Not => (quote!(!#a), PREC_UNARY),
+                }
+            }
+            Op::Binary(op, a, b) if op.is_comparison() => {
+                let prefix = if self.std_simd { "simd" } else { "lanes" };
+                let name = format!("{}_{}", prefix, format!("{:?}", op).to_lowercase());
+                self.method(&name, &[*a, *b])?
+            }
+            Op::Binary(op, a, b) => self.binary(*op, *a, *b)?,
+            Op::Convert(a) => {
+                let scalar = self.scalar(&ty)?;
+                if scalar == Scalar::Bool {
+                    return Err(self.error("conversions to bool are not supported"));
+                }
+                if self.func.ty(*a).is_bool() {
+                    let one = self.literal(&Literal::Int(1), &ty)?;
+                    let zero = self.literal(&Literal::Int(0), &ty)?;
+                    let a = self.operand(*a, PREC_ATOM)?;
+                    return Ok((quote!(#a.select(#one, #zero)), PREC_ATOM));
+                }
+                let elem = self.elem(scalar);
+                let a = self.operand(*a, PREC_ATOM)?;
+                (quote!(#a.cast::<#elem>()), PREC_ATOM)
+            }
+            Op::Bitcast(a) => {
+                let from = self.scalar(self.func.ty(*a))?;
+                let to = self.scalar(&ty)?;
+                if from.is_float() {
+                    self.method("to_bits", &[*a])?
+                } else if to.is_float() {
+                    let vector = self.vector(&ty)?;
+                    let a = self.operand(*a, 0)?;
+                    if self.lanes.is_some() {
+                        (quote!(<#vector>::from_bits(#a)), PREC_ATOM)
+                    } else {
+                        (quote!(#vector::from_bits(#a)), PREC_ATOM)
+                    }
+                } else {
+                    let elem = self.elem(to);
+                    let a = self.operand(*a, PREC_ATOM)?;
+                    (quote!(#a.cast::<#elem>()), PREC_ATOM)
+                }
+            }
+            Op::Select(..) | Op::If(..) if matches!(ty, Ty::Tuple(_)) => {
+                return Err(self.error("selecting between tuples is not supported"));
+            }
+            Op::Select(c, a, b) => self.method("select", &[*c, *a, *b])?,
+            Op::If(c, then_body, else_body) => {
+                self.method("select", &[*c, then_body.result, else_body.result])?
+            }
+            Op::Splat(_) => return Err(self.error("the function already uses vectors")),
+            Op::Call(name, args) if self.lanes.is_none() => self.call(name, args, &ty)?,
+            Op::Call(name, args) => {
+                let name = format_ident!("{}", name);
+                let args = self.args(args)?;
+                (quote!(#name(#(#args),*)), PREC_ATOM)
+            }
+            Op::Intrinsic(i, args) => self.intrinsic(*i, args)?,
+            Op::Tuple(args) => {
+                let args = self.args(args)?;
+                (quote!((#(#args),*)), PREC_ATOM)
+            }
+            Op::Extract(a, index) => {
+                let a = self.operand(*a, PREC_ATOM)?;
+                let index = syn::Index::from(*index);
+                (quote!(#a.#index), PREC_ATOM)
+            }
+        })
     }
-}
 
-fn convert_lit_float(f: &syn::LitFloat) -> Expr {
-    match f.suffix() {
-        "u32" | "u64" => {
-            parse_quote! { UintType::splat(#f) }
+    fn intrinsic(&mut self, i: Intrinsic, args: &[Value]) -> Result<(TokenStream, u8)> {
+        use Intrinsic::*;
+        // Vector integer arithmetic wraps.
+        match i {
+            WrappingAdd => return self.binary(BinaryOp::Add, args[0], args[1]),
+            WrappingSub => return self.binary(BinaryOp::Sub, args[0], args[1]),
+            WrappingMul => return self.binary(BinaryOp::Mul, args[0], args[1]),
+            // Unsigned vectors have no negation.
+            WrappingNeg if !self.scalar(self.func.ty(args[0]))?.is_signed() => {
+                let zero = self.literal(&Literal::Int(0), self.func.ty(args[0]))?;
+                let a = self.operand(args[0], binary_prec(BinaryOp::Sub) + 1)?;
+                return Ok((quote!(#zero - #a), binary_prec(BinaryOp::Sub)));
+            }
+            WrappingNeg => {
+                let a = self.operand(args[0], PREC_UNARY)?;
+                return Ok((quote!(-#a), PREC_UNARY));
+            }
+            _ => (),
         }
-        "i32" | "i64" => {
-            parse_quote! { IntType::splat(#f) }
+        let name = match (i, self.std_simd) {
+            (Min, true) => "simd_min",
+            (Max, true) => "simd_max",
+            (i, _) => i.name(),
+        };
+        // Methods of the trait being implemented, such as `sqrt`, would be
+        // ambiguous with those of `std::simd`, so name the trait.
+        let std_trait = match i {
+            Sqrt | MulAdd | Floor | Ceil | Round | Trunc | Fract | Sin | Cos | Exp | Exp2 | Ln
+            | Log | Log2 | Log10 => Some(quote!(StdFloat)),
+            Abs | Recip | Copysign | Signum | Min | Max | IsNan | IsInfinite | IsFinite
+            | IsSignNegative | IsSignPositive => Some(quote!(SimdFloat)),
+            _ => None,
+        };
+        match std_trait {
+            Some(std_trait) if self.std_simd && self.lanes.is_none() && self.is_receiver(args[0]) => {
+                let args = self.args(args)?;
+                let name = format_ident!("{}", name);
+                Ok((quote!(#std_trait::#name(#(#args),*)), PREC_ATOM))
+            }
+            _ => self.method(name, args),
         }
-        "f32" | "f64" | _ => {
-            parse_quote! { Self::splat(#f) }
+    }
+
+    // Statements for the values that are not inline.
+    fn body(&mut self, body: &Body) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            if self.func.is_inline(value, &self.use_counts)
+                || self.use_counts[value.0] == 0 && !matches!(inst.op, Op::Call(..))
+            {
+                continue;
+            }
+            if let Op::If(_, then_body, else_body) = &inst.op {
+                self.body(then_body)?;
+                self.body(else_body)?;
+            }
+            let (expr, _) = self.op(inst)?;
+            let name = self.name(value);
+            self.stmts.push(quote!(let #name = #expr;));
         }
+        Ok(())
+    }
+
+    // The statements and result of the function body.
+    fn block(mut self) -> Result<(Vec<TokenStream>, TokenStream)> {
+        self.body(&self.func.body)?;
+        let (result, _) = self.expr(self.func.body.result)?;
+        Ok((self.stmts, result))
     }
 }
 
-fn convert_lit_int(f: &syn::LitInt) -> Expr {
-    match f.suffix() {
-        "u32" | "u64" => {
-            parse_quote! { Self::UintType::splat(#f) }
+// Bind the constants used by a method, and the constants they use, first.
+fn const_lets(module: &Module, func: &Function, options: &Options, lets: &mut Vec<TokenStream>, done: &mut Vec<String>) -> Result<()> {
+    for name in func.consts_used() {
+        if done.contains(&name) {
+            continue;
         }
-        "i32" | "i64" => {
-            parse_quote! { Self::IntType::splat(#f) }
-        }
-        "f32" | "f64" | _ => {
-            parse_quote! { Self::splat(#f) }
+        done.push(name.clone());
+        let c = match module.constant(&name) {
+            Some(c) => c,
+            None => {
+                return Err(Error::new(ErrorKind::UnsupportedCodegen)
+                    .with_operation(format!("vectorising {}", func.name))
+                    .with_message(format!("the constant {} is not defined", name)))
+            }
+        };
+        const_lets(module, c, options, lets, done)?;
+        let ident = format_ident!("{}", name);
+        let mut printer = Printer::new(c, None, options.num_bits);
+        printer.std_simd = options.std_simd;
+        let (stmts, expr) = printer.block()?;
+        if stmts.is_empty() {
+            lets.push(quote!(let #ident = #expr;));
+        } else {
+            lets.push(quote!(let #ident = { #(#stmts)* #expr };));
         }
     }
+    Ok(())
 }
 
-// fn convert_path(f: &syn::Path) -> Expr {
-//     // match f.to_token_stream().to_string().as_str() {
-//     //     "uty" => {
-//     //         *expr = parse_quote! { Self::UintType }
-//     //     }
-//     //     "ity" => {
-//     //         *expr = parse_quote! { Self::IntType }
-//     //     }
-//     //     "fty" | _ => {
-//     //         *expr = parse_quote! { Self }
-//     //     }
-//     // }
-//     f.into()
-// }
-
-pub fn to_simd(file: &syn::File, options: Options) -> syn::File {
-    let mut methods = Vec::new();
-
-    let fty = if options.num_bits == 32 { quote!(f32) } else { quote!(f64) };
-    let ity = if options.num_bits == 32 { quote!(i32) } else { quote!(i64) };
-    let uty = if options.num_bits == 32 { quote!(u32) } else { quote!(u64) };
-
-    let mut cv = SimdVisitor::new(options);
-
-    for it in &file.items {
-        cv.idents_used.clear();
-        use Item::*;
-        match it {
-            Const(item) => {
-                cv.consts.push(item.clone());
-            }
-            Fn(item) => {
-                let mut new_sig = item.sig.clone();
-                visit_signature_mut(&mut cv, &mut new_sig);
-                let first_arg = item.sig.inputs.iter().next().unwrap();
-                let pat = &**match first_arg {
-                    syn::FnArg::Typed(syn::PatType{pat, ..}) => pat,
-                    _ => unreachable!("expected typed arg"),
-                };
-                let ident = match pat {
-                    syn::Pat::Ident(id) => &id.ident,
-                    _ => unreachable!("expected identifier"),
-                };
-                cv.visit_signature_mut(&mut new_sig);
-                let mut new_block = (*item.block).clone();
-                new_block.stmts.insert(0, parse_quote! {let #ident = self;});
-                cv.visit_block_mut(&mut new_block);
-                for c in cv.consts.clone() {
-                    let ident = &c.ident;
-                    if cv.idents_used.contains(ident) {
-                        let mut expr = (&*c.expr).clone();
-                        cv.visit_expr_mut(&mut expr);
-                        let new_stmt = parse_quote! {let #ident = #expr;};
-                        new_block.stmts.insert(0, new_stmt);
-                    }
-                }
-                let new_method = syn::ImplItemMethod {
-                    attrs: vec![parse_quote!(#[inline])],
-                    vis: Visibility::Inherited,
-                    defaultness: None,
-                    sig: new_sig,
-                    block: new_block,
-                };
-                methods.push(new_method);
+// A method if the first parameter is a float, otherwise an associated function.
+fn to_method(module: &Module, func: &Function, options: &Options) -> Result<TokenStream> {
+    let mut printer = Printer::new(func, None, options.num_bits);
+    printer.std_simd = options.std_simd;
+    let (receiver, rest) = match func.params.split_first() {
+        Some((first, rest)) if printer.is_receiver(*first) => {
+            let first = printer.name(*first);
+            (Some(quote!(let #first = self;)), rest)
+        }
+        _ => (None, &func.params[..]),
+    };
+    let mut lets = Vec::new();
+    let params = rest
+        .iter()
+        .map(|p| {
+            let name = printer.name(*p);
+            let (ty, sized) = printer.interface(func.ty(*p))?;
+            if sized.is_some() {
+                let elem = printer.elem(printer.scalar(func.ty(*p))?);
+                lets.push(quote!(let #name = #name.cast::<#elem>();));
             }
-            Type(_item) => {}
-            _ => {}
+            Ok(quote!(#name: #ty))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (ret, sized) = printer.interface(&func.ret)?;
+    let name = format_ident!("{}", func.name);
+    const_lets(module, func, options, &mut lets, &mut Vec::new())?;
+    let (stmts, mut result) = printer.block()?;
+    if let Some(sized) = sized {
+        let elem = format_ident!("{}", sized.name());
+        result = quote!((#result).cast::<#elem>());
+    }
+    let params = match receiver {
+        Some(_) => quote!(self #(, #params)*),
+        None => quote!(#(#params),*),
+    };
+    Ok(quote! {
+        #[inline]
+        fn #name(#params) -> #ret {
+            #receiver
+            #(#lets)*
+            #(#stmts)*
+            #result
         }
+    })
+}
+
+pub fn to_simd(file: &syn::File, options: Options) -> Result<syn::File> {
+    let module = lower_file(file)?;
+    let num_bits = options.num_bits;
+    let methods = module
+        .functions
+        .iter()
+        .map(|func| to_method(&module, func, &options))
+        .collect::<Result<Vec<_>>>()?;
+
+    let fty = format_ident!("f{}", num_bits);
+    let ity = format_ident!("i{}", num_bits);
+    let uty = format_ident!("u{}", num_bits);
+
+    if options.std_simd {
+        return Ok(parse_quote! {
+            #![allow(non_snake_case)]
+            #![allow(clippy::excessive_precision)]
+            #![allow(clippy::approx_constant)]
+
+            use super::StdLibm;
+            use super::StdFloat;
+            use super::simd::prelude::*;
+
+            impl<const N: usize> StdLibm for Simd<#fty, N> {
+                type IntType = Simd<#ity, N>;
+                type UintType = Simd<#uty, N>;
+
+                #(#methods)*
+            }
+        });
     }
 
-    parse_quote! {
+    Ok(parse_quote! {
         #![allow(non_snake_case)]
         #![allow(clippy::excessive_precision)]
         #![allow(clippy::approx_constant)]
@@ -302,7 +581,7 @@ pub fn to_simd(file: &syn::File, options: Options) -> syn::File {
         use super ::StdLibm ;
         use super ::StdFloat ;
         use super ::simd::{
-          LaneCount ,Simd ,SupportedLaneCount 
+          LaneCount ,Simd ,SupportedLaneCount
         };
 
         impl<const N: usize> StdLibm for Simd<#fty, N>
@@ -314,7 +593,82 @@ pub fn to_simd(file: &syn::File, options: Options) -> syn::File {
 
             #(#methods)*
         }
+    })
+}
+
+/// Convert a scalar function to a twin taking and returning `Simd` vectors.
+///
+/// Scalar parameter and return types become `Simd<T, N>`, `bool` becomes a mask,
+/// literals and float constants are splatted and comparisons and `if`
+/// expressions become lane-wise operations.
+/// The generated code requires `std::simd::prelude::*` to be in scope and
+/// `std::simd::StdFloat` for methods such as `mul_add`.
+pub fn to_simd_fn(item: &ItemFn, options: FnOptions) -> Result<ItemFn> {
+    let sig = &item.sig;
+    if !sig.generics.params.is_empty() {
+        return Err(Error::at(ErrorKind::UnsupportedCodegen, &sig.generics)
+            .with_operation("vectorising a function")
+            .with_message("generic functions are not supported"));
+    }
+    let mut types = sig.inputs.iter().map(|arg| match arg {
+        syn::FnArg::Typed(pat_type) => Ok(&*pat_type.ty),
+        syn::FnArg::Receiver(_) => Err(Error::at(ErrorKind::UnsupportedCodegen, arg)
+            .with_operation("vectorising a function")
+            .with_message("methods are not supported")),
+    }).collect::<Result<Vec<_>>>()?;
+    if let syn::ReturnType::Type(_, ty) = &sig.output {
+        types.push(ty);
     }
+    let mut num_bits = 32;
+    for ty in &types {
+        let scalar = |ty: &Type| matches!(ty.to_token_stream().to_string().as_str(),
+            "f32" | "f64" | "i32" | "i64" | "u32" | "u64" | "bool");
+        let ok = match ty {
+            Type::Tuple(tuple) => tuple.elems.iter().all(scalar),
+            ty => scalar(ty),
+        };
+        if !ok {
+            return Err(Error::at(ErrorKind::UnsupportedCodegen, *ty)
+                .with_operation("vectorising a function")
+                .with_message("expected scalar numbers, bools or tuples of them"));
+        }
+        if ty.to_token_stream().to_string().contains("f64") {
+            num_bits = 64;
+        }
+    }
+
+    let func = lower_fn(item, &Env::new())?;
+    let printer = Printer::new(&func, Some(options.lanes), num_bits);
+    let params = func
+        .params
+        .iter()
+        .map(|p| {
+            let name = printer.name(*p);
+            let ty = printer.vector(func.ty(*p))?;
+            Ok(quote!(#name: #ty))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let ret = match &sig.output {
+        syn::ReturnType::Default => quote!(),
+        syn::ReturnType::Type(..) => {
+            let ty = printer.vector(&func.ret)?;
+            quote!(-> #ty)
+        }
+    };
+    let (stmts, result) = printer.block()?;
+    let name = options
+        .name
+        .unwrap_or_else(|| format_ident!("{}_simd", sig.ident));
+    let attrs = &item.attrs;
+    let vis = &item.vis;
+    Ok(parse_quote! {
+        #(#attrs)*
+        #[inline]
+        #vis fn #name(#(#params),*) #ret {
+            #(#stmts)*
+            #result
+        }
+    })
 }
 
 #[test]
@@ -334,11 +688,97 @@ fn test() {
         }
     };
 
-    let options = Options::default();
-    let file = to_simd(&code, options);
+    let options = Options { num_bits: 64, ..Options::default() };
+    let file = to_simd(&code, options).unwrap();
+    let text = file.to_token_stream().to_string();
+    assert!(text.contains("fn sin (self) -> Self { let arg = self ; let RECIP_2PI = Self :: splat (0.1591549430918953357688837633725143620345) ;"));
+    assert!(text.contains("let scaled = arg * RECIP_2PI ;"));
+    assert!(text.contains("let x = scaled - scaled . round () ;"));
+    assert!(text.contains("(- Self :: splat (0.0000795978135564681619446994463825844449)) . mul_add (x * x , Self :: splat (0.0011251039233483632093906670512638370694))"));
+}
+
+#[test]
+fn test_to_simd_fn() {
+    let item: ItemFn = parse_quote! {
+        pub fn kernel(x: f32, n: i32) -> f32 {
+            let y: f32 = x * std::f32::consts::PI + n as f32;
+            if y < 0.0 { -y } else { y.mul_add(y, 1.0) }
+        }
+    };
+    let options = FnOptions { lanes: 8, name: None };
+    let simd = to_simd_fn(&item, options).unwrap().to_token_stream().to_string();
+    assert!(simd.starts_with("# [inline] pub fn kernel_simd (x : std :: simd :: Simd < f32 , 8usize >"));
+    assert!(simd.contains("n : std :: simd :: Simd < i32 , 8usize >"));
+    assert!(simd.contains("-> std :: simd :: Simd < f32 , 8usize >"));
+    assert!(simd.contains("< std :: simd :: Simd < f32 , 8usize > > :: splat (3.14159265358979323846264338327950288)"));
+    assert!(simd.contains("n . cast :: < f32 > ()"));
+    assert!(simd.contains("y . simd_lt ("));
+    assert!(simd.contains(". select (- y , y . mul_add (y ,"));
+
+    let item: ItemFn = parse_quote! { fn f(x: &[f64]) -> f64 { x[0] } };
+    let e = to_simd_fn(&item, FnOptions::default()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnsupportedCodegen);
+}
 
-    println!(
-        "res\n{}",
-        super::rust::format_token_stream(file.to_token_stream())
-    );
+#[test]
+fn test_control_flow_and_integers() {
+    let code: syn::File = parse_quote! {
+        const ONE_BITS: u32 = 0x3f800000;
+
+        pub fn rand(index: usize, scale: f32) -> f32 {
+            let z: u64 = (index as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15);
+            let z1: u64 = z ^ (z >> 30);
+            let x: f32 = f32::from_bits((z1 >> 41) as u32 | ONE_BITS) - 1.0;
+            x * scale
+        }
+
+        pub fn f(x: f32) -> f32 {
+            let y: f32 = if x < 0.0 {
+                let a: f32 = x.abs();
+                let bits: u32 = a.to_bits().wrapping_neg();
+                f32::from_bits(bits)
+            } else {
+                let n: u32 = (x > 1.0) as u32;
+                x + n as f32
+            };
+            y * rand(3, x)
+        }
+    };
+    let file = to_simd(&code, Options::default()).unwrap();
+    let text = file.to_token_stream().to_string();
+    assert!(text.contains("fn rand (index : Self :: UintType , scale : Self) -> Self { let index = index . cast :: < usize > () ;"));
+    assert!(text.contains("let ONE_BITS = Self :: UintType :: splat (1065353216) ;"));
+    assert!(text.contains("let z = (index . cast :: < u64 > () + < super :: simd :: Simd < u64 , N > > :: splat (1)) * < super :: simd :: Simd < u64 , N > > :: splat (11400714819323198485) ;"));
+    assert!(text.contains("z ^ z >> < super :: simd :: Simd < u64 , N > > :: splat (30)"));
+    assert!(text.contains("Self :: from_bits ((z1 >> < super :: simd :: Simd < u64 , N > > :: splat (41)) . cast :: < u32 > () | ONE_BITS)"));
+    assert!(text.contains("fn f (self) -> Self { let x = self ;"));
+    // Both branches are evaluated before the select.
+    assert!(text.contains("let a = x . abs () ; let bits = Self :: UintType :: splat (0) - a . to_bits () ;"));
+    assert!(text.contains("let n = x . lanes_gt (Self :: splat (1.0)) . select (Self :: UintType :: splat (1) , Self :: UintType :: splat (0)) ;"));
+    assert!(text.contains(". select (Self :: from_bits (bits) , x +"));
+    assert!(text.contains("* Self :: rand (< super :: simd :: Simd < usize , N > > :: splat (3) . cast :: < u32 > () , x)"));
+
+    let code: syn::File = parse_quote! {
+        pub fn g(x: f32) -> f32 { x * MISSING }
+    };
+    assert!(to_simd(&code, Options::default()).is_err());
+    let code: syn::File = parse_quote! {
+        pub fn h(x: f32) -> (f32, f32) { if x < 0.0 { (x, 1.0) } else { (1.0, x) } }
+    };
+    let e = to_simd(&code, Options::default()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnsupportedCodegen);
+}
+
+#[test]
+fn test_std_simd() {
+    let code: syn::File = parse_quote! {
+        pub fn f(x: f64) -> f64 { if x > 1.0 { x.min(2.0) } else { x } }
+    };
+    let options = Options { num_bits: 64, std_simd: true };
+    let text = to_simd(&code, options).unwrap().to_token_stream().to_string();
+    assert!(text.contains("use super :: simd :: prelude :: * ;"));
+    assert!(text.contains("impl < const N : usize > StdLibm for Simd < f64 , N > {"));
+    assert!(text.contains("x . simd_gt (Self :: splat (1.0))"));
+    assert!(text.contains("SimdFloat :: simd_min (x , Self :: splat (2.0))"));
+    assert!(!text.contains("LaneCount"));
 }
@@ -37,6 +37,10 @@ fn append_token(text: &mut String, tt: &TokenTree, depth: usize) {
             }
         }
     } else if let TokenTree::Ident(_) = &tt {
+        // `extern "C"fn` would be a string with a suffix.
+        if text.ends_with('"') {
+            text.push(' ');
+        }
         let tok = tt.to_string();
         text.extend(tok.chars());
         text.extend(" ".chars());
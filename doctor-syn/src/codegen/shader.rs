@@ -0,0 +1,993 @@
+//! Translate Rust functions into WGSL or GLSL shader functions.
+//!
+//! Shaders only have 32 bit floats and integers, so only `f32` functions
+//! can be translated. WGSL does not allow NaN or infinity in constant
+//! expressions, so these come from helper functions and constants that use
+//! them become functions too. GLSL needs version 4.00 or later for `fma`.
+
+use super::fortran::sorted_consts;
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Special,
+    Ty, UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// The shading language to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// WebGPU Shading Language.
+    Wgsl,
+    /// OpenGL Shading Language 4.00 or later.
+    Glsl,
+}
+
+pub struct Options {
+    /// Prepended to the names of functions, constants and structs, eg. `ds32_`.
+    /// Shaders have no namespaces and built in functions such as `exp`
+    /// can not be redefined, so this should not be empty.
+    pub prefix: String,
+    pub dialect: Dialect,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: "ds_".to_string(),
+            dialect: Dialect::Wgsl,
+        }
+    }
+}
+
+// Keywords, types and built in functions of both languages that a Rust
+// binding could shadow.
+const RESERVED: &[&str] = &[
+    "abs", "acos", "acosh", "active", "alias", "asin", "asinh", "atan", "atan2", "atanh",
+    "attribute", "bitCount", "bitcast", "bool", "buffer", "case", "ceil", "centroid", "common",
+    "const", "cos", "cosh", "countLeadingZeros", "countOneBits", "countTrailingZeros", "default",
+    "discard", "do", "double", "exp", "exp2", "f16", "f32", "filter", "findLSB", "findMSB",
+    "fixed", "flat", "float", "floatBitsToInt", "floatBitsToUint", "floor", "fma", "fn", "goto",
+    "half", "highp", "i32", "in", "inline", "inout", "input", "int", "intBitsToFloat",
+    "interface", "invariant", "layout", "length", "let", "log", "log2", "long", "lowp", "main",
+    "max", "mediump", "min", "noinline", "noperspective", "out", "output", "override", "patch",
+    "pow", "precise", "precision", "public", "sample", "select", "shared", "short", "sign", "sin",
+    "sinh", "smooth", "sqrt", "switch", "tan", "tanh", "trunc", "u32", "uint", "uintBitsToFloat",
+    "uniform", "unsigned", "var", "varying", "vec2", "void", "volatile",
+];
+
+// Operator precedence, higher binds tighter. WGSL does not allow bitwise
+// operators to be mixed with others, so their operands are parenthesised.
+const PREC_TERNARY: u8 = 3;
+const PREC_REL: u8 = 9;
+const PREC_SHIFT: u8 = 10;
+const PREC_ADD: u8 = 11;
+const PREC_MUL: u8 = 12;
+const PREC_UNARY: u8 = 14;
+const PREC_ATOM: u8 = 16;
+
+fn binary_prec(op: BinaryOp) -> u8 {
+    use BinaryOp::*;
+    match op {
+        Mul | Div | Rem => PREC_MUL,
+        Add | Sub => PREC_ADD,
+        Shl | Shr => PREC_SHIFT,
+        Eq | Ne | Lt | Le | Gt | Ge => PREC_REL,
+        BitAnd => 8,
+        BitXor => 7,
+        BitOr => 6,
+        And => 5,
+        Or => 4,
+    }
+}
+
+impl Dialect {
+    fn name(self) -> &'static str {
+        match self {
+            Dialect::Wgsl => "WGSL",
+            Dialect::Glsl => "GLSL",
+        }
+    }
+
+    fn scalar_type(self, scalar: Scalar) -> Option<&'static str> {
+        use Scalar::*;
+        let wgsl = self == Dialect::Wgsl;
+        Some(match scalar {
+            Bool => "bool",
+            I32 | Isize if wgsl => "i32",
+            U32 | Usize if wgsl => "u32",
+            F32 if wgsl => "f32",
+            I32 | Isize => "int",
+            U32 | Usize => "uint",
+            F32 => "float",
+            _ => return None,
+        })
+    }
+
+    fn float_literal(self, digits: &str) -> String {
+        let mut text = digits.to_string();
+        if !text.contains(['.', 'e', 'E']) {
+            text.push_str(".0");
+        }
+        if self == Dialect::Wgsl {
+            text.push('f');
+        }
+        text
+    }
+
+    fn int_literal(self, value: u128, scalar: Scalar) -> String {
+        let int = self.scalar_type(scalar).unwrap_or("int");
+        match scalar {
+            Scalar::F32 => self.float_literal(&value.to_string()),
+            // Values that do not fit are reinterpreted from unsigned.
+            Scalar::I32 | Scalar::Isize if value > i32::MAX as u128 => {
+                format!("{}({}u)", int, value as u32)
+            }
+            Scalar::I32 | Scalar::Isize if self == Dialect::Wgsl => format!("{}i", value),
+            Scalar::I32 | Scalar::Isize => value.to_string(),
+            _ => format!("{}u", value),
+        }
+    }
+
+    // A bit cast between 32 bit scalars.
+    fn bitcast(self, to: Scalar, from: Scalar, x: &str) -> String {
+        use Scalar::*;
+        let to_ty = self.scalar_type(to).unwrap_or("uint");
+        match (self, to, from) {
+            (Dialect::Wgsl, ..) => format!("bitcast<{}>({})", to_ty, x),
+            (Dialect::Glsl, F32, I32 | Isize) => format!("intBitsToFloat({})", x),
+            (Dialect::Glsl, F32, _) => format!("uintBitsToFloat({})", x),
+            (Dialect::Glsl, I32 | Isize, F32) => format!("floatBitsToInt({})", x),
+            (Dialect::Glsl, _, F32) => format!("floatBitsToUint({})", x),
+            (Dialect::Glsl, ..) => format!("{}({})", to_ty, x),
+        }
+    }
+}
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    // Tuples of scalars become structs with fields `v0`, `v1`...
+    tuples: BTreeSet<Vec<Scalar>>,
+    // Indices into `HELPERS` of functions used.
+    helpers: BTreeSet<usize>,
+    // Constants that are printed as functions.
+    runtime_consts: BTreeSet<String>,
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    options: &'a Options,
+    use_counts: Vec<usize>,
+    decls: &'a mut Decls,
+    // True if something printed can not be in a constant expression.
+    runtime: bool,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(func: &'a Function, options: &'a Options, decls: &'a mut Decls) -> Self {
+        Printer {
+            func,
+            options,
+            use_counts: func.use_counts(),
+            decls,
+            runtime: false,
+            text: String::new(),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!(
+                "generating {} for {}",
+                self.options.dialect.name(),
+                self.func.name
+            ))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) if self.options.dialect.scalar_type(*scalar).is_some() => {
+                Ok(*scalar)
+            }
+            ty => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    fn shader_type(&mut self, ty: &Ty) -> Result<String> {
+        let dialect = self.options.dialect;
+        let unsupported = || self.error(&format!("the type {} is not supported", ty));
+        let scalars = match ty {
+            Ty::Scalar(scalar) => {
+                return dialect
+                    .scalar_type(*scalar)
+                    .map(str::to_string)
+                    .ok_or_else(unsupported)
+            }
+            Ty::Tuple(elems) => elems
+                .iter()
+                .map(|e| match e {
+                    Ty::Scalar(scalar) if dialect.scalar_type(*scalar).is_some() => Some(*scalar),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(unsupported)?,
+            _ => return Err(unsupported()),
+        };
+        let name = tuple_name(&self.options.prefix, &scalars);
+        self.decls.tuples.insert(scalars);
+        Ok(name)
+    }
+
+    fn name(&self, value: Value) -> String {
+        shader_name(&self.func.name(value))
+    }
+
+    fn operand(&mut self, value: Value, prec: u8) -> Result<String> {
+        let (text, p) = self.expr(value)?;
+        Ok(if p < prec { format!("({})", text) } else { text })
+    }
+
+    fn args(&mut self, args: &[Value]) -> Result<String> {
+        let args = args
+            .iter()
+            .map(|a| self.operand(*a, PREC_TERNARY))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(args.join(", "))
+    }
+
+    // True if a value is printed at its use.
+    fn is_inline(&self, value: Value) -> bool {
+        match self.func.inst(value) {
+            Some(Inst {
+                op: Op::If(_, then_body, else_body),
+                ..
+            }) => {
+                self.func.values[value.0].name.is_none()
+                    && self.use_counts[value.0] == 1
+                    && self.is_select(value, then_body, else_body)
+            }
+            _ => self.func.is_inline(value, &self.use_counts),
+        }
+    }
+
+    // True if an `if` can be printed as a selection of its results.
+    fn is_select(&self, value: Value, then_body: &Body, else_body: &Body) -> bool {
+        matches!(self.func.ty(value), Ty::Scalar(_))
+            && self.is_simple(then_body)
+            && self.is_simple(else_body)
+    }
+
+    // A value as an expression and its precedence.
+    fn expr(&mut self, value: Value) -> Result<(String, u8)> {
+        match self.func.inst(value) {
+            Some(inst) if self.is_inline(value) => self.op(inst),
+            _ => Ok((self.name(value), PREC_ATOM)),
+        }
+    }
+
+    // A call of a helper function.
+    fn helper(&mut self, name: &str, args: &[Value]) -> Result<(String, u8)> {
+        let index = HELPERS.iter().position(|h| h.0 == name).unwrap();
+        self.decls.helpers.insert(index);
+        self.runtime = true;
+        let name = helper_name(&self.options.prefix, name);
+        Ok((format!("{}({})", name, self.args(args)?), PREC_ATOM))
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<(String, u8)> {
+        let dialect = self.options.dialect;
+        // Operations whose values need not be scalars.
+        match &inst.op {
+            Op::Call(name, args) => {
+                self.runtime = true;
+                let args = self.args(args)?;
+                return Ok((format!("{}{}({})", self.options.prefix, name, args), PREC_ATOM));
+            }
+            Op::Tuple(args) => {
+                let ty = self.shader_type(self.func.ty(inst.value))?;
+                return Ok((format!("{}({})", ty, self.args(args)?), PREC_ATOM));
+            }
+            Op::Extract(a, i) => {
+                let a = self.operand(*a, PREC_ATOM)?;
+                return Ok((format!("{}.v{}", a, i), PREC_ATOM));
+            }
+            _ => (),
+        }
+        let scalar = self.scalar(inst.value)?;
+        let ty = dialect.scalar_type(scalar).unwrap();
+        Ok(match &inst.op {
+            Op::Lit(Literal::Float(digits)) => (dialect.float_literal(digits), PREC_ATOM),
+            Op::Lit(Literal::Int(i)) => (dialect.int_literal(*i, scalar), PREC_ATOM),
+            Op::Lit(Literal::Bool(b)) => (b.to_string(), PREC_ATOM),
+            Op::Lit(Literal::Special(s)) => self.special(*s)?,
+            Op::Const(name) if self.decls.runtime_consts.contains(name) => {
+                self.runtime = true;
+                (format!("{}{}()", self.options.prefix, name), PREC_ATOM)
+            }
+            Op::Const(name) => (format!("{}{}", self.options.prefix, name), PREC_ATOM),
+            Op::Unary(UnaryOp::Neg, a) if !scalar.is_signed() => {
+                let zero = dialect.int_literal(0, scalar);
+                (format!("{} - {}", zero, self.operand(*a, PREC_ADD + 1)?), PREC_ADD)
+            }
+            Op::Unary(op, a) => {
+                let sym = match (op, scalar) {
+                    (UnaryOp::Neg, _) => "-",
+                    (UnaryOp::Not, Scalar::Bool) => "!",
+                    (UnaryOp::Not, _) => "~",
+                };
+                (format!("{}{}", sym, self.unary_operand(*a)?), PREC_UNARY)
+            }
+            Op::Binary(op, a, b) => self.binary(*op, *a, *b)?,
+            Op::Convert(a) => {
+                let from = self.scalar(*a)?;
+                if scalar == Scalar::Bool {
+                    return Err(self.error("conversions to bool are not supported"));
+                }
+                if dialect.scalar_type(from) == Some(ty) {
+                    return self.expr(*a);
+                }
+                (format!("{}({})", ty, self.args(&[*a])?), PREC_ATOM)
+            }
+            Op::Bitcast(a) => {
+                let from = self.scalar(*a)?;
+                let x = self.args(&[*a])?;
+                (dialect.bitcast(scalar, from, &x), PREC_ATOM)
+            }
+            Op::Select(c, a, b) => self.select(*c, *a, *b)?,
+            Op::If(c, then_body, else_body) => {
+                self.select(*c, then_body.result, else_body.result)?
+            }
+            Op::Intrinsic(i, args) => self.intrinsic(*i, args)?,
+            Op::Splat(_) => return Err(self.error("vectors are not supported")),
+            Op::Call(..) | Op::Tuple(_) | Op::Extract(..) => unreachable!("printed above"),
+        })
+    }
+
+    // The operand of a prefix operator, which must not make `--`.
+    fn unary_operand(&mut self, a: Value) -> Result<String> {
+        let text = self.operand(a, PREC_UNARY)?;
+        Ok(if text.starts_with('-') {
+            format!("({})", text)
+        } else {
+            text
+        })
+    }
+
+    fn special(&mut self, special: Special) -> Result<(String, u8)> {
+        let dialect = self.options.dialect;
+        Ok(match special {
+            Special::Nan => self.helper("nan", &[])?,
+            Special::Infinity => self.helper("infinity", &[])?,
+            Special::NegInfinity => {
+                let (inf, _) = self.helper("infinity", &[])?;
+                (format!("-{}", inf), PREC_UNARY)
+            }
+            Special::MinPositive => (dialect.float_literal("1.17549435e-38"), PREC_ATOM),
+            Special::Max => (dialect.float_literal("3.40282347e38"), PREC_ATOM),
+            Special::Min => (format!("-{}", dialect.float_literal("3.40282347e38")), PREC_UNARY),
+            Special::Epsilon => (dialect.float_literal("1.1920929e-7"), PREC_ATOM),
+        })
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: Value, b: Value) -> Result<(String, u8)> {
+        use BinaryOp::*;
+        let dialect = self.options.dialect;
+        let scalar = self.scalar(a)?;
+        // Bitwise operators on bools become logical ones, which GLSL needs.
+        let op = match op {
+            BitAnd if scalar == Scalar::Bool => And,
+            BitOr if scalar == Scalar::Bool => Or,
+            BitXor if scalar == Scalar::Bool => Ne,
+            op => op,
+        };
+        if op == Rem && scalar.is_float() && dialect == Dialect::Glsl {
+            // GLSL's `mod` rounds towards negative infinity.
+            let x = self.operand(a, PREC_ADD)?;
+            let y = self.operand(b, PREC_MUL + 1)?;
+            let xy = self.args(&[a, b])?.replace(", ", " / ");
+            let text = format!("{} - {} * trunc({})", x, y, xy);
+            return Ok((text, PREC_ADD));
+        }
+        let prec = binary_prec(op);
+        let (min_a, min_b) = match op {
+            Shl | Shr | BitAnd | BitXor | BitOr => (PREC_UNARY, PREC_UNARY),
+            And | Or => (PREC_REL, PREC_REL),
+            Eq | Ne | Lt | Le | Gt | Ge => (PREC_SHIFT, PREC_SHIFT),
+            _ => (prec, prec + 1),
+        };
+        let x = self.operand(a, min_a)?;
+        // WGSL shifts by a `u32`.
+        let by_u32 = matches!(self.scalar(b)?, Scalar::U32 | Scalar::Usize);
+        let y = if matches!(op, Shl | Shr) && dialect == Dialect::Wgsl && !by_u32 {
+            match self.func.inst(b).map(|inst| &inst.op) {
+                Some(Op::Lit(Literal::Int(i))) if self.is_inline(b) => format!("{}u", i),
+                _ => format!("u32({})", self.args(&[b])?),
+            }
+        } else {
+            self.operand(b, min_b)?
+        };
+        Ok((format!("{} {} {}", x, op.symbol(), y), prec))
+    }
+
+    fn select(&mut self, c: Value, a: Value, b: Value) -> Result<(String, u8)> {
+        if !matches!(self.func.ty(a), Ty::Scalar(_)) {
+            return Err(self.error("selecting tuples is not supported"));
+        }
+        if self.options.dialect == Dialect::Wgsl {
+            return Ok((format!("select({})", self.args(&[b, a, c])?), PREC_ATOM));
+        }
+        let c = self.operand(c, PREC_TERNARY + 1)?;
+        let a = self.operand(a, PREC_TERNARY + 1)?;
+        let b = self.operand(b, PREC_TERNARY)?;
+        Ok((format!("{} ? {} : {}", c, a, b), PREC_TERNARY))
+    }
+
+    // The bits of a float as an unsigned integer.
+    fn bits(&mut self, x: Value) -> Result<String> {
+        let x = self.args(&[x])?;
+        Ok(self.options.dialect.bitcast(Scalar::U32, Scalar::F32, &x))
+    }
+
+    fn intrinsic(&mut self, i: Intrinsic, args: &[Value]) -> Result<(String, u8)> {
+        use Intrinsic::*;
+        let dialect = self.options.dialect;
+        let wgsl = dialect == Dialect::Wgsl;
+        let arg_scalar = self.scalar(args[0])?;
+        let call = |this: &mut Self, name: &str| -> Result<(String, u8)> {
+            Ok((format!("{}({})", name, this.args(args)?), PREC_ATOM))
+        };
+        let one = dialect.float_literal("1.0");
+        if arg_scalar.is_int() {
+            let signed = arg_scalar.is_signed();
+            let x = self.args(&args[0..1])?;
+            return match i {
+                WrappingAdd => self.binary(BinaryOp::Add, args[0], args[1]),
+                WrappingSub => self.binary(BinaryOp::Sub, args[0], args[1]),
+                WrappingMul => self.binary(BinaryOp::Mul, args[0], args[1]),
+                WrappingNeg if signed => {
+                    Ok((format!("-{}", self.unary_operand(args[0])?), PREC_UNARY))
+                }
+                WrappingNeg => {
+                    let zero = dialect.int_literal(0, arg_scalar);
+                    let x = self.operand(args[0], PREC_ADD + 1)?;
+                    Ok((format!("{} - {}", zero, x), PREC_ADD))
+                }
+                // Counts are `u32` in Rust.
+                CountOnes | LeadingZeros | TrailingZeros if wgsl => {
+                    let name = match i {
+                        CountOnes => "countOneBits",
+                        LeadingZeros => "countLeadingZeros",
+                        _ => "countTrailingZeros",
+                    };
+                    let count = format!("{}({})", name, x);
+                    Ok((if signed { format!("u32({})", count) } else { count }, PREC_ATOM))
+                }
+                CountOnes => Ok((format!("uint(bitCount({}))", x), PREC_ATOM)),
+                // `findMSB` and `findLSB` return -1 for zero.
+                LeadingZeros => Ok((format!("uint(31 - findMSB(uint({})))", x), PREC_ATOM)),
+                TrailingZeros => Ok((format!("min(uint(findLSB({})), 32u)", x), PREC_ATOM)),
+                Abs => call(self, "abs"),
+                Min => call(self, "min"),
+                Max => call(self, "max"),
+                _ => Err(self.error(&format!("`{}` is not supported for integers", i.name()))),
+            };
+        }
+        match i {
+            Abs | Sqrt | Sin | Cos | Tan | Asin | Acos | Atan | Sinh | Cosh | Tanh | Asinh
+            | Acosh | Atanh | Exp | Exp2 | Log2 | Floor | Ceil | Trunc | Min | Max => {
+                call(self, i.name())
+            }
+            Atan2 if wgsl => call(self, "atan2"),
+            Atan2 => call(self, "atan"),
+            Ln => call(self, "log"),
+            Powf => call(self, "pow"),
+            Round | Copysign | Signum | Cbrt => self.helper(i.name(), args),
+            MulAdd => call(self, "fma"),
+            ExpM1 => Ok((format!("exp({}) - {}", self.args(args)?, one), PREC_ADD)),
+            Ln1p => {
+                let x = self.operand(args[0], PREC_ADD + 1)?;
+                Ok((format!("log({} + {})", one, x), PREC_ATOM))
+            }
+            Log10 => {
+                let x = self.args(args)?;
+                let ten = dialect.float_literal("10.0");
+                Ok((format!("log({}) / log({})", x, ten), PREC_MUL))
+            }
+            Log => {
+                let x = self.args(&args[0..1])?;
+                let base = self.args(&args[1..2])?;
+                Ok((format!("log({}) / log({})", x, base), PREC_MUL))
+            }
+            Powi => {
+                let x = self.args(&args[0..1])?;
+                let n = self.args(&args[1..2])?;
+                let ty = dialect.scalar_type(Scalar::F32).unwrap();
+                Ok((format!("pow({}, {}({}))", x, ty, n), PREC_ATOM))
+            }
+            Hypot => {
+                let vec2 = if wgsl { "vec2<f32>" } else { "vec2" };
+                Ok((format!("length({}({}))", vec2, self.args(args)?), PREC_ATOM))
+            }
+            Recip => {
+                let x = self.operand(args[0], PREC_MUL + 1)?;
+                Ok((format!("{} / {}", one, x), PREC_MUL))
+            }
+            // Rust's `fract` is relative to `trunc`, the built in one to `floor`.
+            Fract => {
+                let x = self.operand(args[0], PREC_ADD)?;
+                Ok((format!("{} - trunc({})", x, self.args(args)?), PREC_ADD))
+            }
+            // Shaders may assume that there are no NaNs, so these test the bits.
+            IsNan | IsInfinite | IsFinite => {
+                let op = match i {
+                    IsNan => ">",
+                    IsInfinite => "==",
+                    _ => "<",
+                };
+                let bits = self.bits(args[0])?;
+                let text = format!("({} & 0x7fffffffu) {} 0x7f800000u", bits, op);
+                Ok((text, PREC_REL))
+            }
+            IsSignNegative | IsSignPositive => {
+                let op = if i == IsSignNegative { ">=" } else { "<" };
+                let bits = self.bits(args[0])?;
+                Ok((format!("{} {} 0x80000000u", bits, op), PREC_REL))
+            }
+            _ => Err(self.error(&format!("`{}` is not supported", i.name()))),
+        }
+    }
+
+    fn line(&mut self, indent: usize, line: &str) {
+        let _ = writeln!(self.text, "{}{}", "    ".repeat(indent), line);
+    }
+
+    // A statement that defines a variable.
+    fn define(&mut self, indent: usize, ty: &str, name: &str, expr: Option<&str>) {
+        let line = match (self.options.dialect, expr) {
+            (Dialect::Wgsl, Some(expr)) => format!("let {}: {} = {};", name, ty, expr),
+            (Dialect::Wgsl, None) => format!("var {}: {};", name, ty),
+            (Dialect::Glsl, Some(expr)) => format!("{} {} = {};", ty, name, expr),
+            (Dialect::Glsl, None) => format!("{} {};", ty, name),
+        };
+        self.line(indent, &line);
+    }
+
+    // Print the materialised instructions of a body.
+    fn body(&mut self, body: &Body, indent: usize) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            // Functions are pure so unused calls can be dropped.
+            if self.is_inline(value) || self.use_counts[value.0] == 0 {
+                continue;
+            }
+            let name = self.name(value);
+            let ty = self.shader_type(self.func.ty(value))?;
+            match &inst.op {
+                Op::If(c, then_body, else_body)
+                    if !self.is_select(value, then_body, else_body) =>
+                {
+                    let (cond, _) = self.expr(*c)?;
+                    self.define(indent, &ty, &name, None);
+                    if self.options.dialect == Dialect::Wgsl {
+                        self.line(indent, &format!("if {} {{", cond));
+                    } else {
+                        self.line(indent, &format!("if ({}) {{", cond));
+                    }
+                    self.branch(then_body, &name, indent + 1)?;
+                    self.line(indent, "} else {");
+                    self.branch(else_body, &name, indent + 1)?;
+                    self.line(indent, "}");
+                }
+                _ => {
+                    let (expr, _) = self.op(inst)?;
+                    self.define(indent, &ty, &name, Some(&expr));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn branch(&mut self, body: &Body, name: &str, indent: usize) -> Result<()> {
+        self.body(body, indent)?;
+        let (result, _) = self.expr(body.result)?;
+        self.line(indent, &format!("{} = {};", name, result));
+        Ok(())
+    }
+
+    // True if a branch has no statements of its own.
+    fn is_simple(&self, body: &Body) -> bool {
+        body.insts.iter().all(|inst| self.is_inline(inst.value))
+    }
+}
+
+// A Rust name as a shader name. WGSL names can not start with `__`.
+fn shader_name(name: &str) -> String {
+    if name.starts_with('_') {
+        format!("u{}", name)
+    } else if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn tuple_name(prefix: &str, scalars: &[Scalar]) -> String {
+    let names = scalars.iter().map(|s| s.name()).collect::<Vec<_>>();
+    format!("{}tuple_{}", prefix, names.join("_"))
+}
+
+fn helper_name(prefix: &str, name: &str) -> String {
+    format!("{}{}_f32", prefix, name)
+}
+
+// Functions without a built in, their parameters and their WGSL and GLSL bodies.
+// NaN and infinity are loaded from variables so they are not constant expressions.
+const HELPERS: &[(&str, &[&str], &str, &str)] = &[
+    (
+        "nan",
+        &[],
+        "var bits = 0x7fc00000u;\nreturn bitcast<f32>(bits);",
+        "uint bits = 0x7fc00000u;\nreturn uintBitsToFloat(bits);",
+    ),
+    (
+        "infinity",
+        &[],
+        "var bits = 0x7f800000u;\nreturn bitcast<f32>(bits);",
+        "uint bits = 0x7f800000u;\nreturn uintBitsToFloat(bits);",
+    ),
+    // The built in `round` may round halfway cases to even.
+    (
+        "round",
+        &["x"],
+        "let t = trunc(x);\nreturn select(t, t + sign(x), abs(x - t) >= 0.5f);",
+        "float t = trunc(x);\nreturn abs(x - t) >= 0.5 ? t + sign(x) : t;",
+    ),
+    (
+        "copysign",
+        &["x", "y"],
+        "return bitcast<f32>((bitcast<u32>(x) & 0x7fffffffu) | (bitcast<u32>(y) & 0x80000000u));",
+        "return uintBitsToFloat((floatBitsToUint(x) & 0x7fffffffu) | (floatBitsToUint(y) & 0x80000000u));",
+    ),
+    // The built in `sign` is zero for zero.
+    (
+        "signum",
+        &["x"],
+        "let bits = bitcast<u32>(x);\nlet one = bitcast<f32>((bits & 0x80000000u) | 0x3f800000u);\nreturn select(one, x, (bits & 0x7fffffffu) > 0x7f800000u);",
+        "uint bits = floatBitsToUint(x);\nfloat one = uintBitsToFloat((bits & 0x80000000u) | 0x3f800000u);\nreturn (bits & 0x7fffffffu) > 0x7f800000u ? x : one;",
+    ),
+    (
+        "cbrt",
+        &["x"],
+        "return sign(x) * pow(abs(x), 1.0f / 3.0f);",
+        "return sign(x) * pow(abs(x), 1.0 / 3.0);",
+    ),
+];
+
+// The signature of a function without the trailing `{` or `;`.
+fn signature(dialect: Dialect, ret: &str, name: &str, params: &[(String, String)]) -> String {
+    match dialect {
+        Dialect::Wgsl => {
+            let params = params
+                .iter()
+                .map(|(ty, name)| format!("{}: {}", name, ty))
+                .collect::<Vec<_>>();
+            format!("fn {}({}) -> {}", name, params.join(", "), ret)
+        }
+        Dialect::Glsl => {
+            let params = params
+                .iter()
+                .map(|(ty, name)| format!("{} {}", ty, name))
+                .collect::<Vec<_>>();
+            format!("{} {}({})", ret, name, params.join(", "))
+        }
+    }
+}
+
+fn print_helper(options: &Options, index: usize) -> String {
+    let (name, params, wgsl, glsl) = HELPERS[index];
+    let (body, ty) = match options.dialect {
+        Dialect::Wgsl => (wgsl, "f32"),
+        Dialect::Glsl => (glsl, "float"),
+    };
+    let params = params
+        .iter()
+        .map(|p| (ty.to_string(), p.to_string()))
+        .collect::<Vec<_>>();
+    let name = helper_name(&options.prefix, name);
+    let mut text = format!("{} {{\n", signature(options.dialect, ty, &name, &params));
+    for line in body.lines() {
+        let _ = writeln!(text, "    {}", line);
+    }
+    text.push_str("}\n");
+    text
+}
+
+fn print_tuple(dialect: Dialect, prefix: &str, scalars: &[Scalar]) -> String {
+    let mut text = format!("struct {} {{\n", tuple_name(prefix, scalars));
+    for (i, scalar) in scalars.iter().enumerate() {
+        let ty = dialect.scalar_type(*scalar).unwrap();
+        match dialect {
+            Dialect::Wgsl => {
+                let _ = writeln!(text, "    v{}: {},", i, ty);
+            }
+            Dialect::Glsl => {
+                let _ = writeln!(text, "    {} v{};", ty, i);
+            }
+        }
+    }
+    text.push_str(if dialect == Dialect::Wgsl { "}\n" } else { "};\n" });
+    text
+}
+
+fn docs(text: &mut String, func: &Function) {
+    for doc in &func.docs {
+        let _ = writeln!(text, "//{}", doc);
+    }
+}
+
+fn print_fn(func: &Function, options: &Options, decls: &mut Decls) -> Result<String> {
+    let mut printer = Printer::new(func, options, decls);
+    let mut params = Vec::new();
+    for p in &func.params {
+        let ty = printer.shader_type(func.ty(*p))?;
+        params.push((ty, printer.name(*p)));
+    }
+    let ret = printer.shader_type(&func.ret)?;
+    printer.body(&func.body, 1)?;
+    let (result, _) = printer.expr(func.body.result)?;
+    printer.line(1, &format!("return {};", result));
+
+    let name = format!("{}{}", options.prefix, func.name);
+    let mut text = String::new();
+    docs(&mut text, func);
+    let _ = writeln!(text, "{} {{", signature(options.dialect, &ret, &name, &params));
+    text.push_str(&printer.text);
+    text.push_str("}\n");
+    Ok(text)
+}
+
+// Constants that are not constant expressions become functions.
+fn print_const(func: &Function, options: &Options, decls: &mut Decls) -> Result<String> {
+    let mut printer = Printer::new(func, options, decls);
+    if !printer.is_simple(&func.body) {
+        return Err(printer.error("constants must be expressions"));
+    }
+    let ty = printer.shader_type(&func.ret)?;
+    let (expr, _) = printer.expr(func.body.result)?;
+    let name = format!("{}{}", options.prefix, func.name);
+    let mut text = String::new();
+    docs(&mut text, func);
+    if printer.runtime {
+        printer.decls.runtime_consts.insert(func.name.clone());
+        let signature = signature(options.dialect, &ty, &name, &[]);
+        let _ = writeln!(text, "{} {{\n    return {};\n}}", signature, expr);
+    } else if options.dialect == Dialect::Wgsl {
+        let _ = writeln!(text, "const {}: {} = {};", name, ty, expr);
+    } else {
+        let _ = writeln!(text, "const {} {} = {};", ty, name, expr);
+    }
+    Ok(text)
+}
+
+// Functions in an order where each is defined after the functions it calls,
+// as GLSL needs.
+// The names of the functions that `func` calls.
+fn calls(func: &Function) -> Vec<String> {
+    let mut names = Vec::new();
+    func.body.walk(&mut |inst| {
+        if let Op::Call(name, _) = &inst.op {
+            names.push(name.clone());
+        }
+    });
+    names
+}
+
+fn sorted_functions(module: &Module) -> Vec<&Function> {
+    let mut sorted: Vec<&Function> = Vec::new();
+    let mut pending = module.functions.iter().collect::<Vec<_>>();
+    while !pending.is_empty() {
+        let before = pending.len();
+        pending.retain(|f| {
+            let ready = calls(f).iter().all(|called| {
+                sorted.iter().any(|s| &s.name == called) || module.function(called).is_none()
+            });
+            if ready {
+                sorted.push(f);
+            }
+            !ready
+        });
+        // Recursion is left for the compiler to report.
+        if pending.len() == before {
+            sorted.append(&mut pending);
+        }
+    }
+    sorted
+}
+
+/// Translate a module of the IR into WGSL or GLSL functions.
+///
+/// GLSL has no `#version` line so that the functions can be
+/// pasted into a shader.
+pub fn module_to_shader(module: &Module, options: &Options) -> Result<String> {
+    let mut decls = Decls::default();
+    let mut consts = Vec::new();
+    for c in sorted_consts(module) {
+        consts.push(print_const(c, options, &mut decls)?);
+    }
+    let mut functions = Vec::new();
+    for func in sorted_functions(module) {
+        functions.push(print_fn(func, options, &mut decls)?);
+    }
+
+    let mut text = String::new();
+    for scalars in &decls.tuples {
+        text.push_str(&print_tuple(options.dialect, &options.prefix, scalars));
+        text.push('\n');
+    }
+    for index in &decls.helpers {
+        text.push_str(&print_helper(options, *index));
+        text.push('\n');
+    }
+    for c in &consts {
+        text.push_str(c);
+    }
+    for func in &functions {
+        text.push('\n');
+        text.push_str(func);
+    }
+    Ok(text)
+}
+
+/// Translate a Rust file into WGSL or GLSL functions.
+pub fn to_shader(file: &syn::File, options: Options) -> Result<String> {
+    module_to_shader(&lower_file(file)?, &options)
+}
+
+/// Translate the functions of a Rust file that the dialect can express,
+/// leaving out the others and those that call them. Also returns why each
+/// was left out, or fails with the first reason if none are left.
+pub fn to_shader_partial(file: &syn::File, options: Options) -> Result<(String, Vec<Error>)> {
+    let mut module = lower_file(file)?;
+    let mut left_out: Vec<String> = Vec::new();
+    let mut errors = Vec::new();
+    for func in sorted_functions(&module) {
+        let result = match calls(func).into_iter().find(|name| left_out.contains(name)) {
+            Some(name) => Err(Error::new(ErrorKind::UnsupportedCodegen)
+                .with_operation(format!("generating {} for {}", options.dialect.name(), func.name))
+                .with_message(format!("it calls {}, which is left out", name))),
+            None => print_fn(func, &options, &mut Decls::default()).map(|_| ()),
+        };
+        if let Err(e) = result {
+            left_out.push(func.name.clone());
+            errors.push(e);
+        }
+    }
+    if !errors.is_empty() && left_out.len() == module.functions.len() {
+        return Err(errors.remove(0));
+    }
+    module.functions.retain(|f| !left_out.contains(&f.name));
+    Ok((module_to_shader(&module, &options)?, errors))
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        const HALF: f32 = 0.5;
+        const INFINITY: f32 = f32::INFINITY;
+
+        /// Add half.
+        pub fn f(x: f32) -> f32 {
+            let y = x * -2.0;
+            if x < 0.0 {
+                let z = y.round();
+                z.mul_add(HALF, INFINITY)
+            } else {
+                if x > 1.0 { y } else { HALF }
+            }
+        }
+    };
+    let w = to_shader(&code, Options::default()).unwrap();
+    assert!(w.contains("fn ds_round_f32(x: f32) -> f32 {\n    let t = trunc(x);\n"));
+    assert!(w.contains("const ds_HALF: f32 = 0.5f;\n"));
+    assert!(w.contains("fn ds_INFINITY() -> f32 {\n    return ds_infinity_f32();\n}\n"));
+    assert!(w.contains("// Add half.\nfn ds_f(x: f32) -> f32 {\n    let y: f32 = x * -2.0f;\n"));
+    assert!(w.contains("    if x < 0.0f {\n        let z: f32 = ds_round_f32(y);\n"));
+    assert!(w.contains("fma(z, ds_HALF, ds_INFINITY());\n    } else {\n"));
+    assert!(w.contains("select(ds_HALF, y, x > 1.0f);\n"));
+
+    let options = Options {
+        prefix: "ds_".to_string(),
+        dialect: Dialect::Glsl,
+    };
+    let g = to_shader(&code, options).unwrap();
+    assert!(g.contains("float ds_round_f32(float x) {\n    float t = trunc(x);\n"));
+    assert!(g.contains("const float ds_HALF = 0.5;\n"));
+    assert!(g.contains("float ds_f(float x) {\n    float y = x * -2.0;\n"));
+    assert!(g.contains("    if (x < 0.0) {\n"));
+    assert!(g.contains("x > 1.0 ? y : ds_HALF;\n"));
+}
+
+#[test]
+fn test_bits_and_tuples() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn g(x: f32) -> f32 {
+            let (a, b) = f(x);
+            if b { a } else { -a }
+        }
+        fn f(x: f32) -> (f32, bool) {
+            let bits = x.to_bits();
+            let e = ((bits >> 23) & 0xff) as i32 - 127;
+            let y = f32::from_bits((bits >> 1) & 0xff800000);
+            (if x.is_nan() { f32::NAN } else { y * (e as f32) }, bits.leading_zeros() < 3)
+        }
+    };
+    let w = to_shader(&code, Options::default()).unwrap();
+    assert!(w.contains("struct ds_tuple_f32_bool {\n    v0: f32,\n    v1: bool,\n}\n"));
+    assert!(w.contains("let bits: u32 = bitcast<u32>(x);\n"));
+    assert!(w.contains("let e: i32 = i32((bits >> 23u) & 255u) - 127i;\n"));
+    assert!(w.contains("bitcast<f32>((bits >> 1u) & 4286578688u)"));
+    assert!(w.contains("select(y * f32(e), ds_nan_f32(), (bitcast<u32>(x) & 0x7fffffffu) > 0x7f800000u)"));
+    assert!(w.contains("countLeadingZeros(bits) < 3u"));
+    assert!(w.contains("    let v1: ds_tuple_f32_bool = ds_f(x);\n    let a: f32 = v1.v0;\n"));
+    assert!(w.contains("    return select(-a, a, b);\n"));
+    // Functions are defined before they are called.
+    assert!(w.find("fn ds_f(").unwrap() < w.find("fn ds_g(").unwrap());
+
+    let options = Options {
+        prefix: "ds_".to_string(),
+        dialect: Dialect::Glsl,
+    };
+    let g = to_shader(&code, options).unwrap();
+    assert!(g.contains("struct ds_tuple_f32_bool {\n    float v0;\n    bool v1;\n};\n"));
+    assert!(g.contains("uint bits = floatBitsToUint(x);\n"));
+    assert!(g.contains("int e = int((bits >> 23) & 255u) - 127;\n"));
+    assert!(g.contains("uint(31 - findMSB(uint(bits))) < 3u"));
+    assert!(g.contains("ds_tuple_f32_bool(("));
+
+    let code: syn::File = parse_quote! {
+        fn h(x: f64) -> f64 {
+            x
+        }
+    };
+    let e = to_shader(&code, Options::default()).unwrap_err();
+    assert!(e.to_string().contains("the type f64 is not supported"));
+}
+
+#[test]
+fn test_partial() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            x * 2.0
+        }
+        fn g(i: u64) -> f32 {
+            (i >> 40) as f32
+        }
+        fn h(i: u64) -> f32 {
+            f(g(i))
+        }
+    };
+    let (w, errors) = to_shader_partial(&code, Options::default()).unwrap();
+    assert!(w.contains("fn ds_f(x: f32) -> f32 {\n"));
+    assert!(!w.contains("ds_g") && !w.contains("ds_h"));
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].to_string().contains("generating WGSL for g"));
+    assert!(errors[0].to_string().contains("the type u64 is not supported"));
+    assert!(errors[1].to_string().contains("it calls g, which is left out"));
+
+    let code: syn::File = parse_quote! {
+        fn g(i: u64) -> f32 {
+            (i >> 40) as f32
+        }
+    };
+    let e = to_shader_partial(&code, Options::default()).unwrap_err();
+    assert!(e.to_string().contains("the type u64 is not supported"));
+}
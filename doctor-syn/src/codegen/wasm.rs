@@ -0,0 +1,1162 @@
+//! Translate Rust functions into the WebAssembly text format.
+//!
+//! Values used once are folded into the expression that uses them, other
+//! values become locals. With a lane count every value is a SIMD128 `v128`,
+//! eg. `f64x2`, bools are lane masks and `if`s evaluate both branches and
+//! `bitselect`. Operations without a vector instruction are done lane by lane.
+//!
+//! WebAssembly has no fused multiply-add and no maths functions: `mul_add`
+//! is a multiply and an add, or `relaxed_madd` if allowed, and functions
+//! such as `tan` are imported from the JavaScript `Math` object.
+
+use crate::codegen::llvm_ir::special_value;
+use crate::ir::{
+    lower_file, BinaryOp, Body, Function, Inst, Intrinsic, Literal, Module, Op, Scalar, Ty,
+    UnaryOp, Value,
+};
+use crate::{Error, ErrorKind, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+pub struct Options {
+    /// Prepended to the names of functions and constants, eg. `ds64_`.
+    pub prefix: String,
+    /// Make every value a 128 bit vector of this many lanes, 2 or 4.
+    pub lanes: Option<usize>,
+    /// Use `relaxed_madd` from the relaxed SIMD proposal for vector `mul_add`.
+    pub relaxed_simd: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: "".to_string(),
+            lanes: None,
+            relaxed_simd: false,
+        }
+    }
+}
+
+fn num_type(scalar: Scalar) -> Option<&'static str> {
+    use Scalar::*;
+    match scalar {
+        Bool | I32 | U32 => Some("i32"),
+        I64 | U64 | Isize | Usize => Some("i64"),
+        F32 => Some("f32"),
+        F64 => Some("f64"),
+        I8 | U8 | I16 | U16 => None,
+    }
+}
+
+// The number of bits of a scalar in WebAssembly.
+fn wasm_bits(scalar: Scalar) -> usize {
+    match num_type(scalar) {
+        Some("i64") | Some("f64") => 64,
+        _ => 32,
+    }
+}
+
+// Signed or unsigned, the suffix of integer instructions.
+fn sign(scalar: Scalar) -> &'static str {
+    if scalar.is_signed() {
+        "s"
+    } else {
+        "u"
+    }
+}
+
+// A float in a form that reads back exactly.
+fn float_text(value: f64, scalar: Scalar) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else if scalar == Scalar::F32 {
+        format!("{:?}", value as f32)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+// An integer wrapped to the size of the scalar, as a signed value.
+fn int_text(value: u128, scalar: Scalar) -> String {
+    let shift = 128 - wasm_bits(scalar);
+    (((value as i128) << shift) >> shift).to_string()
+}
+
+// Declarations shared by the functions of a module.
+#[derive(Default)]
+struct Decls {
+    // Imports of `Math` functions.
+    imports: BTreeSet<String>,
+    // Constants that are globals rather than functions.
+    globals: BTreeSet<String>,
+}
+
+struct Printer<'a> {
+    func: &'a Function,
+    options: &'a Options,
+    // Constants are scalars even when functions are vectors.
+    lanes: Option<usize>,
+    decls: &'a mut Decls,
+    use_counts: Vec<usize>,
+    // The expressions that push each value, one per tuple element.
+    operands: Vec<Option<Vec<String>>>,
+    // Local names in use, which must be unique in a function.
+    names: BTreeSet<String>,
+    locals: Vec<String>,
+    depth: usize,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(
+        func: &'a Function,
+        options: &'a Options,
+        lanes: Option<usize>,
+        decls: &'a mut Decls,
+    ) -> Self {
+        Printer {
+            func,
+            options,
+            lanes,
+            decls,
+            use_counts: func.use_counts(),
+            operands: vec![None; func.values.len()],
+            names: BTreeSet::new(),
+            locals: Vec::new(),
+            depth: 0,
+            text: String::new(),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation(format!("generating WebAssembly for {}", self.func.name))
+            .with_message(message)
+    }
+
+    fn scalar(&self, value: Value) -> Result<Scalar> {
+        match self.func.ty(value) {
+            Ty::Scalar(scalar) => Ok(*scalar),
+            ty => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    // The bits of a mask lane, which is the size of the widest lane.
+    fn mask_bits(&self) -> usize {
+        128 / self.lanes.unwrap_or(4)
+    }
+
+    // The bits of a lane of a scalar, bools are masks.
+    fn lane_bits(&self, scalar: Scalar) -> usize {
+        match scalar {
+            Scalar::Bool => self.mask_bits(),
+            scalar => wasm_bits(scalar),
+        }
+    }
+
+    // The vector shape of a scalar, eg. `i32x4`, narrow values use the low lanes.
+    fn shape(&self, scalar: Scalar) -> String {
+        let bits = self.lane_bits(scalar);
+        let kind = if scalar.is_float() { "f" } else { "i" };
+        format!("{}{}x{}", kind, bits, 128 / bits)
+    }
+
+    fn scalar_type(&self, scalar: Scalar) -> Result<&'static str> {
+        let ty = num_type(scalar)
+            .ok_or_else(|| self.error(&format!("the type {} is not supported", scalar.name())))?;
+        match self.lanes {
+            Some(lanes) if self.lane_bits(scalar) * lanes > 128 => Err(self.error(&format!(
+                "the type {} does not fit in {} lanes",
+                scalar.name(),
+                lanes
+            ))),
+            Some(_) => Ok("v128"),
+            None => Ok(ty),
+        }
+    }
+
+    // The scalars of a type, the elements of tuples.
+    fn scalars(&self, ty: &Ty) -> Result<Vec<Scalar>> {
+        match ty {
+            Ty::Scalar(scalar) => Ok(vec![*scalar]),
+            Ty::Tuple(elems) => elems
+                .iter()
+                .map(|e| match e {
+                    Ty::Scalar(scalar) => Ok(*scalar),
+                    _ => Err(self.error(&format!("the type {} is not supported", ty))),
+                })
+                .collect(),
+            _ => Err(self.error(&format!("the type {} is not supported", ty))),
+        }
+    }
+
+    fn types(&self, ty: &Ty) -> Result<Vec<&'static str>> {
+        self.scalars(ty)?
+            .into_iter()
+            .map(|s| self.scalar_type(s))
+            .collect()
+    }
+
+    // An instruction on a scalar, eg. `f64.add` or `f64x2.add`.
+    fn ins(&self, scalar: Scalar, name: &str) -> String {
+        match self.lanes {
+            Some(_) => format!("{}.{}", self.shape(scalar), name),
+            None => format!("{}.{}", num_type(scalar).unwrap_or("i32"), name),
+        }
+    }
+
+    // A bitwise instruction, which is the same for every vector shape.
+    fn bitwise(&self, scalar: Scalar, name: &str) -> String {
+        match self.lanes {
+            Some(_) => format!("v128.{}", name),
+            None => self.ins(scalar, name),
+        }
+    }
+
+    // A constant, splatted if there are lanes. Bools are `0` or `1`.
+    fn constant(&self, scalar: Scalar, text: &str) -> String {
+        match self.lanes {
+            Some(lanes) if scalar == Scalar::Bool => {
+                let lane = if text == "0" { "0" } else { "-1" };
+                let shape = format!("i{}x{}", self.mask_bits(), lanes);
+                format!("(v128.const {} {})", shape, vec![lane; lanes].join(" "))
+            }
+            Some(_) => {
+                let lanes = 128 / wasm_bits(scalar);
+                let lanes = vec![text; lanes].join(" ");
+                format!("(v128.const {} {})", self.shape(scalar), lanes)
+            }
+            None => format!("({}.const {})", num_type(scalar).unwrap_or("i32"), text),
+        }
+    }
+
+    // Broadcast a scalar expression to every lane.
+    fn splat(&self, scalar: Scalar, x: String) -> String {
+        if self.lanes.is_none() {
+            return x;
+        }
+        if scalar != Scalar::Bool {
+            return format!("({}.splat {})", self.shape(scalar), x);
+        }
+        // A bool of 0 or 1 becomes a mask of zeros or ones.
+        match self.mask_bits() {
+            32 => format!("(i32x4.splat (i32.sub (i32.const 0) {}))", x),
+            _ => format!(
+                "(i64x2.splat (i64.sub (i64.const 0) (i64.extend_i32_u {})))",
+                x
+            ),
+        }
+    }
+
+    // A new local name based on a Rust name.
+    fn fresh(&mut self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut n = 0;
+        while self.names.contains(&name) {
+            n += 1;
+            name = format!("{}.{}", base, n);
+        }
+        self.names.insert(name.clone());
+        format!("${}", name)
+    }
+
+    fn local(&mut self, base: &str, ty: &str) -> String {
+        let name = self.fresh(base);
+        self.locals.push(format!("(local {} {})", name, ty));
+        name
+    }
+
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.text, "    {}{}", "  ".repeat(self.depth), line);
+    }
+
+    // An expression that can be used more than once, in a local if need be.
+    fn shared(&mut self, x: String, ty: &str) -> String {
+        let simple = [
+            "(local.get ",
+            "(global.get ",
+            "(v128.const ",
+            "(f32.const ",
+            "(f64.const ",
+        ]
+        .iter()
+        .any(|s| x.starts_with(s));
+        if simple || x.starts_with("(i32.const ") || x.starts_with("(i64.const ") {
+            return x;
+        }
+        let name = self.local("tmp", ty);
+        self.line(&format!("(local.set {} {})", name, x));
+        format!("(local.get {})", name)
+    }
+
+    // A scalar expression used more than once.
+    fn shared_scalar(&mut self, scalar: Scalar, x: String) -> Result<String> {
+        let ty = self.scalar_type(scalar)?;
+        Ok(self.shared(x, ty))
+    }
+
+    fn operand(&self, value: Value) -> String {
+        self.elems(value).remove(0)
+    }
+
+    fn elems(&self, value: Value) -> Vec<String> {
+        self.operands[value.0].clone().unwrap_or_default()
+    }
+
+    // Apply a scalar operation lane by lane to non-bool vectors.
+    fn per_lane(
+        &mut self,
+        to: Scalar,
+        args: Vec<(Scalar, String)>,
+        f: impl Fn(&mut Self, Vec<String>) -> Result<String>,
+    ) -> Result<String> {
+        let lanes = match self.lanes {
+            Some(lanes) => lanes,
+            None => return f(self, args.into_iter().map(|(_, a)| a).collect()),
+        };
+        let mut vectors = Vec::new();
+        for (scalar, a) in args {
+            vectors.push((self.shape(scalar), self.shared(a, "v128")));
+        }
+        let result_shape = self.shape(to);
+        let mut result = "(v128.const i64x2 0 0)".to_string();
+        self.lanes = None;
+        for lane in 0..lanes {
+            let lane_args = vectors
+                .iter()
+                .map(|(shape, a)| format!("({}.extract_lane {} {})", shape, lane, a))
+                .collect();
+            let y = match f(self, lane_args) {
+                Ok(y) => y,
+                Err(e) => {
+                    self.lanes = Some(lanes);
+                    return Err(e);
+                }
+            };
+            result = format!("({}.replace_lane {} {} {})", result_shape, lane, result, y);
+        }
+        self.lanes = Some(lanes);
+        Ok(result)
+    }
+
+    // Call a function of the JavaScript `Math` object, which takes doubles.
+    fn math(&mut self, name: &str, scalar: Scalar, args: Vec<String>) -> String {
+        let params = vec!["f64"; args.len()].join(" ");
+        self.decls.imports.insert(format!(
+            "(import \"Math\" \"{}\" (func $Math.{} (param {}) (result f64)))",
+            name, name, params
+        ));
+        let f32 = scalar == Scalar::F32;
+        let args = args
+            .into_iter()
+            .map(|a| match f32 {
+                true => format!("(f64.promote_f32 {})", a),
+                false => a,
+            })
+            .collect::<Vec<_>>();
+        let call = format!("(call $Math.{} {})", name, args.join(" "));
+        match f32 {
+            true => format!("(f32.demote_f64 {})", call),
+            false => call,
+        }
+    }
+
+    // Call a `Math` function lane by lane.
+    fn math_per_lane(
+        &mut self,
+        name: &'static str,
+        scalar: Scalar,
+        args: &[String],
+    ) -> Result<String> {
+        let args = args.iter().map(|a| (scalar, a.clone())).collect();
+        self.per_lane(scalar, args, |this, args| Ok(this.math(name, scalar, args)))
+    }
+
+    // A reference to a module constant.
+    fn constant_ref(&self, name: &str) -> String {
+        if self.decls.globals.contains(name) {
+            format!("(global.get ${}{})", self.options.prefix, name)
+        } else {
+            format!("(call ${}{})", self.options.prefix, name)
+        }
+    }
+
+    // The value of a float literal, rounded to its type.
+    fn float_value(&self, value: Value) -> Option<f64> {
+        let scalar = self.scalar(value).ok().filter(|s| s.is_float())?;
+        match &self.func.inst(value)?.op {
+            Op::Lit(Literal::Float(digits)) if scalar == Scalar::F32 => {
+                digits.parse::<f32>().ok().map(|x| x as f64)
+            }
+            Op::Lit(Literal::Float(digits)) => digits.parse::<f64>().ok(),
+            Op::Lit(Literal::Int(i)) => Some(*i as f64),
+            Op::Lit(Literal::Special(s)) => Some(special_value(*s, scalar)),
+            _ => None,
+        }
+    }
+
+    // The text of a literal, folding negation.
+    fn literal(&self, value: Value) -> Option<String> {
+        let scalar = self.scalar(value).ok()?;
+        if let Some(x) = self.float_value(value) {
+            return Some(float_text(x, scalar));
+        }
+        match &self.func.inst(value)?.op {
+            Op::Lit(Literal::Int(i)) => Some(int_text(*i, scalar)),
+            Op::Lit(Literal::Bool(b)) => Some((*b as u8).to_string()),
+            Op::Unary(UnaryOp::Neg, a) if scalar.is_float() => {
+                self.float_value(*a).map(|x| float_text(-x, scalar))
+            }
+            Op::Unary(UnaryOp::Neg, a) => match &self.func.inst(*a)?.op {
+                Op::Lit(Literal::Int(i)) => Some(int_text(i.wrapping_neg(), scalar)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn op(&mut self, inst: &Inst) -> Result<Vec<String>> {
+        let value = inst.value;
+        // Operations whose values need not be scalars.
+        match &inst.op {
+            Op::Tuple(args) => return Ok(args.iter().flat_map(|a| self.elems(*a)).collect()),
+            Op::Extract(a, i) => return Ok(vec![self.elems(*a)[*i].clone()]),
+            Op::Select(c, a, b) => {
+                let scalars = self.scalars(self.func.ty(value))?;
+                let c = self.operand(*c);
+                let c = self.shared_scalar(Scalar::Bool, c)?;
+                let (a, b) = (self.elems(*a), self.elems(*b));
+                return Ok(scalars
+                    .into_iter()
+                    .zip(a.into_iter().zip(b))
+                    .map(|(s, (a, b))| self.choose(s, a, b, c.clone()))
+                    .collect());
+            }
+            _ => (),
+        }
+        let scalar = self.scalar(value)?;
+        self.scalar_type(scalar)?;
+        if let Some(text) = self.literal(value) {
+            return Ok(vec![self.constant(scalar, &text)]);
+        }
+        Ok(vec![match &inst.op {
+            Op::Lit(lit) => return Err(self.error(&format!("bad literal {:?}", lit))),
+            Op::Const(c) => self.splat(scalar, self.constant_ref(c)),
+            Op::Unary(UnaryOp::Neg, a) if scalar.is_float() => {
+                format!("({} {})", self.ins(scalar, "neg"), self.operand(*a))
+            }
+            Op::Unary(UnaryOp::Neg, a) => format!(
+                "({} {} {})",
+                self.ins(scalar, "sub"),
+                self.constant(scalar, "0"),
+                self.operand(*a)
+            ),
+            Op::Unary(UnaryOp::Not, a) => {
+                let ones = if scalar == Scalar::Bool { "1" } else { "-1" };
+                format!(
+                    "({} {} {})",
+                    self.bitwise(scalar, "xor"),
+                    self.operand(*a),
+                    self.constant(scalar, ones)
+                )
+            }
+            Op::Binary(op, a, b) => self.binary(*op, *a, *b)?,
+            Op::Convert(a) => {
+                let from = self.scalar(*a)?;
+                if scalar == Scalar::Bool && from != Scalar::Bool {
+                    return Err(self.error("conversions to bool are not supported"));
+                }
+                self.scalar_type(from)?;
+                let a = self.operand(*a);
+                self.convert(from, scalar, a)?
+            }
+            Op::Bitcast(a) => {
+                let from = self.scalar(*a)?;
+                if wasm_bits(from) != wasm_bits(scalar) {
+                    return Err(self.error("bit casts must be between scalars of the same size"));
+                }
+                let a = self.operand(*a);
+                self.reinterpret(from, scalar, a)
+            }
+            Op::Intrinsic(i, args) => self.intrinsic(scalar, *i, args)?,
+            // Values are already vectors.
+            Op::Splat(a) if self.lanes.is_some() => self.operand(*a),
+            Op::Splat(_) => return Err(self.error("vectors need a lane count")),
+            Op::Call(func, args) => self.call(func, args),
+            Op::If(..) => unreachable!("printed by body"),
+            Op::Tuple(_) | Op::Extract(..) | Op::Select(..) => unreachable!("printed above"),
+        }])
+    }
+
+    fn call(&self, func: &str, args: &[Value]) -> String {
+        let mut call = format!("(call ${}{}", self.options.prefix, func);
+        for a in args {
+            for x in self.elems(*a) {
+                call.push(' ');
+                call.push_str(&x);
+            }
+        }
+        call.push(')');
+        call
+    }
+
+    // `select` or, for vectors, `bitselect` with the mask narrowed to fit.
+    fn choose(&mut self, scalar: Scalar, a: String, b: String, c: String) -> String {
+        if self.lanes.is_none() {
+            return format!("(select {} {} {})", a, b, c);
+        }
+        let c = if self.lane_bits(scalar) < self.mask_bits() {
+            let c = self.shared(c, "v128");
+            let low = "0 1 2 3 8 9 10 11 0 1 2 3 8 9 10 11";
+            format!("(i8x16.shuffle {} {} {})", low, c, c)
+        } else {
+            c
+        };
+        format!("(v128.bitselect {} {} {})", a, b, c)
+    }
+
+    // Compare two values, giving a bool.
+    fn compare(&mut self, op: BinaryOp, scalar: Scalar, x: String, y: String) -> String {
+        use BinaryOp::*;
+        if scalar == Scalar::Bool && self.lanes.is_some() {
+            let ne = format!("(v128.xor {} {})", x, y);
+            return match op {
+                Eq => format!("(v128.not {})", ne),
+                _ => ne,
+            };
+        }
+        let name = match op {
+            Eq => "eq",
+            Ne => "ne",
+            Lt => "lt",
+            Le => "le",
+            Gt => "gt",
+            _ => "ge",
+        };
+        let (name, x, y) = match op {
+            _ if scalar.is_float() || matches!(op, Eq | Ne) => (name.to_string(), x, y),
+            // There are only signed comparisons of 64 bit lanes.
+            _ if self.lanes.is_some() && !scalar.is_signed() && wasm_bits(scalar) == 64 => {
+                let flip = self.constant(scalar, "-9223372036854775808");
+                let x = format!("(v128.xor {} {})", x, flip);
+                let y = format!("(v128.xor {} {})", y, flip);
+                (format!("{}_s", name), x, y)
+            }
+            _ => (format!("{}_{}", name, sign(scalar)), x, y),
+        };
+        let mask = format!("({} {} {})", self.ins(scalar, &name), x, y);
+        if self.lanes.is_some() && self.lane_bits(scalar) < self.mask_bits() {
+            format!("(i64x2.extend_low_i32x4_s {})", mask)
+        } else {
+            mask
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: Value, b: Value) -> Result<String> {
+        use BinaryOp::*;
+        let scalar = self.scalar(a)?;
+        let float = scalar.is_float();
+        let x = self.operand(a);
+        let y = self.operand(b);
+        let name = match op {
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+            Div if float => "div",
+            Rem if float => {
+                // `x - y * trunc(x / y)`, as there is no float remainder.
+                let x = self.shared_scalar(scalar, x)?;
+                let y = self.shared_scalar(scalar, y)?;
+                let q = format!("({} {} {})", self.ins(scalar, "div"), x, y);
+                let t = format!("({} {})", self.ins(scalar, "trunc"), q);
+                let p = format!("({} {} {})", self.ins(scalar, "mul"), y, t);
+                return Ok(format!("({} {} {})", self.ins(scalar, "sub"), x, p));
+            }
+            Div | Rem => {
+                // There is no vector integer division.
+                let name = format!("{}_{}", if op == Div { "div" } else { "rem" }, sign(scalar));
+                return self.per_lane(scalar, vec![(scalar, x), (scalar, y)], |this, a| {
+                    Ok(format!("({} {} {})", this.ins(scalar, &name), a[0], a[1]))
+                });
+            }
+            BitAnd | And => return Ok(format!("({} {} {})", self.bitwise(scalar, "and"), x, y)),
+            BitOr | Or => return Ok(format!("({} {} {})", self.bitwise(scalar, "or"), x, y)),
+            BitXor => return Ok(format!("({} {} {})", self.bitwise(scalar, "xor"), x, y)),
+            Shl | Shr => return self.shift(op, scalar, x, b),
+            Eq | Ne | Lt | Le | Gt | Ge => return Ok(self.compare(op, scalar, x, y)),
+        };
+        Ok(format!("({} {} {})", self.ins(scalar, name), x, y))
+    }
+
+    fn shift(&mut self, op: BinaryOp, scalar: Scalar, x: String, b: Value) -> Result<String> {
+        let name = match op {
+            BinaryOp::Shl => "shl".to_string(),
+            _ => format!("shr_{}", sign(scalar)),
+        };
+        let by = self.scalar(b)?;
+        let y = self.operand(b);
+        if self.lanes.is_none() {
+            // Both operands of a scalar shift have the same type.
+            let y = self.convert(by, scalar, y)?;
+            return Ok(format!("({} {} {})", self.ins(scalar, &name), x, y));
+        }
+        // Vectors are shifted by an `i32`, the same for every lane.
+        let count = match &self.func.inst(b).map(|inst| &inst.op) {
+            Some(Op::Lit(Literal::Int(i))) => Some(format!("(i32.const {})", *i as u32 as i32)),
+            Some(Op::Const(c)) if wasm_bits(by) == 64 => {
+                Some(format!("(i32.wrap_i64 {})", self.constant_ref(c)))
+            }
+            Some(Op::Const(c)) => Some(self.constant_ref(c)),
+            _ => None,
+        };
+        match count {
+            Some(count) => Ok(format!("({} {} {})", self.ins(scalar, &name), x, count)),
+            None => self.per_lane(scalar, vec![(scalar, x), (by, y)], |this, a| {
+                let y = this.convert(by, scalar, a[1].clone())?;
+                Ok(format!("({} {} {})", this.ins(scalar, &name), a[0], y))
+            }),
+        }
+    }
+
+    // Convert as Rust's `as` does, float to integer conversions saturate.
+    fn convert(&mut self, from: Scalar, to: Scalar, x: String) -> Result<String> {
+        let (from, x) = match (from, self.lanes) {
+            _ if from == to => return Ok(x),
+            // Masks become zero or one.
+            (Scalar::Bool, Some(_)) => {
+                let uint = Scalar::uint(self.mask_bits()).unwrap_or(Scalar::U64);
+                let x = format!("(v128.and {} {})", x, self.constant(uint, "1"));
+                (uint, x)
+            }
+            _ => (from, x),
+        };
+        let (from_bits, to_bits) = (wasm_bits(from), wasm_bits(to));
+        if self.lanes.is_none() {
+            let (from_ty, to_ty) = (
+                num_type(from).unwrap_or("i32"),
+                num_type(to).unwrap_or("i32"),
+            );
+            let name = match (from.is_float(), to.is_float()) {
+                (true, true) if from_bits < to_bits => "f64.promote_f32".to_string(),
+                (true, true) if from_bits > to_bits => "f32.demote_f64".to_string(),
+                (false, true) => format!("{}.convert_{}_{}", to_ty, from_ty, sign(from)),
+                (true, false) => format!("{}.trunc_sat_{}_{}", to_ty, from_ty, sign(to)),
+                (false, false) if from_bits < to_bits => format!("i64.extend_i32_{}", sign(from)),
+                (false, false) if from_bits > to_bits => "i32.wrap_i64".to_string(),
+                _ => return Ok(x),
+            };
+            return Ok(format!("({} {})", name, x));
+        }
+        let name = match (from.is_float(), to.is_float(), from_bits, to_bits) {
+            (true, true, 32, 64) => "f64x2.promote_low_f32x4".to_string(),
+            (true, true, 64, 32) => "f32x4.demote_f64x2_zero".to_string(),
+            (false, true, 32, 32) => format!("f32x4.convert_i32x4_{}", sign(from)),
+            (false, true, 32, 64) => format!("f64x2.convert_low_i32x4_{}", sign(from)),
+            (true, false, 32, 32) => format!("i32x4.trunc_sat_f32x4_{}", sign(to)),
+            (true, false, 64, 32) => format!("i32x4.trunc_sat_f64x2_{}_zero", sign(to)),
+            (false, false, 32, 64) => format!("i64x2.extend_low_i32x4_{}", sign(from)),
+            (false, false, 64, 32) => {
+                let x = self.shared(x, "v128");
+                let low = "0 1 2 3 8 9 10 11 0 1 2 3 8 9 10 11";
+                return Ok(format!("(i8x16.shuffle {} {} {})", low, x, x));
+            }
+            (true, true, _, _) | (false, false, _, _) => return Ok(x),
+            // 64 bit integers and floats are converted lane by lane.
+            _ => {
+                return self.per_lane(to, vec![(from, x)], |this, a| {
+                    this.convert(from, to, a[0].clone())
+                })
+            }
+        };
+        Ok(format!("({} {})", name, x))
+    }
+
+    // Reinterpret the bits of a value, which vectors need not do.
+    fn reinterpret(&self, from: Scalar, to: Scalar, x: String) -> String {
+        if self.lanes.is_some() || from.is_float() == to.is_float() {
+            return x;
+        }
+        let (from_ty, to_ty) = (
+            num_type(from).unwrap_or("i32"),
+            num_type(to).unwrap_or("i32"),
+        );
+        format!("({}.reinterpret_{} {})", to_ty, from_ty, x)
+    }
+
+    // The magnitude of `x` with the sign of `y`.
+    fn copysign(&self, scalar: Scalar, x: String, y: String) -> String {
+        if self.lanes.is_none() {
+            return format!("({} {} {})", self.ins(scalar, "copysign"), x, y);
+        }
+        let bits = wasm_bits(scalar);
+        let int = Scalar::int(bits).unwrap_or(Scalar::I64);
+        let sign = int_text(1 << (bits - 1), int);
+        format!("(v128.bitselect {} {} {})", y, x, self.constant(int, &sign))
+    }
+
+    fn intrinsic(&mut self, scalar: Scalar, i: Intrinsic, args: &[Value]) -> Result<String> {
+        use Intrinsic::*;
+        let arg_scalar = self.scalar(args[0])?;
+        let xs = args.iter().map(|a| self.operand(*a)).collect::<Vec<_>>();
+        let x = xs[0].clone();
+        let s = arg_scalar;
+        let unary = |this: &Self, name: &str| format!("({} {})", this.ins(s, name), xs[0]);
+        let binary =
+            |this: &Self, name: &str| format!("({} {} {})", this.ins(s, name), xs[0], xs[1]);
+        if arg_scalar.is_int() {
+            let zero = self.constant(s, "0");
+            return Ok(match i {
+                WrappingAdd => binary(self, "add"),
+                WrappingSub => binary(self, "sub"),
+                WrappingMul => binary(self, "mul"),
+                WrappingNeg => format!("({} {} {})", self.ins(s, "sub"), zero, x),
+                // Counts are `u32` in Rust.
+                CountOnes | LeadingZeros | TrailingZeros => {
+                    let name = match i {
+                        CountOnes => "popcnt",
+                        LeadingZeros => "clz",
+                        _ => "ctz",
+                    };
+                    let unsigned = Scalar::uint(wasm_bits(s)).unwrap_or(Scalar::U64);
+                    self.per_lane(scalar, vec![(s, x)], |this, a| {
+                        let count = format!("({} {})", this.ins(s, name), a[0]);
+                        this.convert(unsigned, scalar, count)
+                    })?
+                }
+                Abs => {
+                    let x = self.shared_scalar(s, x)?;
+                    let neg = format!("({} {} {})", self.ins(s, "sub"), zero, x);
+                    let c = self.compare(BinaryOp::Lt, s, x.clone(), zero);
+                    self.choose(s, neg, x, c)
+                }
+                Min | Max => {
+                    let x = self.shared_scalar(s, x)?;
+                    let y = self.shared_scalar(s, xs[1].clone())?;
+                    let op = if i == Min { BinaryOp::Lt } else { BinaryOp::Gt };
+                    let c = self.compare(op, s, x.clone(), y.clone());
+                    self.choose(s, x, y, c)
+                }
+                _ => {
+                    return Err(self.error(&format!("`{}` is not supported for integers", i.name())))
+                }
+            });
+        }
+        let one = self.constant(s, "1.0");
+        let inf = self.constant(s, "inf");
+        Ok(match i {
+            Abs | Sqrt | Floor | Ceil | Trunc => unary(self, i.name()),
+            Copysign => self.copysign(s, x, xs[1].clone()),
+            MulAdd if self.options.relaxed_simd && self.lanes.is_some() => {
+                format!(
+                    "({} {} {} {})",
+                    self.ins(s, "relaxed_madd"),
+                    x,
+                    xs[1],
+                    xs[2]
+                )
+            }
+            MulAdd => format!("({} {} {})", self.ins(s, "add"), binary(self, "mul"), xs[2]),
+            Recip => format!("({} {} {})", self.ins(s, "div"), one, x),
+            // Rust's `fract` is relative to `trunc`.
+            Fract => {
+                let x = self.shared_scalar(s, x)?;
+                let t = format!("({} {})", self.ins(s, "trunc"), x);
+                format!("({} {} {})", self.ins(s, "sub"), x, t)
+            }
+            // Halfway cases round away from zero, unlike `nearest`.
+            Round => {
+                let x = self.shared_scalar(s, x)?;
+                let t = format!("({} {})", self.ins(s, "trunc"), x);
+                let t = self.shared_scalar(s, t)?;
+                let d = format!("({} {} {})", self.ins(s, "sub"), x, t);
+                let d = format!("({} {})", self.ins(s, "abs"), d);
+                let c = self.compare(BinaryOp::Ge, s, d, self.constant(s, "0.5"));
+                let away = format!(
+                    "({} {} {})",
+                    self.ins(s, "add"),
+                    t,
+                    self.copysign(s, one, x)
+                );
+                self.choose(s, away, t, c)
+            }
+            // Rust's `min` and `max` ignore NaNs.
+            Min | Max => {
+                let x = self.shared_scalar(s, x)?;
+                let y = self.shared_scalar(s, xs[1].clone())?;
+                let m = format!("({} {} {})", self.ins(s, i.name()), x, y);
+                let y_nan = self.compare(BinaryOp::Ne, s, y.clone(), y.clone());
+                let m = self.choose(s, x.clone(), m, y_nan);
+                let x_nan = self.compare(BinaryOp::Ne, s, x.clone(), x);
+                self.choose(s, y, m, x_nan)
+            }
+            Signum => {
+                let x = self.shared_scalar(s, x)?;
+                let nan = self.compare(BinaryOp::Ne, s, x.clone(), x.clone());
+                let sign = self.copysign(s, one, x.clone());
+                self.choose(s, x, sign, nan)
+            }
+            IsNan => {
+                let x = self.shared_scalar(s, x)?;
+                self.compare(BinaryOp::Ne, s, x.clone(), x)
+            }
+            IsInfinite | IsFinite => {
+                let op = if i == IsInfinite {
+                    BinaryOp::Eq
+                } else {
+                    BinaryOp::Lt
+                };
+                self.compare(op, s, unary(self, "abs"), inf)
+            }
+            IsSignNegative | IsSignPositive => {
+                let int = Scalar::int(wasm_bits(s)).unwrap_or(Scalar::I64);
+                let bits = self.reinterpret(s, int, x);
+                let op = if i == IsSignNegative {
+                    BinaryOp::Lt
+                } else {
+                    BinaryOp::Ge
+                };
+                let zero = self.constant(int, "0");
+                self.compare(op, int, bits, zero)
+            }
+            Sin | Cos | Tan | Asin | Acos | Atan | Atan2 | Sinh | Cosh | Tanh | Asinh | Acosh
+            | Atanh | Exp | Log2 | Log10 | Cbrt | Hypot => self.math_per_lane(i.name(), s, &xs)?,
+            Ln => self.math_per_lane("log", s, &xs)?,
+            ExpM1 => self.math_per_lane("expm1", s, &xs)?,
+            Ln1p => self.math_per_lane("log1p", s, &xs)?,
+            Powf => self.math_per_lane("pow", s, &xs)?,
+            Exp2 => {
+                let two = self.constant(s, "2.0");
+                self.math_per_lane("pow", s, &[two, x])?
+            }
+            Log => {
+                let lx = self.math_per_lane("log", s, &xs[0..1])?;
+                let lb = self.math_per_lane("log", s, &xs[1..2])?;
+                format!("({} {} {})", self.ins(s, "div"), lx, lb)
+            }
+            Powi => {
+                let n = self.scalar(args[1])?;
+                let n = self.convert(n, s, xs[1].clone())?;
+                self.math_per_lane("pow", s, &[x, n])?
+            }
+            _ => return Err(self.error(&format!("`{}` is not supported", i.name()))),
+        })
+    }
+
+    // New locals for the elements of a value.
+    fn locals_for(&mut self, value: Value) -> Result<Vec<String>> {
+        let types = self.types(self.func.ty(value))?;
+        let name = self.func.name(value);
+        let mut locals = Vec::new();
+        for (i, ty) in types.iter().enumerate() {
+            let base = match types.len() {
+                1 => name.clone(),
+                _ => format!("{}.{}", name, i),
+            };
+            locals.push(self.local(&base, ty));
+        }
+        Ok(locals)
+    }
+
+    // Put the elements of a value in new locals.
+    fn store(&mut self, value: Value, exprs: Vec<String>) -> Result<Vec<String>> {
+        let locals = self.locals_for(value)?;
+        for (local, x) in locals.iter().zip(exprs) {
+            self.line(&format!("(local.set {} {})", local, x));
+        }
+        Ok(locals
+            .into_iter()
+            .map(|l| format!("(local.get {})", l))
+            .collect())
+    }
+
+    // Put the values on the stack into new locals.
+    fn pop(&mut self, value: Value) -> Result<Vec<String>> {
+        let locals = self.locals_for(value)?;
+        for local in locals.iter().rev() {
+            self.line(&format!("(local.set {})", local));
+        }
+        Ok(locals
+            .into_iter()
+            .map(|l| format!("(local.get {})", l))
+            .collect())
+    }
+
+    // Scalar `if`s branch, vector ones evaluate both sides.
+    fn branch(
+        &mut self,
+        value: Value,
+        c: Value,
+        then_body: &Body,
+        else_body: &Body,
+    ) -> Result<Vec<String>> {
+        let c = self.operand(c);
+        if self.lanes.is_some() {
+            let c = self.shared(c, "v128");
+            self.body(then_body)?;
+            self.body(else_body)?;
+            let scalars = self.scalars(self.func.ty(value))?;
+            let (a, b) = (self.elems(then_body.result), self.elems(else_body.result));
+            let exprs = scalars
+                .into_iter()
+                .zip(a.into_iter().zip(b))
+                .map(|(s, (a, b))| self.choose(s, a, b, c.clone()))
+                .collect();
+            return self.store(value, exprs);
+        }
+        let types = self.types(self.func.ty(value))?;
+        self.line(&format!("(if (result {}) {}", types.join(" "), c));
+        for (body, part) in [(then_body, "then"), (else_body, "else")] {
+            self.depth += 1;
+            self.line(&format!("({}", part));
+            self.depth += 1;
+            self.body(body)?;
+            for x in self.elems(body.result) {
+                self.line(&x);
+            }
+            self.depth -= 1;
+            self.line(")");
+            self.depth -= 1;
+        }
+        self.line(")");
+        self.pop(value)
+    }
+
+    // Print the instructions of a body.
+    fn body(&mut self, body: &Body) -> Result<()> {
+        for inst in &body.insts {
+            let value = inst.value;
+            let exprs = match &inst.op {
+                Op::If(c, then_body, else_body) => self.branch(value, *c, then_body, else_body)?,
+                // Tuples are returned on the stack.
+                Op::Call(func, args) if !matches!(self.func.ty(value), Ty::Scalar(_)) => {
+                    let call = self.call(func, args);
+                    self.line(&call);
+                    self.pop(value)?
+                }
+                op => {
+                    let exprs = self.op(inst)?;
+                    let inline = self.func.is_inline(value, &self.use_counts)
+                        || matches!(op, Op::Lit(_))
+                        || self.literal(value).is_some();
+                    if inline {
+                        exprs
+                    } else {
+                        self.store(value, exprs)?
+                    }
+                }
+            };
+            self.operands[value.0] = Some(exprs);
+        }
+        Ok(())
+    }
+}
+
+fn docs(text: &mut String, func: &Function) {
+    for doc in &func.docs {
+        let _ = writeln!(text, "  ;;{}", doc);
+    }
+}
+
+fn print_fn(
+    func: &Function,
+    options: &Options,
+    lanes: Option<usize>,
+    decls: &mut Decls,
+) -> Result<String> {
+    let mut printer = Printer::new(func, options, lanes, decls);
+    let mut params = Vec::new();
+    for p in &func.params {
+        let types = printer.types(func.ty(*p))?;
+        let name = func.name(*p);
+        let mut elems = Vec::new();
+        for (i, ty) in types.iter().enumerate() {
+            let base = match types.len() {
+                1 => name.clone(),
+                _ => format!("{}.{}", name, i),
+            };
+            let param = printer.fresh(&base);
+            params.push(format!("(param {} {})", param, ty));
+            elems.push(format!("(local.get {})", param));
+        }
+        printer.operands[p.0] = Some(elems);
+    }
+    let ret = printer.types(&func.ret)?;
+    printer.body(&func.body)?;
+    for x in printer.elems(func.body.result) {
+        printer.line(&x);
+    }
+
+    let name = format!("{}{}", options.prefix, func.name);
+    let export = match func.is_pub {
+        true => format!(" (export \"{}\")", name),
+        false => String::new(),
+    };
+    let mut text = String::new();
+    docs(&mut text, func);
+    let _ = write!(text, "  (func ${}{}", name, export);
+    for param in &params {
+        let _ = write!(text, " {}", param);
+    }
+    let _ = writeln!(text, " (result {})", ret.join(" "));
+    for local in &printer.locals {
+        let _ = writeln!(text, "    {}", local);
+    }
+    text.push_str(&printer.text);
+    text.push_str("  )\n");
+    Ok(text)
+}
+
+/// Translate a module of the IR into the WebAssembly text format.
+pub fn module_to_wat(module: &Module, options: &Options) -> Result<String> {
+    if !matches!(options.lanes, None | Some(2) | Some(4)) {
+        return Err(Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation("generating WebAssembly")
+            .with_message("SIMD128 vectors have 2 or 4 lanes"));
+    }
+    let mut decls = Decls::default();
+    let mut globals = String::new();
+    let mut funcs = String::new();
+    // Literal constants are globals, others are functions.
+    for c in &module.consts {
+        let printer = Printer::new(c, options, None, &mut decls);
+        let scalar = printer.scalar(c.body.result).ok();
+        let literal = match (scalar, printer.literal(c.body.result)) {
+            (Some(scalar), Some(text)) => {
+                let ty = printer.scalar_type(scalar)?;
+                Some((ty, printer.constant(scalar, &text)))
+            }
+            _ => None,
+        };
+        match literal {
+            Some((ty, value)) => {
+                let name = format!("{}{}", options.prefix, c.name);
+                let _ = writeln!(globals, "  (global ${} {} {})", name, ty, value);
+                decls.globals.insert(c.name.clone());
+            }
+            None => {
+                funcs.push_str(&print_fn(c, options, None, &mut decls)?);
+                funcs.push('\n');
+            }
+        }
+    }
+    for func in &module.functions {
+        funcs.push_str(&print_fn(func, options, options.lanes, &mut decls)?);
+        funcs.push('\n');
+    }
+    let mut text = "(module\n".to_string();
+    for import in &decls.imports {
+        let _ = writeln!(text, "  {}", import);
+    }
+    if !globals.is_empty() {
+        text.push_str(&globals);
+        text.push('\n');
+    }
+    text.push_str(funcs.trim_end());
+    text.push_str("\n)\n");
+    Ok(text)
+}
+
+/// Translate a Rust file into the WebAssembly text format.
+pub fn to_wat(file: &syn::File, options: Options) -> Result<String> {
+    module_to_wat(&lower_file(file)?, &options)
+}
+
+#[test]
+fn test() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        const HALF: f64 = 0.5;
+
+        /// Add half.
+        pub fn f(x: f64) -> f64 {
+            let y = x * -2.0;
+            if x < 0.0 {
+                let z = y.floor();
+                z.mul_add(HALF, 1.0)
+            } else {
+                y
+            }
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        ..Options::default()
+    };
+    let wat = to_wat(&code, options).unwrap();
+    assert!(wat.starts_with("(module\n  (global $ds_HALF f64 (f64.const 0.5))\n\n  ;; Add half.\n"));
+    assert!(wat.contains("  (func $ds_f (export \"ds_f\") (param $x f64) (result f64)\n"));
+    assert!(wat.contains("    (local.set $y (f64.mul (local.get $x) (f64.const -2.0)))\n"));
+    assert!(
+        wat.contains("    (if (result f64) (f64.lt (local.get $x) (f64.const 0.0))\n      (then\n")
+    );
+    assert!(wat.contains(
+        "        (f64.add (f64.mul (local.get $z) (global.get $ds_HALF)) (f64.const 1.0))\n"
+    ));
+    assert!(
+        wat.contains("      (else\n        (local.get $y)\n      )\n    )\n    (local.set $v10)\n")
+    );
+}
+
+#[test]
+fn test_vectors_and_bits() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f32) -> f32 {
+            let bits = x.to_bits();
+            let e = (bits >> 23) as i32 - 127;
+            if x.is_nan() { f32::INFINITY } else { f32::from_bits(bits & 0x807fffff) * (e as f32) }
+        }
+    };
+    let options = Options {
+        prefix: "ds_".to_string(),
+        lanes: Some(4),
+        relaxed_simd: false,
+    };
+    let wat = to_wat(&code, options).unwrap();
+    assert!(wat.contains("  (func $ds_f (param $x v128) (result v128)\n"));
+    assert!(wat.contains("(i32x4.shr_u (local.get $bits) (i32.const 23))"));
+    assert!(wat.contains("(v128.and (local.get $bits) (v128.const i32x4 -2139095041 "));
+    assert!(wat.contains("(f32x4.convert_i32x4_s (local.get $e))"));
+    assert!(wat.contains("(local.set $tmp (f32x4.ne (local.get $x) (local.get $x)))\n"));
+    assert!(wat.contains("(v128.bitselect (v128.const f32x4 inf inf inf inf) (f32x4.mul "));
+}
+
+#[test]
+fn test_per_lane() {
+    use syn::parse_quote;
+
+    let code: syn::File = parse_quote! {
+        fn f(x: f64, n: i64) -> f64 {
+            let y = x.mul_add(2.0, x.tan());
+            let i = y as i64 / n;
+            (i as i32 as f64).round()
+        }
+    };
+    let options = Options {
+        lanes: Some(2),
+        relaxed_simd: true,
+        ..Options::default()
+    };
+    let wat = to_wat(&code, options).unwrap();
+    assert!(wat.contains("  (import \"Math\" \"tan\" (func $Math.tan (param f64) (result f64)))\n"));
+    assert!(wat.contains("(f64x2.relaxed_madd (local.get $x) (v128.const f64x2 2.0 2.0) "));
+    assert!(wat.contains(
+        "(f64x2.replace_lane 1 (f64x2.replace_lane 0 (v128.const i64x2 0 0) (call $Math.tan "
+    ));
+    assert!(wat.contains("(i64.trunc_sat_f64_s (f64x2.extract_lane 0 "));
+    assert!(wat.contains("(i64.div_s (i64x2.extract_lane 0 "));
+    assert!(wat.contains("(i8x16.shuffle 0 1 2 3 8 9 10 11 0 1 2 3 8 9 10 11 "));
+    assert!(wat.contains("(f64x2.convert_low_i32x4_s "));
+    assert!(to_wat(
+        &code,
+        Options {
+            lanes: Some(4),
+            ..Options::default()
+        }
+    )
+    .is_err());
+}
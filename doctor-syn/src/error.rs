@@ -1,55 +1,289 @@
-use proc_macro2::Span;
-
-#[derive(Debug)]
-pub enum Error {
-    UnsupportedExpr(Span),
-    UnsupportedCodegen(String),
-    UndefinedVariable(String),
-
-    // UnsupportedMethod(Span),
-    // UnsupportedStatement(Span),
-    // UnsuportedClosureArgument(Span),
-    // BlockMustHaveOneStatement(Span),
-    // BadAttribute(Span),
-    NotFound(Span),
-    CouldNotConvertToExpression(Span),
-    CouldNotConvertFromExpression(Span),
-    CouldNotParse(String),
-    CouldNotEvaulate(String),
-    WrongNumberOfTerms(Span),
+//! Errors from transformations and code generators.
+//!
+//! An `Error` records what kind of failure occurred, the text of the
+//! subexpression that caused it, the operation that was being attempted
+//! and, optionally, the error that caused it. This makes failures deep
+//! in an expression readable, for example:
+//!
+//! ```text
+//! could not evaluate `(x * y) . sin ()` while approximating at x = -1.0000
+//!   caused by: could not evaluate `(- 1.0000 * y) . sin ()`
+//!   caused by: undefined variable `y` while evaluating a path
+//! ```
+//!
+//! For use in procedural macros, errors can be converted to `syn::Error`
+//! or directly to a `compile_error!` token stream.
+
+use proc_macro2::{Span, TokenStream};
+use quote::ToTokens;
+use syn::spanned::Spanned;
+
+/// The category of an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnsupportedExpr,
+    UnsupportedCodegen,
+    UndefinedVariable,
+    NotFound,
+    CouldNotConvertToExpression,
+    CouldNotConvertFromExpression,
+    CouldNotParse,
+    CouldNotEvaluate,
+    WrongNumberOfTerms,
     Expected32or64bits,
     Overflow,
 }
 
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ErrorKind::*;
+        let text = match self {
+            UnsupportedExpr => "unsupported expression",
+            UnsupportedCodegen => "unsupported in code generation",
+            UndefinedVariable => "undefined variable",
+            NotFound => "not found",
+            CouldNotConvertToExpression => "could not convert to an expression",
+            CouldNotConvertFromExpression => "could not convert from expression",
+            CouldNotParse => "could not parse",
+            CouldNotEvaluate => "could not evaluate",
+            WrongNumberOfTerms => "wrong number of terms",
+            Expected32or64bits => "expected 32 or 64 bits",
+            Overflow => "overflow",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Clone)]
+pub struct Error {
+    kind: ErrorKind,
+    span: Span,
+    source_text: Option<String>,
+    operation: Option<String>,
+    message: Option<String>,
+    cause: Option<Box<Error>>,
+    // The original parse error, which may hold several diagnostics.
+    syn_error: Option<syn::Error>,
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// An error with no associated source.
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            span: Span::call_site(),
+            source_text: None,
+            operation: None,
+            message: None,
+            cause: None,
+            syn_error: None,
+        }
+    }
+
+    /// An error located at a syntax node, recording its span and text.
+    ///
+    /// ```
+    /// use doctor_syn::{expr, Error, ErrorKind};
+    /// let e = Error::at(ErrorKind::CouldNotEvaluate, expr!(x + 1).as_ref());
+    /// assert_eq!(e.to_string(), "could not evaluate `x + 1`");
+    /// ```
+    pub fn at<T: ToTokens>(kind: ErrorKind, node: &T) -> Self {
+        Self {
+            span: node.span(),
+            source_text: Some(node.to_token_stream().to_string()),
+            ..Self::new(kind)
+        }
+    }
+
+    /// Record the operation being attempted, eg. "evaluating method `sin`".
+    pub fn with_operation<S: Into<String>>(mut self, operation: S) -> Self {
+        self.operation = Some(operation.into());
+        self
+    }
+
+    /// Add a free-form explanation.
+    pub fn with_message<S: Into<String>>(mut self, message: S) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Record the error that caused this one.
+    pub fn caused_by(mut self, cause: Error) -> Self {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The text of the failing subexpression, if known.
+    pub fn source_text(&self) -> Option<&str> {
+        self.source_text.as_deref()
+    }
+
+    /// The operation that was being attempted, if known.
+    pub fn operation(&self) -> Option<&str> {
+        self.operation.as_deref()
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn cause(&self) -> Option<&Error> {
+        self.cause.as_deref()
+    }
+
+    /// Iterate over this error and its causes, outermost first.
+    pub fn chain(&self) -> impl Iterator<Item = &Error> {
+        std::iter::successors(Some(self), |e| e.cause())
+    }
+
+    /// The innermost cause, usually the most specific description.
+    pub fn root_cause(&self) -> &Error {
+        self.chain().last().unwrap()
+    }
+
+    /// Convert to a `syn::Error` for use in procedural macros.
+    ///
+    /// The span is taken from the innermost error that has a source,
+    /// so that the compiler points at the failing subexpression.
+    /// Errors converted from `syn::Error` keep all of their diagnostics.
+    pub fn to_syn_error(&self) -> syn::Error {
+        if let Some(error) = &self.syn_error {
+            return error.clone();
+        }
+        let span = self
+            .chain()
+            .filter(|e| e.source_text.is_some())
+            .last()
+            .map_or(self.span, |e| e.span);
+        let mut error = syn::Error::new(span, self.to_string());
+        // The first diagnostic of a parse error is already in the message.
+        if let Some(parsed) = self.chain().find_map(|e| e.syn_error.as_ref()) {
+            for other in parsed.into_iter().skip(1) {
+                error.combine(other);
+            }
+        }
+        error
+    }
+
+    /// Convert to a `compile_error!(...)` invocation.
+    pub fn to_compile_error(&self) -> TokenStream {
+        self.to_syn_error().to_compile_error()
+    }
+
+    fn fmt_one(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(text) = &self.source_text {
+            write!(f, " `{}`", text)?;
+        }
+        if let Some(operation) = &self.operation {
+            write!(f, " while {}", operation)?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind)
+    }
+}
+
 impl From<syn::Error> for Error {
-    fn from(_: syn::Error) -> Self {
-        Error::CouldNotParse(String::new())
+    fn from(error: syn::Error) -> Self {
+        Self {
+            span: error.span(),
+            message: Some(error.to_string()),
+            syn_error: Some(error),
+            ..Error::new(ErrorKind::CouldNotParse)
+        }
+    }
+}
+
+impl From<Error> for syn::Error {
+    fn from(error: Error) -> Self {
+        error.to_syn_error()
     }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use Error::*;
-        match self {
-            UnsupportedExpr(span) => write!(f, "UnsupportedExpr {:?}", span),
-            UnsupportedCodegen(tokens) => write!(f, "UnsupportedCodegen {}", tokens.to_string()),
-            UndefinedVariable(tokens) => write!(f, "UndefinedVariable {}", tokens.to_string()),
-            NotFound(span) => write!(f, "NotFound {:?}", span),
-            CouldNotConvertToExpression(span) => {
-                write!(f, "CouldNotConvertToExpression {:?}", span)
-            }
-            CouldNotConvertFromExpression(span) => {
-                write!(f, "CouldNotConvertFromExpression {:?}", span)
-            }
-            CouldNotParse(span) => write!(f, "CouldNotParse {:?}", span),
-            CouldNotEvaulate(str) => write!(f, "CouldNotEvaulate({})", str),
-            WrongNumberOfTerms(span) => write!(f, "WrongNumberOfTerms {:?}", span),
-            Overflow => write!(f, "Overflow"),
-            Expected32or64bits => write!(f, "Expected 32 or 64 bits"),
+        self.fmt_one(f)?;
+        for cause in self.chain().skip(1) {
+            write!(f, "\n  caused by: ")?;
+            cause.fmt_one(f)?;
         }
+        Ok(())
     }
 }
 
-impl std::error::Error for Error {}
+// Debug shows the readable form so that `unwrap()` in generators is useful.
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.cause
+            .as_deref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+#[test]
+fn test_display() {
+    use crate::expr;
+    let inner = Error::at(ErrorKind::UndefinedVariable, expr!(x).as_ref())
+        .with_operation("evaluating a path");
+    let outer = Error::at(ErrorKind::CouldNotEvaluate, expr!(x.sin()).as_ref())
+        .with_operation("evaluating method `sin`")
+        .caused_by(inner);
+    assert_eq!(
+        outer.to_string(),
+        "could not evaluate `x . sin ()` while evaluating method `sin`\n  caused by: undefined variable `x` while evaluating a path"
+    );
+    assert_eq!(outer.root_cause().kind(), ErrorKind::UndefinedVariable);
+    assert_eq!(outer.chain().count(), 2);
+}
+
+#[test]
+fn test_from_syn_error() {
+    let err = syn::parse_str::<syn::Expr>("1 +").unwrap_err();
+    let message = err.to_string();
+    let e = Error::from(err);
+    assert_eq!(e.kind(), ErrorKind::CouldNotParse);
+    assert_eq!(e.message(), Some(message.as_str()));
+    assert!(e.to_compile_error().to_string().contains("compile_error"));
+}
+
+#[test]
+fn test_from_combined_syn_error() {
+    let mut err = syn::Error::new(Span::call_site(), "first");
+    err.combine(syn::Error::new(Span::call_site(), "second"));
+    let e = Error::from(err);
+    assert_eq!(e.to_syn_error().into_iter().count(), 2);
+
+    let outer = Error::new(ErrorKind::CouldNotEvaluate).caused_by(e);
+    let messages: Vec<_> = outer
+        .to_syn_error()
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect();
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].contains("caused by: could not parse: first"));
+    assert_eq!(messages[1], "second");
+}
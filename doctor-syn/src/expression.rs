@@ -1,7 +1,7 @@
 use crate::bdmath::*;
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 use crate::transformation::{
-    approx::approx, collect::Collect, eval::Eval, expand::Expand, paren::Paren, subst::Subst,
+    approx::{approx, approx_terms}, collect::Collect, eval::Eval, expand::Expand, paren::Paren, subst::Subst,
     use_number_type::UseNumberType,
 };
 use crate::visitor::Visitor;
@@ -12,6 +12,7 @@ use std::convert::{TryFrom, TryInto};
 use syn::spanned::Spanned;
 use syn::{parse_quote, Expr, ExprLit, Lit};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Parity {
     Odd,
     Even,
@@ -108,18 +109,19 @@ where
     N: std::str::FromStr,
     N::Err: std::fmt::Display,
 {
+    let err = |message: String| {
+        Error::at(ErrorKind::CouldNotConvertFromExpression, expr)
+            .with_operation(format!("converting to {}", std::any::type_name::<N>()))
+            .with_message(message)
+    };
     if let Expr::Lit(ref lit) = expr {
         match &lit.lit {
-            Lit::Float(f) => f
-                .base10_parse()
-                .map_err(|_| Error::CouldNotConvertFromExpression(lit.lit.span())),
-            Lit::Int(i) => i
-                .base10_parse()
-                .map_err(|_| Error::CouldNotConvertFromExpression(lit.lit.span())),
-            _ => return Err(Error::CouldNotConvertFromExpression(lit.lit.span())),
+            Lit::Float(f) => f.base10_parse().map_err(|e| err(e.to_string())),
+            Lit::Int(i) => i.base10_parse().map_err(|e| err(e.to_string())),
+            _ => Err(err("expected a numeric literal".to_string())),
         }
     } else {
-        Err(Error::CouldNotConvertFromExpression(expr.span()))
+        Err(err("expected a numeric literal".to_string()))
     }
 }
 
@@ -289,7 +291,9 @@ macro_rules! expr {
 impl std::str::FromStr for Expression {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self> {
-        let inner: Expr = syn::parse_str(s).map_err(|_| Error::CouldNotParse(s.to_owned()))?;
+        let inner: Expr = syn::parse_str(s).map_err(|e| {
+            Error::from(e).with_operation(format!("parsing `{}` as an expression", s))
+        })?;
         Ok(Self { inner })
     }
 }
@@ -355,7 +359,9 @@ impl Expression {
     /// assert!(expr!(x + 1).eval(20).is_err());
     /// ```
     pub fn eval(&self, num_digits: i64) -> Result<Expression> {
-        let expr: Expr = Eval { num_digits }.visit_expr(&self.inner)?;
+        let expr: Expr = Eval { num_digits }
+            .visit_expr(&self.inner)
+            .map_err(|e| Error::at(ErrorKind::CouldNotEvaluate, &self.inner).caused_by(e))?;
         Ok(Expression::from(expr))
     }
 
@@ -382,6 +388,19 @@ impl Expression {
         approx(self, num_terms, xmin, xmax, variable, parity, num_digits)
     }
 
+    /// Return the coefficients of the polynomial built by `approx`, lowest power first.
+    /// All the terms are returned whatever the parity of the expression.
+    pub fn approx_terms(
+        &self,
+        num_terms: usize,
+        xmin: f64,
+        xmax: f64,
+        variable: Name,
+        num_digits: i64,
+    ) -> Result<Vec<BigDecimal>> {
+        approx_terms(self, num_terms, xmin, xmax, variable, num_digits)
+    }
+
     /// Expand an expression.
     ///
     /// ```
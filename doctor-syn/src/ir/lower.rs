@@ -0,0 +1,586 @@
+//! Lowering of syn functions and constants to the IR.
+//!
+//! Types come from `typeinference`, so every value has a concrete type.
+//! Names of `let` bindings are kept, made unique within a function so that
+//! shadowing such as `let r = r + 1.0;` can be printed in any language.
+
+use super::*;
+use crate::typeinference::{infer_file, infer_fn, Env, TypeMap};
+use crate::{Error, ErrorKind, Result};
+use quote::ToTokens;
+use std::collections::{HashMap, HashSet};
+use syn::{Expr, File, Item, ItemConst, ItemFn, Lit, Pat, Stmt};
+
+/// Lower the functions and constants of a file.
+///
+/// Type aliases and `use` items are resolved or ignored, other items are errors.
+pub fn lower_file(file: &File) -> Result<Module> {
+    let env = Env::from_file(file)?;
+    let types = infer_file(file)?;
+    let mut reserved = HashSet::new();
+    for item in &file.items {
+        match item {
+            Item::Fn(item) => reserved.insert(item.sig.ident.to_string()),
+            Item::Const(item) => reserved.insert(item.ident.to_string()),
+            _ => false,
+        };
+    }
+    let mut module = Module::default();
+    for item in &file.items {
+        match item {
+            Item::Const(item) => {
+                let lowering = Lowering::new(&types, &env, &reserved);
+                module.consts.push(lowering.lower_const(item)?);
+            }
+            Item::Fn(item) => {
+                let lowering = Lowering::new(&types, &env, &reserved);
+                module.functions.push(lowering.lower_fn(item)?);
+            }
+            Item::Type(_) | Item::Use(_) => (),
+            _ => return Err(unsupported(item, "expected a function, constant or type alias")),
+        }
+    }
+    Ok(module)
+}
+
+/// Lower a single function using the aliases, constants and functions of `env`.
+pub fn lower_fn(item: &ItemFn, env: &Env) -> Result<Function> {
+    let types = infer_fn(item, env)?;
+    let reserved = HashSet::new();
+    Lowering::new(&types, env, &reserved).lower_fn(item)
+}
+
+fn unsupported<T: ToTokens>(node: &T, message: &str) -> Error {
+    Error::at(ErrorKind::UnsupportedCodegen, node)
+        .with_operation("lowering to the IR")
+        .with_message(message)
+}
+
+fn docs(attrs: &[syn::Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                lit: Lit::Str(s),
+                ..
+            })) if path.is_ident("doc") => Some(s.value()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Temporaries are printed as `v<n>` so these names are never used for bindings.
+fn is_temporary_name(name: &str) -> bool {
+    name.len() > 1 && name.starts_with('v') && name[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+fn special(name: &str) -> Option<Literal> {
+    let special = match name {
+        "NAN" => Special::Nan,
+        "INFINITY" => Special::Infinity,
+        "NEG_INFINITY" => Special::NegInfinity,
+        "MIN_POSITIVE" => Special::MinPositive,
+        "MAX" => Special::Max,
+        "MIN" => Special::Min,
+        "EPSILON" => Special::Epsilon,
+        _ => return None,
+    };
+    Some(Literal::Special(special))
+}
+
+// Values of `std::f64::consts` to more digits than f64 needs.
+fn float_const(name: &str) -> Option<Literal> {
+    let digits = match name {
+        "PI" => "3.14159265358979323846264338327950288",
+        "TAU" => "6.28318530717958647692528676655900577",
+        "E" => "2.71828182845904523536028747135266250",
+        "FRAC_PI_2" => "1.57079632679489661923132169163975144",
+        "FRAC_PI_3" => "1.04719755119659774615421446109316763",
+        "FRAC_PI_4" => "0.785398163397448309615660845819875721",
+        "FRAC_PI_6" => "0.52359877559829887307710723054658381",
+        "FRAC_PI_8" => "0.39269908169872415480783042290993786",
+        "FRAC_1_PI" => "0.318309886183790671537767526745028724",
+        "FRAC_2_PI" => "0.636619772367581343075535053490057448",
+        "FRAC_2_SQRT_PI" => "1.12837916709551257389615890312154517",
+        "SQRT_2" => "1.41421356237309504880168872420969808",
+        "FRAC_1_SQRT_2" => "0.707106781186547524400844362104849039",
+        "LN_2" => "0.693147180559945309417232121458176568",
+        "LN_10" => "2.30258509299404568401799145468436421",
+        "LOG2_E" => "1.44269504088896340735992468100189214",
+        "LOG2_10" => "3.32192809488736234787031942948939018",
+        "LOG10_E" => "0.434294481903251827651128918916605082",
+        "LOG10_2" => "0.301029995663981195213738894724493027",
+        _ => return None,
+    };
+    Some(Literal::Float(digits.to_string()))
+}
+
+struct Lowering<'a> {
+    types: &'a TypeMap,
+    env: &'a Env,
+    values: Vec<ValueInfo>,
+    scopes: Vec<HashMap<String, Value>>,
+    names: HashSet<String>,
+    bodies: Vec<Vec<Inst>>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(types: &'a TypeMap, env: &'a Env, reserved: &HashSet<String>) -> Self {
+        Self {
+            types,
+            env,
+            values: Vec::new(),
+            scopes: vec![HashMap::new()],
+            names: reserved.clone(),
+            bodies: Vec::new(),
+        }
+    }
+
+    fn unique(&mut self, name: &str) -> String {
+        let mut unique = name.to_string();
+        let mut i = 1;
+        while self.names.contains(&unique) || is_temporary_name(&unique) {
+            unique = format!("{}_{}", name, i);
+            i += 1;
+        }
+        self.names.insert(unique.clone());
+        unique
+    }
+
+    fn new_value(&mut self, ty: Ty, name: Option<String>) -> Value {
+        self.values.push(ValueInfo { ty, name });
+        Value(self.values.len() - 1)
+    }
+
+    fn emit(&mut self, op: Op, ty: Ty) -> Value {
+        let value = self.new_value(ty, None);
+        self.bodies.last_mut().unwrap().push(Inst { value, op });
+        value
+    }
+
+    fn ty_of(&self, expr: &Expr) -> Result<Ty> {
+        self.types
+            .get(expr)
+            .cloned()
+            .ok_or_else(|| unsupported(expr, "the type of this expression is unknown"))
+    }
+
+    fn convert(&mut self, value: Value, ty: &Ty) -> Value {
+        if self.values[value.0].ty == *ty {
+            value
+        } else {
+            self.emit(Op::Convert(value), ty.clone())
+        }
+    }
+
+    // Give a name to a value bound by `let`, or alias an already named value.
+    fn bind(&mut self, name: &str, value: Value) {
+        if self.values[value.0].name.is_none() {
+            let unique = self.unique(name);
+            self.values[value.0].name = Some(unique);
+        }
+        self.scopes.last_mut().unwrap().insert(name.to_string(), value);
+    }
+
+    fn bind_pat(&mut self, pat: &Pat, value: Value) -> Result<()> {
+        match pat {
+            Pat::Ident(ident) => self.bind(&ident.ident.to_string(), value),
+            Pat::Type(pat_type) => self.bind_pat(&pat_type.pat, value)?,
+            Pat::Wild(_) => (),
+            Pat::Tuple(tuple) => {
+                let tys = match &self.values[value.0].ty {
+                    Ty::Tuple(tys) if tys.len() == tuple.elems.len() => tys.clone(),
+                    _ => return Err(unsupported(pat, "expected a tuple")),
+                };
+                for (i, (pat, ty)) in tuple.elems.iter().zip(tys).enumerate() {
+                    let elem = self.emit(Op::Extract(value, i), ty);
+                    self.bind_pat(pat, elem)?;
+                }
+            }
+            _ => return Err(unsupported(pat, "unsupported pattern")),
+        }
+        Ok(())
+    }
+
+    fn lower_fn(mut self, item: &ItemFn) -> Result<Function> {
+        let mut params = Vec::new();
+        for input in &item.sig.inputs {
+            let pat_type = match input {
+                syn::FnArg::Typed(pat_type) => pat_type,
+                syn::FnArg::Receiver(_) => return Err(unsupported(input, "methods are not supported")),
+            };
+            let ident = match &*pat_type.pat {
+                Pat::Ident(ident) => ident.ident.to_string(),
+                pat => return Err(unsupported(pat, "expected a parameter name")),
+            };
+            let ty = self.env.resolve(&pat_type.ty)?;
+            let name = self.unique(&ident);
+            let value = self.new_value(ty, Some(name));
+            self.scopes[0].insert(ident, value);
+            params.push(value);
+        }
+        let ret = self.env.resolve_return(&item.sig.output)?;
+        let mut body = self.lower_block(&item.block)?;
+        self.convert_result(&mut body, &ret);
+        Ok(Function {
+            name: item.sig.ident.to_string(),
+            docs: docs(&item.attrs),
+            is_pub: !matches!(item.vis, syn::Visibility::Inherited),
+            params,
+            ret,
+            body,
+            values: self.values,
+        })
+    }
+
+    fn lower_const(mut self, item: &ItemConst) -> Result<Function> {
+        let ret = self.env.resolve(&item.ty)?;
+        let mut body = self.lower_expr_body(&item.expr)?;
+        self.convert_result(&mut body, &ret);
+        Ok(Function {
+            name: item.ident.to_string(),
+            docs: docs(&item.attrs),
+            is_pub: !matches!(item.vis, syn::Visibility::Inherited),
+            params: Vec::new(),
+            ret,
+            body,
+            values: self.values,
+        })
+    }
+
+    // Convert the result of a body to the declared type, eg. a `u32` literal
+    // assigned to a float constant.
+    fn convert_result(&mut self, body: &mut Body, ty: &Ty) {
+        if self.values[body.result.0].ty != *ty {
+            self.bodies.push(std::mem::take(&mut body.insts));
+            body.result = self.convert(body.result, ty);
+            body.insts = self.bodies.pop().unwrap();
+        }
+    }
+
+    fn lower_block(&mut self, block: &syn::Block) -> Result<Body> {
+        self.bodies.push(Vec::new());
+        self.scopes.push(HashMap::new());
+        let result = self.lower_stmts(&block.stmts, block);
+        self.scopes.pop();
+        let insts = self.bodies.pop().unwrap();
+        Ok(Body {
+            insts,
+            result: result?,
+        })
+    }
+
+    fn lower_expr_body(&mut self, expr: &Expr) -> Result<Body> {
+        self.bodies.push(Vec::new());
+        let result = self.lower_expr(expr);
+        let insts = self.bodies.pop().unwrap();
+        Ok(Body {
+            insts,
+            result: result?,
+        })
+    }
+
+    fn lower_stmts(&mut self, stmts: &[Stmt], block: &syn::Block) -> Result<Value> {
+        let mut result = None;
+        for stmt in stmts {
+            result = None;
+            match stmt {
+                Stmt::Local(local) => {
+                    let init = match &local.init {
+                        Some((_, init)) => init,
+                        None => return Err(unsupported(local, "expected an initialiser")),
+                    };
+                    let mut value = self.lower_expr(init)?;
+                    if let Pat::Type(pat_type) = &local.pat {
+                        let ty = self.env.resolve(&pat_type.ty)?;
+                        value = self.convert(value, &ty);
+                    }
+                    self.bind_pat(&local.pat, value)?;
+                }
+                Stmt::Item(Item::Const(item)) => {
+                    let ty = self.env.resolve(&item.ty)?;
+                    let value = self.lower_expr(&item.expr)?;
+                    let value = self.convert(value, &ty);
+                    self.bind(&item.ident.to_string(), value);
+                }
+                Stmt::Item(item) => return Err(unsupported(item, "unsupported item")),
+                Stmt::Expr(expr) => result = Some(self.lower_expr(expr)?),
+                Stmt::Semi(expr, _) => {
+                    self.lower_expr(expr)?;
+                }
+            }
+        }
+        result.ok_or_else(|| unsupported(block, "expected the block to end with a value"))
+    }
+
+    fn lower_expr(&mut self, expr: &Expr) -> Result<Value> {
+        match expr {
+            Expr::Paren(paren) => self.lower_expr(&paren.expr),
+            Expr::Group(group) => self.lower_expr(&group.expr),
+            Expr::Block(block) => {
+                self.scopes.push(HashMap::new());
+                let result = self.lower_stmts(&block.block.stmts, &block.block);
+                self.scopes.pop();
+                result
+            }
+            Expr::Lit(lit) => {
+                let ty = self.ty_of(expr)?;
+                let lit = match &lit.lit {
+                    Lit::Float(f) => Literal::Float(f.base10_digits().to_string()),
+                    Lit::Int(i) => Literal::Int(i.base10_parse()?),
+                    Lit::Bool(b) => Literal::Bool(b.value),
+                    _ => return Err(unsupported(expr, "unsupported literal")),
+                };
+                Ok(self.emit(Op::Lit(lit), ty))
+            }
+            Expr::Unary(unary) => {
+                let ty = self.ty_of(expr)?;
+                let a = self.lower_expr(&unary.expr)?;
+                let op = match unary.op {
+                    syn::UnOp::Neg(_) => UnaryOp::Neg,
+                    syn::UnOp::Not(_) => UnaryOp::Not,
+                    syn::UnOp::Deref(_) => return Err(unsupported(expr, "dereferencing is not supported")),
+                };
+                Ok(self.emit(Op::Unary(op, a), ty))
+            }
+            Expr::Binary(binary) => {
+                let ty = self.ty_of(expr)?;
+                let a = self.lower_expr(&binary.left)?;
+                let b = self.lower_expr(&binary.right)?;
+                let op = binary_op(&binary.op)
+                    .ok_or_else(|| unsupported(expr, "unsupported operator"))?;
+                Ok(self.emit(Op::Binary(op, a, b), ty))
+            }
+            Expr::Cast(cast) => {
+                let ty = self.ty_of(expr)?;
+                let a = self.lower_expr(&cast.expr)?;
+                // `0.5 as fty` is a literal of the cast type.
+                if let Some(Inst {
+                    op: Op::Lit(lit @ Literal::Float(_)),
+                    value,
+                }) = self.bodies.last().unwrap().last()
+                {
+                    if *value == a && ty.is_float() {
+                        let lit = lit.clone();
+                        self.bodies.last_mut().unwrap().pop();
+                        return Ok(self.emit(Op::Lit(lit), ty));
+                    }
+                }
+                Ok(self.convert(a, &ty))
+            }
+            Expr::Path(path) => self.lower_path(expr, path),
+            Expr::If(exprif) => {
+                let ty = self.ty_of(expr)?;
+                let cond = self.lower_expr(&exprif.cond)?;
+                let else_branch = match &exprif.else_branch {
+                    Some((_, else_branch)) => else_branch,
+                    None => return Err(unsupported(expr, "`if` requires an `else`")),
+                };
+                self.scopes.push(HashMap::new());
+                let then_body = self.lower_block(&exprif.then_branch);
+                self.scopes.pop();
+                let then_body = then_body?;
+                self.scopes.push(HashMap::new());
+                let else_body = self.lower_expr_body(else_branch);
+                self.scopes.pop();
+                let else_body = else_body?;
+                Ok(self.emit(Op::If(cond, then_body, else_body), ty))
+            }
+            Expr::Tuple(tuple) => {
+                let ty = self.ty_of(expr)?;
+                let elems = tuple
+                    .elems
+                    .iter()
+                    .map(|e| self.lower_expr(e))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(self.emit(Op::Tuple(elems), ty))
+            }
+            Expr::Field(field) => {
+                let ty = self.ty_of(expr)?;
+                let index = match &field.member {
+                    syn::Member::Unnamed(index) => index.index as usize,
+                    syn::Member::Named(_) => return Err(unsupported(expr, "expected a tuple field")),
+                };
+                let a = self.lower_expr(&field.base)?;
+                Ok(self.emit(Op::Extract(a, index), ty))
+            }
+            Expr::Call(call) => self.lower_call(expr, call),
+            Expr::MethodCall(call) => self.lower_method_call(expr, call),
+            _ => Err(unsupported(expr, "unsupported expression")),
+        }
+    }
+
+    fn lower_path(&mut self, expr: &Expr, path: &syn::ExprPath) -> Result<Value> {
+        let segments: Vec<_> = path.path.segments.iter().collect();
+        if let [seg] = segments.as_slice() {
+            let name = seg.ident.to_string();
+            if let Some(value) = self.scopes.iter().rev().find_map(|s| s.get(&name)) {
+                return Ok(*value);
+            }
+            if let Some(ty) = self.env.constant(&name) {
+                let ty = ty.clone();
+                return Ok(self.emit(Op::Const(name), ty));
+            }
+            return Err(Error::at(ErrorKind::UndefinedVariable, path)
+                .with_operation("lowering to the IR"));
+        }
+        let last = segments.last().unwrap().ident.to_string();
+        match special(&last).or_else(|| float_const(&last)) {
+            Some(lit) => {
+                let ty = self.ty_of(expr)?;
+                Ok(self.emit(Op::Lit(lit), ty))
+            }
+            None => Err(unsupported(path, "unsupported path")),
+        }
+    }
+
+    fn lower_args(&mut self, args: &syn::punctuated::Punctuated<Expr, syn::Token![,]>) -> Result<Vec<Value>> {
+        args.iter().map(|arg| self.lower_expr(arg)).collect()
+    }
+
+    fn lower_call(&mut self, expr: &Expr, call: &syn::ExprCall) -> Result<Value> {
+        let ty = self.ty_of(expr)?;
+        let func = match &*call.func {
+            Expr::Path(func) => func,
+            func => return Err(unsupported(func, "expected a function name")),
+        };
+        let args = self.lower_args(&call.args)?;
+        if func.qself.is_none() && func.path.segments.len() == 1 {
+            let name = func.path.segments[0].ident.to_string();
+            return Ok(self.emit(Op::Call(name, args), ty));
+        }
+        let assoc = func.path.segments.last().unwrap().ident.to_string();
+        match (assoc.as_str(), args.as_slice()) {
+            ("from_bits", [a]) => Ok(self.emit(Op::Bitcast(*a), ty)),
+            ("splat", [a]) => Ok(self.emit(Op::Splat(*a), ty)),
+            (method, _) => self.lower_method(method, args, ty, call),
+        }
+    }
+
+    fn lower_method_call(&mut self, expr: &Expr, call: &syn::ExprMethodCall) -> Result<Value> {
+        let ty = self.ty_of(expr)?;
+        let recv = self.lower_expr(&call.receiver)?;
+        let mut args = vec![recv];
+        args.extend(self.lower_args(&call.args)?);
+        self.lower_method(&call.method.to_string(), args, ty, call)
+    }
+
+    fn lower_method<T: ToTokens>(&mut self, method: &str, args: Vec<Value>, ty: Ty, node: &T) -> Result<Value> {
+        let comparison = |name: &str| match name {
+            "eq" => Some(BinaryOp::Eq),
+            "ne" => Some(BinaryOp::Ne),
+            "lt" => Some(BinaryOp::Lt),
+            "le" => Some(BinaryOp::Le),
+            "gt" => Some(BinaryOp::Gt),
+            "ge" => Some(BinaryOp::Ge),
+            _ => None,
+        };
+        let stripped = method
+            .strip_prefix("simd_")
+            .or_else(|| method.strip_prefix("lanes_"))
+            .unwrap_or(method);
+        match (method, args.as_slice()) {
+            ("to_bits", [a]) => Ok(self.emit(Op::Bitcast(*a), ty)),
+            ("cast", [a]) => Ok(self.emit(Op::Convert(*a), ty)),
+            ("clone", [a]) => Ok(*a),
+            ("select", [c, a, b]) => Ok(self.emit(Op::Select(*c, *a, *b), ty)),
+            (_, [a, b]) if comparison(stripped).is_some() => {
+                let op = comparison(stripped).unwrap();
+                Ok(self.emit(Op::Binary(op, *a, *b), ty))
+            }
+            ("simd_min", _) | ("simd_max", _) => {
+                let i = Intrinsic::from_name(stripped).unwrap();
+                Ok(self.emit(Op::Intrinsic(i, args), ty))
+            }
+            _ => match Intrinsic::from_name(method) {
+                Some(i) => Ok(self.emit(Op::Intrinsic(i, args), ty)),
+                None => Err(unsupported(node, &format!("unsupported method `{}`", method))),
+            },
+        }
+    }
+}
+
+fn binary_op(op: &syn::BinOp) -> Option<BinaryOp> {
+    use syn::BinOp as B;
+    Some(match op {
+        B::Add(_) => BinaryOp::Add,
+        B::Sub(_) => BinaryOp::Sub,
+        B::Mul(_) => BinaryOp::Mul,
+        B::Div(_) => BinaryOp::Div,
+        B::Rem(_) => BinaryOp::Rem,
+        B::BitAnd(_) => BinaryOp::BitAnd,
+        B::BitOr(_) => BinaryOp::BitOr,
+        B::BitXor(_) => BinaryOp::BitXor,
+        B::Shl(_) => BinaryOp::Shl,
+        B::Shr(_) => BinaryOp::Shr,
+        B::Eq(_) => BinaryOp::Eq,
+        B::Ne(_) => BinaryOp::Ne,
+        B::Lt(_) => BinaryOp::Lt,
+        B::Le(_) => BinaryOp::Le,
+        B::Gt(_) => BinaryOp::Gt,
+        B::Ge(_) => BinaryOp::Ge,
+        B::And(_) => BinaryOp::And,
+        B::Or(_) => BinaryOp::Or,
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_lower_file() {
+    use syn::parse_quote;
+
+    let file: File = parse_quote! {
+        type fty = f32;
+        const HALF: fty = 0.5;
+        fn g(x: fty) -> (fty, fty) { (x, x * HALF) }
+        fn f(x: fty) -> fty {
+            let r = x.to_bits();
+            let r = fty::from_bits(r & 0x7fffffff);
+            let (a, b) = g(r);
+            if a < 1.0 { a + b } else { f32::NAN }
+        }
+    };
+    let module = lower_file(&file).unwrap();
+    assert_eq!(module.consts.len(), 1);
+    assert_eq!(module.constant("HALF").unwrap().ret, Ty::Scalar(Scalar::F32));
+    let f = module.function("f").unwrap();
+    assert_eq!(f.to_string(), "\
+fn f(x: f32) -> f32 {
+  r = %1 : u32 = bitcast x
+  %2 : u32 = lit 2147483647
+  %3 : u32 = and r, %2
+  r_1 = %4 : f32 = bitcast %3
+  %5 : (f32, f32) = call g(r_1)
+  a = %6 : f32 = extract %5.0
+  b = %7 : f32 = extract %5.1
+  %8 : f32 = lit 1.0
+  %9 : bool = lt a, %8
+  %12 : f32 = if %9 {
+    %10 : f32 = add a, b
+    yield %10
+  } else {
+    %11 : f32 = lit Nan
+    yield %11
+  }
+  return %12
+}
+");
+}
+
+#[test]
+fn test_lower_errors() {
+    use syn::parse_quote;
+
+    let file: File = parse_quote! {
+        fn f(x: f32) -> f32 { if x < 0.0 { x } }
+    };
+    let e = lower_file(&file).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnsupportedCodegen);
+    assert_eq!(e.message(), Some("`if` requires an `else`"));
+
+    let file: File = parse_quote! {
+        fn f(x: &[f32]) -> f32 { x[0] }
+    };
+    assert!(lower_file(&file).is_err());
+}
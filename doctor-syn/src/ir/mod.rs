@@ -0,0 +1,547 @@
+//! A typed SSA intermediate representation for numeric code.
+//!
+//! Functions are lowered from syn to a list of instructions, each defining
+//! one value. Control flow is structured: an `if` is a single instruction
+//! whose branches are nested bodies yielding a value, so backends can emit
+//! either a branch or a select.
+//!
+//! Code generators print a `Module` rather than walking syn trees, so a new
+//! language only needs a printer. Values that are used once and have no name
+//! can be printed inline, see `Function::is_inline`.
+//!
+//! ```
+//! use doctor_syn::ir::lower_file;
+//! use doctor_syn::syn::{self, parse_quote};
+//!
+//! let file: syn::File = parse_quote! {
+//!     fn f(x: f32) -> f32 { let y = x * x; y.mul_add(2.0, 1.0) }
+//! };
+//! let module = lower_file(&file).unwrap();
+//! assert_eq!(module.functions[0].to_string(), "\
+//! fn f(x: f32) -> f32 {
+//!   y = %1 : f32 = mul x, x
+//!   %2 : f32 = lit 2.0
+//!   %3 : f32 = lit 1.0
+//!   %4 : f32 = mul_add y, %2, %3
+//!   return %4
+//! }
+//! ");
+//! ```
+
+mod lower;
+
+pub use crate::typeinference::{Scalar, Ty};
+pub use lower::{lower_file, lower_fn};
+
+/// A value defined by a parameter or instruction, an index into `Function::values`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+/// The type and optional source name of a value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueInfo {
+    pub ty: Ty,
+    /// The unique name of a parameter or `let` binding.
+    pub name: Option<String>,
+}
+
+/// Named float constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Special {
+    Nan,
+    Infinity,
+    NegInfinity,
+    MinPositive,
+    Max,
+    Min,
+    Epsilon,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    /// Decimal digits without a suffix, eg. `0.5` or `1e-5`.
+    Float(String),
+    Int(u128),
+    Bool(bool),
+    Special(Special),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    /// Logical not for bools, bitwise not for integers.
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn is_comparison(self) -> bool {
+        use BinaryOp::*;
+        matches!(self, Eq | Ne | Lt | Le | Gt | Ge)
+    }
+
+    /// The Rust and C spelling of the operator.
+    pub fn symbol(self) -> &'static str {
+        use BinaryOp::*;
+        match self {
+            Add => "+",
+            Sub => "-",
+            Mul => "*",
+            Div => "/",
+            Rem => "%",
+            BitAnd => "&",
+            BitOr => "|",
+            BitXor => "^",
+            Shl => "<<",
+            Shr => ">>",
+            Eq => "==",
+            Ne => "!=",
+            Lt => "<",
+            Le => "<=",
+            Gt => ">",
+            Ge => ">=",
+            And => "&&",
+            Or => "||",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        use BinaryOp::*;
+        match self {
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+            Div => "div",
+            Rem => "rem",
+            BitAnd => "and",
+            BitOr => "or",
+            BitXor => "xor",
+            Shl => "shl",
+            Shr => "shr",
+            Eq => "eq",
+            Ne => "ne",
+            Lt => "lt",
+            Le => "le",
+            Gt => "gt",
+            Ge => "ge",
+            And => "logical_and",
+            Or => "logical_or",
+        }
+    }
+}
+
+/// Maths functions, named after the Rust methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic {
+    Abs,
+    Sqrt,
+    Cbrt,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Sinh,
+    Cosh,
+    Tanh,
+    Asinh,
+    Acosh,
+    Atanh,
+    Exp,
+    Exp2,
+    ExpM1,
+    Ln,
+    Ln1p,
+    Log,
+    Log2,
+    Log10,
+    Recip,
+    Round,
+    Floor,
+    Ceil,
+    Trunc,
+    Fract,
+    Copysign,
+    MulAdd,
+    Powf,
+    Powi,
+    Min,
+    Max,
+    Signum,
+    Hypot,
+    SinCos,
+    IsNan,
+    IsInfinite,
+    IsFinite,
+    IsSignNegative,
+    IsSignPositive,
+    WrappingAdd,
+    WrappingSub,
+    WrappingMul,
+    WrappingNeg,
+    CountOnes,
+    LeadingZeros,
+    TrailingZeros,
+}
+
+const INTRINSICS: &[(Intrinsic, &str)] = {
+    use Intrinsic::*;
+    &[
+        (Abs, "abs"),
+        (Sqrt, "sqrt"),
+        (Cbrt, "cbrt"),
+        (Sin, "sin"),
+        (Cos, "cos"),
+        (Tan, "tan"),
+        (Asin, "asin"),
+        (Acos, "acos"),
+        (Atan, "atan"),
+        (Atan2, "atan2"),
+        (Sinh, "sinh"),
+        (Cosh, "cosh"),
+        (Tanh, "tanh"),
+        (Asinh, "asinh"),
+        (Acosh, "acosh"),
+        (Atanh, "atanh"),
+        (Exp, "exp"),
+        (Exp2, "exp2"),
+        (ExpM1, "exp_m1"),
+        (Ln, "ln"),
+        (Ln1p, "ln_1p"),
+        (Log, "log"),
+        (Log2, "log2"),
+        (Log10, "log10"),
+        (Recip, "recip"),
+        (Round, "round"),
+        (Floor, "floor"),
+        (Ceil, "ceil"),
+        (Trunc, "trunc"),
+        (Fract, "fract"),
+        (Copysign, "copysign"),
+        (MulAdd, "mul_add"),
+        (Powf, "powf"),
+        (Powi, "powi"),
+        (Min, "min"),
+        (Max, "max"),
+        (Signum, "signum"),
+        (Hypot, "hypot"),
+        (SinCos, "sin_cos"),
+        (IsNan, "is_nan"),
+        (IsInfinite, "is_infinite"),
+        (IsFinite, "is_finite"),
+        (IsSignNegative, "is_sign_negative"),
+        (IsSignPositive, "is_sign_positive"),
+        (WrappingAdd, "wrapping_add"),
+        (WrappingSub, "wrapping_sub"),
+        (WrappingMul, "wrapping_mul"),
+        (WrappingNeg, "wrapping_neg"),
+        (CountOnes, "count_ones"),
+        (LeadingZeros, "leading_zeros"),
+        (TrailingZeros, "trailing_zeros"),
+    ]
+};
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Intrinsic> {
+        INTRINSICS
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(i, _)| *i)
+    }
+
+    /// The Rust method name.
+    pub fn name(self) -> &'static str {
+        INTRINSICS.iter().find(|(i, _)| *i == self).unwrap().1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Lit(Literal),
+    /// A constant of the module.
+    Const(String),
+    Unary(UnaryOp, Value),
+    Binary(BinaryOp, Value, Value),
+    /// A numeric conversion, as `as` in Rust.
+    Convert(Value),
+    /// Reinterpret the bits as another type of the same size.
+    Bitcast(Value),
+    /// `cond ? a : b`, lane-wise for vectors.
+    Select(Value, Value, Value),
+    /// Broadcast a scalar to all lanes of a vector.
+    Splat(Value),
+    /// Call a function of the module.
+    Call(String, Vec<Value>),
+    Intrinsic(Intrinsic, Vec<Value>),
+    Tuple(Vec<Value>),
+    Extract(Value, usize),
+    /// `if cond { then } else { else }`.
+    If(Value, Body, Body),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub value: Value,
+    pub op: Op,
+}
+
+/// A sequence of instructions yielding a value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub insts: Vec<Inst>,
+    pub result: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub docs: Vec<String>,
+    pub is_pub: bool,
+    /// The parameter values, which are the first values of the function.
+    pub params: Vec<Value>,
+    pub ret: Ty,
+    pub body: Body,
+    pub values: Vec<ValueInfo>,
+}
+
+/// Functions and constants. Constants are functions without parameters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    pub consts: Vec<Function>,
+    pub functions: Vec<Function>,
+}
+
+impl Body {
+    /// Visit every instruction, including those in nested bodies, in order.
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Inst)) {
+        for inst in &self.insts {
+            f(inst);
+            if let Op::If(_, then_body, else_body) = &inst.op {
+                then_body.walk(f);
+                else_body.walk(f);
+            }
+        }
+    }
+}
+
+impl Op {
+    /// The values used by this operation, not including nested bodies.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Op::Lit(_) | Op::Const(_) => vec![],
+            Op::Unary(_, a) | Op::Convert(a) | Op::Bitcast(a) | Op::Splat(a) => vec![*a],
+            Op::Extract(a, _) => vec![*a],
+            Op::Binary(_, a, b) => vec![*a, *b],
+            Op::Select(c, a, b) => vec![*c, *a, *b],
+            Op::Call(_, args) | Op::Intrinsic(_, args) | Op::Tuple(args) => args.clone(),
+            Op::If(c, _, _) => vec![*c],
+        }
+    }
+}
+
+impl Function {
+    pub fn ty(&self, value: Value) -> &Ty {
+        &self.values[value.0].ty
+    }
+
+    /// The source name of a value or `v<n>` for temporaries.
+    pub fn name(&self, value: Value) -> String {
+        self.values[value.0]
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("v{}", value.0))
+    }
+
+    /// The names of the parameters.
+    pub fn param_names(&self) -> Vec<String> {
+        self.params.iter().map(|p| self.name(*p)).collect()
+    }
+
+    /// Find the instruction that defines a value.
+    pub fn inst(&self, value: Value) -> Option<&Inst> {
+        let mut found = None;
+        self.body.walk(&mut |inst| {
+            if inst.value == value {
+                found = Some(inst);
+            }
+        });
+        found
+    }
+
+    /// The number of uses of each value, including body results.
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.values.len()];
+        count_uses(&self.body, &mut counts);
+        counts
+    }
+
+    /// True if a value can be printed at its single use rather than
+    /// being assigned to a variable.
+    pub fn is_inline(&self, value: Value, use_counts: &[usize]) -> bool {
+        self.values[value.0].name.is_none()
+            && use_counts[value.0] <= 1
+            && !matches!(self.inst(value).map(|i| &i.op), Some(Op::If(..)) | None)
+    }
+
+    /// The module constants used by this function.
+    pub fn consts_used(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.body.walk(&mut |inst| {
+            if let Op::Const(name) = &inst.op {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        });
+        names
+    }
+}
+
+fn count_uses(body: &Body, counts: &mut [usize]) {
+    for inst in &body.insts {
+        for v in inst.op.operands() {
+            counts[v.0] += 1;
+        }
+        if let Op::If(_, then_body, else_body) = &inst.op {
+            count_uses(then_body, counts);
+            count_uses(else_body, counts);
+        }
+    }
+    counts[body.result.0] += 1;
+}
+
+impl Module {
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    pub fn constant(&self, name: &str) -> Option<&Function> {
+        self.consts.iter().find(|f| f.name == name)
+    }
+
+    /// The function named after an intrinsic, such as `ln`, that `caller` can
+    /// call with `args` where the target has no instruction or library for it.
+    pub fn function_for(
+        &self,
+        i: Intrinsic,
+        caller: &Function,
+        args: &[Value],
+    ) -> Option<&Function> {
+        self.function(i.name()).filter(|f| {
+            f.name != caller.name
+                && f.params.len() == args.len()
+                && f.params.iter().zip(args).all(|(p, a)| f.ty(*p) == caller.ty(*a))
+        })
+    }
+}
+
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Float(digits) => write!(f, "{}", digits),
+            Literal::Int(i) => write!(f, "{}", i),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Special(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+impl Function {
+    fn fmt_body(&self, f: &mut std::fmt::Formatter<'_>, body: &Body, indent: usize) -> std::fmt::Result {
+        let pad = "  ".repeat(indent);
+        let names = |values: &[Value]| {
+            values
+                .iter()
+                .map(|v| self.display_value(*v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        for inst in &body.insts {
+            let v = inst.value;
+            write!(f, "{}", pad)?;
+            if let Some(name) = &self.values[v.0].name {
+                write!(f, "{} = ", name)?;
+            }
+            write!(f, "%{} : {} = ", v.0, self.ty(v))?;
+            match &inst.op {
+                Op::Lit(lit) => writeln!(f, "lit {}", lit)?,
+                Op::Const(name) => writeln!(f, "const {}", name)?,
+                Op::Unary(op, a) => writeln!(f, "{:?} {}", op, self.display_value(*a))?,
+                Op::Binary(op, a, b) => writeln!(f, "{} {}", op.name(), names(&[*a, *b]))?,
+                Op::Convert(a) => writeln!(f, "convert {}", self.display_value(*a))?,
+                Op::Bitcast(a) => writeln!(f, "bitcast {}", self.display_value(*a))?,
+                Op::Select(c, a, b) => writeln!(f, "select {}", names(&[*c, *a, *b]))?,
+                Op::Splat(a) => writeln!(f, "splat {}", self.display_value(*a))?,
+                Op::Call(name, args) => writeln!(f, "call {}({})", name, names(args))?,
+                Op::Intrinsic(i, args) => writeln!(f, "{} {}", i.name(), names(args))?,
+                Op::Tuple(args) => writeln!(f, "tuple {}", names(args))?,
+                Op::Extract(a, i) => writeln!(f, "extract {}.{}", self.display_value(*a), i)?,
+                Op::If(c, then_body, else_body) => {
+                    writeln!(f, "if {} {{", self.display_value(*c))?;
+                    self.fmt_body(f, then_body, indent + 1)?;
+                    writeln!(f, "{}}} else {{", pad)?;
+                    self.fmt_body(f, else_body, indent + 1)?;
+                    writeln!(f, "{}}}", pad)?;
+                }
+            }
+        }
+        writeln!(f, "{}{} {}", pad, if indent == 1 { "return" } else { "yield" }, self.display_value(body.result))
+    }
+
+    fn display_value(&self, v: Value) -> String {
+        match &self.values[v.0].name {
+            Some(name) => name.clone(),
+            None => format!("%{}", v.0),
+        }
+    }
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let params: Vec<_> = self
+            .params
+            .iter()
+            .map(|p| format!("{}: {}", self.name(*p), self.ty(*p)))
+            .collect();
+        writeln!(f, "fn {}({}) -> {} {{", self.name, params.join(", "), self.ret)?;
+        self.fmt_body(f, &self.body, 1)?;
+        writeln!(f, "}}")
+    }
+}
+
+impl std::fmt::Display for Module {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for c in &self.consts {
+            writeln!(f, "const {}: {} {{", c.name, c.ret)?;
+            c.fmt_body(f, &c.body, 1)?;
+            writeln!(f, "}}")?;
+        }
+        for func in &self.functions {
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
@@ -5,9 +5,11 @@
 
 pub mod error;
 pub mod expression;
+pub mod ir;
 pub mod name;
 pub mod polynomial;
 pub mod transformation;
+pub mod typeinference;
 pub mod variablelist;
 pub mod visitor;
 
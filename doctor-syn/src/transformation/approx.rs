@@ -1,8 +1,8 @@
 use crate::bdmath::*;
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 use crate::polynomial::Polynomial;
 use crate::{Expression, Name, Parity, VariableList};
-use proc_macro2::{Span, TokenStream};
+use proc_macro2::TokenStream;
 use quote::quote;
 use std::convert::TryInto;
 use syn::{parse_quote, Expr};
@@ -15,15 +15,20 @@ fn mul_add_polynomial(
     terms: &[BigDecimal],
     variable: Name,
     parity: Parity,
-    span: Span,
+    expr: &Expression,
 ) -> Result<Expr> {
     let k = terms.len();
+    let wrong_number_of_terms = |parity: &str| {
+        Error::at(ErrorKind::WrongNumberOfTerms, expr.as_ref())
+            .with_operation(format!("building an {} polynomial", parity))
+            .with_message(format!("{} terms is not valid for this parity", k))
+    };
     let highest_coeff = mkexpr(&terms[k - 1]);
     let x = variable.as_ref();
     match parity {
         Parity::Odd => {
             if k % 2 != 0 {
-                return Err(Error::WrongNumberOfTerms(span));
+                return Err(wrong_number_of_terms("odd"));
             }
             let mul_adds: Vec<TokenStream> = (1..k - 1)
                 .step_by(2)
@@ -37,7 +42,7 @@ fn mul_add_polynomial(
         }
         Parity::Even => {
             if k % 2 == 0 {
-                return Err(Error::WrongNumberOfTerms(span));
+                return Err(wrong_number_of_terms("even"));
             }
             let mul_adds: Vec<TokenStream> = (0..k - 1)
                 .step_by(2)
@@ -77,10 +82,27 @@ pub fn approx(
     parity: Parity,
     num_digits: i64,
 ) -> Result<Expression> {
+    let terms = approx_terms(expr, num_terms, xmin, xmax, variable.clone(), num_digits)?;
+    mul_add_polynomial(&terms, variable, parity, expr).map(|e| e.into())
+}
+
+/// The coefficients of the polynomial used by `approx`, lowest power first.
+pub fn approx_terms(
+    expr: &Expression,
+    num_terms: usize,
+    xmin: f64,
+    xmax: f64,
+    variable: Name,
+    num_digits: i64,
+) -> Result<Vec<BigDecimal>> {
+    if num_terms < 2 {
+        return Err(Error::at(ErrorKind::WrongNumberOfTerms, expr.as_ref())
+            .with_operation("approximating")
+            .with_message(format!("{} terms is too few, at least 2 are needed", num_terms)));
+    }
     let xmin = bigdf(xmin);
     let xmax = bigdf(xmax);
 
-    // let err_fn = || Error::CouldNotEvaulate(expr.span());
     let a = (&xmax + &xmin) * half();
     let b = pi(num_digits) / BigDecimal::from_usize(num_terms - 1).unwrap();
     let c = (&xmax - &xmin) * half();
@@ -91,8 +113,16 @@ pub fn approx(
         let x = &a - &c * cos(BigDecimal::from_usize(i).unwrap() * &b, num_digits);
         let mut vars = VariableList::new();
         vars.add_var(variable.clone(), mkexpr(&x).into());
-        let subst = expr.subst(vars)?;
-        let y: BigDecimal = subst.eval(num_digits)?.try_into()?;
+        let context = |e: Error| {
+            Error::at(ErrorKind::CouldNotEvaluate, expr.as_ref())
+                .with_operation(format!("approximating at {} = {}", variable, x))
+                .caused_by(e)
+        };
+        let subst = expr.subst(vars).map_err(context)?;
+        let y: BigDecimal = subst
+            .eval(num_digits)
+            .and_then(|y| y.try_into())
+            .map_err(context)?;
         // println!("x={:16} y={:16} {}", x, y, subst);
         xvalues.push(x);
         yvalues.push(y);
@@ -100,5 +130,39 @@ pub fn approx(
 
     let poly = Polynomial::from_points(xvalues.as_slice(), yvalues.as_slice(), num_digits);
 
-    mul_add_polynomial(poly.terms(), variable, parity, expr.span()).map(|e| e.into())
+    Ok(poly.terms().to_vec())
+}
+
+#[test]
+fn test_approx_errors() {
+    use crate::{expr, name, ErrorKind};
+    let e = expr!((x * y).sin())
+        .approx(4, -1.0, 1.0, name!(x), Parity::Neither, 20)
+        .unwrap_err();
+    assert!(e.operation().unwrap().starts_with("approximating at x = "));
+    assert_eq!(e.root_cause().kind(), ErrorKind::UndefinedVariable);
+    assert_eq!(e.root_cause().source_text(), Some("y"));
+
+    let e = expr!(x.sin())
+        .approx(5, -1.0, 1.0, name!(x), Parity::Odd, 20)
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::WrongNumberOfTerms);
+
+    for num_terms in 0..2 {
+        let e = expr!(x.sin())
+            .approx(num_terms, -1.0, 1.0, name!(x), Parity::Neither, 20)
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WrongNumberOfTerms);
+    }
+}
+
+#[test]
+fn test_approx_terms() {
+    use crate::{expr, name};
+    let terms = expr!(x * x * 3 + 2).approx_terms(3, -1.0, 1.0, name!(x), 20).unwrap();
+    let terms: Vec<f64> = terms.iter().map(|t| t.to_string().parse().unwrap()).collect();
+    assert_eq!(terms.len(), 3);
+    assert!((terms[0] - 2.0).abs() < 1e-15);
+    assert!(terms[1].abs() < 1e-15);
+    assert!((terms[2] - 3.0).abs() < 1e-15);
 }
@@ -4,7 +4,7 @@
 //! But `PI.sin()` should be zero.
 //! But `(PI/2).sin()` should be one.
 
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 use crate::visitor::Visitor;
 use crate::Expression;
 
@@ -18,8 +18,8 @@ pub struct Eval {
     pub(crate) num_digits: i64,
 }
 
-fn eval_err(e: Expr) -> Error {
-    Error::CouldNotEvaulate(Expression::from(e).to_string())
+fn eval_err<T: quote::ToTokens>(node: &T, operation: String) -> Error {
+    Error::at(ErrorKind::CouldNotEvaluate, node).with_operation(operation)
 }
 
 impl Visitor for Eval {
@@ -37,18 +37,26 @@ impl Visitor for Eval {
             .map(|a| -> Result<Expression> { Ok(self.visit_expr(a)?.into()) })
             .collect::<Result<Vec<_>>>()?;
 
-        let receiver: BigDecimal = receiver.try_into()?;
+        let method = expr.method.to_string();
+        let operation = || format!("evaluating method `{}`", method);
+        let receiver: BigDecimal = receiver
+            .try_into()
+            .map_err(|e| eval_err(expr, operation()).caused_by(e))?;
         let args: Vec<BigDecimal> = args
             .iter()
             .map(|a| a.try_into())
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| eval_err(expr, operation()).caused_by(e))?;
 
-        let errfn = || Error::CouldNotEvaulate(Expression::from(Expr::from(expr.clone())).to_string());
+        let errfn = || {
+            eval_err(expr, operation())
+                .with_message("the result is undefined for these arguments")
+        };
         let arg0 = || args[0].clone();
         let arg1 = || args[1].clone();
         // let mkexpr = |e : BigDecimal| Result::Ok(Expr::from(Expression::from(e)));
 
-        match (expr.method.to_string().as_str(), receiver, args.len()) {
+        match (method.as_str(), receiver, args.len()) {
             // ("is_nan", receiver, 0) => Ok(Expression::from(is_nan(x, self.num_digits)).into()),
             // ("is_infinite", x, 0) => Ok(Expression::from(is_infinite(x, self.num_digits)).into()),
             // ("is_finite", x, 0) => Ok(Expression::from(is_finite(x, self.num_digits)).into()),
@@ -148,7 +156,11 @@ impl Visitor for Eval {
             )
             .into()),
 
-            ("round_ieee", x, 1) => Ok(Expression::from(round_ieee(x, arg0(), self.num_digits)?).into()),
+            ("round_ieee", x, 1) => Ok(Expression::from(
+                round_ieee(x, arg0(), self.num_digits)
+                    .map_err(|e| eval_err(expr, operation()).caused_by(e))?,
+            )
+            .into()),
 
             // ("atan2", x, 1) => Ok(x
             //     .atan2(arg0().try_into()?)
@@ -165,7 +177,10 @@ impl Visitor for Eval {
             // ("acosh", x, 0) => Ok(Expression::from(acosh(x, self.num_digits)).into()),
             // ("atanh", x, 0) => Ok(Expression::from(atanh(x, self.num_digits)).into()),
             // ("integer_decode", x, 0) => Ok(Expression::from(integer_decode(x, self.num_digits)).into()),
-            _ => Err(eval_err(expr.clone().into())),
+            (_, _, num_args) => Err(eval_err(expr, operation()).with_message(format!(
+                "unsupported method or wrong number of arguments ({})",
+                num_args
+            ))),
         }
     }
 
@@ -173,6 +188,8 @@ impl Visitor for Eval {
         let left: Expression = self.visit_expr(&exprbinary.left)?.into();
         let right: Expression = self.visit_expr(&exprbinary.right)?.into();
 
+        let op = &exprbinary.op;
+        let operation = || format!("evaluating operator `{}`", quote::quote!(#op));
         if left.is_numeric() && right.is_numeric() {
             let left: BigDecimal = left.try_into().unwrap();
             let right: BigDecimal = right.try_into().unwrap();
@@ -191,10 +208,10 @@ impl Visitor for Eval {
                 BinOp::Ge(_) => Ok(Expression::from(left >= right).into()),
                 BinOp::Eq(_) => Ok(Expression::from(left == right).into()),
                 BinOp::Ne(_) => Ok(Expression::from(left != right).into()),
-                _ => Err(eval_err(exprbinary.clone().into())),
+                _ => Err(eval_err(exprbinary, operation()).with_message("unsupported operator")),
             }
         } else {
-            Err(eval_err(exprbinary.clone().into()))
+            Err(eval_err(exprbinary, operation()).with_message("operands are not numeric"))
         }
     }
 
@@ -206,10 +223,12 @@ impl Visitor for Eval {
                 // UnOp::Deref(_) => (),
                 // UnOp::Not(_) => (),
                 UnOp::Neg(_) => Ok(Expression::from(-expr).into()),
-                _ => Err(eval_err(exprunary.clone().into())),
+                _ => Err(eval_err(exprunary, "evaluating a unary operator".to_string())
+                    .with_message("unsupported operator")),
             }
         } else {
-            Err(eval_err(exprunary.clone().into()))
+            Err(eval_err(exprunary, "evaluating a unary operator".to_string())
+                .with_message("operand is not numeric"))
         }
     }
 
@@ -219,10 +238,34 @@ impl Visitor for Eval {
             if name == "PI" {
                 Ok(Expression::from(pi(self.num_digits)).into())
             } else {
-                Err(eval_err(exprpath.clone().into()))
+                Err(Error::at(ErrorKind::UndefinedVariable, exprpath)
+                    .with_operation("evaluating a path"))
             }
         } else {
-            Err(eval_err(exprpath.clone().into()))
+            Err(Error::at(ErrorKind::UndefinedVariable, exprpath)
+                .with_operation("evaluating a path")
+                .with_message("only `PI` is known"))
         }
     }
 }
+
+#[test]
+fn test_eval_errors() {
+    use crate::{expr, ErrorKind};
+    let e = expr!((x * PI).sin() + 1).eval(20).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::CouldNotEvaluate);
+    assert_eq!(e.source_text(), Some("(x * PI) . sin () + 1"));
+    assert_eq!(e.root_cause().kind(), ErrorKind::UndefinedVariable);
+    assert_eq!(e.root_cause().source_text(), Some("x"));
+
+    let e = expr!(2.0.frobnicate()).eval(20).unwrap_err();
+    assert_eq!(e.source_text(), Some("2.0 . frobnicate ()"));
+    assert_eq!(
+        e.cause().unwrap().operation(),
+        Some("evaluating method `frobnicate`")
+    );
+
+    let e = expr!((-1.0).sqrt()).eval(20).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::CouldNotEvaluate);
+    assert_eq!(e.cause().unwrap().operation(), Some("evaluating method `sqrt`"));
+}
@@ -1,7 +1,6 @@
 use super::tools::*;
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 use crate::visitor::Visitor;
-use syn::spanned::Spanned;
 use syn::{BinOp, Expr, ExprBinary, ExprUnary, UnOp};
 
 #[derive(Debug)]
@@ -51,8 +50,8 @@ fn match_sum(expr: &Expr, sum: &mut Vec<Expr>, is_negated: bool) -> Result<()> {
 impl Visitor for Expand {
     fn visit_unary(&self, exprunary: &ExprUnary) -> Result<Expr> {
         match exprunary.op {
-            UnOp::Deref(_) => Err(Error::UnsupportedExpr(exprunary.span())),
-            UnOp::Not(_) => Err(Error::UnsupportedExpr(exprunary.span())),
+            UnOp::Deref(_) | UnOp::Not(_) => Err(Error::at(ErrorKind::UnsupportedExpr, exprunary)
+                .with_operation("expanding a unary expression")),
             UnOp::Neg(_) => {
                 let mut sum = Vec::new();
                 match_sum(deparen(&exprunary.expr), &mut sum, true)?;
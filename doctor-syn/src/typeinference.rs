@@ -0,0 +1,1034 @@
+//! Type inference for function bodies.
+//!
+//! Code generators need the type of every expression, for example to
+//! choose between `roundf` and `round` in C or to splat a literal in SIMD.
+//! `infer_file` resolves type aliases such as `fty`, constants and function
+//! signatures and then annotates every expression in every function with
+//! its type, using `let` annotations, casts, literal suffixes and the
+//! return types of methods.
+//!
+//! ```
+//! use doctor_syn::typeinference::{infer_file, Scalar, Ty};
+//! use doctor_syn::syn::{self, parse_quote};
+//!
+//! let file: syn::File = parse_quote! {
+//!     type fty = f32;
+//!     fn f(x: fty) -> fty { x.mul_add(2.0, 1.0) }
+//! };
+//! let types = infer_file(&file).unwrap();
+//! if let syn::Item::Fn(f) = &file.items[1] {
+//!     if let syn::Stmt::Expr(body) = &f.block.stmts[0] {
+//!         assert_eq!(types.get(body), Some(&Ty::Scalar(Scalar::F32)));
+//!     }
+//! }
+//! ```
+//!
+//! The types are keyed by the address of the expression, so the syntax
+//! tree must not be moved or modified between inference and queries.
+
+use crate::{Error, ErrorKind, Result};
+use quote::ToTokens;
+use std::collections::HashMap;
+use syn::{BinOp, Expr, File, Item, ItemFn, Lit, Pat, Stmt, UnOp};
+
+/// A primitive type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scalar {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    Isize,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    pub fn from_name(name: &str) -> Option<Scalar> {
+        use Scalar::*;
+        Some(match name {
+            "bool" => Bool,
+            "i8" => I8,
+            "i16" => I16,
+            "i32" => I32,
+            "i64" => I64,
+            "isize" => Isize,
+            "u8" => U8,
+            "u16" => U16,
+            "u32" => U32,
+            "u64" => U64,
+            "usize" => Usize,
+            "f32" => F32,
+            "f64" => F64,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        use Scalar::*;
+        match self {
+            Bool => "bool",
+            I8 => "i8",
+            I16 => "i16",
+            I32 => "i32",
+            I64 => "i64",
+            Isize => "isize",
+            U8 => "u8",
+            U16 => "u16",
+            U32 => "u32",
+            U64 => "u64",
+            Usize => "usize",
+            F32 => "f32",
+            F64 => "f64",
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Scalar::F32 | Scalar::F64)
+    }
+
+    pub fn is_int(self) -> bool {
+        !self.is_float() && self != Scalar::Bool
+    }
+
+    pub fn is_signed(self) -> bool {
+        use Scalar::*;
+        matches!(self, I8 | I16 | I32 | I64 | Isize | F32 | F64)
+    }
+
+    /// The size in bits, taking `usize` and `isize` as 64 bits.
+    pub fn num_bits(self) -> usize {
+        use Scalar::*;
+        match self {
+            Bool | I8 | U8 => 8,
+            I16 | U16 => 16,
+            I32 | U32 | F32 => 32,
+            I64 | U64 | F64 | Isize | Usize => 64,
+        }
+    }
+
+    /// The float type with `num_bits` bits.
+    pub fn float(num_bits: usize) -> Option<Scalar> {
+        match num_bits {
+            32 => Some(Scalar::F32),
+            64 => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    /// The unsigned integer type with `num_bits` bits.
+    pub fn uint(num_bits: usize) -> Option<Scalar> {
+        match num_bits {
+            8 => Some(Scalar::U8),
+            16 => Some(Scalar::U16),
+            32 => Some(Scalar::U32),
+            64 => Some(Scalar::U64),
+            _ => None,
+        }
+    }
+
+    /// The signed integer type with `num_bits` bits.
+    pub fn int(num_bits: usize) -> Option<Scalar> {
+        match num_bits {
+            8 => Some(Scalar::I8),
+            16 => Some(Scalar::I16),
+            32 => Some(Scalar::I32),
+            64 => Some(Scalar::I64),
+            _ => None,
+        }
+    }
+}
+
+/// The type of an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ty {
+    Unit,
+    Scalar(Scalar),
+    /// `Simd<T, N>`, or `Mask<_, N>` if the scalar is `Bool`.
+    Vector(Scalar, usize),
+    Tuple(Vec<Ty>),
+    Array(Box<Ty>, usize),
+    /// A slice reference, `&[T]` or `&mut [T]`.
+    Slice(Box<Ty>),
+}
+
+impl Ty {
+    /// The scalar type, or the element type of a vector.
+    pub fn scalar(&self) -> Option<Scalar> {
+        match self {
+            Ty::Scalar(s) | Ty::Vector(s, _) => Some(*s),
+            _ => None,
+        }
+    }
+
+    /// The number of lanes of a vector.
+    pub fn lanes(&self) -> Option<usize> {
+        match self {
+            Ty::Vector(_, lanes) => Some(*lanes),
+            _ => None,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        self.scalar().is_some_and(Scalar::is_float)
+    }
+
+    pub fn is_int(&self) -> bool {
+        self.scalar().is_some_and(Scalar::is_int)
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.scalar() == Some(Scalar::Bool)
+    }
+
+    /// The same shape with a different scalar, eg. `Simd<f32, 4>` to `Simd<u32, 4>`.
+    pub fn with_scalar(&self, scalar: Scalar) -> Ty {
+        match self {
+            Ty::Vector(_, lanes) => Ty::Vector(scalar, *lanes),
+            _ => Ty::Scalar(scalar),
+        }
+    }
+
+    /// The element type of arrays and slices.
+    pub fn element(&self) -> Option<&Ty> {
+        match self {
+            Ty::Array(ty, _) | Ty::Slice(ty) => Some(ty),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Unit => write!(f, "()"),
+            Ty::Scalar(s) => write!(f, "{}", s.name()),
+            Ty::Vector(Scalar::Bool, lanes) => write!(f, "Mask<_, {}>", lanes),
+            Ty::Vector(s, lanes) => write!(f, "Simd<{}, {}>", s.name(), lanes),
+            Ty::Tuple(tys) => {
+                let tys: Vec<_> = tys.iter().map(|ty| ty.to_string()).collect();
+                write!(f, "({})", tys.join(", "))
+            }
+            Ty::Array(ty, len) => write!(f, "[{}; {}]", ty, len),
+            Ty::Slice(ty) => write!(f, "&[{}]", ty),
+        }
+    }
+}
+
+/// The types of expressions, keyed by the address of the expression.
+#[derive(Debug, Default, Clone)]
+pub struct TypeMap {
+    types: HashMap<*const Expr, Ty>,
+}
+
+impl TypeMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, expr: &Expr) -> Option<&Ty> {
+        self.types.get(&(expr as *const Expr))
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    fn insert(&mut self, expr: &Expr, ty: Ty) {
+        self.types.insert(expr as *const Expr, ty);
+    }
+
+    fn extend(&mut self, other: TypeMap) {
+        self.types.extend(other.types);
+    }
+}
+
+/// Type aliases, constants and function signatures visible in function bodies.
+#[derive(Debug, Default, Clone)]
+pub struct Env {
+    aliases: HashMap<String, Ty>,
+    consts: HashMap<String, Ty>,
+    functions: HashMap<String, (Vec<Ty>, Ty)>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the aliases, constants and functions of a file.
+    pub fn from_file(file: &File) -> Result<Self> {
+        let mut env = Env::new();
+        // Aliases first as constants and signatures use them.
+        for item in &file.items {
+            if let Item::Type(item) = item {
+                let ty = env.resolve(&item.ty)?;
+                env.add_alias(&item.ident.to_string(), ty);
+            }
+        }
+        for item in &file.items {
+            match item {
+                Item::Const(item) => {
+                    let ty = env.resolve(&item.ty)?;
+                    env.add_const(&item.ident.to_string(), ty);
+                }
+                Item::Fn(item) => env.add_fn(item)?,
+                _ => (),
+            }
+        }
+        Ok(env)
+    }
+
+    /// The parameter and return types of a function.
+    pub fn function(&self, name: &str) -> Option<&(Vec<Ty>, Ty)> {
+        self.functions.get(name)
+    }
+
+    pub fn constant(&self, name: &str) -> Option<&Ty> {
+        self.consts.get(name)
+    }
+
+    pub fn add_alias(&mut self, name: &str, ty: Ty) {
+        self.aliases.insert(name.to_string(), ty);
+    }
+
+    pub fn add_const(&mut self, name: &str, ty: Ty) {
+        self.consts.insert(name.to_string(), ty);
+    }
+
+    /// Add the signature of a function so that calls to it can be typed.
+    pub fn add_fn(&mut self, item: &ItemFn) -> Result<()> {
+        let mut params = Vec::new();
+        for input in &item.sig.inputs {
+            match input {
+                syn::FnArg::Typed(pat_type) => params.push(self.resolve(&pat_type.ty)?),
+                syn::FnArg::Receiver(_) => {
+                    return Err(unsupported(input, "methods are not supported"))
+                }
+            }
+        }
+        let ret = self.resolve_return(&item.sig.output)?;
+        self.functions
+            .insert(item.sig.ident.to_string(), (params, ret));
+        Ok(())
+    }
+
+    /// Convert a Rust type to a `Ty`, expanding aliases.
+    pub fn resolve(&self, ty: &syn::Type) -> Result<Ty> {
+        match ty {
+            syn::Type::Path(path) if path.qself.is_none() => self.resolve_path(&path.path, ty),
+            syn::Type::Paren(paren) => self.resolve(&paren.elem),
+            syn::Type::Group(group) => self.resolve(&group.elem),
+            syn::Type::Tuple(tuple) if tuple.elems.is_empty() => Ok(Ty::Unit),
+            syn::Type::Tuple(tuple) => Ok(Ty::Tuple(
+                tuple
+                    .elems
+                    .iter()
+                    .map(|ty| self.resolve(ty))
+                    .collect::<Result<_>>()?,
+            )),
+            syn::Type::Array(array) => {
+                let elem = self.resolve(&array.elem)?;
+                let len = lit_usize(&array.len)
+                    .ok_or_else(|| unsupported(&array.len, "expected a constant length"))?;
+                Ok(Ty::Array(Box::new(elem), len))
+            }
+            syn::Type::Reference(reference) => match &*reference.elem {
+                syn::Type::Slice(slice) => Ok(Ty::Slice(Box::new(self.resolve(&slice.elem)?))),
+                _ => Err(unsupported(ty, "only slice references are supported")),
+            },
+            _ => Err(unsupported(ty, "unsupported type")),
+        }
+    }
+
+    pub fn resolve_return(&self, output: &syn::ReturnType) -> Result<Ty> {
+        match output {
+            syn::ReturnType::Default => Ok(Ty::Unit),
+            syn::ReturnType::Type(_, ty) => self.resolve(ty),
+        }
+    }
+
+    fn resolve_path(&self, path: &syn::Path, ty: &syn::Type) -> Result<Ty> {
+        let last = path.segments.last().unwrap();
+        let name = last.ident.to_string();
+        if path.segments.len() == 1 {
+            if let Some(ty) = self.aliases.get(&name) {
+                return Ok(ty.clone());
+            }
+            if let Some(s) = Scalar::from_name(&name) {
+                return Ok(Ty::Scalar(s));
+            }
+        }
+        // `Simd<f32, 8>` and `Mask<i32, 8>`, possibly with a `std::simd::` prefix.
+        if let syn::PathArguments::AngleBracketed(args) = &last.arguments {
+            let args: Vec<_> = args.args.iter().collect();
+            if let [syn::GenericArgument::Type(elem), syn::GenericArgument::Const(lanes)] =
+                args.as_slice()
+            {
+                let elem = self.resolve(elem)?.scalar();
+                let lanes = lit_usize(lanes);
+                match (name.as_str(), elem, lanes) {
+                    ("Simd", Some(elem), Some(lanes)) => return Ok(Ty::Vector(elem, lanes)),
+                    ("Mask", Some(_), Some(lanes)) => return Ok(Ty::Vector(Scalar::Bool, lanes)),
+                    _ => (),
+                }
+            }
+        }
+        Err(Error::at(ErrorKind::NotFound, ty).with_operation("resolving a type"))
+    }
+}
+
+fn unsupported<T: ToTokens>(node: &T, message: &str) -> Error {
+    Error::at(ErrorKind::UnsupportedExpr, node)
+        .with_operation("inferring types")
+        .with_message(message)
+}
+
+fn lit_usize(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Int(i), ..
+        }) => i.base10_parse().ok(),
+        _ => None,
+    }
+}
+
+/// Infer the types of all expressions in the functions and constants of a file.
+pub fn infer_file(file: &File) -> Result<TypeMap> {
+    let env = Env::from_file(file)?;
+    let mut types = TypeMap::new();
+    for item in &file.items {
+        match item {
+            Item::Fn(item) => types.extend(infer_fn(item, &env)?),
+            Item::Const(item) => {
+                let ty = env.resolve(&item.ty)?;
+                let mut inference = Inference::new(&env, Ty::Unit);
+                inference.infer(&item.expr, Some(&ty))?;
+                types.extend(inference.types);
+            }
+            _ => (),
+        }
+    }
+    Ok(types)
+}
+
+/// Infer the types of the expressions in a function.
+pub fn infer_fn(item: &ItemFn, env: &Env) -> Result<TypeMap> {
+    let ret = env.resolve_return(&item.sig.output)?;
+    let mut inference = Inference::new(env, ret.clone());
+    for input in &item.sig.inputs {
+        if let syn::FnArg::Typed(pat_type) = input {
+            let ty = env.resolve(&pat_type.ty)?;
+            inference.bind(&pat_type.pat, ty)?;
+        }
+    }
+    inference.infer_block(&item.block, Some(&ret))?;
+    Ok(inference.types)
+}
+
+struct Inference<'a> {
+    env: &'a Env,
+    scopes: Vec<HashMap<String, Ty>>,
+    types: TypeMap,
+    ret: Ty,
+}
+
+// True for literals such as `1.0` and `-2` whose type comes from context.
+fn is_untyped(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Float(f), ..
+        }) => f.suffix().is_empty(),
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Int(i), ..
+        }) => i.suffix().is_empty(),
+        Expr::Unary(syn::ExprUnary {
+            op: UnOp::Neg(_),
+            expr,
+            ..
+        }) => is_untyped(expr),
+        Expr::Paren(syn::ExprParen { expr, .. }) | Expr::Group(syn::ExprGroup { expr, .. }) => {
+            is_untyped(expr)
+        }
+        Expr::Binary(syn::ExprBinary { left, right, .. }) => {
+            is_untyped(left) && is_untyped(right)
+        }
+        _ => false,
+    }
+}
+
+// The scalar of a path such as `f32`, `fty` or `std::f64`.
+fn path_scalar(env: &Env, segments: &[&syn::PathSegment]) -> Option<Ty> {
+    for seg in segments.iter().rev() {
+        let name = seg.ident.to_string();
+        if let Some(ty) = env.aliases.get(&name) {
+            return Some(ty.clone());
+        }
+        if let Some(s) = Scalar::from_name(&name) {
+            return Some(Ty::Scalar(s));
+        }
+    }
+    None
+}
+
+impl<'a> Inference<'a> {
+    fn new(env: &'a Env, ret: Ty) -> Self {
+        Self {
+            env,
+            scopes: vec![HashMap::new()],
+            types: TypeMap::new(),
+            ret,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Ty> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.env.consts.get(name))
+    }
+
+    fn bind(&mut self, pat: &Pat, ty: Ty) -> Result<()> {
+        match (pat, &ty) {
+            (Pat::Ident(ident), _) => {
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(ident.ident.to_string(), ty);
+            }
+            (Pat::Type(pat_type), _) => self.bind(&pat_type.pat, ty)?,
+            (Pat::Wild(_), _) => (),
+            (Pat::Tuple(tuple), Ty::Tuple(tys)) if tuple.elems.len() == tys.len() => {
+                for (pat, ty) in tuple.elems.iter().zip(tys) {
+                    self.bind(pat, ty.clone())?;
+                }
+            }
+            _ => return Err(unsupported(pat, &format!("cannot bind a value of type {}", ty))),
+        }
+        Ok(())
+    }
+
+    fn infer_block(&mut self, block: &syn::Block, expected: Option<&Ty>) -> Result<Ty> {
+        self.scopes.push(HashMap::new());
+        let mut ty = Ty::Unit;
+        for (i, stmt) in block.stmts.iter().enumerate() {
+            let last = i + 1 == block.stmts.len();
+            ty = Ty::Unit;
+            match stmt {
+                Stmt::Local(local) => {
+                    let declared = match &local.pat {
+                        Pat::Type(pat_type) => Some(self.env.resolve(&pat_type.ty)?),
+                        _ => None,
+                    };
+                    let ty = match (&local.init, declared) {
+                        (Some((_, init)), declared) => {
+                            let ty = self.infer(init, declared.as_ref())?;
+                            declared.unwrap_or(ty)
+                        }
+                        (None, Some(declared)) => declared,
+                        (None, None) => {
+                            return Err(unsupported(local, "expected a type or initialiser"))
+                        }
+                    };
+                    self.bind(&local.pat, ty)?;
+                }
+                Stmt::Item(Item::Const(item)) => {
+                    let declared = self.env.resolve(&item.ty)?;
+                    self.infer(&item.expr, Some(&declared))?;
+                    self.scopes
+                        .last_mut()
+                        .unwrap()
+                        .insert(item.ident.to_string(), declared);
+                }
+                Stmt::Item(item) => return Err(unsupported(item, "unsupported item")),
+                Stmt::Expr(expr) if last => ty = self.infer(expr, expected)?,
+                Stmt::Expr(expr) | Stmt::Semi(expr, _) => {
+                    self.infer(expr, None)?;
+                }
+            }
+        }
+        self.scopes.pop();
+        Ok(ty)
+    }
+
+    // Infer two operands which should have the same type, typing the
+    // non-literal first so that literals take its type.
+    fn infer_pair(&mut self, a: &Expr, b: &Expr, expected: Option<&Ty>) -> Result<(Ty, Ty)> {
+        if is_untyped(a) && !is_untyped(b) {
+            let tb = self.infer(b, expected)?;
+            let ta = self.infer(a, Some(&tb))?;
+            Ok((ta, tb))
+        } else {
+            let ta = self.infer(a, expected)?;
+            let tb = self.infer(b, Some(&ta))?;
+            Ok((ta, tb))
+        }
+    }
+
+    /// Infer the type of an expression and record it.
+    fn infer(&mut self, expr: &Expr, expected: Option<&Ty>) -> Result<Ty> {
+        let ty = self.infer_inner(expr, expected)?;
+        self.types.insert(expr, ty.clone());
+        Ok(ty)
+    }
+
+    fn infer_inner(&mut self, expr: &Expr, expected: Option<&Ty>) -> Result<Ty> {
+        match expr {
+            Expr::Lit(lit) => self.infer_lit(lit, expected),
+            Expr::Paren(paren) => self.infer(&paren.expr, expected),
+            Expr::Group(group) => self.infer(&group.expr, expected),
+            Expr::Block(block) => self.infer_block(&block.block, expected),
+            Expr::Unary(unary) => match unary.op {
+                UnOp::Neg(_) | UnOp::Not(_) => self.infer(&unary.expr, expected),
+                UnOp::Deref(_) => Err(unsupported(expr, "dereferencing is not supported")),
+            },
+            Expr::Binary(binary) => self.infer_binary(binary, expected),
+            Expr::Cast(cast) => {
+                self.infer(&cast.expr, None)?;
+                self.env.resolve(&cast.ty)
+            }
+            Expr::Path(path) => self.infer_path(path),
+            Expr::If(exprif) => {
+                let cond = self.infer(&exprif.cond, None)?;
+                if !cond.is_bool() {
+                    return Err(unsupported(&exprif.cond, "expected a bool condition"));
+                }
+                match &exprif.else_branch {
+                    Some((_, else_branch)) => {
+                        let then_ty = self.infer_block(&exprif.then_branch, expected)?;
+                        let else_ty = self.infer(else_branch, Some(&then_ty))?;
+                        // Literal `then` branches take the type of the `else` branch.
+                        if then_ty != else_ty && then_is_untyped(&exprif.then_branch) {
+                            self.infer_block(&exprif.then_branch, Some(&else_ty))?;
+                            Ok(else_ty)
+                        } else {
+                            Ok(then_ty)
+                        }
+                    }
+                    None => {
+                        self.infer_block(&exprif.then_branch, None)?;
+                        Ok(Ty::Unit)
+                    }
+                }
+            }
+            Expr::Tuple(tuple) => {
+                let expected: Vec<Option<&Ty>> = match expected {
+                    Some(Ty::Tuple(tys)) if tys.len() == tuple.elems.len() => {
+                        tys.iter().map(Some).collect()
+                    }
+                    _ => vec![None; tuple.elems.len()],
+                };
+                let tys = tuple
+                    .elems
+                    .iter()
+                    .zip(expected)
+                    .map(|(e, ty)| self.infer(e, ty))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(if tys.is_empty() { Ty::Unit } else { Ty::Tuple(tys) })
+            }
+            Expr::Array(array) => {
+                let elem_expected = expected.and_then(Ty::element).cloned();
+                let mut elem = elem_expected;
+                for e in &array.elems {
+                    let ty = self.infer(e, elem.as_ref())?;
+                    elem.get_or_insert(ty);
+                }
+                let elem = elem.ok_or_else(|| unsupported(expr, "empty arrays are not supported"))?;
+                Ok(Ty::Array(Box::new(elem), array.elems.len()))
+            }
+            Expr::Index(index) => {
+                let base = self.infer(&index.expr, None)?;
+                self.infer(&index.index, Some(&Ty::Scalar(Scalar::Usize)))?;
+                base.element()
+                    .cloned()
+                    .ok_or_else(|| unsupported(expr, "expected an array or slice"))
+            }
+            Expr::Assign(assign) => {
+                let ty = self.infer(&assign.left, None)?;
+                self.infer(&assign.right, Some(&ty))?;
+                Ok(Ty::Unit)
+            }
+            Expr::AssignOp(assign) => {
+                let ty = self.infer(&assign.left, None)?;
+                let rhs = if matches!(assign.op, BinOp::ShlEq(_) | BinOp::ShrEq(_)) {
+                    None
+                } else {
+                    Some(&ty)
+                };
+                self.infer(&assign.right, rhs)?;
+                Ok(Ty::Unit)
+            }
+            Expr::Return(ret) => {
+                let ret_ty = self.ret.clone();
+                if let Some(e) = &ret.expr {
+                    self.infer(e, Some(&ret_ty))?;
+                }
+                Ok(ret_ty)
+            }
+            Expr::Call(call) => self.infer_call(call, expected),
+            Expr::MethodCall(call) => self.infer_method_call(call, expected),
+            Expr::ForLoop(for_loop) => {
+                let elem = match &*for_loop.expr {
+                    Expr::Range(range) => {
+                        let (from, to) = (range.from.as_deref(), range.to.as_deref());
+                        let usize_ty = Ty::Scalar(Scalar::Usize);
+                        match (from, to) {
+                            (Some(a), Some(b)) => self.infer_pair(a, b, Some(&usize_ty))?.0,
+                            (Some(e), None) | (None, Some(e)) => self.infer(e, Some(&usize_ty))?,
+                            (None, None) => usize_ty,
+                        }
+                    }
+                    _ => return Err(unsupported(&for_loop.expr, "expected a range")),
+                };
+                self.scopes.push(HashMap::new());
+                self.bind(&for_loop.pat, elem)?;
+                self.infer_block(&for_loop.body, None)?;
+                self.scopes.pop();
+                Ok(Ty::Unit)
+            }
+            Expr::While(expr_while) => {
+                self.infer(&expr_while.cond, None)?;
+                self.infer_block(&expr_while.body, None)?;
+                Ok(Ty::Unit)
+            }
+            Expr::Break(_) | Expr::Continue(_) => Ok(Ty::Unit),
+            _ => Err(unsupported(expr, "unsupported expression")),
+        }
+    }
+
+    fn infer_lit(&mut self, lit: &syn::ExprLit, expected: Option<&Ty>) -> Result<Ty> {
+        let from_suffix = |suffix: &str| Scalar::from_name(suffix).map(Ty::Scalar);
+        let expected_scalar = |pred: fn(Scalar) -> bool| {
+            expected
+                .and_then(Ty::scalar)
+                .filter(|s| pred(*s))
+                .map(Ty::Scalar)
+        };
+        match &lit.lit {
+            Lit::Float(f) => Ok(from_suffix(f.suffix())
+                .or_else(|| expected_scalar(Scalar::is_float))
+                .unwrap_or(Ty::Scalar(Scalar::F64))),
+            Lit::Int(i) => Ok(from_suffix(i.suffix())
+                .or_else(|| expected_scalar(Scalar::is_int))
+                .unwrap_or(Ty::Scalar(Scalar::I32))),
+            Lit::Bool(_) => Ok(Ty::Scalar(Scalar::Bool)),
+            _ => Err(unsupported(lit, "unsupported literal")),
+        }
+    }
+
+    fn infer_binary(&mut self, binary: &syn::ExprBinary, expected: Option<&Ty>) -> Result<Ty> {
+        use BinOp::*;
+        match binary.op {
+            Shl(_) | Shr(_) => {
+                let ty = self.infer(&binary.left, expected)?;
+                self.infer(&binary.right, None)?;
+                Ok(ty)
+            }
+            Eq(_) | Lt(_) | Le(_) | Ne(_) | Ge(_) | Gt(_) => {
+                let (ty, _) = self.infer_pair(&binary.left, &binary.right, None)?;
+                Ok(ty.with_scalar(Scalar::Bool))
+            }
+            And(_) | Or(_) => {
+                let bool_ty = Ty::Scalar(Scalar::Bool);
+                let (ty, _) = self.infer_pair(&binary.left, &binary.right, Some(&bool_ty))?;
+                Ok(ty)
+            }
+            _ => {
+                let (ta, tb) = self.infer_pair(&binary.left, &binary.right, expected)?;
+                // A vector operand makes the result a vector.
+                Ok(if tb.lanes().is_some() { tb } else { ta })
+            }
+        }
+    }
+
+    fn infer_path(&mut self, path: &syn::ExprPath) -> Result<Ty> {
+        let segments: Vec<_> = path.path.segments.iter().collect();
+        if let [seg] = segments.as_slice() {
+            let name = seg.ident.to_string();
+            return self.lookup(&name).cloned().ok_or_else(|| {
+                Error::at(ErrorKind::UndefinedVariable, path).with_operation("inferring types")
+            });
+        }
+        // Associated constants such as `f32::NAN` or `std::f64::consts::PI`.
+        path_scalar(self.env, &segments[..segments.len() - 1])
+            .ok_or_else(|| Error::at(ErrorKind::NotFound, path).with_operation("inferring types"))
+    }
+
+    fn infer_call(&mut self, call: &syn::ExprCall, expected: Option<&Ty>) -> Result<Ty> {
+        let func = match &*call.func {
+            Expr::Path(func) => func,
+            _ => return Err(unsupported(&call.func, "expected a function name")),
+        };
+        let args: Vec<&Expr> = call.args.iter().collect();
+        let segments: Vec<_> = func.path.segments.iter().collect();
+        if let (None, [seg]) = (&func.qself, segments.as_slice()) {
+            let name = seg.ident.to_string();
+            let (params, ret) = self.env.functions.get(&name).cloned().ok_or_else(|| {
+                Error::at(ErrorKind::NotFound, func).with_operation("inferring the type of a call")
+            })?;
+            if params.len() != args.len() {
+                return Err(unsupported(call, "wrong number of arguments"));
+            }
+            for (arg, param) in args.iter().zip(&params) {
+                self.infer(arg, Some(param))?;
+            }
+            return Ok(ret);
+        }
+
+        // Associated functions such as `fty::from_bits(x)` or `<Simd<f32, 4>>::splat(x)`.
+        let assoc = segments.last().unwrap().ident.to_string();
+        let ty = match &func.qself {
+            Some(qself) => Some(self.env.resolve(&qself.ty)?),
+            None => {
+                let mut prefix = func.path.clone();
+                prefix.segments.pop();
+                let prefix_ty: syn::Type = syn::TypePath {
+                    qself: None,
+                    path: syn::Path {
+                        leading_colon: prefix.leading_colon,
+                        segments: prefix.segments.into_pairs().map(|p| p.into_value()).collect(),
+                    },
+                }
+                .into();
+                self.env
+                    .resolve(&prefix_ty)
+                    .ok()
+                    .or_else(|| path_scalar(self.env, &segments[..segments.len() - 1]))
+            }
+        };
+        let ty = ty.ok_or_else(|| {
+            Error::at(ErrorKind::NotFound, func).with_operation("inferring the type of a call")
+        })?;
+        match (assoc.as_str(), args.as_slice()) {
+            ("from_bits", [arg]) => {
+                let bits = ty.scalar().map_or(64, Scalar::num_bits);
+                let uty = ty.with_scalar(Scalar::uint(bits).unwrap());
+                self.infer(arg, Some(&uty))?;
+                Ok(ty)
+            }
+            ("splat", [arg]) => {
+                let elem = ty.scalar().map(Ty::Scalar);
+                self.infer(arg, elem.as_ref())?;
+                Ok(ty)
+            }
+            // Methods called as functions, eg. `f32::max(a, b)`.
+            (_, [receiver, rest @ ..]) => {
+                let recv = self.infer(receiver, Some(&ty))?;
+                self.infer_method(&assoc, &recv, rest, None, expected, &call.func)
+            }
+            _ => Err(unsupported(call, "unsupported associated function")),
+        }
+    }
+
+    fn infer_method_call(&mut self, call: &syn::ExprMethodCall, expected: Option<&Ty>) -> Result<Ty> {
+        let method = call.method.to_string();
+        // Comparisons and selects do not have the receiver's type.
+        let receiver_expected = match method.as_str() {
+            "select" | "to_bits" | "cast" => None,
+            name if name.starts_with("simd_") || name.starts_with("lanes_") => None,
+            _ => expected,
+        };
+        let recv = self.infer(&call.receiver, receiver_expected)?;
+        let args: Vec<&Expr> = call.args.iter().collect();
+        let turbofish = call.turbofish.as_ref().and_then(|t| t.args.first()).and_then(|arg| {
+            match arg {
+                syn::GenericMethodArgument::Type(ty) => Some(ty),
+                _ => None,
+            }
+        });
+        self.infer_method(&method, &recv, &args, turbofish, expected, call)
+    }
+
+    fn infer_method<T: ToTokens>(
+        &mut self,
+        method: &str,
+        recv: &Ty,
+        args: &[&Expr],
+        turbofish: Option<&syn::Type>,
+        expected: Option<&Ty>,
+        node: &T,
+    ) -> Result<Ty> {
+        let scalar = recv
+            .scalar()
+            .ok_or_else(|| unsupported(node, &format!("no method `{}` on {}", method, recv)))?;
+        let u32_ty = recv.with_scalar(Scalar::U32);
+        let (arg_tys, ret): (Vec<Ty>, Ty) = match method {
+            // Methods returning the receiver's type with arguments of the same type.
+            "abs" | "sqrt" | "cbrt" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan"
+            | "atan2" | "sinh" | "cosh" | "tanh" | "asinh" | "acosh" | "atanh" | "exp"
+            | "exp2" | "exp_m1" | "ln" | "ln_1p" | "log" | "log2" | "log10" | "recip"
+            | "round" | "floor" | "ceil" | "trunc" | "fract" | "copysign" | "mul_add"
+            | "powf" | "min" | "max" | "signum" | "to_degrees" | "to_radians" | "hypot"
+            | "clamp" | "rem_euclid" | "div_euclid" | "wrapping_add" | "wrapping_sub"
+            | "wrapping_mul" | "wrapping_neg" | "saturating_add" | "saturating_sub"
+            | "saturating_mul" | "swap_bytes" | "reverse_bits" | "clone" | "simd_min"
+            | "simd_max" | "simd_clamp" => (vec![recv.clone(); args.len()], recv.clone()),
+            "powi" => (vec![recv.with_scalar(Scalar::I32)], recv.clone()),
+            "pow" | "rotate_left" | "rotate_right" | "wrapping_shl" | "wrapping_shr" => {
+                (vec![u32_ty], recv.clone())
+            }
+            "sin_cos" => (vec![], Ty::Tuple(vec![recv.clone(), recv.clone()])),
+            "to_bits" => {
+                let uint = Scalar::uint(scalar.num_bits()).unwrap();
+                (vec![], recv.with_scalar(uint))
+            }
+            "count_ones" | "count_zeros" | "leading_zeros" | "trailing_zeros" => {
+                (vec![], u32_ty)
+            }
+            "is_nan" | "is_infinite" | "is_finite" | "is_normal" | "is_sign_negative"
+            | "is_sign_positive" => (vec![], recv.with_scalar(Scalar::Bool)),
+            "eq" | "ne" | "lt" | "le" | "gt" | "ge" | "lanes_eq" | "lanes_ne" | "lanes_lt"
+            | "lanes_le" | "lanes_gt" | "lanes_ge" | "simd_eq" | "simd_ne" | "simd_lt"
+            | "simd_le" | "simd_gt" | "simd_ge" => {
+                (vec![recv.clone()], recv.with_scalar(Scalar::Bool))
+            }
+            "reduce_sum" | "reduce_product" | "reduce_max" | "reduce_min" => {
+                (vec![], Ty::Scalar(scalar))
+            }
+            "select" if scalar == Scalar::Bool => {
+                if let [a, b] = args {
+                    let (ta, _) = self.infer_pair(a, b, expected)?;
+                    return Ok(ta);
+                }
+                return Err(unsupported(node, "expected two arguments to `select`"));
+            }
+            "cast" => {
+                let ty = turbofish.ok_or_else(|| unsupported(node, "expected `cast::<T>()`"))?;
+                let elem = self.env.resolve(ty)?.scalar();
+                let elem = elem.ok_or_else(|| unsupported(ty, "expected a scalar type"))?;
+                (vec![], recv.with_scalar(elem))
+            }
+            _ => {
+                return Err(unsupported(
+                    node,
+                    &format!("unknown method `{}` on {}", method, recv),
+                ))
+            }
+        };
+        if arg_tys.len() != args.len() {
+            return Err(unsupported(node, &format!("wrong number of arguments to `{}`", method)));
+        }
+        for (arg, ty) in args.iter().zip(&arg_tys) {
+            self.infer(arg, Some(ty))?;
+        }
+        Ok(ret)
+    }
+}
+
+fn then_is_untyped(block: &syn::Block) -> bool {
+    matches!(block.stmts.as_slice(), [Stmt::Expr(e)] if is_untyped(e))
+}
+
+#[test]
+fn test_infer_file() {
+    use syn::parse_quote;
+    let file: File = parse_quote! {
+        type fty = f32;
+        type uty = u32;
+        type ity = i32;
+        const ONE_BITS: uty = 0x3f800000_u32;
+        const LOG2_SHIFT: ity = 23_i32;
+        const MIN_POSITIVE: fty = f32::MIN_POSITIVE;
+
+        fn log2(arg: fty) -> fty {
+            let arg_bits: uty = arg.to_bits();
+            let exponent: ity = (arg_bits as ity >> LOG2_SHIFT) - 127;
+            let x = fty::from_bits((arg_bits & 0x007fffff) | ONE_BITS) - 1.5;
+            if arg < MIN_POSITIVE { -1.0 } else { x + (exponent as fty) }
+        }
+
+        fn pair(x: f64, i: usize) -> (f64, bool) {
+            let (s, c) = x.sin_cos();
+            (s.mul_add(2.0, c) * std::f64::consts::PI, i as u64 + 1 > 3)
+        }
+    };
+    let types = infer_file(&file).unwrap();
+    let ty_of = |expr: &Expr| types.get(expr).unwrap().to_string();
+
+    let log2 = match &file.items[6] {
+        Item::Fn(f) => f,
+        _ => unreachable!(),
+    };
+    let stmts = &log2.block.stmts;
+    let init = |i: usize| match &stmts[i] {
+        Stmt::Local(local) => &*local.init.as_ref().unwrap().1,
+        _ => unreachable!(),
+    };
+    assert_eq!(ty_of(init(0)), "u32");
+    assert_eq!(ty_of(init(1)), "i32");
+    assert_eq!(ty_of(init(2)), "f32");
+    if let Expr::Binary(sub) = init(2) {
+        // `1.5` takes the type of `fty::from_bits(..)`.
+        assert_eq!(ty_of(&sub.right), "f32");
+        if let Expr::Call(call) = &*sub.left {
+            // `0x007fffff` takes the type of `arg_bits`.
+            assert_eq!(ty_of(&call.args[0]), "u32");
+        }
+    }
+    if let Stmt::Expr(Expr::If(exprif)) = &stmts[3] {
+        assert_eq!(ty_of(&exprif.cond), "bool");
+        if let Stmt::Expr(then) = &exprif.then_branch.stmts[0] {
+            assert_eq!(ty_of(then), "f32");
+        }
+    }
+
+    let pair = match &file.items[7] {
+        Item::Fn(f) => f,
+        _ => unreachable!(),
+    };
+    if let Stmt::Expr(Expr::Tuple(tuple)) = &pair.block.stmts[1] {
+        assert_eq!(ty_of(&tuple.elems[0]), "f64");
+        assert_eq!(ty_of(&tuple.elems[1]), "bool");
+        if let Expr::Binary(gt) = &tuple.elems[1] {
+            assert_eq!(ty_of(&gt.left), "u64");
+            assert_eq!(ty_of(&gt.right), "u64");
+        }
+    }
+}
+
+#[test]
+fn test_infer_vectors() {
+    use syn::parse_quote;
+    let item: ItemFn = parse_quote! {
+        fn f(x: std::simd::Simd<f32, 8>, n: [f32; 4]) -> std::simd::Simd<f32, 8> {
+            let m = x.simd_lt(<std::simd::Simd<f32, 8>>::splat(0.0));
+            let y = x.cast::<i32>();
+            m.select(x, x * x + n[0])
+        }
+    };
+    let types = infer_fn(&item, &Env::new()).unwrap();
+    let init = |i: usize| match &item.block.stmts[i] {
+        Stmt::Local(local) => &*local.init.as_ref().unwrap().1,
+        _ => unreachable!(),
+    };
+    assert_eq!(types.get(init(0)).unwrap().to_string(), "Mask<_, 8>");
+    assert_eq!(types.get(init(1)).unwrap().to_string(), "Simd<i32, 8>");
+    if let Stmt::Expr(e) = &item.block.stmts[2] {
+        assert_eq!(types.get(e), Some(&Ty::Vector(Scalar::F32, 8)));
+    }
+}
+
+#[test]
+fn test_infer_errors() {
+    use syn::parse_quote;
+    let item: ItemFn = parse_quote! { fn f(x: f32) -> f32 { x + y } };
+    let e = infer_fn(&item, &Env::new()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UndefinedVariable);
+    assert_eq!(e.source_text(), Some("y"));
+
+    let item: ItemFn = parse_quote! { fn f(x: f32) -> f32 { x.frobnicate() } };
+    let e = infer_fn(&item, &Env::new()).unwrap_err();
+    assert_eq!(e.message(), Some("unknown method `frobnicate` on f32"));
+}
+
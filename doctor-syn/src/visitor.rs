@@ -1,6 +1,5 @@
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 use quote::ToTokens;
-use syn::spanned::Spanned;
 use syn::{
     punctuated::Punctuated, Expr, ExprBinary, ExprField, ExprLit, ExprMethodCall, ExprParen,
     ExprPath, ExprUnary, Token,
@@ -128,7 +127,7 @@ pub trait Visitor {
             Lit(exprlit) => self.visit_lit(&exprlit),
             Path(exprpath) => self.visit_path(exprpath),
             Field(exprfield) => self.visit_field(exprfield),
-            _ => Err(Error::UnsupportedExpr(expr.span())),
+            _ => Err(Error::at(ErrorKind::UnsupportedExpr, expr)),
         }
     }
 }
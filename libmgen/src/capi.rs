@@ -0,0 +1,249 @@
+//! A Rust crate exporting the functions through the C ABI.
+//!
+//! Each public function gets a `#[no_mangle] extern "C"` entry point taking
+//! the same scalars and a `_slice` entry point taking a pointer per argument
+//! and a length, so that R, Octave and NumPy can link the Rust versions.
+//! The crate builds as a `cdylib`, a `staticlib` and an `rlib`.
+
+use crate::Config;
+use doctor_syn::{Error, ErrorKind, Result};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// The C spelling of a parameter or return type.
+fn c_type(ty: &syn::Type, config: &Config) -> Option<&'static str> {
+    let name = match ty {
+        syn::Type::Path(path) => path.path.get_ident()?.to_string(),
+        _ => return None,
+    };
+    let bits = config.num_bits();
+    Some(match (name.as_str(), bits) {
+        ("fty", 32) | ("f32", _) => "float",
+        ("fty", _) | ("f64", _) => "double",
+        ("uty", 32) | ("u32", _) => "uint32_t",
+        ("uty", _) | ("u64", _) => "uint64_t",
+        ("ity", 32) | ("i32", _) => "int32_t",
+        ("ity", _) | ("i64", _) => "int64_t",
+        ("usize", _) => "size_t",
+        ("isize", _) => "ptrdiff_t",
+        ("bool", _) => "bool",
+        _ => return None,
+    })
+}
+
+/// A public function with a C signature.
+struct Export<'a> {
+    item: &'a syn::ItemFn,
+    params: Vec<(syn::Ident, &'a syn::Type, &'static str)>,
+    ret: (&'a syn::Type, &'static str),
+}
+
+fn exports<'a>(file: &'a syn::File, config: &Config) -> Result<Vec<Export<'a>>> {
+    let unsupported = |item: &syn::ItemFn| {
+        Error::new(ErrorKind::UnsupportedCodegen)
+            .with_operation("generating C entry points")
+            .with_message(format!(
+                "{} has a type without a C equivalent",
+                item.sig.ident
+            ))
+    };
+    let mut exports = Vec::new();
+    for item in &file.items {
+        let item = match item {
+            syn::Item::Fn(item) if matches!(item.vis, syn::Visibility::Public(_)) => item,
+            _ => continue,
+        };
+        let mut params = Vec::new();
+        for input in &item.sig.inputs {
+            match input {
+                syn::FnArg::Typed(syn::PatType { pat, ty, .. }) => match &**pat {
+                    syn::Pat::Ident(ident) => {
+                        let c = c_type(ty, config).ok_or_else(|| unsupported(item))?;
+                        params.push((ident.ident.clone(), &**ty, c));
+                    }
+                    _ => return Err(unsupported(item)),
+                },
+                syn::FnArg::Receiver(_) => return Err(unsupported(item)),
+            }
+        }
+        let ret = match &item.sig.output {
+            syn::ReturnType::Type(_, ty) => {
+                (&**ty, c_type(ty, config).ok_or_else(|| unsupported(item))?)
+            }
+            syn::ReturnType::Default => return Err(unsupported(item)),
+        };
+        exports.push(Export { item, params, ret });
+    }
+    Ok(exports)
+}
+
+/// The scalar and slice entry points of a function.
+fn gen_entry_points(export: &Export, prefix: &str) -> TokenStream {
+    let name = &export.item.sig.ident;
+    let docs = export.item.attrs.iter().filter(|a| a.path.is_ident("doc"));
+    let scalar = format_ident!("{}{}", prefix, name);
+    let slice = format_ident!("{}{}_slice", prefix, name);
+    let names = export
+        .params
+        .iter()
+        .map(|(name, _, _)| name)
+        .collect::<Vec<_>>();
+    let types = export
+        .params
+        .iter()
+        .map(|(_, ty, _)| ty)
+        .collect::<Vec<_>>();
+    let ret = export.ret.0;
+    let apply = format!(" Apply `{}` to `len` elements of each argument.", name);
+    let safety = format!(
+        " `{}` must point to `len` readable elements and `output` to `len` writable elements. \
+         `output` may be one of the arguments, to compute in place.",
+        names
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("`, `")
+    );
+    quote! {
+        #(#docs)*
+        #[no_mangle]
+        pub extern "C" fn #scalar(#(#names: #types),*) -> #ret {
+            #name(#(#names),*)
+        }
+
+        #[doc = #apply]
+        ///
+        /// # Safety
+        ///
+        #[doc = #safety]
+        #[no_mangle]
+        pub unsafe extern "C" fn #slice(#(#names: *const #types,)* output: *mut #ret, len: usize) {
+            // Element by element, as `output` may overlap the arguments.
+            for i in 0..len {
+                output.add(i).write(#name(#(#names.add(i).read()),*));
+            }
+        }
+    }
+}
+
+/// The functions and their C entry points as `lib.rs`.
+pub fn gen_lib(config: &Config, functions: TokenStream) -> Result<String> {
+    let file: syn::File = syn::parse2(functions.clone())?;
+    let prefix = config.prefix();
+    let mut tokens = quote! {
+        #![allow(dead_code, non_snake_case, clippy::all)]
+    };
+    tokens.extend(functions);
+    for export in exports(&file, config)? {
+        tokens.extend(gen_entry_points(&export, &prefix));
+    }
+    Ok(doctor_syn::codegen::rust::format_token_stream(tokens))
+}
+
+/// A C header declaring the entry points of `lib.rs`.
+pub fn gen_header(config: &Config, functions: TokenStream, name: &str) -> Result<String> {
+    let file: syn::File = syn::parse2(functions)?;
+    let prefix = config.prefix();
+    let guard = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let mut text = String::new();
+    let _ = writeln!(text, "#ifndef {}\n#define {}\n", guard, guard);
+    text.push_str("#include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n\n");
+    text.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    for export in exports(&file, config)? {
+        for attr in &export.item.attrs {
+            if let Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                lit: syn::Lit::Str(doc),
+                ..
+            })) = attr.parse_meta()
+            {
+                if path.is_ident("doc") {
+                    let _ = writeln!(text, "//{}", doc.value());
+                }
+            }
+        }
+        let name = &export.item.sig.ident;
+        let params = export
+            .params
+            .iter()
+            .map(|(name, _, c)| format!("{} {}", c, name))
+            .collect::<Vec<_>>();
+        let _ = writeln!(
+            text,
+            "{} {}{}({});",
+            export.ret.1,
+            prefix,
+            name,
+            params.join(", ")
+        );
+        let pointers = export
+            .params
+            .iter()
+            .map(|(name, _, c)| format!("const {} *{}", c, name))
+            .collect::<Vec<_>>();
+        let _ = writeln!(text, "// `output` may be one of the arguments.");
+        let _ = writeln!(
+            text,
+            "void {}{}_slice({}, {} *output, size_t len);\n",
+            prefix,
+            name,
+            pointers.join(", "),
+            export.ret.1
+        );
+    }
+    text.push_str("#ifdef __cplusplus\n}\n#endif\n\n");
+    let _ = writeln!(text, "#endif // {}", guard);
+    Ok(text)
+}
+
+/// The manifest of a crate named `name`.
+pub fn gen_manifest(name: &str) -> String {
+    format!(
+        r#"[package]
+name = "{}"
+version = "0.1.0"
+edition = "2021"
+description = "Maths functions generated by libmgen with a C ABI."
+
+[lib]
+crate-type = ["cdylib", "staticlib", "rlib"]
+
+# Unwinding out of an extern "C" function aborts anyway.
+[profile.dev]
+panic = "abort"
+
+[profile.release]
+panic = "abort"
+"#,
+        name
+    )
+}
+
+/// The files of a crate, `Cargo.toml`, `src/lib.rs` and `include/<name>.h`,
+/// relative to its directory.
+pub fn gen_crate(config: &Config, functions: TokenStream) -> Result<Vec<(PathBuf, String)>> {
+    let name = format!("ds{}", config.num_bits());
+    let header = format!("{}.h", name);
+    Ok(vec![
+        (PathBuf::from("Cargo.toml"), gen_manifest(&name)),
+        (
+            PathBuf::from("src/lib.rs"),
+            gen_lib(config, functions.clone())?,
+        ),
+        (
+            PathBuf::from("include").join(&header),
+            gen_header(config, functions, &header)?,
+        ),
+    ])
+}
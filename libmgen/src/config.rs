@@ -20,6 +20,13 @@ impl Config {
         self.options.num_bits
     }
 
+    /// The same options for another size of float.
+    pub fn with_num_bits(&self, num_bits: usize) -> Self {
+        let mut options = self.options.clone();
+        options.num_bits = num_bits;
+        Self::new(options)
+    }
+
     pub fn num_digits(&self) -> i64 {
         if self.num_bits() == 32 {
             20
@@ -44,28 +51,33 @@ impl Config {
         self.options.generate_plots
     }
 
-    pub fn prefix(&self) -> &str {
-        if self.language() == "c" {
-            if self.num_bits() == 32 {
-                "ds32_"
-            } else {
-                "ds64_"
-            }
-        } else {
-            ""
+    pub fn prefix(&self) -> String {
+        if let (Some(format), "c" | "verilog") = (self.fixed_point(), self.language()) {
+            return format!("q{}_{}_", format.int_bits, format.frac_bits);
+        }
+        match self.language() {
+            "c" | "fortran" | "wgsl" | "glsl" | "rust-capi" => format!("ds{}_", self.num_bits()),
+            "c-vector" => format!("ds{}x{}_", self.num_bits(), self.lanes()),
+            "llvm-ir" | "wat" | "asm" => match self.options.lanes {
+                Some(lanes) => format!("ds{}x{}_", self.num_bits(), lanes),
+                None => format!("ds{}_", self.num_bits()),
+            },
+            _ => String::new(),
         }
     }
 
-    pub fn float_suffix(&self) -> &str {
-        if self.language() == "c" {
-            if self.num_bits() == 32 {
-                "f"
-            } else {
-                ""
-            }
-        } else {
-            ""
-        }
+    /// The number of elements in each vector, a 256 bit vector by default.
+    pub fn lanes(&self) -> usize {
+        self.options.lanes.unwrap_or(256 / self.num_bits())
+    }
+
+    /// The number of elements in each vector if `--lanes` was given.
+    pub fn lanes_given(&self) -> Option<usize> {
+        self.options.lanes
+    }
+
+    pub fn flavour(&self) -> &str {
+        self.options.flavour.as_str()
     }
 
     // pub fn get_one(&self) -> proc_macro2::TokenStream {
@@ -121,4 +133,65 @@ impl Config {
     pub fn output(&self) -> Option<&std::path::PathBuf> {
         self.options.output.as_ref()
     }
+
+    pub fn linkage(&self) -> &str {
+        self.options.linkage.as_str()
+    }
+
+    pub fn header(&self) -> Option<&std::path::PathBuf> {
+        self.options.header.as_ref()
+    }
+
+    pub fn pytest(&self) -> Option<&std::path::PathBuf> {
+        self.options.pytest.as_ref()
+    }
+
+    /// The name of the NumPy module, from the output file.
+    pub fn python_module(&self) -> String {
+        self.output()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| format!("ds{}", self.num_bits()))
+    }
+
+    pub fn freestanding(&self) -> bool {
+        self.options.freestanding
+    }
+
+    pub fn declare_simd(&self) -> bool {
+        self.options.declare_simd
+    }
+
+    pub fn vector_abi(&self) -> bool {
+        self.options.vector_abi
+    }
+
+    pub fn no_std(&self) -> bool {
+        self.options.no_std
+    }
+
+    pub fn fma(&self) -> &str {
+        self.options.fma.as_str()
+    }
+
+    pub fn slices(&self) -> bool {
+        self.options.slices
+    }
+
+    pub fn std_simd(&self) -> bool {
+        self.options.std_simd
+    }
+
+    pub fn relaxed_simd(&self) -> bool {
+        self.options.relaxed_simd
+    }
+
+    pub fn fixed_point(&self) -> Option<crate::fixed::Format> {
+        self.options.fixed_point.as_deref().and_then(crate::fixed::Format::parse)
+    }
+
+    /// The names given with -f.
+    pub fn function_names(&self) -> Vec<String> {
+        self.options.functions.split(',').map(str::to_string).collect()
+    }
 }
\ No newline at end of file
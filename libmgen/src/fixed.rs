@@ -0,0 +1,451 @@
+//! Fixed point functions for processors without floating point.
+//!
+//! Values are Qm.n signed integers: one sign bit, m integer bits and n fraction
+//! bits in an 8, 16 or 32 bit word. The polynomial coefficients are quantised
+//! to a Q format of their own and evaluated by Horner's method using only
+//! integer multiplies, shifts and saturating adds in a double width integer.
+
+use doctor_syn::{expr, name};
+use doctor_syn::{Expression, Parity};
+use num_traits::ToPrimitive;
+use proc_macro2::{Literal, TokenStream};
+use quote::{format_ident, quote};
+
+/// A signed Qm.n format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub int_bits: u32,
+    pub frac_bits: u32,
+}
+
+impl Format {
+    /// Parse "Qm.n" or "m.n" where 1 + m + n is 8, 16 or 32.
+    pub fn parse(text: &str) -> Option<Format> {
+        let text = text.strip_prefix('Q').unwrap_or(text);
+        let (m, n) = text.split_once('.')?;
+        let format = Format {
+            int_bits: m.parse().ok()?,
+            frac_bits: n.parse().ok()?,
+        };
+        if format.frac_bits == 0 || ![8, 16, 32].contains(&format.word_bits()) {
+            return None;
+        }
+        Some(format)
+    }
+
+    pub fn word_bits(&self) -> u32 {
+        1 + self.int_bits + self.frac_bits
+    }
+
+    pub fn max(&self) -> i64 {
+        (1 << (self.word_bits() - 1)) - 1
+    }
+
+    pub fn min(&self) -> i64 {
+        -1 << (self.word_bits() - 1)
+    }
+
+    pub fn saturate(&self, x: i64) -> i64 {
+        x.clamp(self.min(), self.max())
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Q{}.{}", self.int_bits, self.frac_bits)
+    }
+}
+
+pub struct FixedFunction {
+    pub name: &'static str,
+    /// What the function computes, for the docs.
+    pub description: &'static str,
+    pub expr: fn() -> Expression,
+    /// The same function in f64 to measure the error.
+    pub reference: fn(f64) -> f64,
+    pub xmin: f64,
+    pub xmax: f64,
+    pub parity: Parity,
+}
+
+/// The domains fit in Q0.n so any format can represent the arguments.
+pub static FIXED_FUNCTIONS: &[FixedFunction] = &[
+    FixedFunction {
+        name: "sin_pi",
+        description: "sin(PI * x)",
+        expr: || expr!((x * PI).sin()),
+        reference: |x| (x * std::f64::consts::PI).sin(),
+        xmin: -1.0,
+        xmax: 1.0,
+        parity: Parity::Odd,
+    },
+    FixedFunction {
+        name: "cos_pi",
+        description: "cos(PI * x)",
+        expr: || expr!((x * PI).cos()),
+        reference: |x| (x * std::f64::consts::PI).cos(),
+        xmin: -1.0,
+        xmax: 1.0,
+        parity: Parity::Even,
+    },
+    FixedFunction {
+        name: "exp2",
+        description: "2 to the power x",
+        expr: || expr!(x.exp2()),
+        reference: f64::exp2,
+        xmin: -1.0,
+        xmax: 0.0,
+        parity: Parity::Neither,
+    },
+    FixedFunction {
+        name: "log2_1p",
+        description: "log2(1 + x)",
+        expr: || expr!((x + 1).log2()),
+        reference: |x| x.ln_1p() * std::f64::consts::LOG2_E,
+        xmin: 0.0,
+        xmax: 1.0,
+        parity: Parity::Neither,
+    },
+    FixedFunction {
+        name: "atan",
+        description: "atan(x)",
+        expr: || expr!(x.atan()),
+        reference: f64::atan,
+        xmin: -1.0,
+        xmax: 1.0,
+        parity: Parity::Odd,
+    },
+];
+
+/// The functions named on the command line, "all" for every function.
+pub fn get_fixed_functions(names: &[String]) -> Vec<&'static FixedFunction> {
+    for name in names {
+        if name != "all" && !FIXED_FUNCTIONS.iter().any(|f| f.name == name) {
+            eprintln!("function {} not found", name);
+        }
+    }
+    FIXED_FUNCTIONS
+        .iter()
+        .filter(|f| names.iter().any(|n| n == "all" || n == f.name))
+        .collect()
+}
+
+/// A quantised polynomial and its measured error.
+#[derive(Debug, Clone)]
+pub struct Approx {
+    /// Coefficients of x, or x*x for odd and even functions, lowest power first.
+    pub coeffs: Vec<i64>,
+    /// Fraction bits of each coefficient and of the Horner step that adds it.
+    pub frac_bits: Vec<u32>,
+    /// Fraction bits of x, or x*x.
+    pub arg_frac_bits: u32,
+    /// Maximum error in LSBs of the output.
+    pub max_error: f64,
+}
+
+impl Approx {
+    /// The result for the integer argument `x`, as the generated code computes it.
+    pub fn eval(&self, f: &FixedFunction, format: Format, x: i64) -> i64 {
+        Arith { format }.eval(self, f.parity, x)
+    }
+}
+
+/// The integer arithmetic of the generated code, to measure the error.
+struct Arith {
+    format: Format,
+}
+
+impl Arith {
+    /// Multiply and shift right, rounding to nearest.
+    fn mul(&self, a: i64, b: i64, shift: u32) -> i64 {
+        self.format.saturate((((a * b) >> (shift - 1)) + 1) >> 1)
+    }
+
+    fn add(&self, a: i64, b: i64) -> i64 {
+        self.format.saturate(a + b)
+    }
+
+    fn eval(&self, approx: &Approx, parity: Parity, x: i64) -> i64 {
+        let n = self.format.frac_bits;
+        let vf = approx.arg_frac_bits;
+        let v = match parity {
+            Parity::Neither => x,
+            _ => self.mul(x, x, 2 * n - vf),
+        };
+        let f = &approx.frac_bits;
+        let k = approx.coeffs.len() - 1;
+        let mut y = approx.coeffs[k];
+        for i in (0..k).rev() {
+            y = self.add(self.mul(y, v, vf + f[i + 1] - f[i]), approx.coeffs[i]);
+        }
+        match parity {
+            Parity::Odd => self.mul(y, x, f[0]),
+            _ if f[0] > n => self.mul(y, 1, f[0] - n),
+            _ => self.format.saturate(y << (n - f[0])),
+        }
+    }
+}
+
+/// The arguments to measure the error at, every value if there are not too many.
+fn test_args(f: &FixedFunction, format: Format) -> Vec<i64> {
+    let scale = (format.frac_bits as f64).exp2();
+    let lo = format.saturate((f.xmin * scale).ceil() as i64);
+    let hi = format.saturate((f.xmax * scale).floor() as i64);
+    let step = ((hi - lo) / 65536).max(1);
+    let mut args: Vec<i64> = (lo..=hi).step_by(step as usize).collect();
+    if args.last() != Some(&hi) {
+        args.push(hi);
+    }
+    args
+}
+
+/// Quantise a polynomial giving each Horner step enough integer bits,
+/// plus `headroom`, for the range of its partial sum over the domain.
+fn quantise(f: &FixedFunction, format: Format, terms: &[f64], headroom: u32) -> Approx {
+    let args = test_args(f, format);
+    let n = format.frac_bits;
+    let word_bits = format.word_bits();
+    // x*x is at most one so it can keep more fraction bits than x.
+    let arg_frac_bits = match f.parity {
+        Parity::Neither => n,
+        _ => (word_bits - 2).min(2 * n - 1),
+    };
+    let scale = (n as f64).exp2();
+    let k = terms.len() - 1;
+    let mut ranges: Vec<f64> = terms.iter().map(|t| t.abs()).collect();
+    for x in &args {
+        let x = *x as f64 / scale;
+        let v = if f.parity == Parity::Neither {
+            x
+        } else {
+            x * x
+        };
+        let mut y = terms[k];
+        for i in (0..k).rev() {
+            y = y * v + terms[i];
+            ranges[i] = ranges[i].max(y.abs());
+        }
+    }
+
+    let mut frac_bits = vec![0; terms.len()];
+    for i in (0..=k).rev() {
+        let int_bits = (ranges[i].log2().ceil().max(0.0) as u32 + headroom).min(word_bits - 2);
+        frac_bits[i] = word_bits - 1 - int_bits;
+        // Every multiply must shift right.
+        if i < k {
+            frac_bits[i] = frac_bits[i].min(arg_frac_bits + frac_bits[i + 1] - 1);
+        }
+    }
+    let coeffs = terms
+        .iter()
+        .zip(&frac_bits)
+        .map(|(t, f)| ((t * (*f as f64).exp2()).round() as i64).clamp(-format.max(), format.max()))
+        .collect();
+
+    let mut approx = Approx {
+        coeffs,
+        frac_bits,
+        arg_frac_bits,
+        max_error: 0.0,
+    };
+    let arith = Arith { format };
+    for x in args {
+        let y = arith.eval(&approx, f.parity, x) as f64;
+        let expected = (f.reference)(x as f64 / scale) * scale;
+        approx.max_error = approx.max_error.max((y - expected).abs());
+    }
+    approx
+}
+
+/// The shortest polynomial with the smallest error.
+pub fn best_approx(f: &FixedFunction, format: Format) -> Approx {
+    let (first, step) = match f.parity {
+        Parity::Odd => (2, 2),
+        Parity::Even => (3, 2),
+        Parity::Neither => (2, 1),
+    };
+    let mut best: Option<Approx> = None;
+    let mut worse = 0;
+    for num_terms in (first..=24).step_by(step) {
+        let terms = (f.expr)()
+            .approx_terms(num_terms, f.xmin, f.xmax, name!(x), 30)
+            .unwrap();
+        let terms: Vec<f64> = terms.iter().map(|t| t.to_f64().unwrap()).collect();
+        let terms: Vec<f64> = match f.parity {
+            Parity::Odd => terms.into_iter().skip(1).step_by(2).collect(),
+            Parity::Even => terms.into_iter().step_by(2).collect(),
+            Parity::Neither => terms,
+        };
+        let approx = (0..2)
+            .map(|headroom| quantise(f, format, &terms, headroom))
+            .min_by(|a, b| a.max_error.partial_cmp(&b.max_error).unwrap())
+            .unwrap();
+        match &best {
+            Some(b) if approx.max_error >= b.max_error => worse += 1,
+            _ => {
+                best = Some(approx);
+                worse = 0;
+            }
+        }
+        // More terms than this only add rounding error.
+        if worse == 2 {
+            break;
+        }
+    }
+    best.unwrap()
+}
+
+fn int_lit(value: i64, bits: u32) -> TokenStream {
+    let lit = match bits {
+        8 => Literal::i8_suffixed(value.abs() as i8),
+        16 => Literal::i16_suffixed(value.abs() as i16),
+        32 => Literal::i32_suffixed(value.abs() as i32),
+        _ => Literal::i64_suffixed(value.abs()),
+    };
+    if value < 0 {
+        quote!(-#lit)
+    } else {
+        quote!(#lit)
+    }
+}
+
+/// The saturating arithmetic used by all the functions.
+fn gen_helpers(format: Format) -> TokenStream {
+    let bits = format.word_bits();
+    let ty = format_ident!("i{}", bits);
+    let wide = format_ident!("i{}", bits * 2);
+    let max = int_lit(format.max(), bits * 2);
+    let min = int_lit(format.min(), bits * 2);
+    let one = int_lit(1, bits * 2);
+    let sat_doc = format!(" Saturate a double width value to {} bits.", bits);
+    quote!(
+        #[doc = #sat_doc]
+        fn qsat(x: #wide) -> #ty {
+            let y = if x > #max {
+                #max
+            } else if #min > x {
+                #min
+            } else {
+                x
+            };
+            y as #ty
+        }
+
+        /// Add with saturation.
+        fn qadd(a: #ty, b: #ty) -> #ty {
+            qsat(a as #wide + b as #wide)
+        }
+
+        /// Multiply and shift right by at least one bit, rounding to nearest.
+        fn qmul(a: #ty, b: #ty, shift: #wide) -> #ty {
+            qsat((((a as #wide * b as #wide) >> (shift - #one)) + #one) >> #one)
+        }
+    )
+}
+
+fn gen_function(f: &FixedFunction, format: Format) -> TokenStream {
+    let approx = best_approx(f, format);
+    let bits = format.word_bits();
+    let ty = format_ident!("i{}", bits);
+    let wide = format_ident!("i{}", bits * 2);
+    let name = format_ident!("{}", f.name);
+    let n = format.frac_bits;
+    let vf = approx.arg_frac_bits;
+    let shift = |s: u32| int_lit(s as i64, bits * 2);
+
+    let v = match f.parity {
+        Parity::Neither => quote!(x),
+        _ => quote!(x2),
+    };
+    let fb = &approx.frac_bits;
+    let k = approx.coeffs.len() - 1;
+    let mut poly = int_lit(approx.coeffs[k], bits);
+    for i in (0..k).rev() {
+        let c = int_lit(approx.coeffs[i], bits);
+        let s = shift(vf + fb[i + 1] - fb[i]);
+        poly = quote!(qadd(qmul(#poly, #v, #s), #c));
+    }
+
+    let square = match f.parity {
+        Parity::Neither => quote!(),
+        _ => {
+            let s = shift(2 * n - vf);
+            quote!(let x2: #ty = qmul(x, x, #s);)
+        }
+    };
+    let one = int_lit(1, bits);
+    let result = match f.parity {
+        Parity::Odd => {
+            let s = shift(fb[0]);
+            quote!(qmul(y, x, #s))
+        }
+        _ if fb[0] > n => {
+            let s = shift(fb[0] - n);
+            quote!(qmul(y, #one, #s))
+        }
+        _ if fb[0] < n => {
+            let s = shift(n - fb[0]);
+            quote!(qsat((y as #wide) << #s))
+        }
+        _ => quote!(y),
+    };
+
+    let doc = format!(" {} with x and the result in {}.", f.description, format);
+    let domain = format!(
+        " Domain: {} <= x <= {}, error at most {} LSB.",
+        f.xmin,
+        f.xmax,
+        approx.max_error.ceil()
+    );
+    quote!(
+        #[doc = #doc]
+        #[doc = #domain]
+        pub fn #name(x: #ty) -> #ty {
+            #square
+            let y: #ty = #poly;
+            #result
+        }
+    )
+}
+
+pub fn gen_fixed(format: Format, funcs: &[&FixedFunction]) -> TokenStream {
+    let mut tokens = gen_helpers(format);
+    for f in funcs {
+        tokens.extend(gen_function(f, format));
+    }
+    tokens
+}
+
+#[test]
+fn test_parse_format() {
+    let q = |int_bits, frac_bits| {
+        Some(Format {
+            int_bits,
+            frac_bits,
+        })
+    };
+    assert_eq!(Format::parse("Q1.14"), q(1, 14));
+    assert_eq!(Format::parse("0.31"), q(0, 31));
+    assert_eq!(Format::parse("Q3.4"), q(3, 4));
+    assert_eq!(Format::parse("Q1.13"), None);
+    assert_eq!(Format::parse("Q7.0"), None);
+    assert_eq!(Format::parse("Q1"), None);
+}
+
+#[test]
+fn test_fixed_accuracy() {
+    // Sixteen bit formats are measured exhaustively.
+    for (text, limit) in [("Q1.14", 6.0), ("Q3.12", 2.0), ("Q1.30", 9.0)] {
+        let format = Format::parse(text).unwrap();
+        for f in FIXED_FUNCTIONS {
+            let approx = best_approx(f, format);
+            assert!(
+                approx.max_error <= limit,
+                "{} {} error {} LSB",
+                f.name,
+                text,
+                approx.max_error
+            );
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Rust functions for every float type behind one trait.
+//!
+//! The functions become methods of a `StdLibm` trait, like the one the
+//! portable-simd output implements, with implementations for `f32`, `f64`,
+//! `Simd<f32, N>` and `Simd<f64, N>`. Each size has its own coefficients.
+//! The scalar implementations call the functions in private `scalar32` and
+//! `scalar64` modules and the vector ones are the `std::simd` flavour of the
+//! portable-simd output, so the file needs nightly Rust and
+//! `#![feature(portable_simd)]`.
+
+use crate::{functions, gen_functions, Config};
+use doctor_syn::codegen::portable_simd::{to_simd, Options};
+use doctor_syn::{Error, ErrorKind, Result};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse_quote;
+
+/// How a parameter or result of a scalar function appears in the trait.
+enum Kind {
+    Float,
+    Int,
+    Uint,
+}
+
+fn kind(ty: &syn::Type) -> Option<Kind> {
+    let name = match ty {
+        syn::Type::Path(path) => path.path.get_ident()?.to_string(),
+        _ => return None,
+    };
+    match name.as_str() {
+        "fty" | "f32" | "f64" => Some(Kind::Float),
+        "ity" | "i32" | "i64" | "isize" => Some(Kind::Int),
+        "uty" | "u32" | "u64" | "usize" => Some(Kind::Uint),
+        _ => None,
+    }
+}
+
+fn trait_type(kind: &Kind) -> TokenStream {
+    match kind {
+        Kind::Float => quote!(Self),
+        Kind::Int => quote!(Self::IntType),
+        Kind::Uint => quote!(Self::UintType),
+    }
+}
+
+/// A function of the scalar modules as a method.
+struct Method<'a> {
+    item: &'a syn::ItemFn,
+    params: Vec<(syn::Ident, &'a syn::Type, Kind)>,
+    ret: (&'a syn::Type, Kind),
+}
+
+impl<'a> Method<'a> {
+    fn new(item: &'a syn::ItemFn) -> Result<Self> {
+        let unsupported = || {
+            Error::new(ErrorKind::UnsupportedCodegen)
+                .with_operation("generating generic functions")
+                .with_message(format!(
+                    "{} has a type other than a float or an integer",
+                    item.sig.ident
+                ))
+        };
+        let mut params = Vec::new();
+        for input in &item.sig.inputs {
+            match input {
+                syn::FnArg::Typed(syn::PatType { pat, ty, .. }) => match (&**pat, kind(ty)) {
+                    (syn::Pat::Ident(ident), Some(kind)) => {
+                        params.push((ident.ident.clone(), &**ty, kind))
+                    }
+                    _ => return Err(unsupported()),
+                },
+                syn::FnArg::Receiver(_) => return Err(unsupported()),
+            }
+        }
+        let ret = match &item.sig.output {
+            syn::ReturnType::Type(_, ty) => (&**ty, kind(ty).ok_or_else(unsupported)?),
+            syn::ReturnType::Default => return Err(unsupported()),
+        };
+        Ok(Method { item, params, ret })
+    }
+
+    // The first float parameter is `self`, as in the portable-simd output.
+    fn has_receiver(&self) -> bool {
+        matches!(self.params.first(), Some((_, _, Kind::Float)))
+    }
+
+    // `self` and the other parameters with the trait's types.
+    fn params(&self) -> Vec<TokenStream> {
+        self.params
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _, kind))| match i {
+                0 if self.has_receiver() => quote!(self),
+                _ => {
+                    let ty = trait_type(kind);
+                    quote!(#name: #ty)
+                }
+            })
+            .collect()
+    }
+
+    /// The declaration in the trait.
+    fn declaration(&self) -> TokenStream {
+        let name = &self.item.sig.ident;
+        let params = self.params();
+        let ret = trait_type(&self.ret.1);
+        let docs = match self.item.vis {
+            syn::Visibility::Public(_) => {
+                let docs = self.item.attrs.iter().filter(|a| a.path.is_ident("doc"));
+                quote!(#(#docs)*)
+            }
+            _ => quote!(#[doc(hidden)]),
+        };
+        quote! {
+            #docs
+            fn #name(#(#params),*) -> #ret;
+        }
+    }
+
+    /// The implementation for `f32` or `f64`, calling the function in `module`.
+    fn definition(&self, module: &syn::Ident, num_bits: usize) -> TokenStream {
+        let name = &self.item.sig.ident;
+        // Integers of other sizes are cast to and from the trait's types.
+        let sized = |ty: &syn::Type, kind: &Kind| {
+            let sized = match kind {
+                Kind::Float => format_ident!("f{}", num_bits),
+                Kind::Int => format_ident!("i{}", num_bits),
+                Kind::Uint => format_ident!("u{}", num_bits),
+            };
+            match ty {
+                syn::Type::Path(path)
+                    if path.path.is_ident("fty")
+                        || path.path.is_ident("ity")
+                        || path.path.is_ident("uty")
+                        || path.path.is_ident(&sized) =>
+                {
+                    None
+                }
+                _ => Some(sized),
+            }
+        };
+        let params = self.params();
+        let args = self.params.iter().enumerate().map(|(i, (name, ty, kind))| {
+            let name = match i {
+                0 if self.has_receiver() => quote!(self),
+                _ => quote!(#name),
+            };
+            match sized(ty, kind) {
+                Some(_) => quote!(#name as #ty),
+                None => name,
+            }
+        });
+        let ret = trait_type(&self.ret.1);
+        let call = quote!(#module::#name(#(#args),*));
+        let body = match sized(self.ret.0, &self.ret.1) {
+            Some(sized) => quote!(#call as #sized),
+            None => call,
+        };
+        quote! {
+            #[inline]
+            fn #name(#(#params),*) -> #ret {
+                #body
+            }
+        }
+    }
+}
+
+fn methods(file: &syn::File) -> Result<Vec<Method<'_>>> {
+    file.items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Fn(item) => Some(Method::new(item)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The `StdLibm` trait alone, which the portable-simd output implements.
+pub fn gen_trait(config: &Config, funcs: &[&functions::Function]) -> Result<TokenStream> {
+    let file: syn::File = syn::parse2(gen_functions(config, funcs))?;
+    let declarations = methods(&file)?
+        .iter()
+        .map(Method::declaration)
+        .collect::<Vec<_>>();
+    Ok(quote! {
+        /// Maths functions for `f32`, `f64` and vectors of them.
+        pub trait StdLibm {
+            /// Signed integers with the same number of bits and lanes.
+            type IntType;
+            /// Unsigned integers with the same number of bits and lanes.
+            type UintType;
+
+            #(#declarations)*
+        }
+    })
+}
+
+/// The trait, its implementations and the functions they use.
+pub fn gen_generic(config: &Config, funcs: &[&functions::Function]) -> Result<TokenStream> {
+    let mut scalar_modules = Vec::new();
+    let mut scalar_impls = Vec::new();
+    let mut vector_modules = Vec::new();
+    for num_bits in [32, 64] {
+        let config = config.with_num_bits(num_bits);
+        let file: syn::File = syn::parse2(gen_functions(&config, funcs))?;
+        let methods = methods(&file)?;
+
+        let module = format_ident!("scalar{}", num_bits);
+        let definitions = methods
+            .iter()
+            .map(|method| method.definition(&module, num_bits));
+        let fty = format_ident!("f{}", num_bits);
+        let ity = format_ident!("i{}", num_bits);
+        let uty = format_ident!("u{}", num_bits);
+        scalar_impls.push(quote! {
+            impl StdLibm for #fty {
+                type IntType = #ity;
+                type UintType = #uty;
+
+                #(#definitions)*
+            }
+        });
+
+        let mut scalar = file.clone();
+        for item in &mut scalar.items {
+            if let syn::Item::Fn(f) = item {
+                f.vis = parse_quote!(pub(super));
+            }
+        }
+        let items = &scalar.items;
+        scalar_modules.push(quote! {
+            #[allow(dead_code, non_snake_case, clippy::all)]
+            mod #module {
+                #(#items)*
+            }
+        });
+
+        let options = Options {
+            num_bits,
+            std_simd: true,
+        };
+        let vector = to_simd(&file, options)?;
+        let module = format_ident!("simd{}", num_bits);
+        vector_modules.push(quote! {
+            #[allow(unused_parens)]
+            mod #module {
+                #vector
+            }
+        });
+    }
+    let declaration = gen_trait(config, funcs)?;
+    Ok(quote! {
+        use std::simd;
+        use std::simd::StdFloat;
+
+        #declaration
+
+        #(#scalar_impls)*
+        #(#scalar_modules)*
+        #(#vector_modules)*
+    })
+}
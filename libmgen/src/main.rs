@@ -31,22 +31,27 @@ use quote::ToTokens;
 use std::io::Write;
 
 mod auxfuncs;
+mod capi;
 mod config;
+mod fixed;
 mod functions;
+mod generic;
 mod hyperbolic;
 mod inv_trig;
 mod log_exp;
 mod recip_sqrt;
+mod slices;
 mod stats_norm;
 mod stats_random;
 mod test;
 mod trig;
+mod verilog;
 
 use config::Config;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt)]
 #[structopt(
     name = "libmgen",
     about = "Generate maths and stats functions in many languages."
@@ -89,13 +94,76 @@ pub struct Opt {
     #[structopt(long, default_value = "decimal")]
     number_type: String,
 
-    /// Target language. C, C++, rust, fortran.
+    /// Target language. Use "help" for a list.
     #[structopt(long, default_value = "rust")]
     language: String,
 
     /// Function prefix
     #[structopt(long, default_value = "")]
     function_prefix: String,
+
+    /// Linkage of C functions: extern or static-inline.
+    #[structopt(long, default_value = "extern")]
+    linkage: String,
+
+    /// Also write a C header to this file for the C or rust-capi output to include.
+    #[structopt(long, parse(from_os_str))]
+    header: Option<PathBuf>,
+
+    /// Also write pytest tests to this file for the NumPy output. The tests
+    /// import the module named after the output file, ds64 by default.
+    #[structopt(long, parse(from_os_str))]
+    pytest: Option<PathBuf>,
+
+    /// Generate C that does not need libm, for -ffreestanding -nostdlib.
+    #[structopt(long)]
+    freestanding: bool,
+
+    /// Generate Rust that only needs `core`, for `#![no_std]` crates.
+    #[structopt(long)]
+    no_std: bool,
+
+    /// `mul_add` and `sqrt` in --no-std Rust: software, hardware (the x86-64
+    /// instructions if the target features enable them) or unfused. With unfused
+    /// C prints `mul_add` as `a * b + c` in place of `fma`.
+    #[structopt(long, default_value = "software")]
+    fma: String,
+
+    /// Number of elements in C vectors and portable-simd slice kernels, 256 bits worth by default.
+    /// LLVM IR, WebAssembly and assembly are only vectorised if this is given.
+    #[structopt(long)]
+    lanes: Option<usize>,
+
+    /// Intrinsics for C vectors: generic, sse, avx2 or avx512.
+    #[structopt(long, default_value = "generic")]
+    flavour: String,
+
+    /// Mark C functions `#pragma omp declare simd` for -fopenmp-simd.
+    #[structopt(long)]
+    declare_simd: bool,
+
+    /// Name C vector functions for the x86 vector function ABI, eg. _ZGVdN4v_ds64_sin.
+    #[structopt(long)]
+    vector_abi: bool,
+
+    /// Also generate slice kernels, eg. `sin_slice(input, output)` and
+    /// `sin_slice_in_place(data)`, for the portable-simd output.
+    #[structopt(long)]
+    slices: bool,
+
+    /// Use the `std::simd` API of nightly Rust in the portable-simd output,
+    /// rather than that of the portable-simd crate.
+    #[structopt(long)]
+    std_simd: bool,
+
+    /// Use relaxed_madd for mul_add in WebAssembly vectors, which may not be fused.
+    #[structopt(long)]
+    relaxed_simd: bool,
+
+    /// Generate fixed point functions in Rust, C or Verilog with this Qm.n format, eg. Q1.14.
+    /// The word length 1 + m + n must be 8, 16 or 32 bits.
+    #[structopt(long)]
+    fixed_point: Option<String>,
 }
 
 /*
@@ -283,7 +351,7 @@ fn generate_libm(
             for stmt in functions.iter().chain(tests.iter()) {
                 if let Stmt::Item(item) = stmt {
                     use c::AsC;
-                    let context = c::Context::new(config.prefix());
+                    let context = c::Context::new(&config.prefix());
                     let code = item.as_c(&context)?;
                     file.write_all(code.as_bytes())?;
                 }
@@ -296,7 +364,7 @@ fn generate_libm(
             for stmt in functions.iter().chain(tests.iter()) {
                 if let Stmt::Item(item) = stmt {
                     use c::AsC;
-                    let context = c::Context::new(config.prefix());
+                    let context = c::Context::new(&config.prefix());
                     let code = item.as_c(&context)?;
                     file.write_all(code.as_bytes())?;
                 }
@@ -345,6 +413,288 @@ fn generate() {
 }
 */
 
+fn gen_tokens(config: &Config, funcs: &[&functions::Function]) -> TokenStream {
+    let mut tokens = gen_functions(config, funcs);
+
+    if config.generate_tests() {
+        tokens.extend(crate::auxfuncs::gen_test_function(0, config));
+        for f in funcs {
+            for t in f.test_specs {
+                tokens.extend(crate::test::gen_test(t, config));
+            }
+        }
+    }
+
+    tokens
+}
+
+/// The functions without tests.
+fn gen_functions(config: &Config, funcs: &[&functions::Function]) -> TokenStream {
+    let mut tokens = TokenStream::new();
+
+    for f in funcs.iter() {
+        if let Some(gen) = f.gen {
+            let num_terms = if config.num_bits() == 32 {
+                f.num_terms[0]
+            } else {
+                f.num_terms[1]
+            };
+            tokens.extend(gen(num_terms, config));
+        }
+    }
+
+    tokens
+}
+
+/// The functions without tests, with their domains documented.
+fn documented_functions(config: &Config, funcs: &[&functions::Function]) -> doctor_syn::Result<TokenStream> {
+    let mut file = syn::parse2(gen_functions(config, funcs))?;
+    document_domains(&mut file, funcs, config);
+    Ok(file.into_token_stream())
+}
+
+/// Add the tested domain and accuracy of each function to its docs.
+fn document_domains(file: &mut syn::File, funcs: &[&functions::Function], config: &Config) {
+    for item in &mut file.items {
+        if let syn::Item::Fn(item) = item {
+            let name = item.sig.ident.to_string();
+            let specs = funcs.iter().filter(|f| f.name == name).flat_map(|f| f.test_specs);
+            for t in specs {
+                if let functions::TestType::MaxAbs(min, max, bits32, bits64, _) = t.test {
+                    let bits = if config.num_bits() == 32 { bits32 } else { bits64 };
+                    let doc = format!(" Domain: {} <= x <= {}, error at most {} LSB.", min, max, bits);
+                    item.attrs.push(syn::parse_quote!(#[doc = #doc]));
+                }
+            }
+        }
+    }
+}
+
+fn c_options(config: &Config) -> doctor_syn::codegen::c::Options {
+    use doctor_syn::codegen::c::{Linkage, Options};
+    Options {
+        prefix: config.prefix(),
+        linkage: if config.linkage() == "static-inline" {
+            Linkage::StaticInline
+        } else {
+            Linkage::Extern
+        },
+        header: config
+            .header()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().to_string()),
+        freestanding: config.freestanding(),
+        declare_simd: config.declare_simd(),
+        unfused_mul_add: config.fma() == "unfused",
+    }
+}
+
+/// Generate a C header for the functions.
+fn generate_c_header(config: &Config, funcs: &[&functions::Function]) -> doctor_syn::Result<String> {
+    if config.language() == "rust-capi" {
+        let name = config
+            .header()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| format!("ds{}.h", config.num_bits()));
+        return capi::gen_header(config, documented_functions(config, funcs)?, &name);
+    }
+    let file = if let Some(format) = config.fixed_point() {
+        let funcs = fixed::get_fixed_functions(&config.function_names());
+        syn::parse2(fixed::gen_fixed(format, &funcs))?
+    } else {
+        let mut file = syn::parse2(gen_tokens(config, funcs))?;
+        document_domains(&mut file, funcs, config);
+        file
+    };
+    doctor_syn::codegen::c::to_c_header(&file, c_options(config))
+}
+
+/// Generate fixed point functions, which only have Rust, C and Verilog output.
+fn generate_fixed(config: &Config, format: fixed::Format) -> doctor_syn::Result<Option<String>> {
+    use doctor_syn::{Error, ErrorKind};
+    let funcs = fixed::get_fixed_functions(&config.function_names());
+    let tokens = fixed::gen_fixed(format, &funcs);
+    let text = match config.language() {
+        "rust" => doctor_syn::codegen::rust::format_token_stream(tokens),
+        "c" => {
+            let file = syn::parse2(tokens)?;
+            doctor_syn::codegen::c::to_c(&file, c_options(config))?
+        }
+        "verilog" => verilog::to_verilog(format, &funcs, &config.prefix(), config.generate_tests()),
+        "c-vector" | "fortran" | "cpp" | "portable-simd" | "llvm-ir" | "wat" | "asm" | "wgsl"
+        | "glsl" | "numpy" | "rust-capi" | "rust-generic" => {
+            return Err(Error::new(ErrorKind::UnsupportedCodegen)
+                .with_operation("generating fixed point functions")
+                .with_message(format!("{} is not supported, use rust, c or verilog", config.language())))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(text))
+}
+
+/// Generate functions in the configured language, `None` if the language is unknown.
+fn generate(config: &Config, funcs: &[&functions::Function]) -> doctor_syn::Result<Option<String>> {
+    if let Some(format) = config.fixed_point() {
+        return generate_fixed(config, format);
+    }
+    let tokens = gen_tokens(config, funcs);
+    let text = match config.language() {
+        "rust" if config.no_std() => {
+            use doctor_syn::codegen::no_std::{to_no_std, Fma, Options};
+            let options = Options {
+                num_bits: config.num_bits(),
+                fma: match config.fma() {
+                    "hardware" => Fma::Hardware,
+                    "unfused" => Fma::Unfused,
+                    _ => Fma::Software,
+                },
+            };
+            let file = to_no_std(&syn::parse2(tokens)?, &options);
+            doctor_syn::codegen::rust::format_token_stream(file.into_token_stream())
+        }
+        "rust" => doctor_syn::codegen::rust::format_token_stream(tokens),
+        "c" => {
+            let mut file = syn::parse2(tokens)?;
+            document_domains(&mut file, funcs, config);
+            doctor_syn::codegen::c::to_c(&file, c_options(config))?
+        }
+        "c-vector" => {
+            use doctor_syn::codegen::c_vector::{to_c_vector, Flavour, Options};
+            let flavour = match config.flavour() {
+                "sse" => Flavour::Sse,
+                "avx2" => Flavour::Avx2,
+                "avx512" => Flavour::Avx512,
+                _ => Flavour::Generic,
+            };
+            let mut file = syn::parse2(tokens)?;
+            document_domains(&mut file, funcs, config);
+            let options = Options {
+                prefix: config.prefix(),
+                lanes: config.lanes(),
+                flavour,
+                vector_abi: config.vector_abi().then(|| format!("ds{}_", config.num_bits())),
+            };
+            to_c_vector(&file, options)?
+        }
+        "fortran" => {
+            use doctor_syn::codegen::fortran::{to_fortran, Options};
+            let mut file = syn::parse2(tokens)?;
+            document_domains(&mut file, funcs, config);
+            let options = Options {
+                prefix: config.prefix(),
+                module: format!("ds{}", config.num_bits()),
+            };
+            to_fortran(&file, options)?
+        }
+        "cpp" => {
+            use doctor_syn::codegen::cpp::{to_cpp, Options};
+            // One header serves both sizes of float.
+            let mut files = Vec::new();
+            for num_bits in [32, 64] {
+                let config = config.with_num_bits(num_bits);
+                let mut file = syn::parse2(gen_tokens(&config, funcs))?;
+                document_domains(&mut file, funcs, &config);
+                files.push(file);
+            }
+            let options = Options {
+                namespace: "ds".to_string(),
+            };
+            to_cpp(&files[0], &files[1], options)?
+        }
+        "llvm-ir" => {
+            use doctor_syn::codegen::llvm_ir::{to_llvm_ir, Options};
+            let mut file = syn::parse2(tokens)?;
+            document_domains(&mut file, funcs, config);
+            let options = Options {
+                prefix: config.prefix(),
+                lanes: config.lanes_given(),
+            };
+            to_llvm_ir(&file, options)?
+        }
+        "wat" => {
+            use doctor_syn::codegen::wasm::{to_wat, Options};
+            let mut file = syn::parse2(tokens)?;
+            document_domains(&mut file, funcs, config);
+            let options = Options {
+                prefix: config.prefix(),
+                lanes: config.lanes_given(),
+                relaxed_simd: config.relaxed_simd(),
+            };
+            to_wat(&file, options)?
+        }
+        "asm" => {
+            use doctor_syn::codegen::asm::{to_asm, Options};
+            let mut file = syn::parse2(tokens)?;
+            document_domains(&mut file, funcs, config);
+            let options = Options {
+                prefix: config.prefix(),
+                lanes: config.lanes_given(),
+            };
+            to_asm(&file, options)?
+        }
+        "wgsl" | "glsl" => {
+            use doctor_syn::codegen::shader::{to_shader_partial, Dialect, Options};
+            // Shaders only have f32.
+            let config = config.with_num_bits(32);
+            let mut file = syn::parse2(gen_tokens(&config, funcs))?;
+            document_domains(&mut file, funcs, &config);
+            let dialect = match config.language() {
+                "wgsl" => Dialect::Wgsl,
+                _ => Dialect::Glsl,
+            };
+            let options = Options {
+                prefix: config.prefix(),
+                dialect,
+            };
+            // Leave out functions such as runif that need 64 bit integers.
+            let (text, errors) = to_shader_partial(&file, options)?;
+            for e in errors {
+                eprintln!("warning: {}, leaving it out", e);
+            }
+            text
+        }
+        "rust-capi" => capi::gen_lib(config, documented_functions(config, funcs)?)?,
+        "rust-generic" => {
+            doctor_syn::codegen::rust::format_token_stream(generic::gen_generic(config, funcs)?)
+        }
+        "numpy" => {
+            use doctor_syn::codegen::numpy::{to_numpy, Options};
+            // The tests are written separately with --pytest.
+            let mut file = syn::parse2(gen_functions(config, funcs))?;
+            document_domains(&mut file, funcs, config);
+            let options = Options {
+                prefix: config.prefix(),
+            };
+            to_numpy(&file, options)?
+        }
+        "verilog" => {
+            return Err(doctor_syn::Error::new(doctor_syn::ErrorKind::UnsupportedCodegen)
+                .with_operation("generating verilog")
+                .with_message("verilog is only generated for fixed point functions, use --fixed-point"))
+        }
+        "portable-simd" => {
+            let mut options = doctor_syn::codegen::portable_simd::Options::default();
+            options.num_bits = config.num_bits();
+            options.std_simd = config.std_simd();
+            // The tests compare the methods with the scalar functions.
+            let file : syn::File = syn::parse2(gen_functions(config, funcs))?;
+            let new_file = doctor_syn::codegen::portable_simd::to_simd(&file, options)?;
+            let mut tokens = TokenStream::new();
+            new_file.to_tokens(&mut tokens);
+            if config.slices() {
+                tokens.extend(slices::gen_slices(config, &file));
+            }
+            if config.generate_tests() {
+                tokens.extend(crate::test::gen_simd_tests(&file, funcs, config));
+            }
+            doctor_syn::codegen::rust::format_token_stream(tokens)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(text))
+}
+
 fn main() {
     let opt = Opt::from_args();
     if opt.debug {
@@ -356,9 +706,22 @@ fn main() {
         return;
     }
 
+    if let Some(text) = &opt.fixed_point {
+        if fixed::Format::parse(text).is_none() {
+            eprintln!("invalid fixed point format {} use Qm.n with 1 + m + n = 8, 16 or 32.", text);
+            return;
+        }
+    }
+
     if opt.functions == "help" {
-        for f in functions::FUNCTIONS {
-            println!("{}", f.name);
+        if opt.fixed_point.is_some() {
+            for f in fixed::FIXED_FUNCTIONS {
+                println!("{}", f.name);
+            }
+        } else {
+            for f in functions::FUNCTIONS {
+                println!("{}", f.name);
+            }
         }
         return;
     }
@@ -373,63 +736,116 @@ fn main() {
         .split(',')
         .map(str::to_string)
         .collect::<Vec<_>>();
-    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let funcs = if opt.fixed_point.is_some() {
+        Vec::new()
+    } else {
+        functions::get_functions_and_deps(&names, &exclude)
+    };
 
     let config: Config = Config::new(opt);
 
-    let mut tokens = TokenStream::new();
+    if !["extern", "static-inline"].contains(&config.linkage()) {
+        eprintln!("invalid linkage {} use extern or static-inline.", config.linkage());
+        return;
+    }
 
-    for f in funcs.iter() {
-        if let Some(gen) = f.gen {
-            let num_terms = if config.num_bits() == 32 {
-                f.num_terms[0]
-            } else {
-                f.num_terms[1]
-            };
-            tokens.extend(gen(num_terms, &config));
-        }
+    if !["generic", "sse", "avx2", "avx512"].contains(&config.flavour()) {
+        eprintln!("invalid flavour {} use generic, sse, avx2 or avx512.", config.flavour());
+        return;
     }
 
-    if config.generate_tests() {
-        tokens.extend(crate::auxfuncs::gen_test_function(0, &config));
-        for f in funcs {
-            for t in f.test_specs {
-                tokens.extend(crate::test::gen_test(t, &config));
+    if let Some(path) = config.header() {
+        if !["c", "rust-capi"].contains(&config.language()) {
+            eprintln!("--header is only used with --language c or rust-capi");
+            return;
+        }
+        match generate_c_header(&config, &funcs) {
+            Ok(text) => std::fs::write(path, text.as_bytes()).unwrap(),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
     }
 
-    let text = match config.language() {
-        "rust" => doctor_syn::codegen::rust::format_token_stream(tokens),
-        "c" => {
-            let mut options = doctor_syn::codegen::c::Options::default();
-            options.prefix = config.prefix().to_string();
-            options.float_suffix = config.float_suffix().to_string();
-            doctor_syn::codegen::c::to_c(&syn::parse2(tokens).unwrap(), options)
+    if !["software", "hardware", "unfused"].contains(&config.fma()) {
+        eprintln!("invalid fma {} use software, hardware or unfused.", config.fma());
+        return;
+    }
+
+    if config.no_std() && config.language() != "rust" {
+        eprintln!("--no-std is only used with --language rust");
+        return;
+    }
+
+    if config.slices() && config.language() != "portable-simd" {
+        eprintln!("--slices is only used with --language portable-simd");
+        return;
+    }
+
+    if config.std_simd() && config.language() != "portable-simd" {
+        eprintln!("--std-simd is only used with --language portable-simd");
+        return;
+    }
+
+    if let Some(path) = config.pytest() {
+        if config.language() != "numpy" {
+            eprintln!("--pytest is only used with --language numpy");
+            return;
         }
-        "portable-simd" => {
-            let mut options = doctor_syn::codegen::portable_simd::Options::default();
-            options.num_bits = config.num_bits();
-            let file : syn::File = syn::parse2(tokens).unwrap();
-            let new_file = doctor_syn::codegen::portable_simd::to_simd(&file, options);
-            let mut tokens = TokenStream::new();
-            new_file.to_tokens(&mut tokens);
-            doctor_syn::codegen::rust::format_token_stream(tokens)
+        let text = test::gen_pytest(&funcs, &config, &config.python_module());
+        std::fs::write(path, text.as_bytes()).unwrap();
+    }
+
+    // The output of rust-capi is the directory of a crate.
+    if let (Some(dir), "rust-capi") = (config.output(), config.language()) {
+        let files = match capi::gen_crate(&config, documented_functions(&config, &funcs).unwrap()) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        for (path, text) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text.as_bytes()).unwrap();
         }
-        "help" => {
+        return;
+    }
+
+    let text = match generate(&config, &funcs) {
+        Ok(Some(text)) => text,
+        Ok(None) if config.language() == "help" => {
             eprintln!("Available languages:");
             eprintln!("    rust");
             eprintln!("    c");
+            eprintln!("    c-vector");
+            eprintln!("    fortran");
+            eprintln!("    cpp");
             eprintln!("    portable-simd");
+            eprintln!("    llvm-ir");
+            eprintln!("    wat");
+            eprintln!("    asm");
+            eprintln!("    wgsl");
+            eprintln!("    glsl");
+            eprintln!("    numpy");
+            eprintln!("    rust-capi (a crate in the --output directory)");
+            eprintln!("    rust-generic (needs nightly)");
+            eprintln!("    verilog");
             return;
         }
-        _ => {
+        Ok(None) => {
             eprintln!(
                 "invalid language {} use \"help\" to list valid options.",
                 config.language()
             );
             return;
         }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     };
 
     if let Some(path) = &config.output() {
@@ -438,3 +854,1305 @@ fn main() {
         std::io::stdout().write_all(text.as_bytes()).unwrap();
     }
 }
+
+#[test]
+fn test_c_compiles() {
+    // Each function and its dependencies should be warning-free C99.
+    let dir = std::env::temp_dir().join("libmgen_test_c");
+    std::fs::create_dir_all(&dir).unwrap();
+    for num_bits in &["32", "64"] {
+        for f in functions::FUNCTIONS {
+            let args = ["libmgen", "--language", "c", "--num-bits", num_bits, "-f", f.name];
+            let config = Config::new(Opt::from_iter(&args));
+            let (names, exclude) = (vec![f.name.to_string()], vec![]);
+            let funcs = functions::get_functions_and_deps(&names, &exclude);
+            let text = generate(&config, &funcs)
+                .unwrap_or_else(|e| panic!("{} {}: {}", f.name, num_bits, e))
+                .unwrap();
+            let path = dir.join(format!("{}_{}.c", f.name, num_bits));
+            std::fs::write(&path, text).unwrap();
+            let output = std::process::Command::new("cc")
+                .args(["-std=c99", "-Wall", "-Werror", "-c", "-o"])
+                .arg(path.with_extension("o"))
+                .arg(&path)
+                .output();
+            match output {
+                Ok(output) => assert!(
+                    output.status.success(),
+                    "{}\n{}",
+                    path.display(),
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+                Err(_) => {
+                    eprintln!("cc not found, not compiling C");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_c_header() {
+    // The header should declare the library for C and C++.
+    let dir = std::env::temp_dir().join("libmgen_test_c_header");
+    std::fs::create_dir_all(&dir).unwrap();
+    let header = dir.join("libds.h");
+    let args = ["libmgen", "--language", "c", "-f", "all", "--header", header.to_str().unwrap()];
+    let config = Config::new(Opt::from_iter(&args));
+    let (names, exclude) = (vec!["all".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    std::fs::write(&header, generate_c_header(&config, &funcs).unwrap()).unwrap();
+    let source = dir.join("libds.c");
+    std::fs::write(&source, generate(&config, &funcs).unwrap().unwrap()).unwrap();
+    let user = dir.join("user.cpp");
+    std::fs::write(&user, "#include \"libds.h\"\ndouble f(double x) { return ds64_sin(x); }\n").unwrap();
+    let commands = [
+        ("cc", vec!["-std=c99", "-Wall", "-Werror", "-fsyntax-only"], &source),
+        ("c++", vec!["-Wall", "-Werror", "-fsyntax-only"], &user),
+    ];
+    for (compiler, args, path) in &commands {
+        match std::process::Command::new(compiler).args(args).arg(path).output() {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}\n{}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(_) => eprintln!("{} not found, not compiling {}", compiler, path.display()),
+        }
+    }
+}
+
+#[test]
+fn test_c_freestanding() {
+    // Freestanding C should link without any libraries.
+    let dir = std::env::temp_dir().join("libmgen_test_c_freestanding");
+    std::fs::create_dir_all(&dir).unwrap();
+    for num_bits in &["32", "64"] {
+        let args = ["libmgen", "--language", "c", "--num-bits", num_bits, "-f", "all", "--freestanding"];
+        let config = Config::new(Opt::from_iter(&args));
+        let (names, exclude) = (vec!["all".to_string()], vec![]);
+        let funcs = functions::get_functions_and_deps(&names, &exclude);
+        let path = dir.join(format!("libds{}.c", num_bits));
+        std::fs::write(&path, generate(&config, &funcs).unwrap().unwrap()).unwrap();
+        let output = std::process::Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror", "-ffreestanding", "-nostdlib", "-fPIC", "-shared"])
+            .args(["-Wl,--no-undefined", "-o"])
+            .arg(path.with_extension("so"))
+            .arg(&path)
+            .output();
+        match output {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}\n{}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(_) => {
+                eprintln!("cc not found, not compiling C");
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_c_matches_rust() {
+    // C calls `fma`, or its fallback, for `mul_add` so it should give the same bits as Rust.
+    let dir = std::env::temp_dir().join("libmgen_test_c_matches_rust");
+    std::fs::create_dir_all(&dir).unwrap();
+    let (names, exclude) = (vec!["all".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let unary = [
+        "sin", "cos", "tan", "exp", "exp2", "ln", "log2", "log10", "sqrt", "cbrt", "asin", "atan",
+        "tanh",
+    ];
+    for num_bits in ["32", "64"] {
+        let (fty, ctype) = if num_bits == "32" { ("f32", "float") } else { ("f64", "double") };
+        let generate_with = |args: &[&str]| {
+            let common = ["libmgen", "--num-bits", num_bits, "-f", "all"];
+            let config = Config::new(Opt::from_iter(common.iter().chain(args.iter())));
+            generate(&config, &funcs).unwrap().unwrap()
+        };
+        std::fs::write(dir.join("with_rust.rs"), generate_with(&[])).unwrap();
+        std::fs::write(dir.join("hosted.c"), generate_with(&["--language", "c"])).unwrap();
+        // The freestanding functions are renamed so that both can be linked.
+        let prefix = format!("ds{}_", num_bits);
+        let mut free = generate_with(&["--language", "c", "--freestanding"]).replace(&prefix, "free_");
+        free.push_str(&format!(
+            "{t} free_mul_add({t} x, {t} y, {t} z) {{ return free_fma_{f}(x, y, z); }}\n",
+            t = ctype,
+            f = fty
+        ));
+        std::fs::write(dir.join("free.c"), free).unwrap();
+        let compile = std::process::Command::new("cc")
+            .current_dir(&dir)
+            .args(["-std=c99", "-Wall", "-Werror", "-O2", "-c", "hosted.c", "free.c"])
+            .output();
+        match compile {
+            Ok(output) => assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                eprintln!("cc not found, not comparing C with Rust");
+                return;
+            }
+        }
+        let _ = std::fs::remove_file(dir.join("libds.a"));
+        let archive = std::process::Command::new("ar")
+            .current_dir(&dir)
+            .args(["rcs", "libds.a", "hosted.o", "free.o"])
+            .status()
+            .unwrap();
+        assert!(archive.success());
+
+        let mut externs = String::new();
+        let mut calls = String::new();
+        for f in unary.iter() {
+            externs.push_str(&format!("    fn {p}{f}(x: F) -> F;\n    fn free_{f}(x: F) -> F;\n", p = prefix, f = f));
+            calls.push_str(&format!(
+                "        same(with_rust::{f}(x), {p}{f}(x), \"{f}\", x, x, x);\n        same(with_rust::{f}(x), free_{f}(x), \"free_{f}\", x, x, x);\n",
+                p = prefix,
+                f = f
+            ));
+        }
+        let harness = format!(
+            r#"#![allow(dead_code, non_snake_case)]
+mod with_rust;
+
+type F = {fty};
+
+#[link(name = "ds", kind = "static")]
+extern "C" {{
+{externs}    fn {prefix}atan2(y: F, x: F) -> F;
+    fn free_atan2(y: F, x: F) -> F;
+    fn free_mul_add(x: F, y: F, z: F) -> F;
+}}
+
+fn same(a: F, b: F, name: &str, x: F, y: F, z: F) {{
+    assert!(a.to_bits() == b.to_bits() || a.is_nan() && b.is_nan(), "{{}}({{:e}}, {{:e}}, {{:e}}) is {{:e}} not {{:e}}", name, x, y, z, b, a);
+}}
+
+fn main() {{
+    let mut state = 0x9e3779b97f4a7c15u64;
+    let mut random = || {{
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        F::from_bits(state as _)
+    }};
+    let special = [0.0, -0.0, 1.0, -1.0, 0.5, F::MIN_POSITIVE, F::MIN_POSITIVE / 3.0, F::MAX, F::INFINITY, F::NEG_INFINITY, F::NAN];
+    let mut values = special.to_vec();
+    values.extend((0..200000).map(|_| random()));
+    unsafe {{
+        for w in values.windows(3) {{
+            let (x, y, z) = (w[0], w[1], w[2]);
+            same(x.mul_add(y, z), free_mul_add(x, y, z), "fma", x, y, z);
+            // Cancellation and products near the subnormals.
+            same(x.mul_add(y, -(x * y)), free_mul_add(x, y, -(x * y)), "fma", x, y, -(x * y));
+            let t = x * F::MIN_POSITIVE.sqrt();
+            same(t.mul_add(y, z * F::MIN_POSITIVE), free_mul_add(t, y, z * F::MIN_POSITIVE), "fma", t, y, z);
+        }}
+        for i in 0..60001 {{
+            let x = (i as F - 30000.0) / 3000.0;
+{calls}            same(with_rust::atan2(x, 0.5), {prefix}atan2(x, 0.5), "atan2", x, 0.5, 0.5);
+            same(with_rust::atan2(x, 0.5), free_atan2(x, 0.5), "free_atan2", x, 0.5, 0.5);
+        }}
+    }}
+    println!("PASS");
+}}
+"#,
+            fty = fty,
+            prefix = prefix,
+            externs = externs,
+            calls = calls
+        );
+        let path = dir.join("main.rs");
+        std::fs::write(&path, harness).unwrap();
+        let binary = dir.join("main");
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let output = std::process::Command::new(&rustc)
+            .args(["--edition", "2021", "-O", "-L"])
+            .arg(&dir)
+            .args(["-l", "m", "-o"])
+            .arg(&binary)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let output = std::process::Command::new(&binary).output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "PASS\n",
+            "{}: {}",
+            num_bits,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn test_c_vector_compiles() {
+    // Each flavour should compile for its own vector size and for 256 bit vectors.
+    let dir = std::env::temp_dir().join("libmgen_test_c_vector");
+    std::fs::create_dir_all(&dir).unwrap();
+    let flavours = [
+        ("generic", 256, &["-mavx2", "-mfma"][..]),
+        ("sse", 128, &["-msse4.1"][..]),
+        ("avx2", 256, &["-mavx2", "-mfma"][..]),
+        ("avx512", 512, &["-mavx512f", "-mavx512dq"][..]),
+        ("avx512", 256, &["-mavx512f", "-mavx512dq"][..]),
+    ];
+    for (flavour, bits, flags) in &flavours {
+        for num_bits in &[32, 64] {
+            let lanes = (bits / num_bits).to_string();
+            let num_bits = num_bits.to_string();
+            let args = [
+                "libmgen", "--language", "c-vector", "--num-bits", &num_bits, "-f", "all",
+                "--lanes", &lanes, "--flavour", flavour,
+            ];
+            let config = Config::new(Opt::from_iter(&args));
+            let (names, exclude) = (vec!["all".to_string()], vec![]);
+            let funcs = functions::get_functions_and_deps(&names, &exclude);
+            let path = dir.join(format!("{}_{}x{}.c", flavour, num_bits, lanes));
+            std::fs::write(&path, generate(&config, &funcs).unwrap().unwrap()).unwrap();
+            // `usize` vectors are wider than the float vectors for 32 bits.
+            let output = std::process::Command::new("cc")
+                .args(["-std=c99", "-Wall", "-Werror", "-Wno-psabi", "-c", "-o"])
+                .arg(path.with_extension("o"))
+                .arg(&path)
+                .args(*flags)
+                .output();
+            match output {
+                Ok(output) => assert!(
+                    output.status.success(),
+                    "{}\n{}",
+                    path.display(),
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+                Err(_) => {
+                    eprintln!("cc not found, not compiling C");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_c_vector_abi() {
+    // A loop over functions declared simd should link to the vector variants.
+    let dir = std::env::temp_dir().join("libmgen_test_c_vector_abi");
+    std::fs::create_dir_all(&dir).unwrap();
+    let (names, exclude) = (vec!["all".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let header = dir.join("libds.h");
+    let outputs = [
+        ("libds.c", &["--language", "c", "--header", header.to_str().unwrap(), "--declare-simd"][..]),
+        ("sse.c", &["--language", "c-vector", "--flavour", "sse", "--lanes", "2", "--vector-abi"][..]),
+        ("avx2.c", &["--language", "c-vector", "--flavour", "avx2", "--vector-abi"][..]),
+    ];
+    for (name, args) in &outputs {
+        let config = Config::new(Opt::from_iter(["libmgen", "-f", "all"].iter().chain(args.iter())));
+        if config.header().is_some() {
+            std::fs::write(&header, generate_c_header(&config, &funcs).unwrap()).unwrap();
+        }
+        std::fs::write(dir.join(name), generate(&config, &funcs).unwrap().unwrap()).unwrap();
+    }
+    std::fs::write(
+        dir.join("user.c"),
+        concat!(
+            "#include \"libds.h\"\n",
+            "int main(void) {\n",
+            "    /* Calls through these stay scalar, to check the lanes and arguments of the vector calls. */\n",
+            "    double (*volatile sin)(double) = ds64_sin;\n",
+            "    double (*volatile atan2)(double, double) = ds64_atan2;\n",
+            "    double a[100];\n",
+            "    int i;\n",
+            "#pragma omp simd\n",
+            "    for (i = 0; i < 100; i++) a[i] = ds64_sin(i * 0.1) + ds64_atan2(i * 0.1, 0.5);\n",
+            "    for (i = 0; i < 100; i++) {\n",
+            "        if (a[i] != sin(i * 0.1) + atan2(i * 0.1, 0.5)) return 1;\n",
+            "    }\n",
+            "    return 0;\n",
+            "}\n",
+        ),
+    )
+    .unwrap();
+    // The library itself is built without OpenMP, so it has only the scalar functions.
+    let commands = [
+        &["-Wno-unknown-pragmas", "-c", "libds.c"][..],
+        &["-fopenmp-simd", "-mavx2", "user.c", "libds.o", "sse.c", "avx2.c", "-lm", "-o", "user"][..],
+    ];
+    for args in &commands {
+        let output = std::process::Command::new("cc")
+            .current_dir(&dir)
+            .args(["-std=c99", "-Wall", "-Werror", "-Wno-psabi", "-O2", "-mfma"])
+            .args(*args)
+            .output();
+        match output {
+            Ok(output) => assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                eprintln!("cc not found, not compiling C");
+                return;
+            }
+        }
+    }
+    let status = std::process::Command::new(dir.join("user")).status().unwrap();
+    assert!(status.success());
+}
+
+#[test]
+#[ignore = "needs gfortran"]
+fn test_fortran_compiles() {
+    // The module should be standard Fortran 2008 and `runif` should not overflow.
+    let dir = std::env::temp_dir().join("libmgen_test_fortran");
+    std::fs::create_dir_all(&dir).unwrap();
+    let gfortran = |args: &[&str]| {
+        let output = std::process::Command::new("gfortran")
+            .current_dir(&dir)
+            .args(["-std=f2008", "-Wall", "-Werror", "-ftrapv"])
+            .args(args)
+            .output()
+            .expect("gfortran not found");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    };
+    for num_bits in &["32", "64"] {
+        let args = ["libmgen", "--language", "fortran", "--num-bits", num_bits, "-f", "all"];
+        let config = Config::new(Opt::from_iter(&args));
+        let (names, exclude) = (vec!["all".to_string()], vec![]);
+        let funcs = functions::get_functions_and_deps(&names, &exclude);
+        let path = dir.join(format!("ds{}.f90", num_bits));
+        std::fs::write(&path, generate(&config, &funcs).unwrap().unwrap()).unwrap();
+        gfortran(&["-c", path.to_str().unwrap()]);
+    }
+
+    // The largest index makes `index + 1` overflow a signed integer.
+    let indices = [0, 1, 12345, i64::MAX as u64];
+    let program = format!(
+        "program check
+  use, intrinsic :: iso_fortran_env, only: int64, real64
+  use ds64
+  implicit none
+  integer(kind=int64), parameter :: indices(4) = [{}]
+  integer :: i
+  do i = 1, 4
+    write (*, '(z16.16)') transfer(ds64_runif(indices(i), 0.0_real64, 1.0_real64), 0_int64)
+  end do
+end program check
+",
+        indices.iter().map(|i| format!("{}_int64", i)).collect::<Vec<_>>().join(", ")
+    );
+    std::fs::write(dir.join("check.f90"), program).unwrap();
+    gfortran(&["-O2", "check.f90", "ds64.o", "-o", "check"]);
+    let output = std::process::Command::new(dir.join("check")).output().unwrap();
+    let expected = indices
+        .iter()
+        .map(|index| {
+            // splitmix64, as in `runif`.
+            let z = (index + 1).wrapping_mul(0x9e3779b97f4a7c15);
+            let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            let z = z ^ (z >> 31);
+            let x = f64::from_bits((z >> 2) & ((1 << 52) - 1) | 1.0f64.to_bits()) - 1.0;
+            format!("{:016X}\n", x.to_bits())
+        })
+        .collect::<String>();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn test_cpp_compiles() {
+    // The simd overloads should match the scalar ones lane by lane.
+    let dir = std::env::temp_dir().join("libmgen_test_cpp");
+    std::fs::create_dir_all(&dir).unwrap();
+    let args = ["libmgen", "--language", "cpp", "-f", "all"];
+    let config = Config::new(Opt::from_iter(&args));
+    let (names, exclude) = (vec!["all".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    std::fs::write(dir.join("ds.hpp"), generate(&config, &funcs).unwrap().unwrap()).unwrap();
+    std::fs::write(
+        dir.join("user.cpp"),
+        concat!(
+            "#include \"ds.hpp\"\n",
+            "template <typename T>\n",
+            "int check() {\n",
+            "    using V = ds::stdx::native_simd<T>;\n",
+            "    V x([](int i) { return T(0.1) * T(i + 1); });\n",
+            "    V s = ds::sin(x), a = ds::atan2(x, V(T(0.5)) - x);\n",
+            "    int bad = 0;\n",
+            "    for (std::size_t i = 0; i < V::size(); ++i) {\n",
+            "        T xi = x[i];\n",
+            "        bad += s[i] != ds::sin(xi) || a[i] != ds::atan2(xi, T(0.5) - xi);\n",
+            "    }\n",
+            "    return bad;\n",
+            "}\n",
+            "int main() { return check<float>() + check<double>(); }\n",
+        ),
+    )
+    .unwrap();
+    for std in &["-std=c++17", "-std=c++20"] {
+        let output = std::process::Command::new("c++")
+            .current_dir(&dir)
+            .args([std, "-Wall", "-Werror", "-O2", "user.cpp", "-o", "user"])
+            .output();
+        match output {
+            Ok(output) => assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                eprintln!("c++ not found, not compiling C++");
+                return;
+            }
+        }
+        let status = std::process::Command::new(dir.join("user")).status().unwrap();
+        assert!(status.success());
+    }
+}
+
+#[test]
+fn test_portable_simd_tests() {
+    // The tests should call the methods and compare them with the scalar functions.
+    let args = ["libmgen", "--language", "portable-simd", "--num-bits", "32", "-f", "exp,runif", "--generate-tests"];
+    let config = Config::new(Opt::from_iter(&args));
+    let (names, exclude) = (vec!["exp".to_string(), "runif".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let text = generate(&config, &funcs).unwrap().unwrap();
+    assert!(text.contains("fn runif (index : Self :: UintType , min : Self , max : Self)->Self {"));
+    assert!(text.contains("mod tests {"));
+    assert!(text.contains("mod scalar {"));
+    assert!(text.contains("pub (super)fn exp (arg : fty)->fty {\n      exp2 (arg * LOG2_E)\n"));
+    assert!(text.contains("let r =<Self as StdLibm >::round (arg);"));
+    assert!(text.contains("test_function (\"test_exp_1\" , accurate_values , "));
+    assert!(text.contains("| x | < V as StdLibm > :: exp (x) , | x | scalar :: exp (x));"));
+    assert!(text.contains("let ys =<V as StdLibm >::runif (index , V :: splat (0.0) , V :: splat (1.0));"));
+    assert!(text.contains("assert_eq !(ys [lane] . to_bits () , scalar :: runif (i , 0.0 , 1.0) . to_bits ()"));
+}
+
+/// Writes the portable-simd output beside the `StdLibm` trait and `extra`, builds it with
+/// `rustc +nightly --test` and runs the tests, or returns false when there is no nightly rustc.
+#[cfg(test)]
+fn run_portable_simd_tests(name: &str, args: &[&str], extra: &str) -> bool {
+    let dir = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    match std::process::Command::new(&rustc).args(["+nightly", "--version"]).output() {
+        Ok(output) if output.status.success() => (),
+        _ => {
+            eprintln!("nightly rustc not found, not running the portable-simd tests");
+            return false;
+        }
+    }
+    let config = Config::new(Opt::from_iter(args));
+    let (names, exclude) = (config.function_names(), Vec::new());
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let text = generate(&config, &funcs).unwrap().unwrap();
+    std::fs::write(dir.join("ds.rs"), text).unwrap();
+    let declaration = generic::gen_trait(&config, &funcs).unwrap();
+    let lib = format!(
+        "#![feature(portable_simd)]\nuse std::simd;\nuse std::simd::StdFloat;\n{}\nmod ds;\n{}",
+        doctor_syn::codegen::rust::format_token_stream(declaration),
+        extra
+    );
+    let path = dir.join("lib.rs");
+    std::fs::write(&path, lib).unwrap();
+    let binary = dir.join("tests");
+    let output = std::process::Command::new(&rustc)
+        .args(["+nightly", "--edition", "2021", "--test", "-O", "-o"])
+        .arg(&binary)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = std::process::Command::new(&binary).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    true
+}
+
+#[test]
+fn test_portable_simd_tests_run() {
+    // The generated tests should build with std::simd and pass for functions that meet their limits.
+    for num_bits in ["32", "64"] {
+        let args = ["libmgen", "--language", "portable-simd", "--std-simd", "--num-bits", num_bits, "-f", "sin,runif", "--generate-tests"];
+        if !run_portable_simd_tests(&format!("libmgen_test_portable_simd_{}", num_bits), &args, "") {
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_portable_simd_slices() {
+    // The kernels should sit beside the methods and the tests.
+    let args = ["libmgen", "--language", "portable-simd", "-f", "sin,atan2", "--slices", "--generate-tests"];
+    let config = Config::new(Opt::from_iter(&args));
+    let (names, exclude) = (vec!["sin".to_string(), "atan2".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let text = generate(&config, &funcs).unwrap().unwrap();
+    assert!(text.contains("pub mod scalar {"));
+    assert!(text.contains("pub const LANES :usize =4usize;"));
+    assert!(text.contains("pub fn sin_slice (input : & [fty] , output : & mut [fty]){"));
+    assert!(text.contains("pub fn atan2_slice_in_place (y : & mut [fty] , x : & [fty]){"));
+    assert!(text.contains("mod tests {"));
+}
+
+#[test]
+fn test_portable_simd_slices_run() {
+    // The kernels should give the scalar results, including the elements after the last full vector.
+    for (num_bits, lanes) in [("32", 8), ("64", 4)] {
+        let args = ["libmgen", "--language", "portable-simd", "--std-simd", "--num-bits", num_bits, "-f", "sin,atan2", "--slices"];
+        let extra = format!(
+            r#"
+#[test]
+fn slices_match_scalar() {{
+    use ds::{{scalar, slices}};
+    type fty = f{num_bits};
+    let len = 3 * slices::LANES + {tail};
+    let y: Vec<fty> = (0..len).map(|i| i as fty * 0.37 - 2.0).collect();
+    let x: Vec<fty> = (0..len).map(|i| 1.5 - i as fty * 0.11).collect();
+    let mut output = vec![0.0; len];
+    slices::sin_slice(&y, &mut output);
+    let mut in_place = y.clone();
+    slices::sin_slice_in_place(&mut in_place);
+    for i in 0..len {{
+        assert_eq!(output[i].to_bits(), scalar::sin(y[i]).to_bits(), "sin {{}}", i);
+        assert_eq!(in_place[i].to_bits(), output[i].to_bits(), "sin in place {{}}", i);
+    }}
+    slices::atan2_slice(&y, &x, &mut output);
+    let mut in_place = y.clone();
+    slices::atan2_slice_in_place(&mut in_place, &x);
+    for i in 0..len {{
+        assert_eq!(output[i].to_bits(), scalar::atan2(y[i], x[i]).to_bits(), "atan2 {{}}", i);
+        assert_eq!(in_place[i].to_bits(), output[i].to_bits(), "atan2 in place {{}}", i);
+    }}
+}}
+"#,
+            num_bits = num_bits,
+            tail = lanes - 1
+        );
+        if !run_portable_simd_tests(&format!("libmgen_test_portable_simd_slices_{}", num_bits), &args, &extra) {
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_shaders_validate() {
+    // The shaders should parse and validate, without runif and rnorm as there is no 64 bit integer.
+    let (names, exclude) = (vec!["all".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    for language in &["wgsl", "glsl"] {
+        let args = ["libmgen", "--language", language, "-f", "all"];
+        let config = Config::new(Opt::from_iter(&args));
+        let text = generate(&config, &funcs).unwrap().unwrap();
+        assert!(text.contains("ds32_exp2("));
+        assert!(text.contains("ds32_qnorm("));
+        assert!(!text.contains("ds32_runif(") && !text.contains("ds32_rnorm("));
+        let module = if *language == "wgsl" {
+            naga::front::wgsl::parse_str(&text).unwrap_or_else(|e| panic!("{}", e.emit_to_string(&text)))
+        } else {
+            let source = format!("#version 450\n{}\nlayout(local_size_x = 1) in;\nvoid main() {{}}\n", text);
+            let options = naga::front::glsl::Options::from(naga::ShaderStage::Compute);
+            naga::front::glsl::Frontend::default()
+                .parse(&options, &source)
+                .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&source)))
+        };
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+            .validate(&module)
+            .unwrap_or_else(|e| panic!("{}\n{}", text, e.emit_to_string(&text)));
+    }
+    let (names, exclude) = (vec!["runif".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let config = Config::new(Opt::from_iter(&["libmgen", "--language", "wgsl", "-f", "runif"]));
+    assert!(generate(&config, &funcs).unwrap_err().to_string().contains("u64"));
+}
+
+#[test]
+fn test_llvm_ir_assembles() {
+    // The IR should survive llvm-as and llvm-dis, and run under lli.
+    let dir = std::env::temp_dir().join("libmgen_test_llvm_ir");
+    std::fs::create_dir_all(&dir).unwrap();
+    let (names, exclude) = (vec!["all".to_string()], vec![]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    for (num_bits, lanes) in &[("32", None), ("64", None), ("32", Some("8")), ("64", Some("4"))] {
+        let mut args = vec!["libmgen", "--language", "llvm-ir", "--num-bits", num_bits, "-f", "all"];
+        if let Some(lanes) = lanes {
+            args.extend(["--lanes", lanes]);
+        }
+        let config = Config::new(Opt::from_iter(&args));
+        let mut text = generate(&config, &funcs).unwrap().unwrap();
+        if lanes.is_none() && *num_bits == "64" {
+            text.push_str(concat!(
+                "define i32 @main() {\n",
+                "entry:\n",
+                "  %y = call double @ds64_exp2(double 0x3FF0000000000000)\n",
+                "  %ok = fcmp oeq double %y, 0x4000000000000000\n",
+                "  %r = select i1 %ok, i32 0, i32 1\n",
+                "  ret i32 %r\n",
+                "}\n",
+            ));
+        }
+        let path = dir.join(format!("{}.ll", config.prefix()));
+        let bitcode = path.with_extension("bc");
+        std::fs::write(&path, text).unwrap();
+        let output = std::process::Command::new("llvm-as").arg(&path).arg("-o").arg(&bitcode).output();
+        match output {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}\n{}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(_) => {
+                eprintln!("llvm-as not found, not assembling LLVM IR");
+                return;
+            }
+        }
+        let output = std::process::Command::new("llvm-dis").arg(&bitcode).arg("-o").arg("-").output().unwrap();
+        assert!(output.status.success());
+        let name = format!("@{}exp2(", config.prefix());
+        assert!(String::from_utf8_lossy(&output.stdout).contains(&name));
+        if lanes.is_none() && *num_bits == "64" {
+            let status = std::process::Command::new("lli").arg(&bitcode).status().unwrap();
+            assert!(status.success());
+        }
+    }
+}
+
+#[test]
+fn test_wat_runs() {
+    // Scalar WebAssembly runs in wasmi and vectors in node, if it is installed.
+    let (names, exclude) = (vec!["all".to_string()], vec!["runif".to_string(), "rnorm".to_string()]);
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let variants = [("32", None, false), ("64", None, false), ("32", Some(4), false), ("64", Some(2), false), ("64", Some(2), true)];
+    for (num_bits, lanes, relaxed_simd) in variants {
+        let lanes_arg = lanes.map(|l: usize| l.to_string());
+        let mut args = vec!["libmgen", "--language", "wat", "--num-bits", num_bits, "-f", "all"];
+        if let Some(lanes) = &lanes_arg {
+            args.extend(["--lanes", lanes.as_str()]);
+        }
+        if relaxed_simd {
+            args.push("--relaxed-simd");
+        }
+        let config = Config::new(Opt::from_iter(&args));
+        let mut text = generate(&config, &funcs).unwrap().unwrap();
+        assert_eq!(text.contains("relaxed_madd"), relaxed_simd);
+        let fty = format!("f{}", num_bits);
+        if let Some(lanes) = lanes {
+            // JavaScript can not pass vectors, so take and return the lanes.
+            let shape = format!("{}x{}", fty, lanes);
+            let mut vector = format!("({}.splat (local.get 0))", shape);
+            let mut results = String::new();
+            for lane in 1..lanes {
+                vector = format!("({}.replace_lane {} {} (local.get {}))", shape, lane, vector, lane);
+            }
+            for lane in 0..lanes {
+                results.push_str(&format!(" ({}.extract_lane {} (local.get $r))", shape, lane));
+            }
+            let types = vec![fty.as_str(); lanes].join(" ");
+            text.truncate(text.trim_end().len() - 1);
+            text.push_str(&format!(
+                "  (func (export \"lanes_exp2\") (param {}) (result {}) (local $r v128)\n    (local.set $r (call ${}exp2 {})){})\n)\n",
+                types, types, config.prefix(), vector, results
+            ));
+        }
+        let wasm = wat::parse_str(&text).unwrap_or_else(|e| panic!("{}", e));
+        let mut validator = wasmparser::Validator::new_with_features(wasmparser::WasmFeatures::all());
+        validator.validate_all(&wasm).unwrap();
+        match lanes {
+            None => {
+                let engine = wasmi::Engine::default();
+                let module = wasmi::Module::new(&engine, &wasm[..]).unwrap();
+                let mut store = wasmi::Store::new(&engine, ());
+                // `ln_1p` imports `Math.log`.
+                let mut linker = wasmi::Linker::<()>::new(&engine);
+                linker.func_wrap("Math", "log", |x: f64| x.ln()).unwrap();
+                let instance = linker.instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+                for function in ["exp2", "ln", "sin", "cbrt"] {
+                    let reference = |x: f64| match function {
+                        "exp2" => x.exp2(),
+                        "ln" => x.ln(),
+                        "sin" => x.sin(),
+                        _ => x.cbrt(),
+                    };
+                    let name = format!("{}{}", config.prefix(), function);
+                    for x in [0.1, 0.3, 0.7, 1.5] {
+                        let (y, tolerance) = if num_bits == "32" {
+                            let f = instance.get_typed_func::<f32, f32>(&store, &name).unwrap();
+                            (f.call(&mut store, x as f32).unwrap() as f64, 1e-6)
+                        } else {
+                            let f = instance.get_typed_func::<f64, f64>(&store, &name).unwrap();
+                            (f.call(&mut store, x).unwrap(), 2e-15)
+                        };
+                        let error = (y - reference(x)).abs() / reference(x).abs();
+                        assert!(error < tolerance, "{}({}) = {} error {}", name, x, y, error);
+                    }
+                }
+            }
+            Some(_) if relaxed_simd => (),
+            Some(lanes) => {
+                let path = std::env::temp_dir().join(format!("libmgen_test_{}.wasm", config.prefix()));
+                std::fs::write(&path, &wasm).unwrap();
+                let script = format!(
+                    "const m = new WebAssembly.Instance(new WebAssembly.Module(require('fs').readFileSync(process.argv[1])), {{ Math }});\n\
+                     console.log(m.exports.lanes_exp2(...[1, 2, 3, 4].slice(0, {})).toString());",
+                    lanes
+                );
+                let output = match std::process::Command::new("node").arg("-e").arg(&script).arg(&path).output() {
+                    Ok(output) => output,
+                    Err(_) => {
+                        eprintln!("node not found, not running WebAssembly vectors");
+                        return;
+                    }
+                };
+                assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+                let expected = ["2", "4", "8", "16"][0..lanes].join(",");
+                assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), expected);
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_asm_runs() {
+    // The assembly should assemble with `as` and, linked with gcc, match libm.
+    let dir = std::env::temp_dir().join("libmgen_test_asm");
+    std::fs::create_dir_all(&dir).unwrap();
+    let names = vec!["all".to_string()];
+    let variants = [("32", None), ("64", None), ("32", Some("8")), ("64", Some("4"))];
+    for (num_bits, lanes) in variants {
+        // 32 bit runif mixes in 64 bit integers.
+        let exclude = match num_bits {
+            "32" => vec!["runif".to_string(), "rnorm".to_string()],
+            _ => vec![],
+        };
+        let funcs = functions::get_functions_and_deps(&names, &exclude);
+        let mut args = vec!["libmgen", "--language", "asm", "--num-bits", num_bits, "-f", "all"];
+        if let Some(lanes) = lanes {
+            args.extend(["--lanes", lanes]);
+        }
+        let config = Config::new(Opt::from_iter(&args));
+        let text = generate(&config, &funcs).unwrap().unwrap();
+        let path = dir.join(format!("{}.s", config.prefix()));
+        let object = path.with_extension("o");
+        std::fs::write(&path, text).unwrap();
+        let output = std::process::Command::new("as").arg(&path).arg("-o").arg(&object).output();
+        match output {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}\n{}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(_) => {
+                eprintln!("as not found, not assembling");
+                return;
+            }
+        }
+        if !is_x86_feature_detected!("avx2") || !is_x86_feature_detected!("fma") {
+            continue;
+        }
+        let (fty, tolerance) = match num_bits {
+            "32" => ("float", "1e-6"),
+            _ => ("double", "2e-15"),
+        };
+        let lanes = lanes.map_or(1, |l| l.parse::<usize>().unwrap());
+        let vty = match lanes {
+            1 => format!("typedef {} v;", fty),
+            _ => format!("typedef {} v __attribute__((vector_size(32)));", fty),
+        };
+        let harness = format!(
+            "#include <math.h>\n\
+             {vty}\n\
+             v {p}exp2(v), {p}ln(v), {p}sin(v), {p}cbrt(v);\n\
+             static int close(double a, double b) {{ return fabs(a - b) <= {tolerance} * fmax(1.0, fabs(b)); }}\n\
+             int main(void) {{\n\
+             \x20   for (int i = 0; i < 64; i++) {{\n\
+             \x20       v x;\n\
+             \x20       {fty} *xs = ({fty} *)&x;\n\
+             \x20       for (int l = 0; l < {lanes}; l++) xs[l] = 0.1 + (i * {lanes} + l) * 0.01;\n\
+             \x20       v e = {p}exp2(x), n = {p}ln(x), s = {p}sin(x), c = {p}cbrt(x);\n\
+             \x20       for (int l = 0; l < {lanes}; l++) {{\n\
+             \x20           double y = xs[l];\n\
+             \x20           if (!close((({fty} *)&e)[l], exp2(y)) || !close((({fty} *)&n)[l], log(y))\n\
+             \x20               || !close((({fty} *)&s)[l], sin(y)) || !close((({fty} *)&c)[l], cbrt(y)))\n\
+             \x20               return 1;\n\
+             \x20       }}\n\
+             \x20   }}\n\
+             \x20   return 0;\n\
+             }}\n",
+            vty = vty,
+            p = config.prefix(),
+            fty = fty,
+            lanes = lanes,
+            tolerance = tolerance,
+        );
+        let source = path.with_extension("c");
+        let binary = path.with_extension("");
+        std::fs::write(&source, harness).unwrap();
+        let output = std::process::Command::new("gcc")
+            .args(["-mavx2", "-mfma"])
+            .arg(&source)
+            .arg(&object)
+            .arg("-lm")
+            .arg("-o")
+            .arg(&binary)
+            .output();
+        match output {
+            Ok(output) => assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                eprintln!("gcc not found, not running assembly");
+                return;
+            }
+        }
+        let status = std::process::Command::new(&binary).status().unwrap();
+        assert!(status.success(), "{} gave wrong results", binary.display());
+    }
+}
+
+#[test]
+fn test_fixed_point_c_runs() {
+    // Compiled C should be within the documented error over the domain.
+    let dir = std::env::temp_dir().join("libmgen_test_fixed");
+    std::fs::create_dir_all(&dir).unwrap();
+    for text in ["Q0.7", "Q1.14", "Q1.30"] {
+        let config = Config::new(Opt::from_iter(&["libmgen", "--language", "c", "-f", "all", "--fixed-point", text]));
+        let format = config.fixed_point().unwrap();
+        let source = generate(&config, &[]).unwrap().unwrap();
+        let ty = format!("int{}_t", format.word_bits());
+        let prefix = config.prefix();
+        let mut checks = String::new();
+        for f in fixed::FIXED_FUNCTIONS {
+            checks.push_str(&format!(
+                "    printf(\"{name} %f\\n\", check({p}{name}, ref_{name}, {xmin:?}, {xmax:?}));\n",
+                name = f.name,
+                p = prefix,
+                xmin = f.xmin,
+                xmax = f.xmax,
+            ));
+        }
+        let harness = format!(
+            "#include <math.h>\n\
+             #include <stdint.h>\n\
+             #include <stdio.h>\n\
+             {ty} {p}sin_pi({ty}), {p}cos_pi({ty}), {p}exp2({ty}), {p}log2_1p({ty}), {p}atan({ty});\n\
+             static const double pi = 3.14159265358979323846;\n\
+             static double ref_sin_pi(double x) {{ return sin(pi * x); }}\n\
+             static double ref_cos_pi(double x) {{ return cos(pi * x); }}\n\
+             static double ref_exp2(double x) {{ return exp2(x); }}\n\
+             static double ref_log2_1p(double x) {{ return log2(1 + x); }}\n\
+             static double ref_atan(double x) {{ return atan(x); }}\n\
+             static double check({ty} (*f)({ty}), double (*r)(double), double xmin, double xmax) {{\n\
+             \x20   double scale = ldexp(1.0, {n}), e = 0;\n\
+             \x20   long long lo = (long long)ceil(xmin * scale), hi = (long long)floor(xmax * scale);\n\
+             \x20   if (hi > {max}) hi = {max};\n\
+             \x20   long long step = (hi - lo) / 65536 > 1 ? (hi - lo) / 65536 : 1;\n\
+             \x20   for (long long i = lo;; i += step) {{\n\
+             \x20       if (i > hi) i = hi;\n\
+             \x20       double err = fabs(f(({ty})i) - r(i / scale) * scale);\n\
+             \x20       if (err > e) e = err;\n\
+             \x20       if (i == hi) break;\n\
+             \x20   }}\n\
+             \x20   return e;\n\
+             }}\n\
+             int main(void) {{\n\
+             {checks}\
+             \x20   return 0;\n\
+             }}\n",
+            ty = ty,
+            p = prefix,
+            n = format.frac_bits,
+            max = (1i64 << (format.word_bits() - 1)) - 1,
+            checks = checks,
+        );
+        let path = dir.join(format!("{}.c", prefix));
+        let harness_path = dir.join(format!("{}main.c", prefix));
+        let binary = dir.join(&prefix);
+        std::fs::write(&path, &source).unwrap();
+        std::fs::write(&harness_path, harness).unwrap();
+        let output = std::process::Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror"])
+            .arg(&path)
+            .arg(&harness_path)
+            .arg("-lm")
+            .arg("-o")
+            .arg(&binary)
+            .output();
+        match output {
+            Ok(output) => assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                eprintln!("cc not found, not running fixed point C");
+                return;
+            }
+        }
+        let output = std::process::Command::new(&binary).output().unwrap();
+        assert!(output.status.success());
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let (name, error) = line.split_once(' ').unwrap();
+            let error: f64 = error.parse().unwrap();
+            // The bound in the comment before the definition.
+            let definition = format!("{} {}{}({} x) {{", ty, prefix, name, ty);
+            let before = &source[..source.find(&definition).unwrap()];
+            let bound = before.rsplit("error at most ").next().unwrap();
+            let bound: f64 = bound[..bound.find(' ').unwrap()].parse().unwrap();
+            assert!(error <= bound, "{}{} error {} LSB, documented {} LSB", prefix, name, error, bound);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs iverilog"]
+fn test_verilog_simulates() {
+    // The testbench should pass in Icarus Verilog.
+    let dir = std::env::temp_dir().join("libmgen_test_verilog");
+    std::fs::create_dir_all(&dir).unwrap();
+    for text in ["Q0.7", "Q1.14", "Q1.30"] {
+        let args = ["libmgen", "--language", "verilog", "-f", "all", "--fixed-point", text, "--generate-tests"];
+        let config = Config::new(Opt::from_iter(&args));
+        let source = generate(&config, &[]).unwrap().unwrap();
+        assert!(source.contains(&format!("module {}tb;", config.prefix())));
+        let path = dir.join(format!("{}.v", config.prefix()));
+        let binary = path.with_extension("vvp");
+        std::fs::write(&path, source).unwrap();
+        let output = std::process::Command::new("iverilog")
+            .arg("-o")
+            .arg(&binary)
+            .arg(&path)
+            .output()
+            .expect("iverilog not found");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let output = std::process::Command::new("vvp").arg(&binary).output().expect("vvp not found");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("PASS"), "{}\n{}", path.display(), stdout);
+    }
+}
+
+
+#[test]
+#[ignore = "needs numpy and pytest"]
+fn test_numpy_runs() {
+    // The module and its tests should be valid Python and, with NumPy, meet the
+    // limits of the Rust tests except where those need fused multiply-adds.
+    let dir = std::env::temp_dir().join("libmgen_test_numpy");
+    std::fs::create_dir_all(&dir).unwrap();
+    for num_bits in ["32", "64"] {
+        let module = dir.join(format!("ds{}.py", num_bits));
+        let tests = dir.join(format!("test_ds{}.py", num_bits));
+        let args = [
+            "libmgen", "--language", "numpy", "--num-bits", num_bits, "-f", "all",
+            "-o", module.to_str().unwrap(), "--pytest", tests.to_str().unwrap(),
+        ];
+        let config = Config::new(Opt::from_iter(&args));
+        let (names, exclude) = (config.function_names(), Vec::new());
+        let funcs = functions::get_functions_and_deps(&names, &exclude);
+        let source = generate(&config, &funcs).unwrap().unwrap();
+        assert!(source.starts_with("import numpy as np\n"));
+        std::fs::write(&module, source).unwrap();
+        let text = test::gen_pytest(&funcs, &config, &config.python_module());
+        assert!(text.contains(&format!("from ds{} import *\n", num_bits)));
+        assert!(text.contains("@pytest.mark.xfail(reason=\"the limit needs fused multiply-adds\")\ndef test_qnorm_1():"));
+        std::fs::write(&tests, text).unwrap();
+
+        let output = std::process::Command::new("python3")
+            .args(["-m", "py_compile"])
+            .arg(&module)
+            .arg(&tests)
+            .output()
+            .expect("python3 not found");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let output = std::process::Command::new("python3")
+            .args(["-m", "pytest", "-q"])
+            .arg(&tests)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn test_rust_capi_links() {
+    // The crate should build and its entry points should be callable from C.
+    let dir = std::env::temp_dir().join("libmgen_test_rust_capi");
+    for (num_bits, fty, sin) in [("32", "float", "sinf"), ("64", "double", "sin")] {
+        let crate_dir = dir.join(format!("ds{}", num_bits));
+        let args = ["libmgen", "--language", "rust-capi", "--num-bits", num_bits, "-f", "all"];
+        let config = Config::new(Opt::from_iter(&args));
+        let (names, exclude) = (config.function_names(), Vec::new());
+        let funcs = functions::get_functions_and_deps(&names, &exclude);
+        let files = capi::gen_crate(&config, documented_functions(&config, &funcs).unwrap()).unwrap();
+        for (path, text) in files {
+            let path = crate_dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+        let output = std::process::Command::new(cargo)
+            .args(["build", "--release", "--offline", "--quiet"])
+            .current_dir(&crate_dir)
+            .env("CARGO_TARGET_DIR", crate_dir.join("target"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let harness = format!(
+            r#"#include "ds{bits}.h"
+#include <math.h>
+#include <stdio.h>
+
+int main(void) {{
+    {fty} x[5] = {{-1.0, -0.25, 0.0, 0.5, 1.0}}, y[5];
+    ds{bits}_sin_slice(x, y, 5);
+    for (int i = 0; i < 5; ++i) {{
+        if (y[i] != ds{bits}_sin(x[i]) || fabs(y[i] - {sin}(x[i])) > 1e-6) return 1;
+    }}
+    {fty} min[3] = {{0.0, 0.0, 1.0}}, max[3] = {{1.0, 2.0, 2.0}};
+    size_t index[3] = {{0, 1, 2}};
+    ds{bits}_runif_slice(index, min, max, y, 3);
+    for (int i = 0; i < 3; ++i) {{
+        if (y[i] != ds{bits}_runif(index[i], min[i], max[i])) return 2;
+    }}
+    ds{bits}_sin_slice(NULL, NULL, 0);
+    // In place.
+    {fty} z[5] = {{-1.0, -0.25, 0.0, 0.5, 1.0}};
+    ds{bits}_sin_slice(z, z, 5);
+    for (int i = 0; i < 5; ++i) {{
+        if (z[i] != ds{bits}_sin(x[i])) return 3;
+    }}
+    printf("PASS\n");
+    return 0;
+}}
+"#,
+            bits = num_bits,
+            fty = fty,
+            sin = sin
+        );
+        let path = crate_dir.join("harness.c");
+        std::fs::write(&path, harness).unwrap();
+        let binary = crate_dir.join("harness");
+        let output = std::process::Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror", "-o"])
+            .arg(&binary)
+            .arg("-I")
+            .arg(crate_dir.join("include"))
+            .arg(&path)
+            .arg(crate_dir.join("target/release").join(format!("libds{}.a", num_bits)))
+            .args(["-lm", "-lpthread", "-ldl"])
+            .output();
+        match output {
+            Ok(output) => assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                eprintln!("cc not found, not linking the crate from C");
+                return;
+            }
+        }
+        let output = std::process::Command::new(&binary).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "PASS\n", "{}", binary.display());
+    }
+}
+
+#[test]
+fn test_rust_generic_runs() {
+    // Every implementation should be usable from generic code and the vectors should match the scalars.
+    let dir = std::env::temp_dir().join("libmgen_test_rust_generic");
+    std::fs::create_dir_all(&dir).unwrap();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    match std::process::Command::new(&rustc).args(["+nightly", "--version"]).output() {
+        Ok(output) if output.status.success() => (),
+        _ => {
+            eprintln!("nightly rustc not found, not compiling generic Rust");
+            return;
+        }
+    }
+    let args = ["libmgen", "--language", "rust-generic", "-f", "all"];
+    let config = Config::new(Opt::from_iter(&args));
+    let (names, exclude) = (config.function_names(), Vec::new());
+    let funcs = functions::get_functions_and_deps(&names, &exclude);
+    let text = generate(&config, &funcs).unwrap().unwrap();
+    std::fs::write(dir.join("ds.rs"), text).unwrap();
+    let harness = r#"#![feature(portable_simd)]
+mod ds;
+use ds::StdLibm;
+use std::simd::Simd;
+
+fn sin_plus_cos<T: StdLibm + Copy + std::ops::Add<Output = T>>(x: T) -> T {
+    x.sin() + x.cos()
+}
+
+fn main() {
+    let xs: [f64; 8] = [-1.0, -0.25, 0.0, 0.5, 1.0, 2.0, 3.0, 7.5];
+    for x in xs {
+        assert!((sin_plus_cos(x) - (x.sin() + x.cos())).abs() < 1e-15, "{}", x);
+        let x32 = x as f32;
+        assert!((sin_plus_cos(x32) - (x32.sin() + x32.cos())).abs() < 1e-6, "{}", x);
+    }
+    let v64 = sin_plus_cos(Simd::<f64, 4>::from_slice(&xs[..4]));
+    let v32 = <Simd<f32, 8> as StdLibm>::atan2(Simd::from_array(xs.map(|x| x as f32)), Simd::splat(2.0));
+    let r32 = <Simd<f32, 8> as StdLibm>::runif(Simd::from_array([0, 1, 2, 3, 4, 5, 6, 7]), Simd::splat(0.0), Simd::splat(1.0));
+    for i in 0..4 {
+        assert_eq!(v64[i].to_bits(), sin_plus_cos(xs[i]).to_bits());
+    }
+    for i in 0..8 {
+        assert_eq!(v32[i].to_bits(), StdLibm::atan2(xs[i] as f32, 2.0).to_bits());
+        assert_eq!(r32[i].to_bits(), <f32 as StdLibm>::runif(i as u32, 0.0, 1.0).to_bits());
+    }
+    println!("PASS");
+}
+"#;
+    let path = dir.join("main.rs");
+    std::fs::write(&path, harness).unwrap();
+    let binary = dir.join("main");
+    let output = std::process::Command::new(&rustc)
+        .args(["+nightly", "--edition", "2021", "-O", "-o"])
+        .arg(&binary)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = std::process::Command::new(&binary).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "PASS\n", "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_rust_no_std_matches_std() {
+    // The software functions round correctly so the results should be the same bits as std.
+    use doctor_syn::codegen::no_std::{to_no_std, Fma, Options};
+    let dir = std::env::temp_dir().join("libmgen_test_rust_no_std");
+    std::fs::create_dir_all(&dir).unwrap();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let unary = [
+        "sin", "cos", "tan", "exp", "exp2", "ln", "log2", "log10", "sqrt", "cbrt", "asin", "atan",
+        "tanh",
+    ];
+    for (num_bits, fma) in [("32", "software"), ("64", "software"), ("64", "hardware")] {
+        let fty = format!("f{}", num_bits);
+        let std_args = ["libmgen", "--num-bits", num_bits, "-f", "all"];
+        let no_std_args = [
+            "libmgen",
+            "--num-bits",
+            num_bits,
+            "-f",
+            "all",
+            "--no-std",
+            "--fma",
+            fma,
+        ];
+        for (args, name) in [
+            (&std_args[..], "with_std.rs"),
+            (&no_std_args[..], "without_std.rs"),
+        ] {
+            let config = Config::new(Opt::from_iter(args));
+            let (names, exclude) = (config.function_names(), Vec::new());
+            let funcs = functions::get_functions_and_deps(&names, &exclude);
+            std::fs::write(dir.join(name), generate(&config, &funcs).unwrap().unwrap()).unwrap();
+        }
+        let methods: syn::File = syn::parse_str(&format!(
+            "type fty = {};
+            pub fn mul_add(a: fty, b: fty, c: fty) -> fty {{ a.mul_add(b, c) }}
+            pub fn sqrt(x: fty) -> fty {{ x.sqrt() }}
+            pub fn round(x: fty) -> fty {{ x.round() }}
+            pub fn floor(x: fty) -> fty {{ x.floor() }}
+            pub fn ceil(x: fty) -> fty {{ x.ceil() }}
+            pub fn copysign(x: fty, y: fty) -> fty {{ x.copysign(y) }}",
+            fty
+        ))
+        .unwrap();
+        let options = Options {
+            num_bits: fty[1..].parse().unwrap(),
+            fma: if fma == "hardware" {
+                Fma::Hardware
+            } else {
+                Fma::Software
+            },
+        };
+        let text = doctor_syn::codegen::rust::format_token_stream(
+            to_no_std(&methods, &options).into_token_stream(),
+        );
+        std::fs::write(dir.join("methods.rs"), text).unwrap();
+        let calls = unary
+            .iter()
+            .map(|f| {
+                format!(
+                    "        same(with_std::{f}(x), without_std::{f}(x), \"{f}\", x, x, x);\n",
+                    f = f
+                )
+            })
+            .collect::<String>();
+        let harness = format!(
+            r#"#![allow(dead_code, non_snake_case)]
+mod with_std;
+mod without_std;
+mod methods;
+
+type F = {fty};
+
+fn same(a: F, b: F, name: &str, x: F, y: F, z: F) {{
+    assert!(a.to_bits() == b.to_bits() || a.is_nan() && b.is_nan(), "{{}}({{:e}}, {{:e}}, {{:e}}) is {{:e}} not {{:e}}", name, x, y, z, b, a);
+}}
+
+fn main() {{
+    let mut state = 0x9e3779b97f4a7c15u64;
+    let mut random = || {{
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        F::from_bits(state as _)
+    }};
+    let special = [0.0, -0.0, 1.0, -1.0, 0.5, 2.5, -2.5, F::MIN_POSITIVE, F::MIN_POSITIVE / 3.0, F::MAX, F::INFINITY, F::NEG_INFINITY, F::NAN];
+    let mut values = special.to_vec();
+    values.extend((0..200000).map(|_| random()));
+    for w in values.windows(3) {{
+        let (x, y, z) = (w[0], w[1], w[2]);
+        same(x.mul_add(y, z), methods::mul_add(x, y, z), "mul_add", x, y, z);
+        // Cancellation and products near the subnormals.
+        same(x.mul_add(y, -(x * y)), methods::mul_add(x, y, -(x * y)), "mul_add", x, y, -(x * y));
+        let t = x * F::MIN_POSITIVE.sqrt();
+        same(t.mul_add(y, z * F::MIN_POSITIVE), methods::mul_add(t, y, z * F::MIN_POSITIVE), "mul_add", t, y, z);
+        same(x.sqrt(), methods::sqrt(x), "sqrt", x, x, x);
+        same(x.round(), methods::round(x), "round", x, x, x);
+        same(x.floor(), methods::floor(x), "floor", x, x, x);
+        same(x.ceil(), methods::ceil(x), "ceil", x, x, x);
+        same(x.copysign(y), methods::copysign(x, y), "copysign", x, y, y);
+    }}
+    for i in 0..20000 {{
+        let x = (i as F - 10000.0) / 1000.0;
+{calls}        same(with_std::atan2(x, 0.5), without_std::atan2(x, 0.5), "atan2", x, 0.5, 0.5);
+    }}
+    println!("PASS");
+}}
+"#,
+            fty = fty,
+            calls = calls
+        );
+        let path = dir.join("main.rs");
+        std::fs::write(&path, harness).unwrap();
+        let binary = dir.join("main");
+        let output = std::process::Command::new(&rustc)
+            .args(["--edition", "2021", "-O", "-C", "target-cpu=native", "-o"])
+            .arg(&binary)
+            .arg(&path)
+            .output();
+        match output {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(_) => {
+                eprintln!("rustc not found, not comparing no_std Rust");
+                return;
+            }
+        }
+        let output = std::process::Command::new(&binary).output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "PASS\n",
+            "{} {}: {}",
+            num_bits,
+            fma,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
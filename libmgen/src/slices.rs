@@ -0,0 +1,168 @@
+//! Slice kernels for the portable-simd output.
+//!
+//! Each public function whose parameters and result are floats gets a
+//! `name_slice` kernel writing to an output slice and a `name_slice_in_place`
+//! kernel overwriting its first argument. The kernels call the `StdLibm`
+//! methods on `LANES` elements at a time and the scalar functions, in a
+//! `scalar` module, on the remainder. Functions taking integers, such as
+//! `runif`, `rnorm` and `powi`, have no kernels.
+
+use crate::Config;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+
+/// The parameter names of a public `fn(fty, ..) -> fty`.
+fn float_params(item: &syn::ItemFn) -> Option<Vec<syn::Ident>> {
+    let is_fty = |ty: &syn::Type| matches!(ty, syn::Type::Path(path) if path.path.is_ident("fty"));
+    if !matches!(item.vis, syn::Visibility::Public(_)) {
+        return None;
+    }
+    match &item.sig.output {
+        syn::ReturnType::Type(_, ty) if is_fty(ty) => (),
+        _ => return None,
+    }
+    item.sig
+        .inputs
+        .iter()
+        .map(|input| match input {
+            syn::FnArg::Typed(syn::PatType { pat, ty, .. }) if is_fty(ty) => match &**pat {
+                syn::Pat::Ident(ident) => Some(ident.ident.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// The kernels of a function with these parameters.
+fn gen_kernels(name: &syn::Ident, params: &[syn::Ident]) -> TokenStream {
+    let slice = format_ident!("{}_slice", name);
+    let in_place = format_ident!("{}_slice_in_place", name);
+    // A single argument is the input.
+    let inputs = match params {
+        [_] => vec![format_ident!("input")],
+        params => params.to_vec(),
+    };
+    let (first, rest) = (&params[0], &params[1..]);
+    let docs = format!(
+        " Apply `{}` to `{}`, writing the results to `output`.",
+        name,
+        inputs
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("`, `")
+    );
+    let in_place_docs = format!(" Apply `{}` to `{}`, overwriting it.", name, first);
+    let panics = " If the slices have different lengths.";
+    quote! {
+        #[doc = #docs]
+        ///
+        /// # Panics
+        ///
+        #[doc = #panics]
+        pub fn #slice(#(#inputs: &[fty],)* output: &mut [fty]) {
+            let len = output.len();
+            #(assert_eq!(#inputs.len(), len);)*
+            let split = len - len % LANES;
+            for i in (0..split).step_by(LANES) {
+                let result = <V as StdLibm>::#name(#(V::from_slice(&#inputs[i..i + LANES])),*);
+                result.copy_to_slice(&mut output[i..i + LANES]);
+            }
+            for i in split..len {
+                output[i] = scalar::#name(#(#inputs[i]),*);
+            }
+        }
+
+        #[doc = #in_place_docs]
+        ///
+        /// # Panics
+        ///
+        #[doc = #panics]
+        pub fn #in_place(#first: &mut [fty] #(, #rest: &[fty])*) {
+            let len = #first.len();
+            #(assert_eq!(#rest.len(), len);)*
+            let split = len - len % LANES;
+            for i in (0..split).step_by(LANES) {
+                let result = <V as StdLibm>::#name(#(V::from_slice(&#params[i..i + LANES])),*);
+                result.copy_to_slice(&mut #first[i..i + LANES]);
+            }
+            for i in split..len {
+                #first[i] = scalar::#name(#(#params[i]),*);
+            }
+        }
+    }
+}
+
+/// The scalar functions in a `scalar` module and their kernels in a `slices` module.
+pub fn gen_slices(config: &Config, file: &syn::File) -> TokenStream {
+    let items = &file.items;
+    let kernels = file.items.iter().filter_map(|item| match item {
+        syn::Item::Fn(item) => {
+            float_params(item).map(|params| gen_kernels(&item.sig.ident, &params))
+        }
+        _ => None,
+    });
+    let fty = format_ident!("f{}", config.num_bits());
+    let lanes = config.lanes();
+    quote! {
+        #[allow(dead_code, clippy::all)]
+        pub mod scalar {
+            #(#items)*
+        }
+
+        #[allow(clippy::needless_range_loop)]
+        pub mod slices {
+            use super::{scalar, Simd, StdLibm};
+
+            #[allow(non_camel_case_types)]
+            type fty = #fty;
+
+            /// The number of elements computed together.
+            pub const LANES: usize = #lanes;
+
+            type V = Simd<fty, LANES>;
+
+            #(#kernels)*
+        }
+    }
+}
+
+#[test]
+fn test_kernels() {
+    let file: syn::File = syn::parse_quote! {
+        #[allow(non_camel_case_types)]
+        type fty = f32;
+        fn round(x: fty) -> fty { x }
+        pub fn sin(arg: fty) -> fty { arg }
+        pub fn atan2(y: fty, x: fty) -> fty { y / x }
+        pub fn runif(index: usize, min: fty, max: fty) -> fty { min }
+    };
+    let args = [
+        "libmgen",
+        "--language",
+        "portable-simd",
+        "--num-bits",
+        "32",
+        "--slices",
+        "-f",
+        "sin",
+    ];
+    let config = Config::new(structopt::StructOpt::from_iter(args));
+    let text = gen_slices(&config, &file).to_string();
+    assert!(text.contains("pub mod scalar { # [allow (non_camel_case_types)] type fty = f32 ;"));
+    assert!(text.contains("pub const LANES : usize = 8usize ;"));
+    assert!(text.contains("pub fn sin_slice (input : & [fty] , output : & mut [fty]) {"));
+    assert!(text.contains(
+        "let result = < V as StdLibm > :: sin (V :: from_slice (& input [i .. i + LANES])) ;"
+    ));
+    assert!(text.contains("output [i] = scalar :: sin (input [i]) ;"));
+    assert!(text.contains("pub fn sin_slice_in_place (arg : & mut [fty]) {"));
+    assert!(
+        text.contains("pub fn atan2_slice (y : & [fty] , x : & [fty] , output : & mut [fty]) {")
+    );
+    assert!(text.contains("pub fn atan2_slice_in_place (y : & mut [fty] , x : & [fty]) {"));
+    assert!(text.contains("y [i] = scalar :: atan2 (y [i] , x [i]) ;"));
+    assert!(!text.contains("round_slice"));
+    assert!(!text.contains("runif_slice"));
+}
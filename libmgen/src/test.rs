@@ -5,25 +5,15 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote, ToTokens};
 use syn::{parse_quote, Expr};
 
-/// Generate a set of accurate points within a range and
-/// compare the result with these values. The result should
-/// be accurate to a certain value scaled so that 1.0 is the LSB
-/// of 0.5..1
-fn gen_max_abs(
-    t: &TestSpec,
-    config: &Config,
-    min: &str,
-    max: &str,
-    bits32: f64,
-    bits64: f64,
-    n: usize,
-) -> TokenStream {
+/// Generate a set of accurate points within a range as
+/// (x, y rounded, rounding error) tuples.
+fn accurate_values(t: &TestSpec, config: &Config, min: &str, max: &str, n: usize) -> Vec<(Expr, Expr, Expr)> {
     let num_digits = config.num_digits();
     use std::str::FromStr;
     let refexpr = TokenStream::from_str(&t.ref_expr).unwrap();
     let refe: Expression = syn::parse2::<syn::Expr>(refexpr.clone()).unwrap().into();
     let variable = name!(x);
-    let mut accurate_values = TokenStream::new();
+    let mut accurate_values = Vec::new();
     let tmin = TokenStream::from_str(min).unwrap();
     let tmax = TokenStream::from_str(max).unwrap();
     let bits = config.num_bits();
@@ -50,21 +40,52 @@ fn gen_max_abs(
 
             // println!("{} {}", y.to_token_stream(), ye.to_token_stream());
 
-            let row = quote!((#x, #y, #ye),);
-            accurate_values.extend(row.into_iter());
+            accurate_values.push((x, y, ye));
         } else {
             panic!("subst failure building test {}", t.test_name);
         }
     }
+    accurate_values
+}
 
-    let test_name = format_ident!("{}", t.test_name);
-    let test_name_str = t.test_name;
-    let expr = TokenStream::from_str(&t.rust_expr).unwrap();
-    let accuracy = if config.num_bits() == 32 {
+/// The accurate values as a list of Rust tuples.
+fn gen_accurate_values(t: &TestSpec, config: &Config, min: &str, max: &str, n: usize) -> TokenStream {
+    let mut rows = TokenStream::new();
+    for (x, y, ye) in accurate_values(t, config, min, max, n) {
+        let row = quote!((#x, #y, #ye),);
+        rows.extend(row.into_iter());
+    }
+    rows
+}
+
+/// The permitted error, scaled so that 1.0 is the LSB of 0.5..1
+fn accuracy(config: &Config, bits32: f64, bits64: f64) -> f64 {
+    if config.num_bits() == 32 {
         bits32 * 2.0_f64.powi(-23)
     } else {
         bits64 * 2.0_f64.powi(-53)
-    };
+    }
+}
+
+/// Generate a set of accurate points within a range and
+/// compare the result with these values. The result should
+/// be accurate to a certain value scaled so that 1.0 is the LSB
+/// of 0.5..1
+fn gen_max_abs(
+    t: &TestSpec,
+    config: &Config,
+    min: &str,
+    max: &str,
+    bits32: f64,
+    bits64: f64,
+    n: usize,
+) -> TokenStream {
+    let accurate_values = gen_accurate_values(t, config, min, max, n);
+    let test_name = format_ident!("{}", t.test_name);
+    let test_name_str = t.test_name;
+    use std::str::FromStr;
+    let expr = TokenStream::from_str(&t.rust_expr).unwrap();
+    let accuracy = accuracy(config, bits32, bits64);
 
     let plot_function = if config.generate_plots() {
         quote!{
@@ -140,3 +161,325 @@ pub fn gen_test(t: &TestSpec, config: &Config) -> TokenStream {
         }
     }
 }
+
+/// The SIMD and scalar versions of a test expression such as `log(x, 10.0 as fty)`.
+/// `x` and the random index `i` are vectors in the SIMD version, other arguments are splatted.
+fn simd_exprs(t: &TestSpec) -> (TokenStream, TokenStream) {
+    let call = match syn::parse_str::<Expr>(t.rust_expr) {
+        Ok(Expr::Call(call)) => call,
+        _ => panic!("{}: expected a function call, found {}", t.test_name, t.rust_expr),
+    };
+    let func = &call.func;
+    let args = call.args.iter().map(|arg| match arg.to_token_stream().to_string().as_str() {
+        "x" => quote!(x),
+        "i" => quote!(index),
+        _ => quote!(V::splat(#arg)),
+    });
+    let scalar_args = call.args.iter();
+    (
+        quote!(<V as StdLibm>::#func(#(#args),*)),
+        quote!(scalar::#func(#(#scalar_args),*)),
+    )
+}
+
+/// Check every lane against the accurate values and the scalar function.
+fn gen_simd_max_abs(
+    t: &TestSpec,
+    config: &Config,
+    min: &str,
+    max: &str,
+    bits32: f64,
+    bits64: f64,
+    n: usize,
+) -> TokenStream {
+    let accurate_values = gen_accurate_values(t, config, min, max, n);
+    let test_name = format_ident!("{}", t.test_name);
+    let test_name_str = t.test_name;
+    let (simd_expr, scalar_expr) = simd_exprs(t);
+    let accuracy = accuracy(config, bits32, bits64);
+    quote!(
+        #[test]
+        pub fn #test_name() {
+            let accurate_values : &[(fty, fty, fty)] = &[#accurate_values];
+            test_function(#test_name_str, accurate_values, #accuracy as fty, |x| #simd_expr, |x| #scalar_expr);
+        }
+    )
+}
+
+/// Generate a histogram from the lanes of a random function
+/// and check it against the PDF and the scalar function.
+fn gen_simd_histogram(t: &TestSpec, min: &str, max: &str) -> TokenStream {
+    let nbuckets = 32_usize;
+    let niter = 1000000_usize;
+    use std::str::FromStr;
+    let refexpr = TokenStream::from_str(t.ref_expr).unwrap();
+    let tmin = TokenStream::from_str(min).unwrap();
+    let tmax = TokenStream::from_str(max).unwrap();
+    let test_name = format_ident!("{}", t.test_name);
+    let (simd_expr, scalar_expr) = simd_exprs(t);
+    quote!(
+        #[test]
+        pub fn #test_name() {
+            // The reference PDF uses the scalar functions.
+            use scalar::*;
+            let mut h = [0; #nbuckets];
+            for i in (0..#niter).step_by(LANES) {
+                let index = Simd::from_array(core::array::from_fn(|lane| (i + lane) as uty));
+                let ys = #simd_expr;
+                for lane in 0..LANES {
+                    let i = i + lane;
+                    assert_eq!(ys[lane].to_bits(), #scalar_expr.to_bits(), "lane {} index {}", lane, i);
+                    let y = ys[lane] as f64;
+                    let idx = ((y - #tmin) / (#tmax - #tmin) * #nbuckets as f64).floor() as isize;
+                    if idx >= 0 && idx < (#nbuckets) as isize {
+                        h[idx as usize] += 1;
+                    }
+                }
+            }
+            let dx = (#tmax - #tmin) as f64 / (#nbuckets) as f64;
+            let mut max_err : f64 = 0.0;
+            for i in 0..#nbuckets {
+                let x = (((i as f64 + 0.5) / #nbuckets as f64) * (#tmax - #tmin) + #tmin) as fty;
+                let pdf_est = h[i] as f64 / ((#niter) as f64 * dx);
+                let pdf_ref = (#refexpr) as f64;
+                println!("{} {} {}", x, pdf_est, pdf_ref);
+                max_err = (pdf_est - pdf_ref).abs();
+            }
+            println!("max err = {}", max_err);
+            assert!(max_err < 0.001);
+        }
+    )
+}
+
+/// Generate tests for the portable SIMD methods, comparing each lane with
+/// the scalar functions in `file` bit for bit.
+pub fn gen_simd_tests(file: &syn::File, funcs: &[&crate::functions::Function], config: &Config) -> TokenStream {
+    let mut file = file.clone();
+    for item in &mut file.items {
+        if let syn::Item::Fn(f) = item {
+            f.vis = parse_quote!(pub(super));
+        }
+    }
+    let items = &file.items;
+
+    let mut tests = TokenStream::new();
+    for f in funcs {
+        for t in f.test_specs {
+            tests.extend(match t.test {
+                TestType::MaxAbs(min, max, bits32, bits64, n) => {
+                    gen_simd_max_abs(t, config, min, max, bits32, bits64, n)
+                }
+                TestType::Histogram(min, max) => gen_simd_histogram(t, min, max),
+            });
+        }
+    }
+
+    let fty = format_ident!("f{}", config.num_bits());
+    let uty = format_ident!("u{}", config.num_bits());
+    quote! {
+        #[cfg(test)]
+        #[allow(non_camel_case_types)]
+        mod tests {
+            use super::*;
+
+            type fty = #fty;
+            type uty = #uty;
+            const LANES: usize = 4;
+            type V = Simd<fty, LANES>;
+
+            #[allow(dead_code, clippy::all)]
+            mod scalar {
+                #(#items)*
+            }
+
+            // Each lane gets a different value and every value visits every lane.
+            fn test_function<F : Fn(V) -> V, G : Fn(fty) -> fty>(test_name: &str, accurate_values: &[(fty, fty, fty)], limit: fty, f: F, g: G) {
+                let n = accurate_values.len();
+                let mut max_ref_error : fty = 0.0;
+                let mut bad_x : fty = 0.0;
+                for i in 0..n {
+                    let xs = V::from_array(core::array::from_fn(|lane| accurate_values[(i + lane) % n].0));
+                    let ys = f(xs);
+                    for lane in 0..LANES {
+                        let (x, yref, yerr) = accurate_values[(i + lane) % n];
+                        let ycalc = ys[lane];
+                        let yscalar = g(x);
+                        assert!(
+                            ycalc.to_bits() == yscalar.to_bits() || ycalc.is_nan() && yscalar.is_nan(),
+                            "{}: lane {} of x={} is {} but the scalar function gives {}", test_name, lane, x, ycalc, yscalar
+                        );
+                        let eref = (ycalc - yref - yerr). abs ();
+                        if eref > max_ref_error {
+                            max_ref_error = eref;
+                            bad_x = x;
+                        }
+                    }
+                }
+                println!("{}:", test_name);
+                println!("max_ref_error x 2^53   = {:7.2}", max_ref_error * (2.0 as fty).powi(53));
+                println!("limit         x 2^53   = {:7.2}", limit * (2.0 as fty).powi(53));
+                println!("x    ={:016x}            {:25.20}", bad_x.to_bits(), bad_x);
+                assert ! (! max_ref_error . is_nan ());
+                assert ! (max_ref_error <= limit);
+            }
+
+            #tests
+        }
+    }
+}
+
+/// A test expression such as `log(x, 10.0 as fty)` in Python, where `x` and
+/// the random index `i` are arrays and other arguments are floats.
+fn python_expr(expr: &str, prefix: &str) -> String {
+    fn arg(arg: &Expr) -> String {
+        match arg {
+            Expr::Path(_) => arg.to_token_stream().to_string(),
+            Expr::Cast(cast) => arg_value(&cast.expr),
+            _ => arg_value(arg),
+        }
+    }
+    fn arg_value(value: &Expr) -> String {
+        let text = value.to_token_stream().to_string().replace("- ", "-");
+        format!("fty({})", text)
+    }
+    match syn::parse_str::<Expr>(expr) {
+        Ok(Expr::Call(call)) => {
+            let func = call.func.to_token_stream();
+            let args = call.args.iter().map(arg).collect::<Vec<_>>();
+            format!("{}{}({})", prefix, func, args.join(", "))
+        }
+        Ok(other) => other.to_token_stream().to_string(),
+        Err(_) => panic!("expected a test expression, found {}", expr),
+    }
+}
+
+/// A Rust literal from the accurate values as a Python float.
+fn python_number(value: &Expr) -> String {
+    value.to_token_stream().to_string().replace("- ", "-")
+}
+
+/// A pytest test comparing a function with the accurate values.
+fn gen_pytest_max_abs(
+    t: &TestSpec,
+    config: &Config,
+    min: &str,
+    max: &str,
+    bits32: f64,
+    bits64: f64,
+    n: usize,
+) -> String {
+    use std::fmt::Write;
+    let mut text = String::new();
+    let _ = writeln!(text, "def {}():", t.test_name);
+    text.push_str("    accurate_values = [\n");
+    for (x, y, ye) in accurate_values(t, config, min, max, n) {
+        let _ = writeln!(text, "        ({}, {}, {}),", python_number(&x), python_number(&y), python_number(&ye));
+    }
+    text.push_str("    ]\n");
+    let accuracy = accuracy(config, bits32, bits64);
+    let expr = python_expr(t.rust_expr, &config.prefix());
+    let _ = writeln!(text, "    check(\"{}\", accurate_values, {:?}, lambda x: {})", t.test_name, accuracy, expr);
+    text
+}
+
+/// A pytest histogram of a random function checked against the PDF.
+fn gen_pytest_histogram(t: &TestSpec, config: &Config, min: &str, max: &str) -> String {
+    let nbuckets = 32_usize;
+    let niter = 1000000_usize;
+    let expr = python_expr(t.rust_expr, &config.prefix());
+    let refexpr = python_expr(t.ref_expr, &config.prefix());
+    format!(
+        r#"def {name}():
+    i = np.arange({niter}, dtype=uty)
+    with np.errstate(all="ignore"):
+        y = np.asarray({expr}, dtype=np.float64)
+    idx = np.floor((y - {min}) / ({max} - {min}) * {nbuckets})
+    h = np.bincount(idx[(idx >= 0) & (idx < {nbuckets})].astype(np.intp), minlength={nbuckets})
+    dx = ({max} - {min}) / {nbuckets}
+    x = (((np.arange({nbuckets}) + 0.5) / {nbuckets}) * ({max} - {min}) + {min}).astype(fty)
+    pdf_est = h / ({niter} * dx)
+    pdf_ref = np.asarray({refexpr}, dtype=np.float64)
+    # As in the Rust tests, which compare the last bucket.
+    max_err = np.abs(pdf_est - pdf_ref)[-1]
+    print("max err =", max_err)
+    assert max_err < 0.001
+"#,
+        name = t.test_name,
+        niter = niter,
+        nbuckets = nbuckets,
+        expr = expr,
+        refexpr = refexpr,
+        min = min,
+        max = max,
+    )
+}
+
+/// Tests whose limits are not met when the multiplies and adds are rounded
+/// separately, as NumPy does, found with the Rust tests and `--fma unfused`.
+const UNFUSED_FAILURES_32: &[&str] = &["test_qnorm_1", "test_rnorm"];
+const UNFUSED_FAILURES_64: &[&str] = &[
+    "test_acos", "test_acos2", "test_asin", "test_cos", "test_cos2", "test_cos3", "test_dnorm_1",
+    "test_exp2_1", "test_exp2_2", "test_exp_1", "test_exp_2", "test_exp_3", "test_exp_m1_1",
+    "test_ln_1", "test_ln_1p_1", "test_ln_2", "test_log2_1", "test_log2_2", "test_log_1",
+    "test_qnorm_1", "test_rnorm", "test_sin", "test_tan", "test_tan2",
+];
+
+/// An expected failure mark for tests that unfused evaluation can't pass.
+fn pytest_mark(t: &TestSpec, config: &Config) -> &'static str {
+    let failures = if config.num_bits() == 32 { UNFUSED_FAILURES_32 } else { UNFUSED_FAILURES_64 };
+    if failures.contains(&t.test_name) {
+        "@pytest.mark.xfail(reason=\"the limit needs fused multiply-adds\")\n"
+    } else {
+        ""
+    }
+}
+
+/// Generate pytest tests of the NumPy module `module` with the same accurate
+/// values and limits as the Rust tests.
+pub fn gen_pytest(funcs: &[&crate::functions::Function], config: &Config, module: &str) -> String {
+    let mut tests = Vec::new();
+    for f in funcs {
+        for t in f.test_specs {
+            let test = match t.test {
+                TestType::MaxAbs(min, max, bits32, bits64, n) => {
+                    gen_pytest_max_abs(t, config, min, max, bits32, bits64, n)
+                }
+                TestType::Histogram(min, max) => gen_pytest_histogram(t, config, min, max),
+            };
+            tests.push(format!("{}{}", pytest_mark(t, config), test));
+        }
+    }
+
+    let bits = config.num_bits();
+    format!(
+        r#"import numpy as np
+import pytest
+
+from {module} import *
+
+fty = np.float{bits}
+uty = np.uint{bits}
+
+
+def check(test_name, accurate_values, limit, f):
+    values = np.array(accurate_values, dtype=fty)
+    x, yref, yerr = values[:, 0], values[:, 1], values[:, 2]
+    with np.errstate(all="ignore"):
+        ycalc = np.asarray(f(x), dtype=fty)
+        eref = np.abs(ycalc - yref - yerr)
+    # As in the Rust tests, NaN errors are not counted.
+    eref = np.where(np.isnan(eref), fty(0), eref)
+    worst = np.argmax(eref)
+    max_ref_error = eref[worst]
+    print(f"{{test_name}}:")
+    print(f"max_ref_error x 2^53   = {{max_ref_error * 2.0**53:7.2f}}")
+    print(f"limit         x 2^53   = {{limit * 2.0**53:7.2f}}")
+    print(f"x    = {{x[worst]!r}} ycalc = {{ycalc[worst]!r}} yref = {{yref[worst]!r}}")
+    assert max_ref_error <= fty(limit)
+
+{tests}"#,
+        module = module,
+        bits = bits,
+        tests = tests.iter().map(|t| format!("\n{}", t)).collect::<Vec<_>>().join("\n"),
+    )
+}
@@ -0,0 +1,364 @@
+//! Pipelined Verilog for the fixed point functions.
+//!
+//! Each function becomes a module with one pipeline stage per Horner step
+//! so it accepts a new argument every clock cycle. The arithmetic is the same
+//! as the Rust and C fixed point functions, bit for bit.
+
+use crate::fixed::{best_approx, Approx, FixedFunction, Format};
+use doctor_syn::bigdecimal::BigDecimal;
+use doctor_syn::{name, Expression, Parity, VariableList};
+use num_traits::ToPrimitive;
+use std::convert::TryInto;
+use std::fmt::Write;
+
+/// A sized signed literal.
+fn lit(value: i64, bits: u32) -> String {
+    let min = -1_i64 << (bits - 1);
+    if value == min {
+        // The magnitude does not fit.
+        format!("{}'sh{:x}", bits, 1_u64 << (bits - 1))
+    } else if value < 0 {
+        format!("-{}'sd{}", bits, -value)
+    } else {
+        format!("{}'sd{}", bits, value)
+    }
+}
+
+/// Clock cycles from the argument to the result.
+fn latency(approx: &Approx) -> usize {
+    approx.coeffs.len() + 1
+}
+
+fn gen_helpers(text: &mut String, format: Format) {
+    let w = format.word_bits();
+    let msb = w - 1;
+    let wide_msb = 2 * w - 1;
+    writeln!(text, "    // Saturate a double width value to {} bits.", w).unwrap();
+    writeln!(
+        text,
+        "    function signed [{}:0] qsat(input signed [{}:0] v);",
+        msb, wide_msb
+    )
+    .unwrap();
+    writeln!(text, "        begin").unwrap();
+    writeln!(text, "            if (v > {})", lit(format.max(), 2 * w)).unwrap();
+    writeln!(text, "                qsat = {};", lit(format.max(), w)).unwrap();
+    writeln!(
+        text,
+        "            else if (v < {})",
+        lit(format.min(), 2 * w)
+    )
+    .unwrap();
+    writeln!(text, "                qsat = {};", lit(format.min(), w)).unwrap();
+    writeln!(text, "            else").unwrap();
+    writeln!(text, "                qsat = v[{}:0];", msb).unwrap();
+    writeln!(text, "        end").unwrap();
+    writeln!(text, "    endfunction").unwrap();
+    writeln!(text).unwrap();
+    writeln!(text, "    // Add with saturation.").unwrap();
+    writeln!(
+        text,
+        "    function signed [{}:0] qadd(input signed [{}:0] a, input signed [{}:0] b);",
+        msb, msb, msb
+    )
+    .unwrap();
+    writeln!(text, "        reg signed [{}:0] s;", wide_msb).unwrap();
+    writeln!(text, "        begin").unwrap();
+    writeln!(text, "            s = a;").unwrap();
+    writeln!(text, "            s = s + b;").unwrap();
+    writeln!(text, "            qadd = qsat(s);").unwrap();
+    writeln!(text, "        end").unwrap();
+    writeln!(text, "    endfunction").unwrap();
+    writeln!(text).unwrap();
+    writeln!(
+        text,
+        "    // Multiply and shift right by at least one bit, rounding to nearest."
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "    function signed [{}:0] qmul(input signed [{}:0] a, input signed [{}:0] b, input integer shift);",
+        msb, msb, msb
+    )
+    .unwrap();
+    writeln!(text, "        reg signed [{}:0] p;", wide_msb).unwrap();
+    writeln!(text, "        begin").unwrap();
+    writeln!(text, "            p = a;").unwrap();
+    writeln!(text, "            p = p * b;").unwrap();
+    writeln!(text, "            p = p >>> (shift - 1);").unwrap();
+    writeln!(text, "            p = p + {};", lit(1, 2 * w)).unwrap();
+    writeln!(text, "            qmul = qsat(p >>> 1);").unwrap();
+    writeln!(text, "        end").unwrap();
+    writeln!(text, "    endfunction").unwrap();
+}
+
+fn gen_module(text: &mut String, f: &FixedFunction, format: Format, approx: &Approx, prefix: &str) {
+    let w = format.word_bits();
+    let msb = w - 1;
+    let n = format.frac_bits;
+    let vf = approx.arg_frac_bits;
+    let fb = &approx.frac_bits;
+    let k = approx.coeffs.len() - 1;
+    let odd = f.parity == Parity::Odd;
+
+    writeln!(
+        text,
+        "// {} with x and the result in {}.",
+        f.description, format
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "// Domain: {} <= x <= {}, error at most {} LSB.",
+        f.xmin,
+        f.xmax,
+        approx.max_error.ceil()
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "// The result is ready {} clock cycles after x.",
+        latency(approx)
+    )
+    .unwrap();
+    writeln!(text, "module {}{} (", prefix, f.name).unwrap();
+    writeln!(text, "    input wire clk,").unwrap();
+    writeln!(text, "    input wire signed [{}:0] x,", msb).unwrap();
+    writeln!(text, "    output reg signed [{}:0] y", msb).unwrap();
+    writeln!(text, ");").unwrap();
+    gen_helpers(text, format);
+    writeln!(text).unwrap();
+
+    // v is the polynomial variable, x or x*x, a the Horner accumulator.
+    writeln!(text, "    reg signed [{}:0] v0;", msb).unwrap();
+    for j in 1..k {
+        writeln!(text, "    reg signed [{}:0] v{}, a{};", msb, j, j).unwrap();
+    }
+    if k > 0 {
+        writeln!(text, "    reg signed [{}:0] a{};", msb, k).unwrap();
+    }
+    if odd {
+        let xs: Vec<String> = (0..=k).map(|j| format!("x{}", j)).collect();
+        writeln!(text, "    reg signed [{}:0] {};", msb, xs.join(", ")).unwrap();
+    }
+    writeln!(text).unwrap();
+
+    writeln!(text, "    always @(posedge clk) begin").unwrap();
+    match f.parity {
+        Parity::Neither => writeln!(text, "        v0 <= x;").unwrap(),
+        _ => writeln!(text, "        v0 <= qmul(x, x, {});", 2 * n - vf).unwrap(),
+    }
+    if odd {
+        writeln!(text, "        x0 <= x;").unwrap();
+    }
+    for j in 1..=k {
+        let i = k - j;
+        let acc = if j == 1 {
+            lit(approx.coeffs[k], w)
+        } else {
+            format!("a{}", j - 1)
+        };
+        let shift = vf + fb[i + 1] - fb[i];
+        writeln!(text, "        // Stage {}.", j).unwrap();
+        writeln!(
+            text,
+            "        a{} <= qadd(qmul({}, v{}, {}), {});",
+            j,
+            acc,
+            j - 1,
+            shift,
+            lit(approx.coeffs[i], w)
+        )
+        .unwrap();
+        if j < k {
+            writeln!(text, "        v{} <= v{};", j, j - 1).unwrap();
+        }
+        if odd {
+            writeln!(text, "        x{} <= x{};", j, j - 1).unwrap();
+        }
+    }
+    let acc = if k == 0 {
+        lit(approx.coeffs[0], w)
+    } else {
+        format!("a{}", k)
+    };
+    let result = match f.parity {
+        Parity::Odd => format!("qmul({}, x{}, {})", acc, k, fb[0]),
+        _ if fb[0] > n => format!("qmul({}, {}, {})", acc, lit(1, w), fb[0] - n),
+        _ if fb[0] < n => format!(
+            "qsat($signed({{{{{}{{{}[{}]}}}}, {}}}) <<< {})",
+            w,
+            acc,
+            msb,
+            acc,
+            n - fb[0]
+        ),
+        _ => acc,
+    };
+    writeln!(text, "        // Stage {}.", k + 1).unwrap();
+    writeln!(text, "        y <= {};", result).unwrap();
+    writeln!(text, "    end").unwrap();
+    writeln!(text, "endmodule").unwrap();
+    writeln!(text).unwrap();
+}
+
+/// Arguments spread over the domain and the correctly rounded results from bdmath.
+fn reference_vectors(f: &FixedFunction, format: Format) -> Vec<(i64, i64)> {
+    let scale = (format.frac_bits as f64).exp2();
+    let lo = format.saturate((f.xmin * scale).ceil() as i64);
+    let hi = format.saturate((f.xmax * scale).floor() as i64);
+    let mut args: Vec<i64> = (0..=32).map(|i| lo + (hi - lo) * i / 32).collect();
+    args.dedup();
+    let one = BigDecimal::from(1_i64 << format.frac_bits);
+    args.into_iter()
+        .map(|x| {
+            let mut vars = VariableList::new();
+            vars.add_var(name!(x), Expression::from(BigDecimal::from(x) / &one));
+            let y: BigDecimal = (f.expr)()
+                .subst(vars)
+                .and_then(|e| e.eval(30))
+                .and_then(|y| y.try_into())
+                .unwrap();
+            let y = (y * &one).to_f64().unwrap().round() as i64;
+            (x, format.saturate(y))
+        })
+        .collect()
+}
+
+fn gen_testbench(
+    text: &mut String,
+    format: Format,
+    funcs: &[(&FixedFunction, Approx)],
+    prefix: &str,
+) {
+    let w = format.word_bits();
+    let msb = w - 1;
+    writeln!(
+        text,
+        "// Checks the functions against reference values from bdmath."
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "// iverilog -o {}tb file.v && vvp {}tb",
+        prefix, prefix
+    )
+    .unwrap();
+    writeln!(text, "module {}tb;", prefix).unwrap();
+    writeln!(text, "    reg clk = 0;").unwrap();
+    writeln!(text, "    always #5 clk = !clk;").unwrap();
+    writeln!(text, "    integer errors = 0;").unwrap();
+    writeln!(text, "    integer diff;").unwrap();
+    for (f, approx) in funcs {
+        let name = f.name;
+        let tolerance = approx.max_error.ceil() as i64;
+        writeln!(text).unwrap();
+        writeln!(text, "    reg signed [{}:0] {}_x;", msb, name).unwrap();
+        writeln!(text, "    wire signed [{}:0] {}_y;", msb, name).unwrap();
+        writeln!(
+            text,
+            "    {}{} {}_dut(.clk(clk), .x({}_x), .y({}_y));",
+            prefix, name, name, name, name
+        )
+        .unwrap();
+        writeln!(text).unwrap();
+        writeln!(
+            text,
+            "    task check_{}(input signed [{}:0] arg, input signed [{}:0] expected);",
+            name, msb, msb
+        )
+        .unwrap();
+        writeln!(text, "        begin").unwrap();
+        writeln!(text, "            {}_x = arg;", name).unwrap();
+        writeln!(
+            text,
+            "            repeat ({}) @(posedge clk);",
+            latency(approx)
+        )
+        .unwrap();
+        writeln!(text, "            #1;").unwrap();
+        writeln!(text, "            diff = {}_y - expected;", name).unwrap();
+        writeln!(
+            text,
+            "            if (diff > {} || diff < -{}) begin",
+            tolerance, tolerance
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "                $display(\"{}(%0d) = %0d, expected %0d\", arg, {}_y, expected);",
+            name, name
+        )
+        .unwrap();
+        writeln!(text, "                errors = errors + 1;").unwrap();
+        writeln!(text, "            end").unwrap();
+        writeln!(text, "        end").unwrap();
+        writeln!(text, "    endtask").unwrap();
+    }
+    writeln!(text).unwrap();
+    writeln!(text, "    initial begin").unwrap();
+    for (f, _) in funcs {
+        for (x, y) in reference_vectors(f, format) {
+            writeln!(
+                text,
+                "        check_{}({}, {});",
+                f.name,
+                lit(x, w),
+                lit(y, w)
+            )
+            .unwrap();
+        }
+    }
+    writeln!(text, "        if (errors == 0)").unwrap();
+    writeln!(text, "            $display(\"PASS\");").unwrap();
+    writeln!(text, "        else").unwrap();
+    writeln!(text, "            $display(\"FAIL: %0d errors\", errors);").unwrap();
+    writeln!(text, "        $finish;").unwrap();
+    writeln!(text, "    end").unwrap();
+    writeln!(text, "endmodule").unwrap();
+}
+
+/// Verilog modules for the functions, followed by a testbench if asked for.
+pub fn to_verilog(
+    format: Format,
+    funcs: &[&FixedFunction],
+    prefix: &str,
+    testbench: bool,
+) -> String {
+    let funcs: Vec<(&FixedFunction, Approx)> =
+        funcs.iter().map(|f| (*f, best_approx(f, format))).collect();
+    let mut text = String::new();
+    writeln!(text, "`timescale 1ns / 1ps").unwrap();
+    writeln!(text).unwrap();
+    for (f, approx) in &funcs {
+        gen_module(&mut text, f, format, approx, prefix);
+    }
+    if testbench {
+        gen_testbench(&mut text, format, &funcs, prefix);
+    }
+    text
+}
+
+#[test]
+fn test_reference_vectors() {
+    // The testbench tolerance should pass the arithmetic the modules implement.
+    for text in ["Q0.7", "Q1.14", "Q1.30"] {
+        let format = Format::parse(text).unwrap();
+        for f in crate::fixed::FIXED_FUNCTIONS {
+            let approx = best_approx(f, format);
+            let tolerance = approx.max_error.ceil() as i64;
+            for (x, expected) in reference_vectors(f, format) {
+                let y = approx.eval(f, format, x);
+                assert!(
+                    (y - expected).abs() <= tolerance,
+                    "{} {}({}) = {} expected {}",
+                    text,
+                    f.name,
+                    x,
+                    y,
+                    expected
+                );
+            }
+        }
+    }
+}